@@ -0,0 +1,123 @@
+use std::{net::IpAddr, str::FromStr};
+
+use clap::{builder::ValueParser, Parser};
+use eyre::WrapErr;
+use receiver::ReceiverArgs;
+use sender::{delivery::DeliveryArgs, SenderArgs};
+use server::{oidc::OidcArgs, ServerArgs};
+use tokio_util::sync::CancellationToken;
+use tracing_subscriber::{layer::SubscriberExt, reload, util::SubscriberInitExt, EnvFilter};
+use url::Url;
+
+const LOG_LEVEL: &str = "receiver=info,sender=info,server=info,tower_http=debug";
+
+/// Runs both the sender and the receiver in a single process.
+#[derive(Debug, Clone, Parser)]
+#[clap(name = env!("CARGO_PKG_NAME"), version)]
+struct Cli {
+    /// Address to listen on
+    #[arg(default_value = "127.0.0.1", value_parser= ValueParser::new(IpAddr::from_str) )]
+    address: IpAddr,
+    /// Port the receiver listens on
+    #[arg(long, default_value = "9000")]
+    receiver_port: u16,
+    /// Port the sender listens on
+    #[arg(long, default_value = "9001")]
+    sender_port: u16,
+    /// Number of times a failed ping is sent again
+    #[arg(long, default_value = "0")]
+    retries: u32,
+    /// StatsD or DogStatsD server the metrics are also sent to, over UDP
+    #[arg(long, value_name = "HOST:PORT")]
+    statsd_addr: Option<String>,
+    /// Base url of the OpenTelemetry collector the metrics are also exported to, over OTLP/HTTP
+    #[arg(long, env = "OTEL_EXPORTER_OTLP_ENDPOINT", value_name = "URL")]
+    otlp_endpoint: Option<String>,
+    #[command(flatten)]
+    server: ServerArgs,
+    #[command(flatten, next_help_heading = "Receiver")]
+    receiver: ReceiverArgs,
+    #[command(flatten, next_help_heading = "Sender")]
+    sender: SenderArgs,
+}
+
+#[tokio::main]
+async fn main() -> eyre::Result<()> {
+    let cli = Cli::parse();
+
+    color_eyre::install()?;
+
+    // Reloadable from the admin routes of the receiver
+    let (filter, log_filter) =
+        reload::Layer::new(EnvFilter::try_from_default_env().unwrap_or_else(|_| LOG_LEVEL.into()));
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_subscriber::fmt::layer())
+        .try_init()?;
+
+    let receiver_listener = server::bind((cli.address, cli.receiver_port).into(), &cli.server)
+        .wrap_err("couldn't bind the receiver port, change it with --receiver-port")?;
+    let sender_listener = server::bind((cli.address, cli.sender_port).into(), &cli.server)
+        .wrap_err("couldn't bind the sender port, change it with --sender-port")?;
+
+    // The sender pings the receiver of this process
+    let delivery = DeliveryArgs {
+        retries: cli.retries,
+        ..DeliveryArgs::new(Url::parse(&format!(
+            "http://{}",
+            receiver_listener.local_addr()?
+        ))?)
+    };
+
+    // The redirect url of the login is the one of the receiver, the sender UI isn't behind it
+    let sender_server = ServerArgs {
+        oidc: OidcArgs::default(),
+        ..cli.server.clone()
+    };
+
+    let shutdown = CancellationToken::new();
+    tokio::spawn({
+        let shutdown = shutdown.clone();
+
+        async move {
+            receiver::shutdown_signal().await;
+
+            shutdown.cancel();
+        }
+    });
+
+    // A single recorder per process, both servers render all the metrics
+    let metrics = server::telemetry::install_metrics(
+        env!("CARGO_PKG_NAME"),
+        cli.statsd_addr.as_deref(),
+        cli.otlp_endpoint.as_deref(),
+    )?;
+
+    let res = tokio::try_join!(
+        receiver::run(
+            receiver_listener,
+            cli.receiver,
+            cli.server.clone(),
+            false,
+            metrics.clone(),
+            Some(log_filter),
+            shutdown.clone()
+        ),
+        sender::run(
+            Some(sender_listener),
+            delivery,
+            cli.sender,
+            sender_server,
+            false,
+            metrics,
+            shutdown
+        ),
+    );
+
+    server::otlp::shutdown();
+
+    res?;
+
+    Ok(())
+}
@@ -0,0 +1,171 @@
+//! Encodings of the messages.
+
+use serde::{de::DeserializeOwned, Serialize};
+
+pub const JSON: &str = "application/json";
+pub const MESSAGE_PACK: &str = "application/msgpack";
+pub const CBOR: &str = "application/cbor";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Format {
+    #[default]
+    Json,
+    MessagePack,
+    Cbor,
+}
+
+impl Format {
+    /// Format of the media type, ignoring its parameters.
+    ///
+    /// The types with the `+json` and `+cbor` structured syntax suffixes are in their format.
+    pub fn from_media_type(media_type: &str) -> Option<Self> {
+        let essence = media_type.split(';').next().unwrap_or_default().trim();
+        let suffix = essence
+            .rsplit_once('+')
+            .map(|(_, suffix)| suffix)
+            .unwrap_or_default();
+
+        if essence.eq_ignore_ascii_case(JSON) || suffix.eq_ignore_ascii_case("json") {
+            Some(Format::Json)
+        } else if essence.eq_ignore_ascii_case(MESSAGE_PACK)
+            || essence.eq_ignore_ascii_case("application/x-msgpack")
+            || essence.eq_ignore_ascii_case("application/vnd.msgpack")
+        {
+            Some(Format::MessagePack)
+        } else if essence.eq_ignore_ascii_case(CBOR) || suffix.eq_ignore_ascii_case("cbor") {
+            Some(Format::Cbor)
+        } else {
+            None
+        }
+    }
+
+    /// Picks the supported format with the highest weight of an `Accept` header, the first one
+    /// between the same weights.
+    ///
+    /// The media types with `q=0`, or an invalid weight, are not accepted. Returns [`None`] if
+    /// none of the accepted media types is supported.
+    pub fn from_accept(accept: &str) -> Option<Self> {
+        let mut best: Option<(f32, Self)> = None;
+
+        for media_range in accept.split(',') {
+            let mut parts = media_range.split(';');
+            let essence = parts.next().unwrap_or_default().trim();
+
+            let format = match essence {
+                "*/*" | "application/*" => Some(Format::Json),
+                _ => Self::from_media_type(essence),
+            };
+            let Some(format) = format else {
+                continue;
+            };
+
+            let Some(weight) = weight(parts) else {
+                continue;
+            };
+
+            if weight > 0.0 && best.is_none_or(|(best, _)| weight > best) {
+                best = Some((weight, format));
+            }
+        }
+
+        best.map(|(_, format)| format)
+    }
+
+    pub fn media_type(&self) -> &'static str {
+        match self {
+            Format::Json => JSON,
+            Format::MessagePack => MESSAGE_PACK,
+            Format::Cbor => CBOR,
+        }
+    }
+
+    pub fn encode<T>(&self, value: &T) -> eyre::Result<Vec<u8>>
+    where
+        T: Serialize,
+    {
+        let bytes = match self {
+            Format::Json => serde_json::to_vec(value)?,
+            // Encode the structs as maps, to keep the field names like the other formats
+            Format::MessagePack => rmp_serde::to_vec_named(value)?,
+            Format::Cbor => {
+                let mut bytes = Vec::new();
+                ciborium::into_writer(value, &mut bytes)?;
+
+                bytes
+            }
+        };
+
+        Ok(bytes)
+    }
+
+    pub fn decode<T>(&self, bytes: &[u8]) -> eyre::Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        let value = match self {
+            Format::Json => serde_json::from_slice(bytes)?,
+            Format::MessagePack => rmp_serde::from_slice(bytes)?,
+            Format::Cbor => ciborium::from_reader(bytes)?,
+        };
+
+        Ok(value)
+    }
+}
+
+/// Weight of the `q` parameter of a media range, 1 if missing.
+fn weight<'a>(mut params: impl Iterator<Item = &'a str>) -> Option<f32> {
+    let q = params.find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+
+        name.trim().eq_ignore_ascii_case("q").then(|| value.trim())
+    });
+
+    match q {
+        Some(q) => q.parse().ok().filter(|q| (0.0..=1.0).contains(q)),
+        None => Some(1.0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_format_picked_between_the_same_weights() {
+        assert_eq!(
+            Format::from_accept("application/cbor, application/json"),
+            Some(Format::Cbor)
+        );
+        assert_eq!(Format::from_accept("text/html, */*"), Some(Format::Json));
+    }
+
+    #[test]
+    fn highest_weight_picked() {
+        assert_eq!(
+            Format::from_accept("application/json;q=0.5, application/msgpack;q=0.9"),
+            Some(Format::MessagePack)
+        );
+        assert_eq!(
+            Format::from_accept("application/cbor; Q=0.1, application/json"),
+            Some(Format::Json)
+        );
+    }
+
+    #[test]
+    fn media_types_with_zero_weight_not_accepted() {
+        assert_eq!(Format::from_accept("application/json;q=0"), None);
+        assert_eq!(
+            Format::from_accept("application/json;q=0.0, application/cbor;q=0.2"),
+            Some(Format::Cbor)
+        );
+    }
+
+    #[test]
+    fn invalid_weights_not_accepted() {
+        assert_eq!(Format::from_accept("application/json;q=high"), None);
+        assert_eq!(
+            Format::from_accept("application/json;q=2, application/cbor"),
+            Some(Format::Cbor)
+        );
+    }
+}
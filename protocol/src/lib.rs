@@ -0,0 +1,213 @@
+//! Messages exchanged between the sender and the receiver.
+
+use serde::{Deserialize, Serialize};
+use url::Url;
+use uuid::Uuid;
+
+mod format;
+pub mod proto;
+
+pub use self::format::Format;
+
+/// Service type the receivers are advertised with over mDNS.
+pub const MDNS_SERVICE: &str = "_pingpong._tcp.local.";
+
+/// Header with the id of the request a ping is sent for, tracing it from the sender.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Header with the id of the ping, so the retries of a ping are counted once.
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
+/// Version of the schema of the pings sent by this build.
+///
+/// Version 1 is the bare ping without a `schema_version`, with only the id and the callback.
+/// Version 2 adds the sender and its metadata.
+pub const SCHEMA_VERSION: u32 = 2;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(into = "VersionedPing", try_from = "VersionedPing")]
+pub struct Ping {
+    pub id: Uuid,
+    /// Url to send the pong to once the ping is counted
+    pub callback: Option<Url>,
+    /// Id the sender registered with, to track its last ping
+    pub sender: Option<Uuid>,
+    pub metadata: Metadata,
+}
+
+/// Ping as encoded, in any of the supported versions of the schema.
+#[derive(Debug, Serialize, Deserialize)]
+struct VersionedPing {
+    #[serde(default = "v1")]
+    schema_version: u32,
+    #[serde(with = "id")]
+    id: Uuid,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    callback: Option<Url>,
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "id::option")]
+    sender: Option<Uuid>,
+    #[serde(default, skip_serializing_if = "Metadata::is_empty")]
+    metadata: Metadata,
+}
+
+/// The pings without a version are the ones before the versioning.
+fn v1() -> u32 {
+    1
+}
+
+/// Ping in a version of the schema that isn't supported, like a newer one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnsupportedVersion(pub u32);
+
+impl std::fmt::Display for UnsupportedVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "unsupported ping schema version {}, the latest supported is {SCHEMA_VERSION}",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for UnsupportedVersion {}
+
+impl From<Ping> for VersionedPing {
+    fn from(ping: Ping) -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            id: ping.id,
+            callback: ping.callback,
+            sender: ping.sender,
+            metadata: ping.metadata,
+        }
+    }
+}
+
+impl TryFrom<VersionedPing> for Ping {
+    type Error = UnsupportedVersion;
+
+    fn try_from(ping: VersionedPing) -> Result<Self, Self::Error> {
+        match ping.schema_version {
+            // Ignores the fields added later, they weren't part of the schema
+            1 => Ok(Self {
+                id: ping.id,
+                callback: ping.callback,
+                sender: None,
+                metadata: Metadata::default(),
+            }),
+            2 => Ok(Self {
+                id: ping.id,
+                callback: ping.callback,
+                sender: ping.sender,
+                metadata: ping.metadata,
+            }),
+            version => Err(UnsupportedVersion(version)),
+        }
+    }
+}
+
+/// Details of where a ping was sent from, to trace it back to the machine.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Metadata {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    /// Version of the sender
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// Number of the ping between the ones sent by the sender, starting from zero
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sequence: Option<u64>,
+    /// RFC 3339 timestamp of when the ping was sent, by the clock of the sender
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sent_at: Option<String>,
+    /// User logged in to the sender UI the ping was sent from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+}
+
+impl Metadata {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Pong {
+    #[serde(with = "id")]
+    pub id: Uuid,
+}
+
+/// Registration of a sender with the receiver, sent again periodically as a heartbeat.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Registration {
+    #[serde(with = "id")]
+    pub id: Uuid,
+    /// Url of the sender API, missing when the sender is headless
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<Url>,
+    pub version: String,
+}
+
+/// Ping received by the receiver, as a line of the recordings replayed to reproduce the traffic.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Recorded {
+    /// Milliseconds from the start of the recording to when the ping was received
+    pub offset_ms: u64,
+    pub tenant: String,
+    pub ping: Ping,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Count {
+    pub tenant: String,
+    pub count: u64,
+}
+
+/// Encodes the ids as strings also in the binary formats, where [`Uuid`] defaults to bytes.
+mod id {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+    use uuid::Uuid;
+
+    pub fn serialize<S>(id: &Uuid, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(&id.hyphenated())
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Uuid, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let id = String::deserialize(deserializer)?;
+
+        Uuid::parse_str(&id).map_err(D::Error::custom)
+    }
+
+    pub mod option {
+        use serde::{Deserialize, Deserializer, Serializer};
+        use uuid::Uuid;
+
+        pub fn serialize<S>(id: &Option<Uuid>, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            match id {
+                Some(id) => super::serialize(id, serializer),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<Uuid>, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            #[derive(Deserialize)]
+            struct Id(#[serde(with = "super")] Uuid);
+
+            let id = Option::<Id>::deserialize(deserializer)?;
+
+            Ok(id.map(|Id(id)| id))
+        }
+    }
+}
@@ -0,0 +1,30 @@
+//! Protobuf messages, matching the definitions in `proto/events.proto`.
+
+use prost::{Enumeration, Message};
+
+/// Event of a counter of the receiver.
+#[derive(Clone, PartialEq, Message)]
+pub struct CountEvent {
+    #[prost(enumeration = "Kind", tag = "1")]
+    pub kind: i32,
+    /// Id of the message, as the 16 bytes of the uuid.
+    #[prost(bytes = "vec", tag = "2")]
+    pub id: Vec<u8>,
+    #[prost(string, tag = "3")]
+    pub tenant: String,
+    /// Count of the tenant after the event.
+    #[prost(uint64, tag = "4")]
+    pub count: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Enumeration)]
+#[repr(i32)]
+pub enum Kind {
+    Unspecified = 0,
+    /// A ping was counted.
+    Ping = 1,
+    /// A pong was counted.
+    Pong = 2,
+    /// The count was adjusted by an administrator.
+    Adjusted = 3,
+}
@@ -0,0 +1,140 @@
+//! Fingerprints and compresses the assets embedded in the receiver.
+//!
+//! Each file of the `assets` directory is served on `/assets/{stem}.{hash}.{extension}`, with
+//! the hash of its content, so it can be cached forever. The references to `/assets/{name}` in
+//! the templates are rewritten to the fingerprinted paths.
+//!
+//! The assets are also embedded compressed with gzip and brotli, when it makes them smaller, so
+//! they are served without compressing them on each request.
+
+use std::{
+    fmt::Write as _,
+    fs,
+    io::Write as _,
+    path::{Path, PathBuf},
+};
+
+use flate2::{write::GzEncoder, Compression};
+use sha2::{Digest, Sha256};
+
+/// Hex digits of the hash kept in the paths.
+const HASH_LEN: usize = 16;
+
+/// Brotli parameters of the smallest output.
+const BROTLI_QUALITY: u32 = 11;
+const BROTLI_WINDOW: u32 = 22;
+
+struct Asset {
+    name: String,
+    path: String,
+    file: PathBuf,
+}
+
+/// Expression of the embedded file with its compressed variants.
+fn embedded(out_dir: &Path, name: &str, file: &Path) -> String {
+    let content = fs::read(file).expect("couldn't read the embedded file");
+
+    let mut gzip = GzEncoder::new(Vec::new(), Compression::best());
+    gzip.write_all(&content)
+        .expect("couldn't gzip the embedded file");
+    let gzip = gzip.finish().expect("couldn't gzip the embedded file");
+
+    let mut brotli = Vec::new();
+    {
+        let mut writer =
+            brotli::CompressorWriter::new(&mut brotli, 4096, BROTLI_QUALITY, BROTLI_WINDOW);
+        writer
+            .write_all(&content)
+            .expect("couldn't compress the embedded file with brotli");
+    }
+
+    // Only the variants smaller than the file are worth it
+    let variant = |compressed: Vec<u8>, extension: &str| {
+        if compressed.len() >= content.len() {
+            return "None".to_string();
+        }
+
+        let path = out_dir.join(format!("{name}.{extension}"));
+        fs::write(&path, compressed).expect("couldn't write the compressed file");
+
+        format!("Some(include_bytes!({path:?}))")
+    };
+
+    format!(
+        "Embedded {{ content: include_bytes!({file:?}), gzip: {}, brotli: {} }}",
+        variant(gzip, "gz"),
+        variant(brotli, "br")
+    )
+}
+
+fn fingerprint(file: &Path) -> Asset {
+    let content = fs::read(file).expect("couldn't read the asset");
+    let hash = Sha256::digest(&content)
+        .iter()
+        .fold(String::new(), |mut hash, byte| {
+            let _ = write!(hash, "{byte:02x}");
+
+            hash
+        });
+
+    let name = file
+        .file_name()
+        .and_then(|name| name.to_str())
+        .expect("the asset name isn't UTF-8")
+        .to_string();
+    let path = match name.rsplit_once('.') {
+        Some((stem, extension)) => format!("/assets/{stem}.{}.{extension}", &hash[..HASH_LEN]),
+        None => format!("/assets/{name}.{}", &hash[..HASH_LEN]),
+    };
+
+    Asset {
+        name,
+        path,
+        file: file.canonicalize().expect("couldn't resolve the asset"),
+    }
+}
+
+fn main() {
+    let out_dir = PathBuf::from(std::env::var_os("OUT_DIR").expect("OUT_DIR not set"));
+
+    println!("cargo:rerun-if-changed=../assets");
+    println!("cargo:rerun-if-changed=templates");
+
+    let mut files: Vec<PathBuf> = fs::read_dir("../assets")
+        .expect("couldn't read the assets")
+        .map(|entry| entry.expect("couldn't read the assets").path())
+        .filter(|path| path.is_file())
+        .collect();
+    files.sort_unstable();
+
+    let assets: Vec<Asset> = files.iter().map(|file| fingerprint(file)).collect();
+
+    let mut code = String::from("&[\n");
+    for asset in &assets {
+        let _ = writeln!(
+            code,
+            "    Asset {{ name: {:?}, path: {:?}, embedded: {} }},",
+            asset.name,
+            asset.path,
+            embedded(&out_dir, &asset.name, &asset.file)
+        );
+    }
+    code.push(']');
+
+    fs::write(out_dir.join("assets.rs"), code).expect("couldn't write the assets");
+
+    for template in fs::read_dir("templates").expect("couldn't read the templates") {
+        let template = template.expect("couldn't read the templates").path();
+        let mut content = fs::read_to_string(&template).expect("couldn't read the template");
+
+        for asset in &assets {
+            content = content.replace(
+                &format!("/assets/{}\"", asset.name),
+                &format!("{}\"", asset.path),
+            );
+        }
+
+        let name = template.file_name().expect("the template has no name");
+        fs::write(out_dir.join(name), content).expect("couldn't write the template");
+    }
+}
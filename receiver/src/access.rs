@@ -0,0 +1,225 @@
+//! Access log of the HTTP requests, in the Elastic Common Schema.
+//!
+//! Each request is written as a JSON line with the fields of [ECS], separately from the tracing
+//! output, so it can be shipped as is to Elasticsearch. The lines are written by a dedicated
+//! thread, to a file or to the standard output with `-`.
+//!
+//! [ECS]: https://www.elastic.co/guide/en/ecs/current/index.html
+
+use std::{
+    fs::OpenOptions,
+    io::{self, LineWriter, Write},
+    net::{IpAddr, SocketAddr},
+    path::Path,
+    sync::mpsc,
+    time::{Instant, SystemTime},
+};
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{header::USER_AGENT, HeaderMap, HeaderName},
+    middleware::Next,
+    response::Response,
+};
+use eyre::WrapErr;
+use serde::Serialize;
+use tracing::error;
+
+use crate::AppState;
+
+/// Version of the schema the lines follow.
+const ECS_VERSION: &str = "8.11.0";
+
+static REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+#[derive(Debug, Serialize)]
+struct Line<'a> {
+    #[serde(rename = "@timestamp")]
+    timestamp: String,
+    ecs: Ecs,
+    event: Event,
+    http: Http<'a>,
+    url: Url<'a>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    client: Option<Client>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    user_agent: Option<UserAgent<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+struct Ecs {
+    version: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+struct Event {
+    kind: &'static str,
+    category: [&'static str; 1],
+    dataset: &'static str,
+    /// Duration of the request in nanoseconds.
+    duration: u128,
+    outcome: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+struct Http<'a> {
+    version: &'a str,
+    request: HttpRequest<'a>,
+    response: HttpResponse,
+}
+
+#[derive(Debug, Serialize)]
+struct HttpRequest<'a> {
+    method: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<&'a str>,
+}
+
+#[derive(Debug, Serialize)]
+struct HttpResponse {
+    status_code: u16,
+}
+
+#[derive(Debug, Serialize)]
+struct Url<'a> {
+    path: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    query: Option<&'a str>,
+}
+
+#[derive(Debug, Serialize)]
+struct Client {
+    ip: IpAddr,
+    port: u16,
+}
+
+#[derive(Debug, Serialize)]
+struct UserAgent<'a> {
+    original: &'a str,
+}
+
+#[derive(Debug)]
+pub struct AccessLog {
+    lines: mpsc::Sender<Vec<u8>>,
+}
+
+impl AccessLog {
+    /// Opens the file for appending, or the standard output for `-`, and starts the thread
+    /// writing to it.
+    pub fn open(path: &Path) -> eyre::Result<Self> {
+        let out: Box<dyn Write + Send> = if path == Path::new("-") {
+            Box::new(io::stdout())
+        } else {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .wrap_err_with(|| format!("couldn't open {}", path.display()))?;
+
+            Box::new(file)
+        };
+        let (lines, rx) = mpsc::channel();
+
+        std::thread::Builder::new()
+            .name("access-log".to_string())
+            .spawn(move || write(LineWriter::new(out), rx))
+            .wrap_err("couldn't start the access log writer")?;
+
+        Ok(Self { lines })
+    }
+
+    fn record(&self, line: &Line) {
+        let line = match serde_json::to_vec(line) {
+            Ok(line) => line,
+            Err(err) => {
+                error!(error = %err, "couldn't serialize the access log line");
+
+                return;
+            }
+        };
+
+        if self.lines.send(line).is_err() {
+            error!("access log writer stopped, line lost");
+        }
+    }
+}
+
+/// Writes the lines until the log is dropped.
+fn write(mut out: LineWriter<Box<dyn Write + Send>>, lines: mpsc::Receiver<Vec<u8>>) {
+    for mut line in lines {
+        line.push(b'\n');
+
+        if let Err(err) = out.write_all(&line) {
+            error!(error = %err, "couldn't write the access log line");
+        }
+    }
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &HeaderName) -> Option<&'a str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+/// Middleware writing a line for each request, once responded.
+pub async fn log(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let Some(access) = &state.access_log else {
+        return next.run(req).await;
+    };
+
+    let timestamp = humantime::format_rfc3339_millis(SystemTime::now()).to_string();
+    let method = req.method().clone();
+    let version = req.version();
+    let uri = req.uri().clone();
+    let headers = req.headers().clone();
+    let client = req
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| Client {
+            ip: addr.ip().to_canonical(),
+            port: addr.port(),
+        });
+
+    let start = Instant::now();
+    let res = next.run(req).await;
+    let duration = start.elapsed();
+
+    let status = res.status();
+    let outcome = if status.is_client_error() || status.is_server_error() {
+        "failure"
+    } else {
+        "success"
+    };
+    let version = format!("{version:?}");
+
+    access.record(&Line {
+        timestamp,
+        ecs: Ecs {
+            version: ECS_VERSION,
+        },
+        event: Event {
+            kind: "event",
+            category: ["web"],
+            dataset: "receiver.access",
+            duration: duration.as_nanos(),
+            outcome,
+        },
+        http: Http {
+            version: version.strip_prefix("HTTP/").unwrap_or(&version),
+            request: HttpRequest {
+                method: method.as_str(),
+                // The panic responses carry the id they were logged with, returned instead
+                id: header(res.headers(), &REQUEST_ID).or_else(|| header(&headers, &REQUEST_ID)),
+            },
+            response: HttpResponse {
+                status_code: status.as_u16(),
+            },
+        },
+        url: Url {
+            path: uri.path(),
+            query: uri.query(),
+        },
+        client,
+        user_agent: header(&headers, &USER_AGENT).map(|original| UserAgent { original }),
+    });
+
+    res
+}
@@ -0,0 +1,413 @@
+//! Administration of the receiver, behind the admin token.
+//!
+//! The routes are under `/admin`, along with the ones changing the counts under `/v1/count`,
+//! served with the public ones or, with `--admin-port`, only on a separate port that can be kept
+//! off the public frontend. They are authorized with the token as a bearer in the `Authorization`
+//! header; the receiver serves plain HTTP, so there is no mutual TLS, it's left to the proxy in
+//! front of the admin port.
+
+use std::{io, net::SocketAddr, sync::Arc, time::Duration};
+
+use axum::{
+    extract::{ConnectInfo, Query, State},
+    http::StatusCode,
+    middleware,
+    routing::{delete, get, post, put},
+    Json, Router,
+};
+use protocol::Count;
+use serde::{Deserialize, Serialize, Serializer};
+use tracing::info;
+use tracing_subscriber::{reload, EnvFilter, Registry};
+use uuid::Uuid;
+
+use crate::{
+    audit::{AuditLog, Source, Transport},
+    auth::authorize,
+    cluster::Membership,
+    count_gauge,
+    events::Event,
+    ips, keys,
+    maintenance::{self, DEFAULT_RETRY_AFTER},
+    milestone, notify,
+    runtime::{self, RuntimeSnapshot},
+    snapshot::{self, Snapshot},
+    tenant::{RequestedTenant, Tenant, TenantCount},
+    timing,
+    udp::UdpStatsSnapshot,
+    version::{self, Endpoint},
+    wal::{Wal, WalHealth},
+    AppError, AppState, ReceiverArgs,
+};
+
+/// Window the rate of the pings is measured over.
+pub(crate) const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+/// Handle to change the filter of the logs while running.
+pub type LogFilter = reload::Handle<EnvFilter, Registry>;
+
+#[derive(Debug, Deserialize)]
+struct ResetQuery {
+    /// Resets all the tenants if missing.
+    tenant: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AddBody {
+    /// Added to the count, or subtracted if negative.
+    delta: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct SetBody {
+    count: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct MaintenanceBody {
+    enabled: bool,
+    /// Shown on the maintenance page.
+    #[serde(default)]
+    message: Option<String>,
+    /// Sent to the clients in the `Retry-After` header.
+    #[serde(default)]
+    retry_after_secs: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+struct Uploaded {
+    key: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Stats {
+    /// Sum of the counts of all the tenants.
+    total: u64,
+    /// Pings per second over the last minute.
+    rate: f64,
+    tenants: Vec<TenantCount>,
+    /// Pings kept in the history.
+    history: usize,
+    /// Pings evicted from the history since the startup.
+    history_evicted: u64,
+    senders: usize,
+    /// Open HTTP connections, with the upgraded WebSockets.
+    connections: usize,
+    /// Connected WebSocket clients.
+    clients: usize,
+    queues: Queues,
+    udp: UdpStatsSnapshot,
+    runtime: RuntimeSnapshot,
+    /// State of the write-ahead log, if enabled.
+    wal: Option<WalHealth>,
+}
+
+#[derive(Debug, Serialize)]
+struct Queues {
+    /// Events not yet received by all the WebSocket clients.
+    events: usize,
+    /// Entries not yet written to the audit log, if enabled.
+    audit: Option<usize>,
+}
+
+impl Stats {
+    /// Logs the statistics as the fields of a single event.
+    pub fn log(&self) {
+        let tenants = serde_json::to_string(&self.tenants).unwrap_or_default();
+        let runtime = serde_json::to_string(&self.runtime).unwrap_or_default();
+        let udp = serde_json::to_string(&self.udp).unwrap_or_default();
+        let wal = self.wal.and_then(|wal| serde_json::to_string(&wal).ok());
+
+        info!(
+            total = self.total,
+            rate = self.rate,
+            tenants,
+            history = self.history,
+            senders = self.senders,
+            connections = self.connections,
+            clients = self.clients,
+            events_queued = self.queues.events,
+            audit_pending = self.queues.audit,
+            udp,
+            runtime,
+            wal,
+            "statistics dump"
+        );
+    }
+}
+
+/// Serializes the durations as they're given on the command line.
+pub(crate) fn humantime<S>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.collect_str(&humantime::format_duration(*duration))
+}
+
+pub(crate) fn humantime_opt<S>(
+    duration: &Option<Duration>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    match duration {
+        Some(duration) => humantime(duration, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Sets the count of the tenant back to zero, or of all of them.
+pub fn reset_counts(state: &AppState, tenant: Option<Tenant>) -> io::Result<()> {
+    let tenants = match tenant {
+        Some(tenant) => vec![tenant],
+        None => state
+            .counters
+            .tenants()
+            .into_iter()
+            .map(|count| count.tenant)
+            .collect(),
+    };
+
+    for tenant in &tenants {
+        match &state.wal {
+            Some(wal) => wal.set(&state.counters, tenant, 0)?,
+            None => state.counters.set(tenant, 0),
+        }
+
+        count_gauge(state, tenant, 0);
+    }
+
+    info!(tenants = tenants.len(), "counts reset");
+
+    Ok(())
+}
+
+/// Publishes the count of the tenant adjusted by the delta, like the counted pings.
+fn adjusted(state: &AppState, id: Uuid, tenant: &Tenant, count: u64, delta: i64) {
+    info!(%tenant, delta, count, "count adjusted");
+
+    count_gauge(state, tenant, count);
+
+    state.events.publish(Event::Adjusted {
+        id,
+        tenant: tenant.clone(),
+        count,
+        delta,
+    });
+
+    milestone::counted(state, tenant, count);
+    notify::counted(state);
+}
+
+/// Adds a delta to the count of the tenant, for corrections and migrations.
+///
+/// The correction is audited with the delta, the previous count and the address of the caller.
+async fn add_count(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    RequestedTenant(tenant): RequestedTenant,
+    Json(body): Json<AddBody>,
+) -> Result<Json<Count>, AppError> {
+    let tenant = tenant.unwrap_or_default();
+    let (previous, count) = timing::store(|| match &state.wal {
+        Some(wal) => wal.add(&state.counters, &tenant, body.delta),
+        None => Ok(state.counters.add(&tenant, body.delta)),
+    })?;
+
+    let id = Uuid::new_v4();
+
+    if let Some(audit) = &state.audit {
+        let source = Source {
+            transport: Transport::Http,
+            addr,
+        };
+
+        audit.record_add(id, source, &tenant, previous, body.delta, count);
+    }
+
+    adjusted(&state, id, &tenant, count, body.delta);
+
+    Ok(Json(Count {
+        tenant: tenant.to_string(),
+        count,
+    }))
+}
+
+/// Sets the count of the tenant, like when migrating from another counter, in the audit log.
+async fn set_count(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    RequestedTenant(tenant): RequestedTenant,
+    Json(body): Json<SetBody>,
+) -> Result<Json<Count>, AppError> {
+    let tenant = tenant.unwrap_or_default();
+    let count = body.count;
+    let previous = timing::store(|| match &state.wal {
+        Some(wal) => wal.replace(&state.counters, &tenant, count),
+        None => Ok(state.counters.replace(&tenant, count)),
+    })?;
+
+    let id = Uuid::new_v4();
+
+    if let Some(audit) = &state.audit {
+        let source = Source {
+            transport: Transport::Http,
+            addr,
+        };
+
+        audit.record_set(id, source, &tenant, previous, count);
+    }
+
+    let delta = i128::from(count) - i128::from(previous);
+    let delta = i64::try_from(delta).unwrap_or(if delta < 0 { i64::MIN } else { i64::MAX });
+
+    adjusted(&state, id, &tenant, count, delta);
+
+    Ok(Json(Count {
+        tenant: tenant.to_string(),
+        count,
+    }))
+}
+
+async fn reset(
+    State(state): State<AppState>,
+    Query(query): Query<ResetQuery>,
+) -> Result<StatusCode, AppError> {
+    let tenant = query
+        .tenant
+        .map(|tenant| {
+            Tenant::parse(&tenant)
+                .ok_or_else(|| AppError::BadRequest(format!("invalid tenant {tenant}")))
+        })
+        .transpose()?;
+
+    reset_counts(&state, tenant)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub fn stats(state: &AppState) -> Stats {
+    let tenants = state.counters.tenants();
+
+    Stats {
+        total: tenants.iter().map(|tenant| tenant.count).sum(),
+        rate: state.history.rate(RATE_WINDOW, None),
+        tenants,
+        history: state.history.len(),
+        history_evicted: state.history.evicted_total(),
+        senders: state.senders.list(None).len(),
+        connections: server::open_connections(),
+        clients: state.events.clients(),
+        queues: Queues {
+            events: state.events.queued(),
+            audit: state.audit.as_ref().map(AuditLog::pending),
+        },
+        udp: state.udp.snapshot(),
+        runtime: runtime::snapshot(state),
+        wal: state.wal.as_ref().map(Wal::health),
+    }
+}
+
+async fn get_stats(State(state): State<AppState>) -> Json<Stats> {
+    Json(stats(&state))
+}
+
+async fn snapshot(State(state): State<AppState>) -> Json<Snapshot> {
+    Json(snapshot::take(&state))
+}
+
+/// Uploads a snapshot now, without waiting for the next one.
+async fn upload_snapshot(
+    State(state): State<AppState>,
+) -> Result<(StatusCode, Json<Uploaded>), AppError> {
+    let uploader = state
+        .snapshots
+        .as_ref()
+        .ok_or_else(|| AppError::NotFound("no snapshot bucket is configured".to_string()))?;
+
+    let key = uploader.backup(&state).await.map_err(AppError::Internal)?;
+
+    Ok((StatusCode::CREATED, Json(Uploaded { key })))
+}
+
+fn log_filter(state: &AppState) -> Result<&LogFilter, AppError> {
+    state
+        .log_filter
+        .as_ref()
+        .ok_or_else(|| AppError::NotFound("the log filter can't be changed".to_string()))
+}
+
+async fn log_level(State(state): State<AppState>) -> Result<String, AppError> {
+    Ok(log_filter(&state)?.with_current(ToString::to_string)?)
+}
+
+/// Replaces the filter with the directives in the body, same as `RUST_LOG`.
+async fn set_log_level(State(state): State<AppState>, body: String) -> Result<String, AppError> {
+    let filter = EnvFilter::try_new(body.trim())
+        .map_err(|err| AppError::BadRequest(format!("invalid log filter: {err}")))?;
+    let directives = filter.to_string();
+
+    log_filter(&state)?.reload(filter)?;
+
+    info!(filter = directives, "log filter changed");
+
+    Ok(directives)
+}
+
+async fn maintenance(State(state): State<AppState>) -> Json<maintenance::Status> {
+    Json(state.maintenance.status())
+}
+
+/// Turns the maintenance mode on or off.
+async fn set_maintenance(
+    State(state): State<AppState>,
+    Json(body): Json<MaintenanceBody>,
+) -> Json<maintenance::Status> {
+    if body.enabled {
+        let retry_after = body
+            .retry_after_secs
+            .map_or(DEFAULT_RETRY_AFTER, Duration::from_secs);
+
+        state.maintenance.enable(body.message, retry_after);
+    } else {
+        state.maintenance.disable();
+    }
+
+    Json(state.maintenance.status())
+}
+
+/// Effective configuration, without the secrets.
+async fn config(State(state): State<AppState>) -> Json<ReceiverArgs> {
+    Json(state.config.clone())
+}
+
+async fn tenants(State(state): State<AppState>) -> Json<Vec<TenantCount>> {
+    Json(timing::store(|| state.counters.tenants()))
+}
+
+/// Members of the cluster, with the total of the counts of all the tenants.
+async fn cluster(State(state): State<AppState>) -> Json<Membership> {
+    Json(state.cluster.membership(state.counters.total()))
+}
+
+pub fn routes(token: String) -> Router<AppState> {
+    Router::new()
+        .route("/admin/reset", post(reset))
+        .route("/admin/stats", get(get_stats))
+        .route("/admin/snapshot", get(snapshot).post(upload_snapshot))
+        .route("/admin/log-level", get(log_level).put(set_log_level))
+        .route("/admin/keys", get(keys::list).post(keys::add))
+        .route("/admin/keys/:name", delete(keys::remove))
+        .route("/admin/keys/:name/quota", put(keys::update))
+        .route("/admin/config", get(config))
+        .route("/admin/maintenance", get(maintenance).put(set_maintenance))
+        .merge(version::routes([
+            Endpoint::new("/tenants", "/api/tenants", get(tenants)),
+            Endpoint::new("/cluster", "/api/cluster", get(cluster)),
+            Endpoint::new("/stats/ips", "/api/stats/ips", get(ips::stats)),
+            Endpoint::new("/count", "/api/count", put(set_count)),
+            Endpoint::new("/count/add", "/api/count/add", post(add_count)),
+        ]))
+        .route_layer(middleware::from_fn_with_state(Arc::from(token), authorize))
+}
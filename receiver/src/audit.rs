@@ -0,0 +1,496 @@
+//! Append-only audit log of the pings, as JSON lines.
+//!
+//! Every valid ping received is written with its outcome, independently of the in-memory
+//! count, so they can be traced after a restart, along with the counts set by the
+//! administrators. The entries are written by a dedicated thread to not block the requests on the
+//! disk, except when synced after every entry, then they are written before the ping is
+//! acknowledged. Once the file reaches the maximum size it's renamed to
+//! `FILE.1`, shifting the older ones up to the number of files kept.
+
+use std::{
+    ffi::OsString,
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+
+use clap::ValueEnum;
+use eyre::WrapErr;
+use serde::Serialize;
+use tracing::{error, warn};
+use uuid::Uuid;
+
+use crate::tenant::Tenant;
+
+/// Least time between the syncs with [`Fsync::Interval`].
+const SYNC_INTERVAL: Duration = Duration::from_secs(1);
+
+/// When the audit log is flushed to the disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Fsync {
+    /// After every entry, written before the ping is acknowledged, so no accepted ping is lost
+    /// on a crash.
+    Always,
+    /// At most once a second, losing up to a second of entries on a crash.
+    Interval,
+    /// Left to the operating system.
+    Never,
+}
+
+#[derive(Debug, Clone)]
+pub struct AuditConfig {
+    pub path: PathBuf,
+    pub fsync: Fsync,
+    /// Size in bytes after which the file is rotated.
+    pub max_size: u64,
+    /// Rotated files kept, the older ones are removed.
+    pub keep: usize,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Transport {
+    Http,
+    Udp,
+    WebSocket,
+}
+
+impl Transport {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Transport::Http => "http",
+            Transport::Udp => "udp",
+            Transport::WebSocket => "websocket",
+        }
+    }
+}
+
+/// Where the ping was received from.
+#[derive(Debug, Clone, Copy)]
+pub struct Source {
+    pub transport: Transport,
+    pub addr: SocketAddr,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Counted,
+    /// A ping was taken back by the pong.
+    TakenBack,
+    /// The pong found no ping to take back.
+    NothingToTakeBack,
+    QuotaExceeded,
+    RateLimited,
+    /// The count was set by an administrator, not by a ping.
+    Set,
+    /// A delta was added to the count by an administrator.
+    Added,
+}
+
+#[derive(Debug, Serialize)]
+struct Entry {
+    id: Uuid,
+    transport: Transport,
+    source: SocketAddr,
+    tenant: Tenant,
+    timestamp: String,
+    outcome: Outcome,
+    /// Count after the ping, missing if it wasn't counted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    count: Option<u64>,
+    /// Count before it was set or changed by the delta.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    previous: Option<u64>,
+    /// Delta added by the administrator.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    delta: Option<i64>,
+}
+
+#[derive(Debug)]
+pub struct AuditLog {
+    output: Output,
+    /// Entries sent and not yet written.
+    pending: Arc<AtomicUsize>,
+}
+
+/// Where the entries are sent to be written.
+#[derive(Debug)]
+enum Output {
+    /// The thread writing the entries.
+    Thread(mpsc::Sender<Entry>),
+    /// Written and synced by the caller, with [`Fsync::Always`].
+    Inline(Mutex<Writer>),
+}
+
+impl AuditLog {
+    /// Opens the file for appending and starts the thread writing to it, if not synced after
+    /// every entry.
+    pub fn open(config: AuditConfig) -> eyre::Result<Self> {
+        let pending = Arc::new(AtomicUsize::new(0));
+        let fsync = config.fsync;
+        let writer = Writer::open(config, Arc::clone(&pending))?;
+
+        let output = if fsync == Fsync::Always {
+            Output::Inline(Mutex::new(writer))
+        } else {
+            let (entries, rx) = mpsc::channel();
+
+            std::thread::Builder::new()
+                .name("audit-log".to_string())
+                .spawn(move || writer.run(rx))
+                .wrap_err("couldn't start the audit log writer")?;
+
+            Output::Thread(entries)
+        };
+
+        Ok(Self { output, pending })
+    }
+
+    pub fn pending(&self) -> usize {
+        self.pending.load(Ordering::Relaxed)
+    }
+
+    pub fn record(
+        &self,
+        id: Uuid,
+        source: Source,
+        tenant: &Tenant,
+        outcome: Outcome,
+        count: Option<u64>,
+    ) {
+        self.send(Entry {
+            id,
+            transport: source.transport,
+            source: source.addr,
+            tenant: tenant.clone(),
+            timestamp: humantime::format_rfc3339_millis(SystemTime::now()).to_string(),
+            outcome,
+            count,
+            previous: None,
+            delta: None,
+        });
+    }
+
+    /// Records the count set by the administrator from the source.
+    pub fn record_set(&self, id: Uuid, source: Source, tenant: &Tenant, previous: u64, count: u64) {
+        self.send(Entry {
+            id,
+            transport: source.transport,
+            source: source.addr,
+            tenant: tenant.clone(),
+            timestamp: humantime::format_rfc3339_millis(SystemTime::now()).to_string(),
+            outcome: Outcome::Set,
+            count: Some(count),
+            previous: Some(previous),
+            delta: None,
+        });
+    }
+
+    /// Records the delta added to the count by the administrator from the source.
+    pub fn record_add(
+        &self,
+        id: Uuid,
+        source: Source,
+        tenant: &Tenant,
+        previous: u64,
+        delta: i64,
+        count: u64,
+    ) {
+        self.send(Entry {
+            id,
+            transport: source.transport,
+            source: source.addr,
+            tenant: tenant.clone(),
+            timestamp: humantime::format_rfc3339_millis(SystemTime::now()).to_string(),
+            outcome: Outcome::Added,
+            count: Some(count),
+            previous: Some(previous),
+            delta: Some(delta),
+        });
+    }
+
+    fn send(&self, entry: Entry) {
+        let id = entry.id;
+
+        let entries = match &self.output {
+            Output::Thread(entries) => entries,
+            Output::Inline(writer) => {
+                let mut writer = writer.lock().unwrap_or_else(|err| err.into_inner());
+                if let Err(err) = writer.write(&entry) {
+                    error!(%id, error = %err, "couldn't write the audit log entry");
+                }
+
+                return;
+            }
+        };
+
+        self.pending.fetch_add(1, Ordering::Relaxed);
+
+        if entries.send(entry).is_err() {
+            self.pending.fetch_sub(1, Ordering::Relaxed);
+
+            error!(%id, "audit log writer stopped, entry lost");
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Writer {
+    config: AuditConfig,
+    file: File,
+    size: u64,
+    pending: Arc<AtomicUsize>,
+    /// Last sync, if entries were written after it.
+    unsynced: Option<Instant>,
+}
+
+fn append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// Path of the n-th rotated file.
+fn rotated(path: &Path, n: usize) -> PathBuf {
+    let mut path = OsString::from(path);
+    path.push(format!(".{n}"));
+
+    path.into()
+}
+
+impl Writer {
+    fn open(config: AuditConfig, pending: Arc<AtomicUsize>) -> eyre::Result<Self> {
+        let file = append(&config.path)
+            .wrap_err_with(|| format!("couldn't open {}", config.path.display()))?;
+        let size = file.metadata()?.len();
+
+        Ok(Self {
+            config,
+            file,
+            size,
+            pending,
+            unsynced: None,
+        })
+    }
+
+    /// Writes the entries until the log is dropped.
+    fn run(mut self, entries: mpsc::Receiver<Entry>) {
+        loop {
+            // Wait only until the pending sync is due
+            let entry = match self.unsynced {
+                Some(since) => {
+                    match entries.recv_timeout(SYNC_INTERVAL.saturating_sub(since.elapsed())) {
+                        Ok(entry) => Some(entry),
+                        Err(mpsc::RecvTimeoutError::Timeout) => None,
+                        Err(mpsc::RecvTimeoutError::Disconnected) => break,
+                    }
+                }
+                None => match entries.recv() {
+                    Ok(entry) => Some(entry),
+                    Err(mpsc::RecvError) => break,
+                },
+            };
+
+            if let Some(entry) = entry {
+                if let Err(err) = self.write(&entry) {
+                    error!(id = %entry.id, error = %err, "couldn't write the audit log entry");
+                }
+
+                self.pending.fetch_sub(1, Ordering::Relaxed);
+            }
+
+            if let Err(err) = self.sync_if_due() {
+                warn!(error = %err, "couldn't sync the audit log");
+            }
+        }
+
+        if let Err(err) = self.file.sync_data() {
+            warn!(error = %err, "couldn't sync the audit log");
+        }
+    }
+
+    fn write(&mut self, entry: &Entry) -> io::Result<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+
+        if self.size > 0 && self.size + line.len() as u64 > self.config.max_size {
+            self.rotate()?;
+        }
+
+        self.file.write_all(&line)?;
+        self.size += line.len() as u64;
+
+        match self.config.fsync {
+            Fsync::Always => self.file.sync_data()?,
+            Fsync::Interval => {
+                self.unsynced.get_or_insert_with(Instant::now);
+            }
+            Fsync::Never => {}
+        }
+
+        Ok(())
+    }
+
+    fn sync_if_due(&mut self) -> io::Result<()> {
+        if self
+            .unsynced
+            .is_some_and(|since| since.elapsed() >= SYNC_INTERVAL)
+        {
+            self.file.sync_data()?;
+            self.unsynced = None;
+        }
+
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.sync_data()?;
+        self.unsynced = None;
+
+        let path = &self.config.path;
+
+        if self.config.keep == 0 {
+            std::fs::remove_file(path)?;
+        } else {
+            for n in (1..self.config.keep).rev() {
+                match std::fs::rename(rotated(path, n), rotated(path, n + 1)) {
+                    Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+                    _ => {}
+                }
+            }
+
+            std::fs::rename(path, rotated(path, 1))?;
+        }
+
+        self.file = append(path)?;
+        self.size = 0;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Directory in the temporary directory, removed once dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            let path = std::env::temp_dir().join(format!("audit-{}", Uuid::new_v4()));
+            std::fs::create_dir(&path).unwrap();
+
+            Self(path)
+        }
+
+        fn config(&self, fsync: Fsync, max_size: u64, keep: usize) -> AuditConfig {
+            AuditConfig {
+                path: self.0.join("audit.jsonl"),
+                fsync,
+                max_size,
+                keep,
+            }
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn source() -> Source {
+        Source {
+            transport: Transport::Http,
+            addr: "127.0.0.1:4000".parse().unwrap(),
+        }
+    }
+
+    fn entries(path: &Path) -> Vec<serde_json::Value> {
+        std::fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn entries_written_before_returning_when_synced_always() {
+        let dir = TempDir::new();
+        let config = dir.config(Fsync::Always, u64::MAX, 1);
+        let log = AuditLog::open(config.clone()).unwrap();
+        let id = Uuid::new_v4();
+
+        log.record(id, source(), &Tenant::default(), Outcome::Counted, Some(3));
+
+        let entries = entries(&config.path);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0]["id"], id.to_string());
+        assert_eq!(entries[0]["outcome"], "counted");
+        assert_eq!(entries[0]["count"], 3);
+        assert_eq!(log.pending(), 0);
+    }
+
+    #[test]
+    fn entries_written_by_the_thread() {
+        let dir = TempDir::new();
+        let config = dir.config(Fsync::Never, u64::MAX, 1);
+        let log = AuditLog::open(config.clone()).unwrap();
+
+        log.record(
+            Uuid::new_v4(),
+            source(),
+            &Tenant::default(),
+            Outcome::RateLimited,
+            None,
+        );
+        log.record_set(Uuid::new_v4(), source(), &Tenant::default(), 3, 1);
+        log.record_add(Uuid::new_v4(), source(), &Tenant::default(), 1, -1, 0);
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while log.pending() > 0 {
+            assert!(Instant::now() < deadline, "entries not written");
+
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        let entries = entries(&config.path);
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0]["outcome"], "rate_limited");
+        assert!(entries[0].get("count").is_none());
+        assert_eq!(entries[1]["outcome"], "set");
+        assert_eq!(entries[1]["previous"], 3);
+        assert_eq!(entries[1]["count"], 1);
+        assert!(entries[1].get("delta").is_none());
+        assert_eq!(entries[2]["outcome"], "added");
+        assert_eq!(entries[2]["previous"], 1);
+        assert_eq!(entries[2]["delta"], -1);
+        assert_eq!(entries[2]["count"], 0);
+    }
+
+    #[test]
+    fn rotation_keeps_only_the_last_files() {
+        let dir = TempDir::new();
+        // Room for a single entry in each file
+        let config = dir.config(Fsync::Always, 10, 2);
+        let log = AuditLog::open(config.clone()).unwrap();
+
+        let ids: Vec<_> = (0..4).map(|_| Uuid::new_v4()).collect();
+        for id in &ids {
+            log.record(*id, source(), &Tenant::default(), Outcome::Counted, None);
+        }
+
+        let id_in = |path: &Path| entries(path)[0]["id"].as_str().unwrap().to_string();
+        assert_eq!(id_in(&config.path), ids[3].to_string());
+        assert_eq!(id_in(&rotated(&config.path, 1)), ids[2].to_string());
+        assert_eq!(id_in(&rotated(&config.path, 2)), ids[1].to_string());
+        assert!(!rotated(&config.path, 3).exists());
+    }
+}
@@ -0,0 +1,57 @@
+//! Bearer tokens protecting the debug and admin routes.
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{Request, State},
+    http::{header::AUTHORIZATION, request::Parts, HeaderMap},
+    middleware::Next,
+    response::Response,
+};
+
+use crate::AppError;
+
+/// Compares the tokens in a time independent of their common prefix.
+pub fn token_matches(token: &str, expected: &str) -> bool {
+    token.len() == expected.len()
+        && token
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// Token in the `Authorization` header, if it's a bearer.
+pub fn bearer(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+}
+
+/// Rejects the requests without the token, to use with [`axum::middleware::from_fn_with_state`].
+pub async fn authorize(
+    State(token): State<Arc<str>>,
+    req: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let authorized = bearer(req.headers()).is_some_and(|bearer| token_matches(bearer, &token));
+
+    if !authorized {
+        return Err(AppError::Unauthorized(
+            "invalid or missing token".to_string(),
+        ));
+    }
+
+    Ok(next.run(req).await)
+}
+
+/// Token of the request, from the bearer or the `X-Api-Key` header.
+pub fn api_key(parts: &Parts) -> Option<&str> {
+    bearer(&parts.headers).or_else(|| {
+        parts
+            .headers
+            .get("x-api-key")
+            .and_then(|value| value.to_str().ok())
+    })
+}
@@ -0,0 +1,71 @@
+//! Pongs sent back to the callbacks of the pings.
+//!
+//! The callbacks are urls chosen by the clients, so the pongs are sent only to the hosts allowed
+//! with `--callback-host`, none by default, without following the redirects and for a limited
+//! time, so the receiver can't be used to reach the services next to it.
+
+use std::{collections::HashSet, time::Duration};
+
+use protocol::Pong;
+use reqwest::{redirect, Url};
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+/// Time the callback has to answer the pong.
+const PONG_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug)]
+pub struct Callbacks {
+    client: reqwest::Client,
+    /// Hosts the pongs are sent to, lowercase.
+    hosts: HashSet<String>,
+}
+
+impl Callbacks {
+    pub fn new(hosts: &[String]) -> reqwest::Result<Self> {
+        let client = reqwest::Client::builder()
+            .redirect(redirect::Policy::none())
+            .timeout(PONG_TIMEOUT)
+            .build()?;
+
+        Ok(Self {
+            client,
+            hosts: hosts.iter().map(|host| host.to_ascii_lowercase()).collect(),
+        })
+    }
+
+    fn allows(&self, callback: &Url) -> bool {
+        matches!(callback.scheme(), "http" | "https")
+            && callback
+                .host_str()
+                .is_some_and(|host| self.hosts.contains(&host.to_ascii_lowercase()))
+    }
+
+    /// Sends the pong in the background, logging it if the ping was `sampled`.
+    pub fn pong(&self, callback: Url, id: Uuid, sampled: bool) {
+        if !self.allows(&callback) {
+            if sampled {
+                warn!(%id, %callback, "callback host not allowed, pong not sent");
+            }
+
+            return;
+        }
+
+        tokio::spawn(send(self.client.clone(), callback, id, sampled));
+    }
+}
+
+async fn send(client: reqwest::Client, callback: Url, id: Uuid, sampled: bool) {
+    let res = client
+        .post(callback.clone())
+        .json(&Pong { id })
+        .send()
+        .await
+        .and_then(|res| res.error_for_status());
+
+    match res {
+        Ok(_) if sampled => debug!(%id, %callback, "pong sent"),
+        Ok(_) => {}
+        Err(err) => warn!(%id, %callback, error = %err, "couldn't send pong"),
+    }
+}
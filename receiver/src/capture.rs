@@ -0,0 +1,131 @@
+//! Logs of the bodies of the pings and of their responses, to debug the senders.
+//!
+//! The bodies are logged at the debug level, truncated, with the values of the JSON fields that
+//! look like credentials redacted. Since they are logged in the clear otherwise, the capture is
+//! only enabled explicitly and warned about at the startup.
+
+use axum::{
+    body::{self, Body, Bytes},
+    extract::{Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde_json::Value;
+use tracing::{debug, warn};
+
+use crate::{version, AppState};
+
+/// Routes whose bodies are captured.
+const ROUTES: &[&str] = &["/ping", "/pong"];
+
+/// Largest body buffered to be captured, as the default limit of the extractors.
+const MAX_BODY: usize = 2 * 1024 * 1024;
+
+/// Parts of the names of the fields redacted.
+const SECRETS: &[&str] = &["authorization", "key", "password", "secret", "token"];
+
+const REDACTED: &str = "[redacted]";
+
+#[derive(Debug, Clone, Copy)]
+pub struct Capture {
+    /// Bytes of each body logged.
+    limit: usize,
+}
+
+impl Capture {
+    pub fn new(limit: usize) -> Self {
+        warn!("the bodies of the pings are logged, they may contain sensitive data");
+
+        Self { limit }
+    }
+
+    /// Body as logged, redacted and truncated.
+    fn format(&self, body: &[u8]) -> String {
+        let text = match serde_json::from_slice::<Value>(body) {
+            Ok(mut value) => {
+                redact(&mut value);
+
+                value.to_string()
+            }
+            Err(_) => String::from_utf8_lossy(body).into_owned(),
+        };
+
+        if text.len() <= self.limit {
+            return text;
+        }
+
+        let mut end = self.limit;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        format!("{}... ({} bytes)", &text[..end], text.len())
+    }
+}
+
+fn redact(value: &mut Value) {
+    match value {
+        Value::Object(fields) => {
+            for (name, value) in fields {
+                let name = name.to_lowercase();
+
+                if SECRETS.iter().any(|secret| name.contains(secret)) {
+                    *value = Value::String(REDACTED.to_string());
+                } else {
+                    redact(value);
+                }
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(redact),
+        _ => {}
+    }
+}
+
+/// Middleware logging the bodies of the requests to the ping routes and of their responses.
+pub async fn layer(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let Some(capture) = state.capture else {
+        return next.run(req).await;
+    };
+
+    if !ROUTES.contains(&version::unversioned(req.uri().path())) {
+        return next.run(req).await;
+    }
+
+    let (parts, body) = req.into_parts();
+    let body = match body::to_bytes(body, MAX_BODY).await {
+        Ok(body) => body,
+        Err(err) => {
+            debug!(error = %err, "couldn't capture the request body");
+
+            return (StatusCode::PAYLOAD_TOO_LARGE, "the body is too large").into_response();
+        }
+    };
+
+    debug!(
+        method = %parts.method,
+        uri = %parts.uri,
+        body = capture.format(&body),
+        "request body"
+    );
+
+    let res = next.run(Request::from_parts(parts, Body::from(body))).await;
+
+    let (parts, body) = res.into_parts();
+    let body: Bytes = match body::to_bytes(body, MAX_BODY).await {
+        Ok(body) => body,
+        Err(err) => {
+            warn!(error = %err, "couldn't capture the response body");
+
+            Bytes::new()
+        }
+    };
+
+    debug!(
+        status = parts.status.as_u16(),
+        body = capture.format(&body),
+        "response body"
+    );
+
+    Response::from_parts(parts, Body::from(body))
+}
@@ -0,0 +1,192 @@
+//! Faults injected into the receiver, to exercise the resilience of the senders.
+//!
+//! With `--chaos-latency` every request to the public routes is delayed by a random duration in
+//! the range, so the retries, the backoff and the circuit breaker of the senders can be tested
+//! against a slow receiver. With `--chaos-fail-rate` a share of the pings fail instead of being
+//! counted, half with a `500 Internal Server Error` and half left hanging until they time out.
+//! It's meant for testing, never enable it in production.
+
+use std::{fmt::Display, str::FromStr, time::Duration};
+
+use axum::{
+    extract::{Request, State},
+    http::{Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use clap::Args;
+use rand::Rng;
+use serde::{Serialize, Serializer};
+use tracing::{debug, warn};
+
+use crate::{version, AppState};
+
+/// Time the pings failing with a timeout are left hanging, longer than the senders wait.
+const HANG: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Args, Serialize)]
+pub struct ChaosArgs {
+    /// Delay each request by a random duration in the range, like `50ms..500ms`
+    #[arg(long, value_name = "MIN..MAX")]
+    chaos_latency: Option<Latency>,
+    /// Fail this share of the pings, between 0 and 1, with an error or a timeout
+    #[arg(long, value_name = "RATE", value_parser = parse_rate)]
+    chaos_fail_rate: Option<f64>,
+}
+
+fn parse_rate(s: &str) -> Result<f64, String> {
+    let rate: f64 = s
+        .parse()
+        .map_err(|err| format!("invalid rate {s}: {err}"))?;
+
+    if !(0.0..=1.0).contains(&rate) {
+        return Err(format!("the rate {s} isn't between 0 and 1"));
+    }
+
+    Ok(rate)
+}
+
+/// Range of the delays injected.
+#[derive(Debug, Clone, Copy)]
+pub struct Latency {
+    min: Duration,
+    max: Duration,
+}
+
+impl FromStr for Latency {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (min, max) = s.split_once("..").unwrap_or((s, s));
+        let parse = |duration: &str| {
+            humantime::parse_duration(duration.trim())
+                .map_err(|err| format!("invalid duration {duration}: {err}"))
+        };
+
+        let latency = Self {
+            min: parse(min)?,
+            max: parse(max)?,
+        };
+
+        if latency.min > latency.max {
+            return Err(format!(
+                "the minimum latency is greater than the maximum in {s}"
+            ));
+        }
+
+        Ok(latency)
+    }
+}
+
+impl Display for Latency {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}..{}",
+            humantime::format_duration(self.min),
+            humantime::format_duration(self.max)
+        )
+    }
+}
+
+impl Serialize for Latency {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+/// Fault injected into a ping.
+#[derive(Debug, Clone, Copy)]
+enum Fault {
+    Error,
+    Timeout,
+}
+
+#[derive(Debug)]
+pub struct Chaos {
+    latency: Option<Latency>,
+    fail_rate: Option<f64>,
+}
+
+impl Chaos {
+    pub fn new(args: &ChaosArgs) -> Option<Self> {
+        if args.chaos_latency.is_none() && args.chaos_fail_rate.is_none() {
+            return None;
+        }
+
+        if let Some(latency) = args.chaos_latency {
+            warn!(%latency, "injecting latency into the requests");
+        }
+
+        if let Some(rate) = args.chaos_fail_rate {
+            warn!(rate, "injecting failures into the pings");
+        }
+
+        Some(Self {
+            latency: args.chaos_latency,
+            fail_rate: args.chaos_fail_rate,
+        })
+    }
+
+    fn delay(&self) -> Option<Duration> {
+        let latency = self.latency?;
+
+        Some(rand::thread_rng().gen_range(latency.min..=latency.max))
+    }
+
+    fn fault(&self) -> Option<Fault> {
+        let rate = self.fail_rate?;
+        let mut rng = rand::thread_rng();
+
+        if !rng.gen_bool(rate) {
+            return None;
+        }
+
+        if rng.gen() {
+            Some(Fault::Error)
+        } else {
+            Some(Fault::Timeout)
+        }
+    }
+}
+
+/// Middleware injecting the faults into the requests.
+pub async fn layer(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let Some(chaos) = &state.chaos else {
+        return next.run(req).await;
+    };
+
+    if let Some(delay) = chaos.delay() {
+        debug!(delay = %humantime::format_duration(delay), "delaying the request");
+
+        metrics::counter!("receiver_chaos_delayed_total").increment(1);
+
+        tokio::time::sleep(delay).await;
+    }
+
+    let ping = req.method() == Method::POST && version::unversioned(req.uri().path()) == "/ping";
+    let fault = if ping { chaos.fault() } else { None };
+
+    match fault {
+        Some(Fault::Error) => {
+            debug!("failing the ping");
+
+            metrics::counter!("receiver_chaos_faults_total", "fault" => "error").increment(1);
+
+            (StatusCode::INTERNAL_SERVER_ERROR, "injected fault").into_response()
+        }
+        Some(Fault::Timeout) => {
+            debug!("leaving the ping hanging");
+
+            metrics::counter!("receiver_chaos_faults_total", "fault" => "timeout").increment(1);
+
+            tokio::time::sleep(HANG).await;
+
+            (StatusCode::GATEWAY_TIMEOUT, "injected timeout").into_response()
+        }
+        None => next.run(req).await,
+    }
+}
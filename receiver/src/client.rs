@@ -0,0 +1,190 @@
+//! Command line client of the admin API of a running receiver.
+//!
+//! The responses are printed as pretty JSON, the errors of the receiver fail the command with
+//! their message.
+
+use clap::{Args, Subcommand};
+use eyre::WrapErr;
+use reqwest::{Method, RequestBuilder};
+use serde_json::json;
+use url::Url;
+
+#[derive(Debug, Clone, Args)]
+pub struct AdminArgs {
+    /// Url of the receiver, or of its admin port
+    #[arg(long, env = "RECEIVER_URL", default_value = "http://127.0.0.1:9000")]
+    url: Url,
+    /// Token of the admin routes of the receiver
+    #[arg(long, env = "ADMIN_TOKEN", hide_env_values = true)]
+    token: String,
+    #[command(subcommand)]
+    command: AdminCommand,
+}
+
+#[derive(Debug, Clone, Subcommand)]
+enum AdminCommand {
+    /// Sets the counts back to zero
+    Reset {
+        /// Tenant to reset, all of them if missing
+        #[arg(long)]
+        tenant: Option<String>,
+    },
+    /// Shows the counts and the statistics of the receiver
+    Stats,
+    /// Shows a snapshot of the counts and the history
+    Snapshot {
+        /// Upload the snapshot to the bucket now instead
+        #[arg(long)]
+        upload: bool,
+    },
+    /// Manages the API keys
+    #[command(subcommand)]
+    Keys(KeysCommand),
+    /// Manages the maintenance mode
+    #[command(subcommand)]
+    Maintenance(MaintenanceCommand),
+}
+
+#[derive(Debug, Clone, Subcommand)]
+enum MaintenanceCommand {
+    /// Shows whether the receiver is under maintenance
+    Status,
+    /// Refuses the pings and serves the maintenance page
+    On {
+        /// Shown on the maintenance page
+        #[arg(long)]
+        message: Option<String>,
+        /// Seconds the clients are asked to wait before retrying
+        #[arg(long)]
+        retry_after: Option<u64>,
+    },
+    /// Accepts the pings again
+    Off,
+}
+
+#[derive(Debug, Clone, Subcommand)]
+enum KeysCommand {
+    /// Lists the keys with the state of their quotas
+    List,
+    /// Adds a key
+    Add {
+        name: String,
+        key: String,
+        /// Pings allowed each hour
+        #[arg(long)]
+        hourly: Option<u64>,
+        /// Pings allowed each day
+        #[arg(long)]
+        daily: Option<u64>,
+    },
+    /// Removes a key
+    Remove { name: String },
+    /// Sets the pings remaining in the current windows of the quotas of a key
+    Quota {
+        name: String,
+        #[arg(long)]
+        hourly: Option<u64>,
+        #[arg(long)]
+        daily: Option<u64>,
+    },
+}
+
+struct Admin {
+    client: reqwest::Client,
+    url: Url,
+    token: String,
+}
+
+impl Admin {
+    fn request(&self, method: Method, path: &str) -> eyre::Result<RequestBuilder> {
+        let url = self.url.join(path)?;
+
+        Ok(self.client.request(method, url).bearer_auth(&self.token))
+    }
+
+    /// Sends the request and prints the response.
+    async fn send(&self, req: RequestBuilder) -> eyre::Result<()> {
+        let res = req.send().await.wrap_err("couldn't reach the receiver")?;
+        let status = res.status();
+        let body = res.text().await?;
+
+        eyre::ensure!(status.is_success(), "{status}: {body}");
+
+        if body.is_empty() {
+            return Ok(());
+        }
+
+        match serde_json::from_str::<serde_json::Value>(&body) {
+            Ok(value) => println!("{}", serde_json::to_string_pretty(&value)?),
+            Err(_) => println!("{body}"),
+        }
+
+        Ok(())
+    }
+}
+
+pub async fn run(args: AdminArgs) -> eyre::Result<()> {
+    let admin = Admin {
+        client: reqwest::Client::new(),
+        url: args.url,
+        token: args.token,
+    };
+
+    let req = match args.command {
+        AdminCommand::Reset { tenant } => {
+            let req = admin.request(Method::POST, "/admin/reset")?;
+
+            match tenant {
+                Some(tenant) => req.query(&[("tenant", tenant)]),
+                None => req,
+            }
+        }
+        AdminCommand::Stats => admin.request(Method::GET, "/admin/stats")?,
+        AdminCommand::Snapshot { upload: false } => {
+            admin.request(Method::GET, "/admin/snapshot")?
+        }
+        AdminCommand::Snapshot { upload: true } => {
+            admin.request(Method::POST, "/admin/snapshot")?
+        }
+        AdminCommand::Keys(KeysCommand::List) => admin.request(Method::GET, "/admin/keys")?,
+        AdminCommand::Keys(KeysCommand::Add {
+            name,
+            key,
+            hourly,
+            daily,
+        }) => admin.request(Method::POST, "/admin/keys")?.json(&json!({
+            "name": name,
+            "key": key,
+            "hourly": hourly,
+            "daily": daily,
+        })),
+        AdminCommand::Keys(KeysCommand::Remove { name }) => {
+            admin.request(Method::DELETE, &format!("/admin/keys/{name}"))?
+        }
+        AdminCommand::Keys(KeysCommand::Quota {
+            name,
+            hourly,
+            daily,
+        }) => admin
+            .request(Method::PUT, &format!("/admin/keys/{name}/quota"))?
+            .json(&json!({ "hourly": hourly, "daily": daily })),
+        AdminCommand::Maintenance(MaintenanceCommand::Status) => {
+            admin.request(Method::GET, "/admin/maintenance")?
+        }
+        AdminCommand::Maintenance(MaintenanceCommand::On {
+            message,
+            retry_after,
+        }) => admin
+            .request(Method::PUT, "/admin/maintenance")?
+            .json(&json!({
+                "enabled": true,
+                "message": message,
+                "retry_after_secs": retry_after,
+            })),
+        AdminCommand::Maintenance(MaintenanceCommand::Off) => admin
+            .request(Method::PUT, "/admin/maintenance")?
+            .json(&json!({ "enabled": false })),
+    };
+
+    admin.send(req).await
+}
@@ -0,0 +1,430 @@
+//! Gossip based membership between receiver replicas.
+//!
+//! Every node periodically sends its view of the cluster to a random peer, the peer merges it
+//! with its own view and answers with the result. Members are versioned with a heartbeat that
+//! only the owning node increments, so the most recent state always wins.
+//!
+//! The gossip is served only with `--cluster-secret`, sent by the peers as a bearer, so the
+//! clients can't add members the receiver would then send its view to. The members must have an
+//! HTTP url, and at most [`MAX_MEMBERS`] are kept.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+    time::{Duration, Instant},
+};
+
+use axum::{extract::State, middleware, routing::post, Json, Router};
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+use url::Url;
+use uuid::Uuid;
+
+use crate::{
+    auth,
+    version::{self, Endpoint},
+    AppState,
+};
+
+/// Members kept besides the local node, the new ones are ignored beyond it.
+pub const MAX_MEMBERS: usize = 256;
+
+/// State of a node as exchanged between peers.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MemberState {
+    pub id: Uuid,
+    pub url: Url,
+    pub count: u64,
+    pub heartbeat: u64,
+}
+
+/// Message exchanged with a peer during a gossip round.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Gossip {
+    pub members: Vec<MemberState>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MemberStatus {
+    Alive,
+    Dead,
+}
+
+/// Cluster membership as reported by `/v1/cluster`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Membership {
+    pub id: Uuid,
+    /// Sum of the counts of all the alive members.
+    pub count: u64,
+    pub members: Vec<MemberInfo>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MemberInfo {
+    #[serde(flatten)]
+    pub state: MemberState,
+    pub status: MemberStatus,
+    /// Milliseconds since the last heartbeat update was seen.
+    pub last_seen_ms: u128,
+}
+
+#[derive(Debug)]
+struct Member {
+    state: MemberState,
+    updated: Instant,
+}
+
+#[derive(Debug)]
+pub struct Cluster {
+    id: Uuid,
+    url: Url,
+    heartbeat: AtomicU64,
+    seeds: Vec<Url>,
+    timeout: Duration,
+    /// Sent to the peers as a bearer.
+    secret: Option<String>,
+    members: RwLock<HashMap<Uuid, Member>>,
+    client: reqwest::Client,
+}
+
+impl Cluster {
+    pub fn new(url: Url, seeds: Vec<Url>, timeout: Duration, secret: Option<String>) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            url,
+            heartbeat: AtomicU64::new(0),
+            seeds,
+            timeout,
+            secret,
+            members: RwLock::new(HashMap::new()),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    fn local_state(&self, count: u64) -> MemberState {
+        MemberState {
+            id: self.id,
+            url: self.url.clone(),
+            count,
+            heartbeat: self.heartbeat.load(Ordering::Acquire),
+        }
+    }
+
+    /// View of the cluster to send to the peers, only alive members are propagated.
+    pub fn digest(&self, count: u64) -> Gossip {
+        let members = self.members.read().unwrap_or_else(|err| err.into_inner());
+
+        let members = std::iter::once(self.local_state(count))
+            .chain(
+                members
+                    .values()
+                    .filter(|member| member.updated.elapsed() < self.timeout)
+                    .map(|member| member.state.clone()),
+            )
+            .collect();
+
+        Gossip { members }
+    }
+
+    /// Merges the view received from a peer into the local one.
+    pub fn merge(&self, gossip: Gossip) {
+        let mut members = self.members.write().unwrap_or_else(|err| err.into_inner());
+
+        for state in gossip.members {
+            if state.id == self.id {
+                continue;
+            }
+
+            if !matches!(state.url.scheme(), "http" | "https") {
+                debug!(id = %state.id, url = %state.url, "cluster member without an HTTP url");
+
+                continue;
+            }
+
+            let full = members.len() >= MAX_MEMBERS;
+
+            match members.get_mut(&state.id) {
+                Some(member) if member.state.heartbeat < state.heartbeat => {
+                    member.state = state;
+                    member.updated = Instant::now();
+                }
+                Some(_) => {}
+                None if full => {
+                    debug!(id = %state.id, "too many cluster members, ignored");
+                }
+                None => {
+                    info!(id = %state.id, url = %state.url, "new cluster member");
+
+                    members.insert(
+                        state.id,
+                        Member {
+                            state,
+                            updated: Instant::now(),
+                        },
+                    );
+                }
+            }
+        }
+
+        // Forget members that have been dead for a while
+        let forget = self.timeout * 3;
+        members.retain(|id, member| {
+            let keep = member.updated.elapsed() < forget;
+
+            if !keep {
+                info!(%id, url = %member.state.url, "removing dead cluster member");
+            }
+
+            keep
+        });
+    }
+
+    pub fn membership(&self, count: u64) -> Membership {
+        let members = self.members.read().unwrap_or_else(|err| err.into_inner());
+
+        let mut total = count;
+        let local = MemberInfo {
+            state: self.local_state(count),
+            status: MemberStatus::Alive,
+            last_seen_ms: 0,
+        };
+        let peers = members.values().map(|member| {
+            let elapsed = member.updated.elapsed();
+            let status = if elapsed < self.timeout {
+                total += member.state.count;
+
+                MemberStatus::Alive
+            } else {
+                MemberStatus::Dead
+            };
+
+            MemberInfo {
+                state: member.state.clone(),
+                status,
+                last_seen_ms: elapsed.as_millis(),
+            }
+        });
+
+        let members = std::iter::once(local).chain(peers).collect();
+
+        Membership {
+            id: self.id,
+            count: total,
+            members,
+        }
+    }
+
+    /// Picks a random alive member to gossip with, falling back to the seeds.
+    fn pick_peer(&self) -> Option<Url> {
+        let members = self.members.read().unwrap_or_else(|err| err.into_inner());
+
+        let alive: Vec<&Url> = members
+            .values()
+            .filter(|member| member.updated.elapsed() < self.timeout)
+            .map(|member| &member.state.url)
+            .collect();
+
+        let mut rng = rand::thread_rng();
+
+        alive
+            .choose(&mut rng)
+            .copied()
+            .or_else(|| self.seeds.choose(&mut rng))
+            .cloned()
+    }
+
+    /// Runs a single gossip round with a random peer.
+    pub async fn gossip(&self, count: u64) {
+        self.heartbeat.fetch_add(1, Ordering::AcqRel);
+
+        let Some(peer) = self.pick_peer() else {
+            return;
+        };
+
+        match self.exchange(&peer, self.digest(count)).await {
+            Ok(gossip) => {
+                debug!(%peer, members = gossip.members.len(), "gossip exchanged");
+
+                self.merge(gossip);
+            }
+            Err(err) => {
+                warn!(%peer, error = %err, "couldn't gossip with peer");
+            }
+        }
+    }
+
+    async fn exchange(&self, peer: &Url, gossip: Gossip) -> eyre::Result<Gossip> {
+        let req = self
+            .client
+            .post(peer.join("v1/cluster/gossip")?)
+            .timeout(self.timeout)
+            .json(&gossip);
+        let req = match &self.secret {
+            Some(secret) => req.bearer_auth(secret),
+            None => req,
+        };
+
+        let gossip = req.send().await?.error_for_status()?.json().await?;
+
+        Ok(gossip)
+    }
+}
+
+/// Gossip routes, requiring the secret shared by the peers.
+pub fn routes(secret: &str) -> Router<AppState> {
+    version::routes([Endpoint::new(
+        "/cluster/gossip",
+        "/api/cluster/gossip",
+        post(gossip),
+    )])
+    .route_layer(middleware::from_fn_with_state(
+        Arc::<str>::from(secret),
+        auth::authorize,
+    ))
+}
+
+async fn gossip(State(state): State<AppState>, Json(gossip): Json<Gossip>) -> Json<Gossip> {
+    state.cluster.merge(gossip);
+
+    Json(state.cluster.digest(state.counters.total()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(1);
+
+    fn cluster() -> Cluster {
+        Cluster::new(
+            Url::parse("http://10.0.0.1:3000").unwrap(),
+            Vec::new(),
+            TIMEOUT,
+            None,
+        )
+    }
+
+    fn member(id: Uuid, count: u64, heartbeat: u64) -> MemberState {
+        MemberState {
+            id,
+            url: Url::parse("http://10.0.0.2:3000").unwrap(),
+            count,
+            heartbeat,
+        }
+    }
+
+    fn merge(cluster: &Cluster, members: Vec<MemberState>) {
+        cluster.merge(Gossip { members });
+    }
+
+    /// Makes the member idle for the time.
+    fn idle(cluster: &Cluster, id: Uuid, idle: Duration) {
+        let mut members = cluster.members.write().unwrap();
+
+        members.get_mut(&id).unwrap().updated -= idle;
+    }
+
+    fn count(cluster: &Cluster, id: Uuid) -> Option<u64> {
+        cluster
+            .members
+            .read()
+            .unwrap()
+            .get(&id)
+            .map(|member| member.state.count)
+    }
+
+    #[test]
+    fn newer_heartbeat_wins() {
+        let cluster = cluster();
+        let id = Uuid::new_v4();
+
+        merge(&cluster, vec![member(id, 1, 2)]);
+        merge(&cluster, vec![member(id, 5, 1)]);
+        assert_eq!(count(&cluster, id), Some(1));
+
+        merge(&cluster, vec![member(id, 5, 2)]);
+        assert_eq!(count(&cluster, id), Some(1));
+
+        merge(&cluster, vec![member(id, 7, 3)]);
+        assert_eq!(count(&cluster, id), Some(7));
+    }
+
+    #[test]
+    fn local_node_not_merged() {
+        let cluster = cluster();
+
+        merge(&cluster, vec![member(cluster.id(), 100, 100)]);
+
+        assert_eq!(cluster.membership(1).count, 1);
+    }
+
+    #[test]
+    fn dead_members_forgotten_after_three_timeouts() {
+        let cluster = cluster();
+        let id = Uuid::new_v4();
+
+        merge(&cluster, vec![member(id, 1, 1)]);
+
+        idle(&cluster, id, TIMEOUT * 2);
+        merge(&cluster, Vec::new());
+        assert_eq!(count(&cluster, id), Some(1));
+
+        idle(&cluster, id, TIMEOUT);
+        merge(&cluster, Vec::new());
+        assert_eq!(count(&cluster, id), None);
+    }
+
+    #[test]
+    fn only_alive_members_in_the_digest_and_the_total() {
+        let cluster = cluster();
+        let (alive, dead) = (Uuid::new_v4(), Uuid::new_v4());
+
+        merge(&cluster, vec![member(alive, 2, 1), member(dead, 3, 1)]);
+        idle(&cluster, dead, TIMEOUT);
+
+        let ids: Vec<Uuid> = cluster
+            .digest(1)
+            .members
+            .iter()
+            .map(|member| member.id)
+            .collect();
+        assert_eq!(ids, [cluster.id(), alive]);
+
+        assert_eq!(cluster.membership(1).count, 3);
+    }
+
+    #[test]
+    fn members_without_an_http_url_ignored() {
+        let cluster = cluster();
+        let id = Uuid::new_v4();
+
+        let mut state = member(id, 1, 1);
+        state.url = Url::parse("file:///etc/passwd").unwrap();
+        merge(&cluster, vec![state]);
+
+        assert_eq!(count(&cluster, id), None);
+    }
+
+    #[test]
+    fn members_capped() {
+        let cluster = cluster();
+
+        merge(
+            &cluster,
+            (0..MAX_MEMBERS + 10)
+                .map(|_| member(Uuid::new_v4(), 1, 1))
+                .collect(),
+        );
+
+        assert_eq!(cluster.members.read().unwrap().len(), MAX_MEMBERS);
+    }
+}
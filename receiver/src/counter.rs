@@ -0,0 +1,214 @@
+//! Ping counter with optional expiry after an idle period.
+
+use std::time::{Duration, Instant};
+
+use clap::ValueEnum;
+use serde::Serialize;
+
+/// What happens to an idle counter once the TTL elapsed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExpiryMode {
+    /// The count goes back to zero.
+    Reset,
+    /// The count is halved for every TTL elapsed without pings.
+    Decay,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Expiry {
+    pub ttl: Duration,
+    pub mode: ExpiryMode,
+}
+
+#[derive(Debug)]
+pub struct Counter {
+    count: u64,
+    last_activity: Instant,
+}
+
+impl Counter {
+    pub fn new() -> Self {
+        Self {
+            count: 0,
+            last_activity: Instant::now(),
+        }
+    }
+
+    /// Returns the current count, applying the expiry if the counter has been idle.
+    pub fn get(&mut self, expiry: Option<Expiry>) -> u64 {
+        let Some(Expiry { ttl, mode }) = expiry else {
+            return self.count;
+        };
+
+        let idle = self.last_activity.elapsed();
+        if self.count == 0 || idle < ttl {
+            return self.count;
+        }
+
+        match mode {
+            ExpiryMode::Reset => {
+                self.count = 0;
+            }
+            ExpiryMode::Decay => {
+                let periods = (idle.as_nanos() / ttl.as_nanos().max(1)).min(u64::BITS.into());
+
+                self.count = self.count.checked_shr(periods as u32).unwrap_or(0);
+                // Keep the partial period, so reading the counter doesn't delay the next decay
+                self.last_activity += ttl * periods as u32;
+            }
+        }
+
+        self.count
+    }
+
+    /// Increments the counter if the new value is within the quota.
+    pub fn increment(&mut self, expiry: Option<Expiry>, quota: u64) -> Option<u64> {
+        let count = self.get(expiry);
+        if count >= quota {
+            return None;
+        }
+
+        self.count = count + 1;
+        self.last_activity = Instant::now();
+
+        Some(self.count)
+    }
+
+    pub fn set(&mut self, count: u64) {
+        self.count = count;
+        self.last_activity = Instant::now();
+    }
+
+    /// Sets the counter, returning the previous value.
+    pub fn replace(&mut self, expiry: Option<Expiry>, count: u64) -> u64 {
+        let previous = self.get(expiry);
+
+        self.set(count);
+
+        previous
+    }
+
+    /// Adds the delta to the counter, without going below zero nor over the maximum.
+    pub fn add(&mut self, expiry: Option<Expiry>, delta: i64) -> u64 {
+        let count = self.get(expiry);
+
+        self.count = if delta < 0 {
+            count.saturating_sub(delta.unsigned_abs())
+        } else {
+            count.saturating_add(delta.unsigned_abs())
+        };
+        self.last_activity = Instant::now();
+
+        self.count
+    }
+
+    /// Decrements the counter, never going below zero.
+    pub fn decrement(&mut self, expiry: Option<Expiry>) -> u64 {
+        self.count = self.get(expiry).saturating_sub(1);
+        self.last_activity = Instant::now();
+
+        self.count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TTL: Duration = Duration::from_secs(1);
+
+    fn expiry(mode: ExpiryMode) -> Option<Expiry> {
+        Some(Expiry { ttl: TTL, mode })
+    }
+
+    /// Counter with the count, idle for the time.
+    fn idle(count: u64, idle: Duration) -> Counter {
+        let mut counter = Counter::new();
+        counter.set(count);
+        counter.last_activity -= idle;
+
+        counter
+    }
+
+    #[test]
+    fn kept_without_expiry() {
+        let mut counter = idle(8, TTL * 10);
+
+        assert_eq!(counter.get(None), 8);
+    }
+
+    #[test]
+    fn kept_until_idle_for_the_ttl() {
+        let mut counter = idle(8, TTL / 2);
+
+        assert_eq!(counter.get(expiry(ExpiryMode::Reset)), 8);
+        assert_eq!(counter.get(expiry(ExpiryMode::Decay)), 8);
+    }
+
+    #[test]
+    fn reset_once_idle() {
+        let mut counter = idle(8, TTL);
+
+        assert_eq!(counter.get(expiry(ExpiryMode::Reset)), 0);
+    }
+
+    #[test]
+    fn halved_for_every_ttl_idle() {
+        let mut counter = idle(8, TTL * 2 + TTL / 2);
+
+        assert_eq!(counter.get(expiry(ExpiryMode::Decay)), 2);
+        // The partial period is kept
+        assert_eq!(counter.get(expiry(ExpiryMode::Decay)), 2);
+
+        let mut counter = idle(u64::MAX, TTL * 64);
+
+        assert_eq!(counter.get(expiry(ExpiryMode::Decay)), 0);
+    }
+
+    #[test]
+    fn changes_applied_to_the_expired_count() {
+        let mut counter = idle(8, TTL);
+        assert_eq!(
+            counter.increment(expiry(ExpiryMode::Reset), u64::MAX),
+            Some(1)
+        );
+
+        let mut counter = idle(8, TTL);
+        assert_eq!(counter.decrement(expiry(ExpiryMode::Decay)), 3);
+
+        let mut counter = idle(8, TTL);
+        assert_eq!(counter.add(expiry(ExpiryMode::Decay), 2), 6);
+
+        let mut counter = idle(8, TTL);
+        assert_eq!(counter.replace(expiry(ExpiryMode::Reset), 5), 0);
+        assert_eq!(counter.get(expiry(ExpiryMode::Reset)), 5);
+    }
+
+    #[test]
+    fn increments_capped_by_the_quota() {
+        let mut counter = idle(1, Duration::ZERO);
+
+        assert_eq!(counter.increment(None, 2), Some(2));
+        assert_eq!(counter.increment(None, 2), None);
+        assert_eq!(counter.get(None), 2);
+
+        // Within the quota again once expired
+        counter.last_activity -= TTL;
+        assert_eq!(counter.increment(expiry(ExpiryMode::Reset), 2), Some(1));
+    }
+
+    #[test]
+    fn floored_at_zero() {
+        let mut counter = Counter::new();
+
+        assert_eq!(counter.decrement(None), 0);
+        assert_eq!(counter.add(None, -5), 0);
+
+        counter.set(3);
+        assert_eq!(counter.add(None, i64::MIN), 0);
+
+        counter.set(u64::MAX);
+        assert_eq!(counter.add(None, 1), u64::MAX);
+    }
+}
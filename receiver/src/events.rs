@@ -0,0 +1,674 @@
+//! Live events of the counters.
+//!
+//! The WebSocket clients choose the topics streamed to them by sending a JSON text message like
+//! `{"type":"subscribe","topics":["count","rate"]}`, replacing the previous ones. Until then they
+//! receive the `count`, `milestones` and `senders` topics.
+//!
+//! They can also send pings, like `{"type":"ping"}`, counted with the API key of the upgrade
+//! request for its tenant or the one in the message. With the credentials bound to a tenant, the
+//! message can only name that tenant. Each ping is answered with a `counted` or an `error`
+//! message.
+//!
+//! The clients are sent only the events of the tenant of the upgrade request, like the GraphQL
+//! queries. The rate is of the pings of the tenant, and the senders are reported to the tenant
+//! they last pinged for.
+
+use std::{
+    borrow::Cow,
+    net::SocketAddr,
+    pin::pin,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use axum::{
+    extract::{
+        ws::{close_code, CloseFrame, Message, WebSocket},
+        ConnectInfo, Query, State, WebSocketUpgrade,
+    },
+    http::HeaderMap,
+    response::{IntoResponse, Response},
+};
+use futures::StreamExt;
+use prost::Message as _;
+use protocol::{
+    proto::{CountEvent, Kind},
+    Ping,
+};
+use serde::{Deserialize, Serialize};
+use server::{Beat, Heartbeat};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use crate::{
+    admin::RATE_WINDOW,
+    audit::{Source, Transport},
+    jwt::Subject,
+    keys::ApiKey,
+    oidc::LoggedIn,
+    outbox::{self, Outbox},
+    senders::SenderStatus,
+    snapshot,
+    tenant::{Bound, RequestedTenant, Tenant},
+    tickets, AppError, AppState,
+};
+
+/// Capacity of the channel, slower subscribers skip the older events.
+const CAPACITY: usize = 128;
+
+/// Time given to the client to read the closing message.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Longest error message read from the response of a rejected ping.
+const MAX_ERROR_LEN: usize = 64 * 1024;
+
+/// Interval between the rates sent to the clients subscribed to them.
+const RATE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Subprotocol of the WebSocket sending the events as protobuf binary frames.
+pub const PROTOBUF_PROTOCOL: &str = "pingpong.v1.proto";
+/// Subprotocol of the WebSocket sending the events as JSON text frames, the default.
+pub const JSON_PROTOCOL: &str = "pingpong.v1.json";
+/// Subprotocol of the WebSocket sending the counts as plain text frames, like `ping default 42`.
+pub const TEXT_PROTOCOL: &str = "pingpong.v1.text";
+
+/// Encoding of the events sent to a client, chosen with the subprotocol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+    Json,
+    Text,
+    Protobuf,
+}
+
+impl Encoding {
+    fn from_protocol(protocol: Option<&str>) -> Self {
+        match protocol {
+            Some(PROTOBUF_PROTOCOL) => Encoding::Protobuf,
+            Some(TEXT_PROTOCOL) => Encoding::Text,
+            _ => Encoding::Json,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    /// A ping was counted.
+    Ping {
+        id: Uuid,
+        tenant: Tenant,
+        count: u64,
+    },
+    /// A pong was counted.
+    Pong {
+        id: Uuid,
+        tenant: Tenant,
+        count: u64,
+    },
+    /// A sender came online, or became stale.
+    Sender {
+        id: Uuid,
+        /// Tenant of the last ping of the sender, missing until it pings.
+        tenant: Option<Tenant>,
+        status: SenderStatus,
+        /// RFC 3339 timestamp of the last ping received from the sender.
+        last_ping_at: Option<String>,
+    },
+    /// Pings per second of the tenant over the last minute, sent to each client instead of
+    /// published.
+    Rate { tenant: Tenant, rate: f64 },
+    /// A ping was recorded in the history, with its details.
+    History(snapshot::Ping),
+    /// The count was adjusted from the admin routes.
+    Adjusted {
+        id: Uuid,
+        tenant: Tenant,
+        count: u64,
+        delta: i64,
+    },
+    /// The count of a tenant reached a multiple of the configured milestone.
+    Milestone { tenant: Tenant, count: u64 },
+}
+
+/// Streams of events a client can subscribe to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Topic {
+    /// The pings, pongs and adjustments changing the counts.
+    Count,
+    /// The rate of the pings, every second.
+    Rate,
+    /// The senders coming online or becoming stale.
+    Senders,
+    /// The pings recorded in the history.
+    History,
+    /// The counts reaching the milestones.
+    Milestones,
+}
+
+/// Messages sent by the WebSocket clients.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientMessage {
+    Subscribe {
+        topics: Vec<Topic>,
+    },
+    Ping {
+        /// Generated if missing.
+        id: Option<Uuid>,
+        /// Overrides the one of the upgrade request.
+        tenant: Option<String>,
+    },
+}
+
+/// Answers to the pings of the WebSocket clients.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Reply {
+    Counted {
+        id: Uuid,
+        tenant: Tenant,
+        count: u64,
+    },
+    Error {
+        status: u16,
+        error: String,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EventsQuery {
+    /// Issued by `/v1/ws-token`.
+    pub token: Option<String>,
+}
+
+/// What the pings of a WebSocket client are counted with.
+struct Pinger {
+    addr: SocketAddr,
+    tenant: Tenant,
+    /// Whether the tenant is the one of the credentials, that the pings can't change.
+    bound: bool,
+    /// Name of the API key, [`None`] if the key or the JWT of the upgrade request is invalid or
+    /// missing.
+    key: Option<Option<String>>,
+}
+
+impl Pinger {
+    async fn ping(
+        &self,
+        state: &AppState,
+        id: Option<Uuid>,
+        tenant: Option<String>,
+    ) -> Result<Reply, AppError> {
+        let Some(key) = &self.key else {
+            return Err(AppError::Unauthorized(
+                "invalid or missing API key or token".to_string(),
+            ));
+        };
+
+        let tenant = match tenant {
+            Some(tenant) => Tenant::parse(&tenant)
+                .ok_or_else(|| AppError::BadRequest("invalid tenant id".to_string()))?,
+            None => self.tenant.clone(),
+        };
+
+        if self.bound {
+            Tenant::bind(Some(tenant.clone()), Some(self.tenant.clone()))?;
+        }
+
+        crate::consume_quota(state, key.as_deref())?;
+
+        let id = id.unwrap_or_else(Uuid::new_v4);
+        let source = Source {
+            transport: Transport::WebSocket,
+            addr: self.addr,
+        };
+        let ping = Ping {
+            id,
+            callback: None,
+            sender: None,
+            metadata: Default::default(),
+        };
+
+        let count = match crate::ingest::count(state, source, tenant.clone(), ping).await {
+            Ok(count) => count,
+            Err(err) => {
+                crate::refund_quota(state, key.as_deref());
+
+                return Err(err);
+            }
+        };
+
+        Ok(Reply::Counted { id, tenant, count })
+    }
+
+    /// Answers the ping, with the same error messages of the HTTP pings.
+    async fn reply(&self, state: &AppState, id: Option<Uuid>, tenant: Option<String>) -> Reply {
+        let err = match self.ping(state, id, tenant).await {
+            Ok(reply) => return reply,
+            Err(err) => err.into_response(),
+        };
+
+        let status = err.status().as_u16();
+        let error = axum::body::to_bytes(err.into_body(), MAX_ERROR_LEN)
+            .await
+            .map(|body| String::from_utf8_lossy(&body).into_owned())
+            .unwrap_or_default();
+
+        Reply::Error { status, error }
+    }
+}
+
+impl Event {
+    /// Tenant the event is about, missing for the senders that didn't ping yet.
+    fn tenant(&self) -> Option<&Tenant> {
+        match self {
+            Event::Ping { tenant, .. }
+            | Event::Pong { tenant, .. }
+            | Event::Adjusted { tenant, .. }
+            | Event::Milestone { tenant, .. }
+            | Event::Rate { tenant, .. } => Some(tenant),
+            Event::History(ping) => Some(ping.tenant()),
+            Event::Sender { tenant, .. } => tenant.as_ref(),
+        }
+    }
+
+    /// Whether the event is sent to the clients of the tenant, being about it.
+    fn visible_to(&self, tenant: &Tenant) -> bool {
+        self.tenant() == Some(tenant)
+    }
+
+    fn topic(&self) -> Topic {
+        match self {
+            Event::Ping { .. } | Event::Pong { .. } | Event::Adjusted { .. } => Topic::Count,
+            Event::Sender { .. } => Topic::Senders,
+            Event::Rate { .. } => Topic::Rate,
+            Event::History(_) => Topic::History,
+            Event::Milestone { .. } => Topic::Milestones,
+        }
+    }
+
+    /// Only the events of the counters have a protobuf message.
+    fn to_proto(&self) -> Option<CountEvent> {
+        let (kind, id, tenant, count) = match self {
+            Event::Ping { id, tenant, count } => (Kind::Ping, id, tenant, count),
+            Event::Pong { id, tenant, count } => (Kind::Pong, id, tenant, count),
+            Event::Adjusted {
+                id, tenant, count, ..
+            } => (Kind::Adjusted, id, tenant, count),
+            Event::Sender { .. }
+            | Event::Rate { .. }
+            | Event::History(_)
+            | Event::Milestone { .. } => return None,
+        };
+
+        Some(CountEvent {
+            kind: kind.into(),
+            id: id.as_bytes().to_vec(),
+            tenant: tenant.to_string(),
+            count: *count,
+        })
+    }
+
+    /// Only the events of the counters have a plain text line.
+    fn to_text(&self) -> Option<String> {
+        match self {
+            Event::Ping { tenant, count, .. } => Some(format!("ping {tenant} {count}")),
+            Event::Pong { tenant, count, .. } => Some(format!("pong {tenant} {count}")),
+            Event::Adjusted { tenant, count, .. } => Some(format!("adjusted {tenant} {count}")),
+            Event::Milestone { tenant, count } => Some(format!("milestone {tenant} {count}")),
+            Event::Sender { .. } | Event::Rate { .. } | Event::History(_) => None,
+        }
+    }
+
+    /// Message of the event, if it has one in the encoding.
+    fn encode(&self, encoding: Encoding) -> Option<Message> {
+        match encoding {
+            Encoding::Protobuf => self
+                .to_proto()
+                .map(|event| Message::Binary(event.encode_to_vec())),
+            Encoding::Text => self.to_text().map(Message::Text),
+            Encoding::Json => match serde_json::to_string(self) {
+                Ok(text) => Some(Message::Text(text)),
+                Err(err) => {
+                    warn!(error = %err, "couldn't serialize event");
+
+                    None
+                }
+            },
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Events {
+    tx: broadcast::Sender<Event>,
+    /// Number of connected WebSocket clients.
+    clients: AtomicUsize,
+}
+
+impl Events {
+    pub fn new() -> Self {
+        let (tx, _rx) = broadcast::channel(CAPACITY);
+
+        Self {
+            tx,
+            clients: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn publish(&self, event: Event) {
+        // Fails only when nobody is subscribed
+        let _ = self.tx.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.tx.subscribe()
+    }
+
+    pub fn clients(&self) -> usize {
+        self.clients.load(Ordering::Relaxed)
+    }
+
+    /// Events not yet received by all the subscribers.
+    pub fn queued(&self) -> usize {
+        self.tx.len()
+    }
+}
+
+/// Closes the WebSocket when the server is going away from the client.
+fn going_away(reason: &'static str) -> CloseFrame<'static> {
+    CloseFrame {
+        code: close_code::AWAY,
+        reason: reason.into(),
+    }
+}
+
+fn too_slow() -> CloseFrame<'static> {
+    CloseFrame {
+        code: close_code::POLICY,
+        reason: "too slow".into(),
+    }
+}
+
+/// Streams the events in the encoding of the subprotocol the client asks for, the first of
+/// [`PROTOBUF_PROTOCOL`], [`JSON_PROTOCOL`] and [`TEXT_PROTOCOL`] it supports, or JSON without one.
+/// Only the counts are sent as protobuf or plain text. With only the token offered as a
+/// subprotocol, it's the one selected and the events are sent as JSON.
+///
+/// With the WebSocket authentication enabled the upgrade must carry a token, with the login
+/// enabled the session of the user. The pings are counted only with a token, or the API key and
+/// the JWT of the pings over HTTP if enabled.
+#[allow(clippy::too_many_arguments)]
+pub async fn events(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Query(query): Query<EventsQuery>,
+    headers: HeaderMap,
+    RequestedTenant(requested): RequestedTenant,
+    Bound(bound): Bound,
+    key: Result<ApiKey, AppError>,
+    subject: Result<Subject, AppError>,
+    _: LoggedIn,
+    ws: WebSocketUpgrade,
+) -> Result<Response, AppError> {
+    let ticket = tickets::token(query.token.as_deref(), &headers)
+        .and_then(|token| state.tickets.redeem(token));
+
+    if state.ws_auth && ticket.is_none() {
+        return Err(AppError::Unauthorized(
+            "invalid or missing WebSocket token".to_string(),
+        ));
+    }
+
+    // The token is bound to the tenant it was issued for
+    let bound = match &ticket {
+        Some(ticket) => Some(ticket.tenant.clone()),
+        None => bound,
+    };
+
+    let fanout = state.fanout.clone();
+    let pinger = Pinger {
+        addr,
+        tenant: Tenant::bind(requested, bound.clone())?,
+        bound: bound.is_some(),
+        key: ticket.map(|ticket| ticket.key).or_else(|| {
+            key.ok()
+                .filter(|_| subject.is_ok())
+                .map(|ApiKey(name)| name)
+        }),
+    };
+
+    let protocols = [PROTOBUF_PROTOCOL, JSON_PROTOCOL, TEXT_PROTOCOL];
+    let token_protocol = tickets::token_protocol(&headers, &protocols);
+
+    Ok(ws
+        .protocols(
+            protocols
+                .map(Cow::Borrowed)
+                .into_iter()
+                .chain(token_protocol.map(Cow::Owned)),
+        )
+        .on_upgrade(move |socket| fanout.instrument(stream(socket, state, pinger))))
+}
+
+/// How the stream of a client ends.
+enum End {
+    /// The client is gone, nothing more can be written.
+    Gone,
+    /// Closes the WebSocket with the frame.
+    Close(Option<CloseFrame<'static>>),
+}
+
+async fn stream(socket: WebSocket, state: AppState, pinger: Pinger) {
+    let encoding = Encoding::from_protocol(
+        socket
+            .protocol()
+            .and_then(|protocol| protocol.to_str().ok()),
+    );
+    let (sink, mut socket) = socket.split();
+    let outbox = Outbox::new(state.ws_buffer, state.slow_consumer);
+    let mut writer = pin!(outbox.write(sink));
+    let mut rx = state.events.subscribe();
+    let mut heartbeat = Heartbeat::new(state.idle_timeout);
+    let mut topics = vec![Topic::Count, Topic::Milestones, Topic::Senders];
+    let mut rate = tokio::time::interval(RATE_INTERVAL);
+
+    state.events.clients.fetch_add(1, Ordering::Relaxed);
+    metrics::gauge!("receiver_websocket_clients").increment(1.0);
+
+    debug!(?encoding, "events client connected");
+
+    let end = loop {
+        let res = tokio::select! {
+            _ = state.shutdown.cancelled() => break End::Close(Some(going_away("shutting down"))),
+            () = &mut writer => break End::Gone,
+            msg = socket.next() => match msg {
+                Some(Ok(Message::Close(_))) => break End::Close(None),
+                Some(Err(_)) | None => break End::Gone,
+                // Anything from the client, like the pongs, shows it's alive
+                Some(Ok(msg)) => {
+                    heartbeat.seen();
+
+                    if let Message::Text(text) = msg {
+                        match serde_json::from_str(&text) {
+                            Ok(ClientMessage::Subscribe { topics: subscribed }) => {
+                                debug!(topics = ?subscribed, "events client subscribed");
+
+                                topics = subscribed;
+                            }
+                            Ok(ClientMessage::Ping { id, tenant }) => {
+                                let reply = pinger.reply(&state, id, tenant).await;
+
+                                match serde_json::to_string(&reply) {
+                                    // Queued like the events, the client could ping faster
+                                    // than it reads
+                                    Ok(text) => {
+                                        if !outbox.push(Message::Text(text), None) {
+                                            debug!("disconnecting slow events client");
+
+                                            break End::Close(Some(too_slow()));
+                                        }
+                                    }
+                                    Err(err) => warn!(error = %err, "couldn't serialize reply"),
+                                }
+                            }
+                            Err(err) => debug!(error = %err, "invalid message from events client"),
+                        }
+                    }
+
+                    continue;
+                }
+            },
+            _ = rate.tick(), if topics.contains(&Topic::Rate) => Ok(Event::Rate {
+                tenant: pinger.tenant.clone(),
+                rate: state.history.rate(RATE_WINDOW, Some(&pinger.tenant)),
+            }),
+            beat = heartbeat.tick() => {
+                match beat {
+                    Beat::Ping => outbox.send(Message::Ping(Vec::new())),
+                    Beat::Idle => {
+                        debug!("closing idle events client");
+
+                        break End::Close(Some(going_away("idle")));
+                    }
+                }
+
+                continue;
+            }
+            res = rx.recv() => res,
+        };
+
+        let event = match res {
+            Ok(event) => event,
+            Err(RecvError::Lagged(skipped)) => {
+                debug!(skipped, "events client lagging behind");
+
+                outbox::lagged("skipped");
+
+                continue;
+            }
+            Err(RecvError::Closed) => break End::Close(Some(going_away("shutting down"))),
+        };
+
+        if !topics.contains(&event.topic()) || !event.visible_to(&pinger.tenant) {
+            continue;
+        }
+
+        let Some(msg) = event.encode(encoding) else {
+            continue;
+        };
+
+        let tenant = match event {
+            Event::Ping { tenant, .. } | Event::Pong { tenant, .. } => Some(tenant),
+            _ => None,
+        };
+
+        if !outbox.push(msg, tenant) {
+            debug!("disconnecting slow events client");
+
+            break End::Close(Some(too_slow()));
+        }
+    };
+
+    match end {
+        End::Close(frame) => {
+            outbox.close(frame);
+
+            // The client may not read the closing message either
+            if tokio::time::timeout(CLOSE_TIMEOUT, writer).await.is_err() {
+                debug!("events client didn't read the closing message");
+            }
+        }
+        End::Gone => outbox.discard(),
+    }
+
+    state.events.clients.fetch_sub(1, Ordering::Relaxed);
+    metrics::gauge!("receiver_websocket_clients").decrement(1.0);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use crate::history::PingRecord;
+
+    use super::*;
+
+    fn tenant(name: &str) -> Tenant {
+        Tenant::parse(name).unwrap()
+    }
+
+    fn events_of(tenant: &Tenant) -> Vec<Event> {
+        let record = PingRecord {
+            id: Uuid::new_v4(),
+            tenant: tenant.clone(),
+            count: 1,
+            received_at: SystemTime::now(),
+            metadata: Default::default(),
+            location: Default::default(),
+        };
+
+        vec![
+            Event::Ping {
+                id: record.id,
+                tenant: tenant.clone(),
+                count: 1,
+            },
+            Event::Pong {
+                id: Uuid::new_v4(),
+                tenant: tenant.clone(),
+                count: 0,
+            },
+            Event::Adjusted {
+                id: Uuid::new_v4(),
+                tenant: tenant.clone(),
+                count: 5,
+                delta: 5,
+            },
+            Event::Milestone {
+                tenant: tenant.clone(),
+                count: 5,
+            },
+            Event::Rate {
+                tenant: tenant.clone(),
+                rate: 1.0,
+            },
+            Event::Sender {
+                id: Uuid::new_v4(),
+                tenant: Some(tenant.clone()),
+                status: SenderStatus::Alive,
+                last_ping_at: None,
+            },
+            Event::History(record.into()),
+        ]
+    }
+
+    #[test]
+    fn events_sent_only_to_the_clients_of_the_tenant() {
+        let (a, b) = (tenant("a"), tenant("b"));
+
+        for event in events_of(&a) {
+            assert!(event.visible_to(&a), "{event:?} not sent to its tenant");
+            assert!(!event.visible_to(&b), "{event:?} sent to another tenant");
+        }
+
+        for event in events_of(&b) {
+            assert!(!event.visible_to(&a), "{event:?} sent to another tenant");
+        }
+    }
+
+    #[test]
+    fn senders_without_pings_sent_to_none() {
+        let event = Event::Sender {
+            id: Uuid::new_v4(),
+            tenant: None,
+            status: SenderStatus::Alive,
+            last_ping_at: None,
+        };
+
+        assert!(!event.visible_to(&Tenant::default()));
+        assert!(!event.visible_to(&tenant("a")));
+    }
+}
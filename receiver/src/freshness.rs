@@ -0,0 +1,238 @@
+//! Rejection of the stale and the replayed pings, by the timestamps of the senders.
+//!
+//! With `--max-clock-skew` the pings carrying a `sent_at` further from the clock of the receiver
+//! are refused, and the ids of the accepted ones are kept until their timestamp falls out of the
+//! window, refusing the same ping received again. Since a ping older than that is refused by its
+//! timestamp anyway, the ids are kept for at most twice the skew, bounding the memory to the
+//! pings received in that time. The pings without a timestamp aren't checked.
+
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+    sync::{Mutex, MutexGuard},
+    time::{Duration, SystemTime},
+};
+
+use protocol::Ping;
+use uuid::Uuid;
+
+use crate::AppError;
+
+#[derive(Debug, Default)]
+struct Seen {
+    /// Ids of the pings accepted, with when they can be forgotten.
+    ids: HashMap<Uuid, SystemTime>,
+    /// Ids by when they can be forgotten, the earliest first.
+    expiries: BinaryHeap<Reverse<(SystemTime, Uuid)>>,
+}
+
+impl Seen {
+    fn prune(&mut self, now: SystemTime) {
+        while let Some(Reverse((expires, id))) = self.expiries.peek().copied() {
+            if expires > now {
+                break;
+            }
+
+            self.expiries.pop();
+
+            // Unless it was forgotten and accepted again since
+            if self.ids.get(&id) == Some(&expires) {
+                self.ids.remove(&id);
+            }
+        }
+
+        metrics::gauge!("receiver_freshness_ids").set(self.ids.len() as f64);
+    }
+}
+
+#[derive(Debug)]
+pub struct Freshness {
+    skew: Duration,
+    seen: Mutex<Seen>,
+}
+
+/// Id of a ping being counted, forgotten if dropped before keeping it.
+#[derive(Debug)]
+pub struct Fresh<'a> {
+    freshness: &'a Freshness,
+    id: Option<Uuid>,
+}
+
+impl Freshness {
+    pub fn new(skew: Duration) -> Self {
+        Self {
+            skew,
+            seen: Mutex::new(Seen::default()),
+        }
+    }
+
+    fn seen(&self) -> MutexGuard<'_, Seen> {
+        self.seen.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Checks the timestamp of the ping and that it wasn't received already.
+    pub fn claim(&self, ping: &Ping) -> Result<Fresh<'_>, AppError> {
+        let Some(sent_at) = &ping.metadata.sent_at else {
+            return Ok(Fresh {
+                freshness: self,
+                id: None,
+            });
+        };
+
+        let sent = humantime::parse_rfc3339_weak(sent_at)
+            .map_err(|err| AppError::BadRequest(format!("invalid sent_at {sent_at}: {err}")))?;
+        let now = SystemTime::now();
+
+        let skew = match now.duration_since(sent) {
+            Ok(skew) => skew,
+            Err(err) => err.duration(),
+        };
+
+        if skew > self.skew {
+            metrics::counter!("receiver_pings_stale_total", "reason" => "skew").increment(1);
+
+            return Err(AppError::BadRequest(format!(
+                "the ping was sent at {sent_at}, more than {} from the clock of the receiver",
+                humantime::format_duration(self.skew)
+            )));
+        }
+
+        let mut seen = self.seen();
+        seen.prune(now);
+
+        if seen.ids.contains_key(&ping.id) {
+            metrics::counter!("receiver_pings_stale_total", "reason" => "replay").increment(1);
+
+            return Err(AppError::Conflict(format!(
+                "the ping {} was already received",
+                ping.id
+            )));
+        }
+
+        let expires = sent + self.skew;
+        seen.ids.insert(ping.id, expires);
+        seen.expiries.push(Reverse((expires, ping.id)));
+
+        Ok(Fresh {
+            freshness: self,
+            id: Some(ping.id),
+        })
+    }
+}
+
+impl Fresh<'_> {
+    /// Keeps the id until it's stale, once the ping is counted.
+    pub fn keep(mut self) {
+        self.id = None;
+    }
+}
+
+impl Drop for Fresh<'_> {
+    fn drop(&mut self) {
+        if let Some(id) = self.id.take() {
+            self.freshness.seen().ids.remove(&id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use protocol::Metadata;
+
+    use super::*;
+
+    const SKEW: Duration = Duration::from_secs(60);
+
+    fn ping(sent: Option<SystemTime>) -> Ping {
+        Ping {
+            id: Uuid::new_v4(),
+            callback: None,
+            sender: None,
+            metadata: Metadata {
+                sent_at: sent.map(|sent| humantime::format_rfc3339_millis(sent).to_string()),
+                ..Default::default()
+            },
+        }
+    }
+
+    #[test]
+    fn pings_within_the_skew_accepted() {
+        let freshness = Freshness::new(SKEW);
+        let now = SystemTime::now();
+
+        for sent in [now - Duration::from_secs(30), now + Duration::from_secs(30)] {
+            freshness.claim(&ping(Some(sent))).unwrap().keep();
+        }
+    }
+
+    #[test]
+    fn pings_past_the_skew_refused() {
+        let freshness = Freshness::new(SKEW);
+        let now = SystemTime::now();
+
+        for sent in [now - Duration::from_secs(90), now + Duration::from_secs(90)] {
+            let err = freshness.claim(&ping(Some(sent))).unwrap_err();
+
+            assert!(matches!(err, AppError::BadRequest(_)), "{err:?}");
+        }
+    }
+
+    #[test]
+    fn invalid_timestamps_refused() {
+        let freshness = Freshness::new(SKEW);
+        let mut ping = ping(None);
+        ping.metadata.sent_at = Some("yesterday".to_string());
+
+        let err = freshness.claim(&ping).unwrap_err();
+
+        assert!(matches!(err, AppError::BadRequest(_)), "{err:?}");
+    }
+
+    #[test]
+    fn pings_without_a_timestamp_not_checked() {
+        let freshness = Freshness::new(SKEW);
+        let ping = ping(None);
+
+        freshness.claim(&ping).unwrap().keep();
+        freshness.claim(&ping).unwrap().keep();
+    }
+
+    #[test]
+    fn replays_within_the_window_refused() {
+        let freshness = Freshness::new(SKEW);
+        let ping = ping(Some(SystemTime::now()));
+
+        freshness.claim(&ping).unwrap().keep();
+        let err = freshness.claim(&ping).unwrap_err();
+
+        assert!(matches!(err, AppError::Conflict(_)), "{err:?}");
+    }
+
+    #[test]
+    fn pings_not_kept_can_be_received_again() {
+        let freshness = Freshness::new(SKEW);
+        let ping = ping(Some(SystemTime::now()));
+
+        drop(freshness.claim(&ping).unwrap());
+
+        freshness.claim(&ping).unwrap().keep();
+    }
+
+    #[test]
+    fn ids_forgotten_once_out_of_the_window() {
+        let freshness = Freshness::new(SKEW);
+        let now = SystemTime::now();
+        let old = ping(Some(now - Duration::from_secs(59)));
+        let recent = ping(Some(now));
+
+        freshness.claim(&old).unwrap().keep();
+        freshness.claim(&recent).unwrap().keep();
+
+        let mut seen = freshness.seen();
+        seen.prune(now + Duration::from_secs(30));
+
+        assert!(!seen.ids.contains_key(&old.id));
+        assert!(seen.ids.contains_key(&recent.id));
+        assert_eq!(seen.expiries.len(), 1);
+    }
+}
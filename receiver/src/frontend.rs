@@ -0,0 +1,314 @@
+//! Index page and assets of the receiver.
+//!
+//! The assets are fingerprinted by the build script and served with far-future cache headers
+//! on their hashed paths, the ones the templates are rewritten to. Their precompressed variants
+//! are served to the clients accepting them, brotli first.
+//!
+//! The index page is rendered with the current count of the tenant of the request, then kept up
+//! to date by its events, the other tenants aren't shown. While the receiver is under
+//! maintenance, a maintenance page is served instead.
+//!
+//! In dev mode the template and the assets are read from the source tree on each request
+//! instead, so the changes show on the next refresh. The assets are served on their plain
+//! `/assets/{name}` paths, without caching.
+
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    process::{Command, Stdio},
+};
+
+use axum::{
+    extract::{Path, State},
+    http::{
+        header::{
+            ACCEPT, ACCEPT_ENCODING, CACHE_CONTROL, CONTENT_ENCODING, CONTENT_TYPE, RETRY_AFTER,
+            VARY,
+        },
+        HeaderMap, HeaderValue, StatusCode,
+    },
+    response::{Html, IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use cfg_if::cfg_if;
+use protocol::Format;
+use serde::Serialize;
+use tracing::{debug, warn};
+
+use crate::{maintenance::Window, negotiate::Negotiated, tenant::Tenant, AppError, AppState};
+
+/// Cache of the fingerprinted assets, their paths change with the content.
+const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+
+/// Where the files are read from in dev mode.
+const TEMPLATES_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/templates");
+const ASSETS_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../assets");
+
+/// File embedded in the binary, with its compressed variants smaller than it.
+struct Embedded {
+    content: &'static [u8],
+    gzip: Option<&'static [u8]>,
+    brotli: Option<&'static [u8]>,
+}
+
+/// Whether the `Accept-Encoding` header allows the content coding, not refused with `q=0`.
+fn accepts(accept_encoding: &str, coding: &str) -> bool {
+    accept_encoding.split(',').any(|entry| {
+        let mut params = entry.split(';');
+        let name = params.next().unwrap_or_default().trim();
+
+        name == coding
+            && !params.any(|param| {
+                param
+                    .trim()
+                    .strip_prefix("q=")
+                    .and_then(|q| q.parse::<f32>().ok())
+                    == Some(0.0)
+            })
+    })
+}
+
+impl Embedded {
+    /// Responds with the variant accepted by the client.
+    fn respond(&self, req: &HeaderMap, content_type: &'static str) -> Response {
+        let accept_encoding = req
+            .get(ACCEPT_ENCODING)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+
+        let (encoding, content) = match (self.brotli, self.gzip) {
+            (Some(brotli), _) if accepts(accept_encoding, "br") => (Some("br"), brotli),
+            (_, Some(gzip)) if accepts(accept_encoding, "gzip") => (Some("gzip"), gzip),
+            _ => (None, self.content),
+        };
+
+        let mut res = content.into_response();
+        let headers = res.headers_mut();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+        headers.insert(VARY, HeaderValue::from_static("accept-encoding"));
+
+        if let Some(encoding) = encoding {
+            headers.insert(CONTENT_ENCODING, HeaderValue::from_static(encoding));
+        }
+
+        res
+    }
+}
+
+/// Asset embedded in the binary.
+struct Asset {
+    name: &'static str,
+    /// Fingerprinted path the asset is served on.
+    path: &'static str,
+    embedded: Embedded,
+}
+
+/// Media type of the asset, by its extension.
+fn content_type(name: &str) -> &'static str {
+    match name.rsplit_once('.').map(|(_, extension)| extension) {
+        Some("ico") => "image/x-icon",
+        Some("png") => "image/png",
+        Some("svg") => "image/svg+xml",
+        Some("css") => "text/css",
+        Some("js") => "text/javascript",
+        Some("woff2") => "font/woff2",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Reads the asset from the source tree, in dev mode.
+async fn dev_asset(name: &str) -> Result<Response, AppError> {
+    // Only the files directly in the directory
+    if name.starts_with('.') || name.contains(['/', '\\']) {
+        return Err(AppError::NotFound(format!("no asset {name}")));
+    }
+
+    let content = match tokio::fs::read(std::path::Path::new(ASSETS_DIR).join(name)).await {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            return Err(AppError::NotFound(format!("no asset {name}")))
+        }
+        Err(err) => return Err(err.into()),
+    };
+
+    Ok((
+        [
+            (CONTENT_TYPE, content_type(name)),
+            (CACHE_CONTROL, "no-cache"),
+        ],
+        content,
+    )
+        .into_response())
+}
+
+const ASSETS: &[Asset] = include!(concat!(env!("OUT_DIR"), "/assets.rs"));
+const INDEX: &str = include_str!(concat!(env!("OUT_DIR"), "/index.html"));
+const MAINTENANCE: &str = include_str!(concat!(env!("OUT_DIR"), "/maintenance.html"));
+
+/// Status of the receiver, served on the index to the clients not asking for the page.
+#[derive(Debug, Serialize)]
+struct Summary {
+    count: u64,
+    uptime_secs: u64,
+    version: &'static str,
+}
+
+/// Format of the first data type accepted before the page, if any.
+fn preferred_format(accept: &str) -> Option<Format> {
+    accept
+        .split(',')
+        .map(|media_type| media_type.split(';').next().unwrap_or_default().trim())
+        .take_while(|essence| !matches!(*essence, "text/html" | "text/*" | "*/*"))
+        .find_map(Format::from_media_type)
+}
+
+fn render_index(state: &AppState, tenant: &Tenant, template: &str) -> String {
+    template.replace("{{count}}", &state.counters.get(tenant).to_string())
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Page served instead of the index while under maintenance.
+async fn maintenance_page(state: &AppState, maintenance: Window) -> Result<Response, AppError> {
+    let template = if state.dev {
+        tokio::fs::read_to_string(format!("{TEMPLATES_DIR}/maintenance.html")).await?
+    } else {
+        MAINTENANCE.to_string()
+    };
+    let message = maintenance
+        .message
+        .as_deref()
+        .unwrap_or("We'll be back soon.");
+    let page = template.replace("{{message}}", &escape_html(message));
+
+    Ok((
+        StatusCode::SERVICE_UNAVAILABLE,
+        [
+            (RETRY_AFTER, maintenance.retry_after.as_secs().to_string()),
+            (VARY, "accept".to_string()),
+        ],
+        Html(page),
+    )
+        .into_response())
+}
+
+async fn index(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let format = headers
+        .get(ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .and_then(preferred_format);
+
+    let Some(format) = format else {
+        if let Some(maintenance) = state.maintenance.current() {
+            return maintenance_page(&state, maintenance).await;
+        }
+
+        let page = if state.dev {
+            let template = tokio::fs::read_to_string(format!("{TEMPLATES_DIR}/index.html")).await?;
+
+            render_index(&state, &tenant, &template)
+        } else {
+            render_index(&state, &tenant, INDEX)
+        };
+
+        return Ok(([(VARY, "accept")], Html(page)).into_response());
+    };
+
+    let summary = Summary {
+        count: state.counters.get(&tenant),
+        uptime_secs: state.started.elapsed().as_secs(),
+        version: env!("CARGO_PKG_VERSION"),
+    };
+
+    Ok(Negotiated(format, summary).into_response())
+}
+
+async fn asset(
+    State(state): State<AppState>,
+    Path(file): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    if state.dev {
+        return dev_asset(&file).await;
+    }
+
+    let path = format!("/assets/{file}");
+    let asset = ASSETS
+        .iter()
+        .find(|asset| asset.path == path)
+        .ok_or_else(|| AppError::NotFound(format!("no asset {file}")))?;
+
+    let mut res = asset.embedded.respond(&headers, content_type(asset.name));
+    res.headers_mut()
+        .insert(CACHE_CONTROL, HeaderValue::from_static(IMMUTABLE));
+
+    Ok(res)
+}
+
+/// Requested by the browsers on their own, so not fingerprinted.
+async fn favicon_ico(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    if state.dev {
+        return dev_asset("favicon.ico").await;
+    }
+
+    let asset = ASSETS
+        .iter()
+        .find(|asset| asset.name == "favicon.ico")
+        .ok_or_else(|| AppError::NotFound("no favicon".to_string()))?;
+
+    Ok(asset.embedded.respond(&headers, content_type(asset.name)))
+}
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/", get(index))
+        .route("/favicon.ico", get(favicon_ico))
+        .route("/assets/:file", get(asset))
+}
+
+/// Opens the index page in the default browser, in the background.
+pub fn open_browser(addr: SocketAddr) {
+    let ip = match addr.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        ip => ip,
+    };
+    let url = format!("http://{}", SocketAddr::new(ip, addr.port()));
+
+    cfg_if! {
+        if #[cfg(target_os = "macos")] {
+            let mut cmd = Command::new("open");
+            cmd.arg(&url);
+        } else if #[cfg(target_os = "windows")] {
+            let mut cmd = Command::new("cmd");
+            cmd.args(["/C", "start", "", &url]);
+        } else {
+            let mut cmd = Command::new("xdg-open");
+            cmd.arg(&url);
+        }
+    }
+
+    cmd.stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+
+    debug!(%url, "opening the browser");
+
+    tokio::task::spawn_blocking(move || match cmd.status() {
+        Ok(status) if status.success() => {}
+        Ok(status) => warn!(%url, %status, "couldn't open the browser"),
+        Err(err) => warn!(%url, error = %err, "couldn't open the browser"),
+    });
+}
@@ -0,0 +1,199 @@
+//! GeoIP enrichment of the sources of the pings.
+//!
+//! The address of each counted ping is looked up in the MaxMind databases supplied, a country or
+//! city one for the country and an ASN one for the autonomous system. The location is stored in
+//! the history and aggregated for `/v1/stats/geo`.
+
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    path::Path,
+    sync::{Mutex, MutexGuard},
+};
+
+use axum::{extract::State, routing::get, Json, Router};
+use eyre::WrapErr;
+use maxminddb::{geoip2, Reader};
+use serde::Serialize;
+use tracing::debug;
+
+use crate::{
+    version::{self, Endpoint},
+    AppState,
+};
+
+/// Where a ping was sent from, the fields are missing if not found in the databases.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Location {
+    /// ISO 3166-1 code of the country.
+    pub country: Option<String>,
+    pub asn: Option<u32>,
+    /// Organization owning the autonomous system.
+    pub as_org: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct Counts {
+    countries: HashMap<String, u64>,
+    asns: HashMap<u32, (Option<String>, u64)>,
+    unknown: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CountryPings {
+    country: String,
+    pings: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AsnPings {
+    asn: u32,
+    organization: Option<String>,
+    pings: u64,
+}
+
+/// Pings counted by location, the most frequent first.
+#[derive(Debug, Default, Serialize)]
+pub struct GeoStats {
+    countries: Vec<CountryPings>,
+    asns: Vec<AsnPings>,
+    /// Pings from addresses not found in any of the databases.
+    unknown: u64,
+}
+
+pub struct GeoIp {
+    country: Option<Reader<Vec<u8>>>,
+    asn: Option<Reader<Vec<u8>>>,
+    counts: Mutex<Counts>,
+}
+
+impl std::fmt::Debug for GeoIp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // The readers would print the whole databases
+        f.debug_struct("GeoIp")
+            .field("counts", &self.counts)
+            .finish_non_exhaustive()
+    }
+}
+
+fn open(path: &Path) -> eyre::Result<Reader<Vec<u8>>> {
+    let reader = Reader::open_readfile(path)
+        .wrap_err_with(|| format!("couldn't open {}", path.display()))?;
+
+    debug!(
+        path = %path.display(),
+        database = reader.metadata.database_type,
+        "GeoIP database loaded"
+    );
+
+    Ok(reader)
+}
+
+impl GeoIp {
+    /// Loads the databases, returning [`None`] if neither is supplied.
+    pub fn open(country: Option<&Path>, asn: Option<&Path>) -> eyre::Result<Option<Self>> {
+        if country.is_none() && asn.is_none() {
+            return Ok(None);
+        }
+
+        Ok(Some(Self {
+            country: country.map(open).transpose()?,
+            asn: asn.map(open).transpose()?,
+            counts: Mutex::default(),
+        }))
+    }
+
+    fn counts(&self) -> MutexGuard<'_, Counts> {
+        self.counts.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    pub fn locate(&self, ip: IpAddr) -> Location {
+        let mut location = Location::default();
+
+        if let Some(reader) = &self.country {
+            match reader.lookup::<geoip2::Country>(ip) {
+                Ok(found) => {
+                    location.country = found
+                        .and_then(|found| found.country)
+                        .and_then(|country| country.iso_code)
+                        .map(str::to_string);
+                }
+                Err(err) => debug!(%ip, error = %err, "couldn't look up the country"),
+            }
+        }
+
+        if let Some(reader) = &self.asn {
+            match reader.lookup::<geoip2::Asn>(ip) {
+                Ok(Some(found)) => {
+                    location.asn = found.autonomous_system_number;
+                    location.as_org = found.autonomous_system_organization.map(str::to_string);
+                }
+                Ok(None) => {}
+                Err(err) => debug!(%ip, error = %err, "couldn't look up the ASN"),
+            }
+        }
+
+        location
+    }
+
+    /// Counts a ping from the location in the statistics.
+    pub fn record(&self, location: &Location) {
+        let mut counts = self.counts();
+
+        if location.country.is_none() && location.asn.is_none() {
+            counts.unknown += 1;
+
+            return;
+        }
+
+        if let Some(country) = &location.country {
+            *counts.countries.entry(country.clone()).or_insert(0) += 1;
+        }
+
+        if let Some(asn) = location.asn {
+            let (organization, pings) = counts.asns.entry(asn).or_insert((None, 0));
+            organization.clone_from(&location.as_org);
+            *pings += 1;
+        }
+    }
+
+    fn stats(&self) -> GeoStats {
+        let counts = self.counts();
+
+        let mut countries: Vec<CountryPings> = counts
+            .countries
+            .iter()
+            .map(|(country, pings)| CountryPings {
+                country: country.clone(),
+                pings: *pings,
+            })
+            .collect();
+        countries.sort_unstable_by(|a, b| b.pings.cmp(&a.pings).then(a.country.cmp(&b.country)));
+
+        let mut asns: Vec<AsnPings> = counts
+            .asns
+            .iter()
+            .map(|(asn, (organization, pings))| AsnPings {
+                asn: *asn,
+                organization: organization.clone(),
+                pings: *pings,
+            })
+            .collect();
+        asns.sort_unstable_by(|a, b| b.pings.cmp(&a.pings).then(a.asn.cmp(&b.asn)));
+
+        GeoStats {
+            countries,
+            asns,
+            unknown: counts.unknown,
+        }
+    }
+}
+
+async fn stats(State(state): State<AppState>) -> Json<GeoStats> {
+    Json(state.geoip.as_ref().map(GeoIp::stats).unwrap_or_default())
+}
+
+/// Routes of the statistics, served only with the databases.
+pub fn routes() -> Router<AppState> {
+    version::routes([Endpoint::new("/stats/geo", "/api/stats/geo", get(stats))])
+}
@@ -0,0 +1,179 @@
+//! Datasource for the Grafana SimpleJSON and Infinity plugins, charting the pings of the history.
+//!
+//! The `rate` target is the rate of all the pings and `rate.TENANT` the one of a single tenant,
+//! in pings per second over buckets of the interval of the panel. Only the pings still in the
+//! history can be charted.
+
+use std::{
+    collections::BTreeSet,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    extract::State,
+    routing::{get, post},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    tenant::Tenant,
+    version::{self, Endpoint},
+    AppError, AppState,
+};
+
+const RATE: &str = "rate";
+
+/// Most buckets returned for each target, the interval is widened to stay within them.
+const MAX_BUCKETS: u64 = 10_000;
+
+#[derive(Debug, Deserialize)]
+struct Range {
+    from: String,
+    to: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct QueryRequest {
+    range: Range,
+    interval_ms: Option<u64>,
+    max_data_points: Option<u64>,
+    targets: Vec<Target>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Target {
+    target: Option<String>,
+    #[serde(default)]
+    hide: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct Series {
+    target: String,
+    /// Pairs of the rate and of the start of the bucket, in milliseconds since the epoch.
+    datapoints: Vec<(f64, u64)>,
+}
+
+/// Pings counted by a target.
+#[derive(Debug, Clone, Copy)]
+enum Filter<'a> {
+    All,
+    Tenant(&'a str),
+}
+
+impl<'a> Filter<'a> {
+    /// Filter of the target, [`None`] if it's unknown.
+    fn parse(target: &'a str) -> Option<Self> {
+        match target.strip_prefix(RATE)? {
+            "" => Some(Filter::All),
+            rest => rest.strip_prefix('.').map(Filter::Tenant),
+        }
+    }
+
+    fn matches(self, tenant: &Tenant) -> bool {
+        match self {
+            Filter::All => true,
+            Filter::Tenant(name) => tenant.as_str() == name,
+        }
+    }
+}
+
+fn parse_time(time: &str) -> Result<SystemTime, AppError> {
+    humantime::parse_rfc3339_weak(time)
+        .map_err(|err| AppError::BadRequest(format!("invalid time {time}: {err}")))
+}
+
+fn millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Answers the test of the datasource.
+async fn health() {}
+
+/// Lists the targets, the one of all the pings and the one of each tenant.
+async fn search(State(state): State<AppState>) -> Json<Vec<String>> {
+    let tenants: BTreeSet<String> = state
+        .counters
+        .tenants()
+        .into_iter()
+        .map(|count| format!("{RATE}.{}", count.tenant))
+        .collect();
+
+    Json(std::iter::once(RATE.to_string()).chain(tenants).collect())
+}
+
+async fn query(
+    State(state): State<AppState>,
+    Json(req): Json<QueryRequest>,
+) -> Result<Json<Vec<Series>>, AppError> {
+    let from = parse_time(&req.range.from)?;
+    let to = parse_time(&req.range.to)?;
+    if to <= from {
+        return Err(AppError::BadRequest(
+            "the range must end after it starts".to_string(),
+        ));
+    }
+
+    let span = millis(to) - millis(from);
+    let buckets = req
+        .max_data_points
+        .unwrap_or(MAX_BUCKETS)
+        .clamp(1, MAX_BUCKETS);
+    let interval = req
+        .interval_ms
+        .unwrap_or(0)
+        .max(span.div_ceil(buckets))
+        .max(1);
+    let buckets = span.div_ceil(interval) as usize;
+
+    let targets: Vec<String> = req
+        .targets
+        .into_iter()
+        .filter(|target| !target.hide)
+        .filter_map(|target| target.target)
+        .collect();
+
+    let filters: Vec<Option<Filter>> = targets.iter().map(|target| Filter::parse(target)).collect();
+
+    let mut counts = vec![vec![0u64; buckets]; targets.len()];
+    state
+        .history
+        .received_between(from, to, |tenant, received_at| {
+            let bucket = ((millis(received_at) - millis(from)) / interval) as usize;
+
+            for (filter, counts) in filters.iter().zip(&mut counts) {
+                if filter.is_some_and(|filter| filter.matches(tenant)) {
+                    counts[bucket] += 1;
+                }
+            }
+        });
+
+    let seconds = Duration::from_millis(interval).as_secs_f64();
+    let start = millis(from);
+    let series = targets
+        .into_iter()
+        .zip(counts)
+        .map(|(target, counts)| Series {
+            target,
+            datapoints: counts
+                .into_iter()
+                .enumerate()
+                .map(|(n, count)| (count as f64 / seconds, start + n as u64 * interval))
+                .collect(),
+        })
+        .collect();
+
+    Ok(Json(series))
+}
+
+pub fn routes() -> Router<AppState> {
+    version::routes([
+        Endpoint::new("/grafana", "/api/grafana", get(health)),
+        Endpoint::new("/grafana/search", "/api/grafana/search", post(search)),
+        Endpoint::new("/grafana/query", "/api/grafana/query", post(query)),
+    ])
+}
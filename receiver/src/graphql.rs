@@ -0,0 +1,271 @@
+//! GraphQL API over the counters and the history.
+//!
+//! Queries are posted to `/graphql`, subscriptions use the WebSocket at `/graphql/ws`. Both are
+//! behind the login if enabled, and the WebSocket needs a token from `/v1/ws-token` with the
+//! WebSocket authentication, like the one at `/v1/events`. Like on the REST API the clients only
+//! see the tenant of their `X-Tenant-Id`, the one of their credentials or token if bound to one.
+
+use async_graphql::{
+    http::ALL_WEBSOCKET_PROTOCOLS, Context, Data, EmptyMutation, Object, Schema, SimpleObject,
+    Subscription,
+};
+use async_graphql_axum::{GraphQLProtocol, GraphQLRequest, GraphQLResponse, GraphQLWebSocket};
+use axum::{
+    extract::{ws::WebSocketUpgrade, Query as UrlQuery, Request, State},
+    middleware::{self, Next},
+    response::Response,
+    routing::{get, post},
+    Extension, Router,
+};
+use futures::{Stream, StreamExt};
+use tokio::sync::broadcast::error::RecvError;
+use uuid::Uuid;
+
+use crate::{
+    events::{Event, EventsQuery},
+    history::PingRecord,
+    oidc::LoggedIn,
+    tenant::{Bound, RequestedTenant, Tenant},
+    tickets, AppError, AppState,
+};
+
+type PingSchema = Schema<Query, EmptyMutation, Subscription>;
+
+#[derive(Debug, SimpleObject)]
+struct Ping {
+    id: Uuid,
+    tenant: String,
+    /// Count of the tenant after the ping.
+    count: u64,
+    /// RFC 3339 timestamp of when the ping was received.
+    received_at: String,
+    /// Host name of the machine of the sender.
+    hostname: Option<String>,
+    /// Version of the sender.
+    version: Option<String>,
+    /// Number of the ping between the ones sent by the sender.
+    sequence: Option<u64>,
+    /// RFC 3339 timestamp of when the ping was sent, by the clock of the sender.
+    sent_at: Option<String>,
+    /// User logged in to the sender UI the ping was sent from.
+    user: Option<String>,
+    /// ISO 3166-1 code of the country the ping was received from, with the GeoIP databases.
+    country: Option<String>,
+    /// Autonomous system the ping was received from, with the GeoIP databases.
+    asn: Option<u32>,
+}
+
+impl From<PingRecord> for Ping {
+    fn from(record: PingRecord) -> Self {
+        Self {
+            id: record.id,
+            tenant: record.tenant.to_string(),
+            count: record.count,
+            received_at: humantime::format_rfc3339_millis(record.received_at).to_string(),
+            hostname: record.metadata.hostname,
+            version: record.metadata.version,
+            sequence: record.metadata.sequence,
+            sent_at: record.metadata.sent_at,
+            user: record.metadata.user,
+            country: record.location.country,
+            asn: record.location.asn,
+        }
+    }
+}
+
+#[derive(Debug, SimpleObject)]
+struct CountChanged {
+    tenant: String,
+    count: u64,
+}
+
+/// Tenant of the request, that the argument must be if given.
+fn tenant(ctx: &Context<'_>, tenant: Option<String>) -> async_graphql::Result<Tenant> {
+    let requested = ctx.data::<Tenant>()?;
+
+    let Some(tenant) = tenant else {
+        return Ok(requested.clone());
+    };
+
+    let tenant = Tenant::parse(&tenant).ok_or("invalid tenant id")?;
+    if tenant != *requested {
+        return Err(format!("the tenant isn't {requested}, the one of the request").into());
+    }
+
+    Ok(tenant)
+}
+
+struct Query;
+
+#[Object]
+impl Query {
+    /// Count of the tenant of the request.
+    async fn count(&self, ctx: &Context<'_>, tenant: Option<String>) -> async_graphql::Result<u64> {
+        let state = ctx.data::<AppState>()?;
+
+        Ok(state.counters.get(&self::tenant(ctx, tenant)?))
+    }
+
+    /// Most recent pings of the tenant of the request, the latest first, in pages of at most 1000
+    /// pings.
+    async fn pings(
+        &self,
+        ctx: &Context<'_>,
+        tenant: Option<String>,
+        #[graphql(default = 0)] page: u32,
+        #[graphql(default = 50, validator(maximum = 1000))] per_page: u32,
+    ) -> async_graphql::Result<Vec<Ping>> {
+        let state = ctx.data::<AppState>()?;
+        let tenant = self::tenant(ctx, tenant)?;
+
+        let pings = state
+            .history
+            .page(Some(&tenant), page as usize, per_page as usize)
+            .into_iter()
+            .map(Ping::from)
+            .collect();
+
+        Ok(pings)
+    }
+}
+
+struct Subscription;
+
+#[Subscription]
+impl Subscription {
+    /// Count of the tenant of the request, after every ping or pong.
+    async fn count_changed(
+        &self,
+        ctx: &Context<'_>,
+        tenant: Option<String>,
+    ) -> async_graphql::Result<impl Stream<Item = CountChanged>> {
+        let state = ctx.data::<AppState>()?;
+        let tenant = self::tenant(ctx, tenant)?;
+
+        let events = futures::stream::unfold(state.events.subscribe(), |mut rx| async move {
+            loop {
+                match rx.recv().await {
+                    Ok(event) => return Some((event, rx)),
+                    // Only the latest count matters
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        });
+
+        let changes = events.filter_map(move |event| {
+            let change = match event {
+                Event::Ping {
+                    tenant: changed,
+                    count,
+                    ..
+                }
+                | Event::Pong {
+                    tenant: changed,
+                    count,
+                    ..
+                }
+                | Event::Adjusted {
+                    tenant: changed,
+                    count,
+                    ..
+                } => (changed == tenant).then(|| CountChanged {
+                    tenant: changed.to_string(),
+                    count,
+                }),
+                Event::Sender { .. }
+                | Event::Rate { .. }
+                | Event::History(_)
+                | Event::Milestone { .. } => None,
+            };
+
+            async move { change }
+        });
+
+        Ok(changes)
+    }
+}
+
+async fn query(
+    State(schema): State<PingSchema>,
+    Extension(tenant): Extension<Tenant>,
+    req: GraphQLRequest,
+) -> GraphQLResponse {
+    schema.execute(req.into_inner().data(tenant)).await.into()
+}
+
+async fn subscribe(
+    State(schema): State<PingSchema>,
+    Extension(tenant): Extension<Tenant>,
+    protocol: GraphQLProtocol,
+    ws: WebSocketUpgrade,
+) -> Response {
+    ws.protocols(ALL_WEBSOCKET_PROTOCOLS)
+        .on_upgrade(move |socket| {
+            let mut data = Data::default();
+            data.insert(tenant);
+
+            GraphQLWebSocket::new(socket, schema, protocol)
+                .with_data(data)
+                .serve()
+        })
+}
+
+async fn logged_in(_: LoggedIn, req: Request, next: Next) -> Response {
+    next.run(req).await
+}
+
+/// Scopes the queries to the tenant of the request.
+async fn scope(tenant: Tenant, mut req: Request, next: Next) -> Response {
+    req.extensions_mut().insert(tenant);
+
+    next.run(req).await
+}
+
+/// Rejects the subscriptions without a token if the WebSocket authentication is enabled, and
+/// scopes them to the tenant of the token or of the request.
+async fn authorize(
+    State(state): State<AppState>,
+    UrlQuery(query): UrlQuery<EventsQuery>,
+    RequestedTenant(requested): RequestedTenant,
+    Bound(bound): Bound,
+    mut req: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let ticket = tickets::token(query.token.as_deref(), req.headers())
+        .and_then(|token| state.tickets.redeem(token));
+
+    if state.ws_auth && ticket.is_none() {
+        return Err(AppError::Unauthorized(
+            "invalid or missing WebSocket token".to_string(),
+        ));
+    }
+
+    let bound = match ticket {
+        Some(ticket) => Some(ticket.tenant),
+        None => bound,
+    };
+    req.extensions_mut().insert(Tenant::bind(requested, bound)?);
+
+    Ok(next.run(req).await)
+}
+
+pub fn routes(state: AppState) -> Router<AppState> {
+    let schema: PingSchema = Schema::build(Query, EmptyMutation, Subscription)
+        .data(state.clone())
+        .finish();
+
+    let subscriptions = Router::new()
+        .route("/graphql/ws", get(subscribe))
+        .route_layer(middleware::from_fn_with_state(state.clone(), authorize));
+
+    let queries = Router::new()
+        .route("/graphql", post(query))
+        .route_layer(middleware::from_fn_with_state(state.clone(), scope));
+
+    Router::new()
+        .merge(queries)
+        .merge(subscriptions)
+        .route_layer(middleware::from_fn_with_state(state, logged_in))
+        .with_state(schema)
+}
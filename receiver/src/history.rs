@@ -0,0 +1,396 @@
+//! History of the most recent pings.
+//!
+//! The history is bounded by the [`Eviction`] policy, and in any case by [`MAX_RECORDS`], so it
+//! doesn't grow forever on a long-running receiver. The evicted pings are counted by reason.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    num::NonZeroUsize,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, MutexGuard,
+    },
+    time::{Duration, SystemTime},
+};
+
+use clap::{Args, ValueEnum};
+use protocol::Metadata;
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{admin, geo::Location, tenant::Tenant};
+
+/// Most pings kept with any policy, the oldest are dropped first.
+pub const MAX_RECORDS: usize = 1_000_000;
+
+#[derive(Debug, Clone, Args, Serialize)]
+pub struct HistoryArgs {
+    /// How the pings are evicted from the history
+    #[arg(long, value_enum, default_value_t = Eviction::Ring)]
+    history_eviction: Eviction,
+    /// Number of pings kept in the history, or of each tenant with the count eviction
+    #[arg(long, value_name = "PINGS", default_value = "10000")]
+    history_size: NonZeroUsize,
+    /// Age after which the pings are evicted with the window eviction
+    #[arg(long, default_value = "1h", value_parser = humantime::parse_duration)]
+    #[serde(serialize_with = "admin::humantime")]
+    history_window: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Eviction {
+    /// Keeps the latest pings up to the size, dropping the oldest.
+    Ring,
+    /// Keeps the pings received in the window, up to the size.
+    Window,
+    /// Keeps the latest pings of each tenant up to the size.
+    Count,
+}
+
+/// Why the pings were evicted.
+#[derive(Debug, Clone, Copy)]
+enum Reason {
+    Size,
+    Age,
+    Tenant,
+}
+
+impl Reason {
+    fn as_str(self) -> &'static str {
+        match self {
+            Reason::Size => "size",
+            Reason::Age => "age",
+            Reason::Tenant => "tenant",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PingRecord {
+    pub id: Uuid,
+    pub tenant: Tenant,
+    /// Count of the tenant after the ping.
+    pub count: u64,
+    pub received_at: SystemTime,
+    /// Where the ping was sent from, as told by the sender.
+    pub metadata: Metadata,
+    /// Where the ping was received from, with the GeoIP databases.
+    pub location: Location,
+}
+
+#[derive(Debug, Default)]
+struct Records {
+    /// Pings in the order they were received, by sequence number. The ones evicted with the
+    /// count eviction are left as holes, dropped once at the front or when they outnumber the
+    /// pings.
+    records: VecDeque<(u64, Option<PingRecord>)>,
+    /// Pings kept, without the holes.
+    len: usize,
+    /// Sequence number of the next ping.
+    next: u64,
+    /// Sequence numbers of the pings kept of each tenant, the oldest first, with the count
+    /// eviction.
+    tenants: HashMap<Tenant, VecDeque<u64>>,
+}
+
+impl Records {
+    fn iter(&self) -> impl DoubleEndedIterator<Item = &PingRecord> {
+        self.records
+            .iter()
+            .filter_map(|(_, record)| record.as_ref())
+    }
+
+    /// Appends the ping, tracking it by tenant if asked to.
+    fn push(&mut self, record: PingRecord, by_tenant: bool) {
+        let seq = self.next;
+        self.next += 1;
+
+        if by_tenant {
+            self.tenants
+                .entry(record.tenant.clone())
+                .or_default()
+                .push_back(seq);
+        }
+
+        self.records.push_back((seq, Some(record)));
+        self.len += 1;
+    }
+
+    /// Pings kept of the tenant, with the count eviction.
+    fn kept(&self, tenant: &Tenant) -> usize {
+        self.tenants.get(tenant).map_or(0, VecDeque::len)
+    }
+
+    /// Removes the oldest ping if it was received before the time, or in any case without one.
+    fn pop_front(&mut self, before: Option<SystemTime>) -> bool {
+        self.drop_holes();
+
+        let expired = match self.records.front() {
+            Some((_, Some(oldest))) => before.is_none_or(|before| oldest.received_at < before),
+            _ => false,
+        };
+
+        if !expired {
+            return false;
+        }
+
+        let Some((_, Some(oldest))) = self.records.pop_front() else {
+            return false;
+        };
+        self.len -= 1;
+
+        // The oldest ping is also the oldest of its tenant
+        if let Some(seqs) = self.tenants.get_mut(&oldest.tenant) {
+            seqs.pop_front();
+
+            if seqs.is_empty() {
+                self.tenants.remove(&oldest.tenant);
+            }
+        }
+
+        true
+    }
+
+    /// Evicts the oldest ping of the tenant, leaving a hole in its place.
+    fn evict_oldest_of(&mut self, tenant: &Tenant) -> bool {
+        let Some(seq) = self.tenants.get_mut(tenant).and_then(VecDeque::pop_front) else {
+            return false;
+        };
+
+        let index = self.records.partition_point(|(other, _)| *other < seq);
+        if let Some((_, record)) = self.records.get_mut(index) {
+            *record = None;
+            self.len -= 1;
+        }
+
+        self.drop_holes();
+
+        if self.records.len() > 2 * self.len {
+            self.records.retain(|(_, record)| record.is_some());
+        }
+
+        true
+    }
+
+    fn drop_holes(&mut self) {
+        while let Some((_, None)) = self.records.front() {
+            self.records.pop_front();
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct History {
+    eviction: Eviction,
+    size: usize,
+    window: Duration,
+    records: Mutex<Records>,
+    evicted: AtomicU64,
+}
+
+impl History {
+    pub fn new(args: &HistoryArgs) -> Self {
+        let size = match args.history_eviction {
+            Eviction::Ring | Eviction::Window => args.history_size.get().min(MAX_RECORDS),
+            Eviction::Count => args.history_size.get(),
+        };
+
+        Self {
+            eviction: args.history_eviction,
+            size,
+            window: args.history_window,
+            records: Mutex::default(),
+            evicted: AtomicU64::new(0),
+        }
+    }
+
+    fn evicted(&self, reason: Reason, pings: usize) {
+        if pings == 0 {
+            return;
+        }
+
+        self.evicted.fetch_add(pings as u64, Ordering::Relaxed);
+
+        metrics::counter!("receiver_history_evicted_total", "reason" => reason.as_str())
+            .increment(pings as u64);
+    }
+
+    /// The records, without the ones older than the window.
+    fn records(&self) -> MutexGuard<'_, Records> {
+        let mut records = self.records.lock().unwrap_or_else(|err| err.into_inner());
+
+        if self.eviction == Eviction::Window {
+            let since = SystemTime::now() - self.window;
+
+            let mut old = 0;
+            while records.pop_front(Some(since)) {
+                old += 1;
+            }
+
+            self.evicted(Reason::Age, old);
+        }
+
+        records
+    }
+
+    pub fn record(&self, record: PingRecord) {
+        let mut records = self.records();
+        let records = &mut *records;
+
+        let by_tenant = self.eviction == Eviction::Count;
+
+        if by_tenant
+            && records.kept(&record.tenant) >= self.size
+            && records.evict_oldest_of(&record.tenant)
+        {
+            self.evicted(Reason::Tenant, 1);
+        }
+
+        let max = match self.eviction {
+            Eviction::Ring | Eviction::Window => self.size,
+            Eviction::Count => MAX_RECORDS,
+        };
+
+        if records.len >= max && records.pop_front(None) {
+            self.evicted(Reason::Size, 1);
+        }
+
+        records.push(record, by_tenant);
+    }
+
+    /// Pings evicted since the startup.
+    pub fn evicted_total(&self) -> u64 {
+        self.evicted.load(Ordering::Relaxed)
+    }
+
+    pub fn len(&self) -> usize {
+        self.records().len
+    }
+
+    /// Pings per second received over the last window, of the tenant if given, as far as the
+    /// history goes back.
+    pub fn rate(&self, window: Duration, tenant: Option<&Tenant>) -> f64 {
+        let since = SystemTime::now() - window;
+        let pings = self
+            .records()
+            .iter()
+            .rev()
+            .take_while(|record| record.received_at >= since)
+            .filter(|record| tenant.is_none_or(|tenant| record.tenant == *tenant))
+            .count();
+
+        pings as f64 / window.as_secs_f64()
+    }
+
+    /// Calls the function with the tenant and the time of the pings received in the range.
+    pub fn received_between(
+        &self,
+        from: SystemTime,
+        to: SystemTime,
+        mut f: impl FnMut(&Tenant, SystemTime),
+    ) {
+        self.records()
+            .iter()
+            .filter(|record| from <= record.received_at && record.received_at < to)
+            .for_each(|record| f(&record.tenant, record.received_at));
+    }
+
+    /// Returns a page of the pings, of the tenant if given, the latest first.
+    pub fn page(&self, tenant: Option<&Tenant>, page: usize, per_page: usize) -> Vec<PingRecord> {
+        self.records()
+            .iter()
+            .rev()
+            .filter(|record| tenant.is_none_or(|tenant| record.tenant == *tenant))
+            .skip(page.saturating_mul(per_page))
+            .take(per_page)
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history(eviction: Eviction, size: usize) -> History {
+        History::new(&HistoryArgs {
+            history_eviction: eviction,
+            history_size: NonZeroUsize::new(size).unwrap(),
+            history_window: Duration::from_secs(3600),
+        })
+    }
+
+    fn ping(tenant: &str, received_at: SystemTime) -> PingRecord {
+        PingRecord {
+            id: Uuid::new_v4(),
+            tenant: Tenant::parse(tenant).unwrap(),
+            count: 1,
+            received_at,
+            metadata: Metadata::default(),
+            location: Location::default(),
+        }
+    }
+
+    fn ids(records: &[PingRecord]) -> Vec<Uuid> {
+        records.iter().map(|record| record.id).collect()
+    }
+
+    #[test]
+    fn ring_keeps_the_latest_pings() {
+        let history = history(Eviction::Ring, 2);
+        let pings: Vec<_> = (0..3).map(|_| ping("a", SystemTime::now())).collect();
+
+        for ping in &pings {
+            history.record(ping.clone());
+        }
+
+        assert_eq!(history.len(), 2);
+        assert_eq!(history.evicted_total(), 1);
+        assert_eq!(
+            ids(&history.page(None, 0, 10)),
+            ids(&[pings[2].clone(), pings[1].clone()])
+        );
+    }
+
+    #[test]
+    fn window_evicts_the_old_pings() {
+        let history = history(Eviction::Window, 10);
+        let old = ping("a", SystemTime::now() - Duration::from_secs(7200));
+        let recent = ping("a", SystemTime::now());
+
+        history.record(old);
+        history.record(recent.clone());
+
+        assert_eq!(history.len(), 1);
+        assert_eq!(history.evicted_total(), 1);
+        assert_eq!(ids(&history.page(None, 0, 10)), ids(&[recent]));
+    }
+
+    #[test]
+    fn count_keeps_the_latest_pings_of_each_tenant() {
+        let history = history(Eviction::Count, 2);
+        let other = ping("b", SystemTime::now());
+        let pings: Vec<_> = (0..100).map(|_| ping("a", SystemTime::now())).collect();
+
+        history.record(other.clone());
+        for ping in &pings {
+            history.record(ping.clone());
+        }
+
+        assert_eq!(history.len(), 3);
+        assert_eq!(history.evicted_total(), 98);
+        assert_eq!(
+            ids(&history.page(None, 0, 10)),
+            ids(&[pings[99].clone(), pings[98].clone(), other])
+        );
+        assert_eq!(
+            ids(&history.page(Tenant::parse("a").as_ref(), 0, 10)),
+            ids(&[pings[99].clone(), pings[98].clone()])
+        );
+
+        // The holes behind the ping of the other tenant are compacted
+        let records = history.records();
+        assert!(records.records.len() <= 2 * records.len);
+    }
+}
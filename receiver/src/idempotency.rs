@@ -0,0 +1,325 @@
+//! Idempotent pings, with the `Idempotency-Key` header.
+//!
+//! The response of a ping counted with a key is kept for a window, and returned again to the
+//! requests with the same key instead of counting the ping twice, so the senders can retry
+//! safely. The keys are scoped by tenant and API key, and only the most recently used are kept.
+//! A failed ping isn't kept, so it can be retried.
+
+use std::{
+    num::NonZeroUsize,
+    sync::{Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{request::Parts, HeaderMap, HeaderName, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use lru::LruCache;
+use uuid::Uuid;
+
+use crate::{tenant::Tenant, AppError};
+
+/// Number of keys kept, the least recently used are dropped first.
+const KEPT: NonZeroUsize = NonZeroUsize::new(100_000).unwrap();
+
+/// Longest key accepted.
+const MAX_KEY_LEN: usize = 255;
+
+static IDEMPOTENCY_KEY: HeaderName = HeaderName::from_static("idempotency-key");
+static IDEMPOTENT_REPLAYED: HeaderName = HeaderName::from_static("idempotent-replayed");
+
+/// Key of the request, from the `Idempotency-Key` header.
+#[derive(Debug, Clone)]
+pub struct IdempotencyKey(pub Option<String>);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for IdempotencyKey
+where
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Some(key) = parts.headers.get(&IDEMPOTENCY_KEY) else {
+            return Ok(Self(None));
+        };
+
+        let key = key
+            .to_str()
+            .ok()
+            .filter(|key| !key.is_empty() && key.len() <= MAX_KEY_LEN)
+            .ok_or_else(|| {
+                AppError::BadRequest(format!(
+                    "the idempotency key must be visible ASCII of at most {MAX_KEY_LEN} characters"
+                ))
+            })?;
+
+        Ok(Self(Some(key.to_string())))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Scope {
+    tenant: Tenant,
+    api_key: Option<String>,
+    key: String,
+}
+
+#[derive(Debug, Clone)]
+struct Stored {
+    status: StatusCode,
+    headers: HeaderMap,
+    expires: Instant,
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    /// Id of the ping sent with the key.
+    ping: Uuid,
+    /// Response, missing while the ping is counted.
+    response: Option<Stored>,
+}
+
+#[derive(Debug)]
+pub struct Idempotency {
+    window: Duration,
+    entries: Mutex<LruCache<Scope, Entry>>,
+}
+
+/// What to do with a request with a key.
+#[derive(Debug)]
+pub enum Claim<'a> {
+    /// The ping wasn't counted yet, the response is kept once completed.
+    New(Pending<'a>),
+    /// Response of the ping already counted.
+    Replay(Response),
+}
+
+/// Ping counted with a key, forgotten if dropped before completing it.
+#[derive(Debug)]
+pub struct Pending<'a> {
+    idempotency: &'a Idempotency,
+    scope: Option<Scope>,
+}
+
+impl Idempotency {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            entries: Mutex::new(LruCache::new(KEPT)),
+        }
+    }
+
+    fn entries(&self) -> MutexGuard<'_, LruCache<Scope, Entry>> {
+        self.entries.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Claims the key for the ping, or returns the response it got.
+    pub fn claim(
+        &self,
+        tenant: &Tenant,
+        api_key: Option<&str>,
+        key: String,
+        ping: Uuid,
+    ) -> Result<Claim<'_>, AppError> {
+        let scope = Scope {
+            tenant: tenant.clone(),
+            api_key: api_key.map(str::to_string),
+            key,
+        };
+        let mut entries = self.entries();
+
+        // The expired entries are as if missing
+        let entry = entries.get(&scope).filter(|entry| {
+            entry
+                .response
+                .as_ref()
+                .is_none_or(|stored| stored.expires > Instant::now())
+        });
+
+        if let Some(entry) = entry {
+            if entry.ping != ping {
+                return Err(AppError::Conflict(
+                    "the idempotency key was used for another ping".to_string(),
+                ));
+            }
+
+            let Some(stored) = &entry.response else {
+                return Err(AppError::Conflict(
+                    "a ping with the idempotency key is being counted".to_string(),
+                ));
+            };
+
+            let mut headers = stored.headers.clone();
+            headers.insert(
+                IDEMPOTENT_REPLAYED.clone(),
+                HeaderValue::from_static("true"),
+            );
+
+            return Ok(Claim::Replay((stored.status, headers).into_response()));
+        }
+
+        entries.put(
+            scope.clone(),
+            Entry {
+                ping,
+                response: None,
+            },
+        );
+
+        Ok(Claim::New(Pending {
+            idempotency: self,
+            scope: Some(scope),
+        }))
+    }
+}
+
+impl Pending<'_> {
+    /// Keeps the response for the window, if the ping succeeded.
+    pub fn complete(mut self, res: &Response) {
+        let Some(scope) = self.scope.take() else {
+            return;
+        };
+
+        let mut entries = self.idempotency.entries();
+
+        if !res.status().is_success() {
+            entries.pop(&scope);
+
+            return;
+        }
+
+        if let Some(entry) = entries.get_mut(&scope) {
+            entry.response = Some(Stored {
+                status: res.status(),
+                headers: res.headers().clone(),
+                expires: Instant::now() + self.idempotency.window,
+            });
+        }
+    }
+}
+
+impl Drop for Pending<'_> {
+    fn drop(&mut self) {
+        if let Some(scope) = self.scope.take() {
+            self.idempotency.entries().pop(&scope);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::header::CONTENT_TYPE;
+
+    use super::*;
+
+    const KEY: &str = "key-1";
+
+    fn response(status: StatusCode) -> Response {
+        (status, [(CONTENT_TYPE, "application/json")]).into_response()
+    }
+
+    fn claim<'a>(
+        idempotency: &'a Idempotency,
+        api_key: Option<&str>,
+        ping: Uuid,
+    ) -> Result<Claim<'a>, AppError> {
+        idempotency.claim(&Tenant::default(), api_key, KEY.to_string(), ping)
+    }
+
+    fn complete(claim: Result<Claim<'_>, AppError>, status: StatusCode) {
+        let Ok(Claim::New(pending)) = claim else {
+            panic!("the key was already claimed");
+        };
+
+        pending.complete(&response(status));
+    }
+
+    #[test]
+    fn completed_pings_replayed() {
+        let idempotency = Idempotency::new(Duration::from_secs(60));
+        let ping = Uuid::new_v4();
+
+        complete(claim(&idempotency, None, ping), StatusCode::CREATED);
+
+        let Ok(Claim::Replay(res)) = claim(&idempotency, None, ping) else {
+            panic!("the ping wasn't replayed");
+        };
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert_eq!(res.headers()[CONTENT_TYPE], "application/json");
+        assert_eq!(res.headers()[&IDEMPOTENT_REPLAYED], "true");
+    }
+
+    #[test]
+    fn key_of_another_ping_refused() {
+        let idempotency = Idempotency::new(Duration::from_secs(60));
+
+        complete(claim(&idempotency, None, Uuid::new_v4()), StatusCode::OK);
+
+        let res = claim(&idempotency, None, Uuid::new_v4());
+        assert!(matches!(res, Err(AppError::Conflict(_))), "{res:?}");
+    }
+
+    #[test]
+    fn pings_being_counted_refused() {
+        let idempotency = Idempotency::new(Duration::from_secs(60));
+        let ping = Uuid::new_v4();
+
+        let _pending = claim(&idempotency, None, ping).unwrap();
+
+        let res = claim(&idempotency, None, ping);
+        assert!(matches!(res, Err(AppError::Conflict(_))), "{res:?}");
+    }
+
+    #[test]
+    fn failed_pings_can_be_retried() {
+        let idempotency = Idempotency::new(Duration::from_secs(60));
+        let ping = Uuid::new_v4();
+
+        complete(
+            claim(&idempotency, None, ping),
+            StatusCode::SERVICE_UNAVAILABLE,
+        );
+        // Dropped before completing, like a cancelled request
+        drop(claim(&idempotency, None, ping).unwrap());
+
+        complete(claim(&idempotency, None, ping), StatusCode::OK);
+    }
+
+    #[test]
+    fn expired_responses_not_replayed() {
+        let idempotency = Idempotency::new(Duration::ZERO);
+        let ping = Uuid::new_v4();
+
+        complete(claim(&idempotency, None, ping), StatusCode::OK);
+
+        complete(claim(&idempotency, None, Uuid::new_v4()), StatusCode::OK);
+    }
+
+    #[test]
+    fn keys_scoped_by_api_key_and_tenant() {
+        let idempotency = Idempotency::new(Duration::from_secs(60));
+
+        complete(
+            claim(&idempotency, Some("a"), Uuid::new_v4()),
+            StatusCode::OK,
+        );
+
+        complete(
+            claim(&idempotency, Some("b"), Uuid::new_v4()),
+            StatusCode::OK,
+        );
+        complete(
+            idempotency.claim(
+                &Tenant::parse("other").unwrap(),
+                Some("a"),
+                KEY.to_string(),
+                Uuid::new_v4(),
+            ),
+            StatusCode::OK,
+        );
+    }
+}
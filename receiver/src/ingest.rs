@@ -0,0 +1,240 @@
+//! Queue of the pings accepted and waiting to be counted.
+//!
+//! The handlers of all the transports push the pings, and the pongs taking them back, into a
+//! bounded queue, emptied by a pool of worker threads applying the changes and writing them to
+//! the WAL and the audit log, so the disk isn't waited on from the runtime. Once the queue is
+//! full the pings are shed with a `503 Service Unavailable`, instead of piling up.
+
+use std::{
+    sync::{mpsc, Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use eyre::WrapErr;
+use protocol::Ping;
+use tokio::{runtime::Handle, sync::oneshot};
+use tracing::{error, info};
+
+use crate::{audit::Source, tenant::Tenant, timing, AppError, AppState};
+
+/// Time the clients are asked to wait when the pings are shed.
+pub const SHED_RETRY_AFTER: Duration = Duration::from_secs(1);
+
+/// Change of the count requested.
+#[derive(Debug, Clone, Copy)]
+enum Change {
+    /// Counts the ping.
+    Ping,
+    /// Takes back a ping.
+    Pong,
+}
+
+#[derive(Debug)]
+struct Job {
+    change: Change,
+    source: Source,
+    tenant: Tenant,
+    ping: Ping,
+    reply: oneshot::Sender<(Result<u64, AppError>, Duration)>,
+}
+
+#[derive(Debug)]
+pub struct Ingest {
+    jobs: mpsc::SyncSender<Job>,
+    /// Taken by [`start`] to hand the queue to the workers.
+    queue: Mutex<Option<mpsc::Receiver<Job>>>,
+    workers: usize,
+}
+
+impl Ingest {
+    pub fn new(capacity: usize, workers: usize) -> Self {
+        let (jobs, queue) = mpsc::sync_channel(capacity);
+
+        metrics::gauge!("receiver_ingest_queued").set(0.0);
+
+        Self {
+            jobs,
+            queue: Mutex::new(Some(queue)),
+            workers,
+        }
+    }
+}
+
+/// Starts the workers counting the queued pings.
+pub fn start(state: &AppState) -> eyre::Result<()> {
+    let Some(queue) = state
+        .ingest
+        .queue
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .take()
+    else {
+        return Ok(());
+    };
+    let queue = Arc::new(Mutex::new(queue));
+    let runtime = Handle::current();
+
+    for n in 0..state.ingest.workers {
+        // Holding the state would keep the queue open forever
+        let state = state.downgrade();
+        let queue = Arc::clone(&queue);
+        let runtime = runtime.clone();
+
+        std::thread::Builder::new()
+            .name(format!("ingest-{n}"))
+            .spawn(move || {
+                // The pongs and the notifications are spawned on the runtime
+                let _runtime = runtime.enter();
+
+                work(&state, &queue);
+            })
+            .wrap_err("couldn't start the ingestion worker")?;
+    }
+
+    Ok(())
+}
+
+fn work(state: &crate::WeakAppState, queue: &Mutex<mpsc::Receiver<Job>>) {
+    loop {
+        let job = queue.lock().unwrap_or_else(|err| err.into_inner()).recv();
+        let Ok(job) = job else {
+            break;
+        };
+
+        metrics::gauge!("receiver_ingest_queued").decrement(1.0);
+
+        let Some(state) = state.upgrade() else {
+            break;
+        };
+
+        let start = Instant::now();
+        let res = match job.change {
+            Change::Ping => crate::count_ping(&state, job.source, job.tenant, job.ping),
+            Change::Pong => crate::count_pong(&state, job.source, job.tenant, job.ping),
+        };
+
+        // The client may be gone
+        let _ = job.reply.send((res, start.elapsed()));
+    }
+}
+
+/// Fails if the receiver is under maintenance or read-only, logging the ping that isn't counted.
+pub fn writable(
+    state: &AppState,
+    source: Source,
+    tenant: &Tenant,
+    ping: &Ping,
+) -> Result<(), AppError> {
+    if let Some(maintenance) = state.maintenance.current() {
+        return Err(AppError::Maintenance(maintenance.retry_after));
+    }
+
+    if !state.read_only {
+        return Ok(());
+    }
+
+    info!(
+        id = %ping.id,
+        %tenant,
+        transport = source.transport.as_str(),
+        "ping not counted, read-only"
+    );
+
+    metrics::counter!("receiver_pings_read_only_total").increment(1);
+
+    Err(AppError::ReadOnly)
+}
+
+/// Queues the ping, waiting for it to be counted.
+pub async fn count(
+    state: &AppState,
+    source: Source,
+    tenant: Tenant,
+    ping: Ping,
+) -> Result<u64, AppError> {
+    queue(state, Change::Ping, source, tenant, ping).await
+}
+
+/// Queues the pong, waiting for it to take back a ping.
+pub async fn take_back(
+    state: &AppState,
+    source: Source,
+    tenant: Tenant,
+    pong: Ping,
+) -> Result<u64, AppError> {
+    queue(state, Change::Pong, source, tenant, pong).await
+}
+
+async fn queue(
+    state: &AppState,
+    change: Change,
+    source: Source,
+    tenant: Tenant,
+    ping: Ping,
+) -> Result<u64, AppError> {
+    let state = state.clone();
+
+    // Spawned, so a request cancelled once the ping is queued still keeps its id as received
+    let queued = tokio::spawn(async move { enqueue(&state, change, source, tenant, ping).await });
+
+    queued.await.map_err(|err| AppError::Internal(err.into()))?
+}
+
+async fn enqueue(
+    state: &AppState,
+    change: Change,
+    source: Source,
+    tenant: Tenant,
+    ping: Ping,
+) -> Result<u64, AppError> {
+    writable(state, source, &tenant, &ping)?;
+
+    let fresh = state
+        .freshness
+        .as_ref()
+        .map(|freshness| freshness.claim(&ping))
+        .transpose()?;
+
+    // The recordings are replayed as pings
+    if let (Change::Ping, Some(recorder)) = (change, &state.recorder) {
+        recorder.record(&tenant, &ping);
+    }
+
+    let (reply, counted) = oneshot::channel();
+    let job = Job {
+        change,
+        source,
+        tenant,
+        ping,
+        reply,
+    };
+
+    match state.ingest.jobs.try_send(job) {
+        Ok(()) => {
+            metrics::gauge!("receiver_ingest_queued").increment(1.0);
+        }
+        Err(mpsc::TrySendError::Full(_)) => {
+            metrics::counter!("receiver_ingest_shed_total").increment(1);
+
+            return Err(AppError::Overloaded(SHED_RETRY_AFTER));
+        }
+        Err(mpsc::TrySendError::Disconnected(_)) => {
+            error!("ingestion workers stopped");
+
+            return Err(AppError::Overloaded(SHED_RETRY_AFTER));
+        }
+    }
+
+    let (res, elapsed) = counted.await.map_err(|_| {
+        AppError::Internal(eyre::eyre!("the ping was dropped by the ingestion workers"))
+    })?;
+
+    timing::stored(elapsed);
+
+    // Forgotten if not counted, for the sender to retry it
+    if let (Ok(_), Some(fresh)) = (&res, fresh) {
+        fresh.keep();
+    }
+
+    res
+}
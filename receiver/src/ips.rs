@@ -0,0 +1,214 @@
+//! Statistics of the pings by source address.
+//!
+//! Only the most recently seen addresses are tracked, so a flood of spoofed sources can't grow
+//! the memory without bounds. The rate of each address is estimated over a sliding window of a
+//! second, from the count of the current window and the one before it, and is also what the
+//! per address rate limit is checked against. The addresses are listed only to the
+//! administrators, from `/v1/stats/ips` with the admin token.
+
+use std::{
+    net::IpAddr,
+    num::NonZeroUsize,
+    sync::{Mutex, MutexGuard},
+    time::{Duration, Instant, SystemTime},
+};
+
+use axum::{
+    extract::{Query, State},
+    Json,
+};
+use lru::LruCache;
+use serde::{Deserialize, Serialize};
+
+use crate::AppState;
+
+/// Number of addresses tracked, the least recently seen are dropped first.
+const TRACKED: NonZeroUsize = NonZeroUsize::new(10_000).unwrap();
+
+const WINDOW: Duration = Duration::from_secs(1);
+
+/// Number of addresses listed by default.
+const DEFAULT_TOP: usize = 10;
+
+#[derive(Debug)]
+struct Entry {
+    pings: u64,
+    rejected: u64,
+    first_seen: SystemTime,
+    last_seen: SystemTime,
+    window: Instant,
+    current: u64,
+    previous: u64,
+}
+
+impl Entry {
+    fn new(now: Instant) -> Self {
+        Self {
+            pings: 0,
+            rejected: 0,
+            first_seen: SystemTime::now(),
+            last_seen: SystemTime::now(),
+            window: now,
+            current: 0,
+            previous: 0,
+        }
+    }
+
+    /// Moves the window forward to the current time.
+    fn advance(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.window);
+        if elapsed < WINDOW {
+            return;
+        }
+
+        if elapsed < WINDOW * 2 {
+            self.previous = self.current;
+            self.window += WINDOW;
+        } else {
+            // No pings in the previous window either
+            self.previous = 0;
+            self.window = now;
+        }
+        self.current = 0;
+    }
+
+    /// Pings per second over the last window.
+    fn rate(&self, now: Instant) -> f64 {
+        let elapsed = now.duration_since(self.window).as_secs_f64() / WINDOW.as_secs_f64();
+
+        self.previous as f64 * (1.0 - elapsed.min(1.0)) + self.current as f64
+    }
+
+    /// Time until the rate drops below the limit.
+    fn retry_after(&self, now: Instant, limit: u32) -> Duration {
+        let limit = f64::from(limit);
+        let elapsed = now.duration_since(self.window).as_secs_f64() / WINDOW.as_secs_f64();
+
+        let windows = if self.current as f64 >= limit {
+            // Until the current window is the previous one and fades below the limit
+            (1.0 - elapsed) + (1.0 - limit / self.current as f64)
+        } else {
+            let fade = 1.0 - (limit - self.current as f64) / self.previous as f64;
+
+            (fade - elapsed).max(0.0)
+        };
+
+        WINDOW.mul_f64(windows)
+    }
+}
+
+/// The address sent more pings than the rate limit allows.
+#[derive(Debug)]
+pub struct RateLimited {
+    /// Pings per second allowed.
+    pub limit: u32,
+    pub retry_after: Duration,
+}
+
+#[derive(Debug)]
+pub struct IpStats {
+    limit: Option<u32>,
+    entries: Mutex<LruCache<IpAddr, Entry>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct IpSnapshot {
+    ip: IpAddr,
+    pings: u64,
+    /// Pings over the rate limit.
+    rejected: u64,
+    /// Pings per second in the last second.
+    rate: f64,
+    first_seen: String,
+    last_seen: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct IpStatsSnapshot {
+    /// Addresses currently tracked.
+    tracked: usize,
+    rate_limit: Option<u32>,
+    /// The addresses with the most pings first.
+    top: Vec<IpSnapshot>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TopQuery {
+    top: Option<usize>,
+}
+
+impl IpStats {
+    pub fn new(limit: Option<u32>) -> Self {
+        Self {
+            limit,
+            entries: Mutex::new(LruCache::new(TRACKED)),
+        }
+    }
+
+    fn entries(&self) -> MutexGuard<'_, LruCache<IpAddr, Entry>> {
+        self.entries.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Counts a ping from the address, unless it's over the rate limit.
+    pub fn hit(&self, ip: IpAddr) -> Result<(), RateLimited> {
+        let now = Instant::now();
+        let mut entries = self.entries();
+        let entry = entries.get_or_insert_mut(ip, || Entry::new(now));
+
+        entry.advance(now);
+        entry.last_seen = SystemTime::now();
+
+        if let Some(limit) = self.limit {
+            if entry.rate(now) >= f64::from(limit) {
+                entry.rejected += 1;
+
+                return Err(RateLimited {
+                    limit,
+                    retry_after: entry.retry_after(now, limit),
+                });
+            }
+        }
+
+        entry.pings += 1;
+        entry.current += 1;
+
+        Ok(())
+    }
+
+    fn snapshot(&self, top: usize) -> IpStatsSnapshot {
+        let now = Instant::now();
+        let mut entries = self.entries();
+
+        let mut list: Vec<IpSnapshot> = entries
+            .iter_mut()
+            .map(|(ip, entry)| {
+                entry.advance(now);
+
+                IpSnapshot {
+                    ip: *ip,
+                    pings: entry.pings,
+                    rejected: entry.rejected,
+                    rate: entry.rate(now),
+                    first_seen: humantime::format_rfc3339_seconds(entry.first_seen).to_string(),
+                    last_seen: humantime::format_rfc3339_seconds(entry.last_seen).to_string(),
+                }
+            })
+            .collect();
+
+        list.sort_unstable_by(|a, b| b.pings.cmp(&a.pings).then(a.ip.cmp(&b.ip)));
+        list.truncate(top);
+
+        IpStatsSnapshot {
+            tracked: entries.len(),
+            rate_limit: self.limit,
+            top: list,
+        }
+    }
+}
+
+pub async fn stats(
+    State(state): State<AppState>,
+    Query(query): Query<TopQuery>,
+) -> Json<IpStatsSnapshot> {
+    Json(state.ips.snapshot(query.top.unwrap_or(DEFAULT_TOP)))
+}
@@ -0,0 +1,104 @@
+//! JSON Web Tokens the HTTP pings must carry, issued by an existing identity provider.
+//!
+//! With `--jwt-secret` the HS256 tokens are verified with the shared secret, with `--jwt-jwks`
+//! the RS256 ones with the public keys of the provider, fetched at the start and again when a
+//! token is signed by an unknown key, at most once a minute. The tokens are sent as bearers and
+//! must not be expired, and carry the `--jwt-audience` and `--jwt-issuer` if configured. Their
+//! `tenant` claim binds them to a tenant, the default one without it.
+
+use std::{sync::Arc, time::Duration};
+
+use axum::{async_trait, extract::FromRequestParts, http::request::Parts};
+use clap::Args;
+use serde::Serialize;
+use server::jwt::{Claims, Jwks, Jwt};
+use url::Url;
+
+use crate::{admin, auth, AppError, AppState};
+
+#[derive(Debug, Clone, Args, Serialize)]
+pub struct JwtArgs {
+    /// Secret the HS256 tokens the HTTP pings must carry are verified with
+    #[arg(
+        long,
+        env = "JWT_SECRET",
+        hide_env_values = true,
+        conflicts_with = "api_keys"
+    )]
+    #[serde(skip)]
+    jwt_secret: Option<String>,
+    /// Url of the JWKS the RS256 tokens the HTTP pings must carry are verified with
+    #[arg(long, value_name = "URL", conflicts_with = "api_keys")]
+    jwt_jwks: Option<Url>,
+    /// Audience the tokens must be issued for
+    #[arg(long)]
+    jwt_audience: Option<String>,
+    /// Issuer the tokens must be issued by
+    #[arg(long)]
+    jwt_issuer: Option<String>,
+    /// Difference tolerated between the clocks of the issuer and of the receiver
+    #[arg(long, default_value = "1m", value_parser = humantime::parse_duration)]
+    #[serde(serialize_with = "admin::humantime")]
+    jwt_leeway: Duration,
+}
+
+/// Verifies the tokens of the HTTP pings, if enabled.
+pub async fn load(args: &JwtArgs, client: &reqwest::Client) -> eyre::Result<Option<Jwt>> {
+    if args.jwt_secret.is_none() && args.jwt_jwks.is_none() {
+        return Ok(None);
+    }
+
+    let jwks = match &args.jwt_jwks {
+        Some(url) => Some(Jwks::load(url, client).await?),
+        None => None,
+    };
+
+    Ok(Some(Jwt::new(
+        args.jwt_secret.as_deref(),
+        jwks,
+        args.jwt_audience.clone(),
+        args.jwt_issuer.clone(),
+        args.jwt_leeway,
+    )))
+}
+
+/// Outcome of the verification of the token of a request, kept so it's verified once.
+#[derive(Debug, Clone)]
+struct Verified(Result<Arc<Claims>, String>);
+
+/// Claims of the bearer token of the request, verified the first time they are needed.
+pub async fn claims(parts: &mut Parts, jwt: &Jwt) -> Result<Arc<Claims>, AppError> {
+    if let Some(Verified(res)) = parts.extensions.get::<Verified>() {
+        return res.clone().map_err(AppError::Unauthorized);
+    }
+
+    let res = match auth::bearer(&parts.headers) {
+        Some(token) => jwt.verify(token).await.map(Arc::new).map_err(|err| err.0),
+        None => Err("missing token".to_string()),
+    };
+    parts.extensions.insert(Verified(res.clone()));
+
+    res.map_err(AppError::Unauthorized)
+}
+
+/// Subject of the token of the request, [`None`] if the tokens aren't enabled or without one.
+#[derive(Debug, Clone)]
+pub struct Subject(pub Option<String>);
+
+#[async_trait]
+impl FromRequestParts<AppState> for Subject {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let Some(jwt) = &state.jwt else {
+            return Ok(Self(None));
+        };
+
+        let claims = claims(parts, jwt).await?;
+
+        Ok(Self(claims.sub.clone()))
+    }
+}
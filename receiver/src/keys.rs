@@ -0,0 +1,596 @@
+//! API keys of the senders, with hourly and daily quotas of pings.
+//!
+//! The keys are read from a JSON file, a list of objects with the `name` of the key, the `key`
+//! itself, the `tenant` it counts and reads the pings of, the default one if missing, and the
+//! optional `hourly` and `daily` quotas. With the keys enabled the HTTP pings must carry one, as a
+//! bearer or in the `X-Api-Key` header. The UDP pings can't, so `--udp` is refused with the keys.
+//!
+//! The quotas are counted over fixed windows starting at the hour and at the midnight UTC. The
+//! responses to the pings report the window closest to exhaust in the `X-Quota-*` headers.
+
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    path::Path,
+    sync::{Mutex, MutexGuard},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Path as UrlPath, State},
+    http::{request::Parts, HeaderName, HeaderValue, StatusCode},
+    response::{IntoResponseParts, ResponseParts},
+    Json,
+};
+use eyre::WrapErr;
+use serde::{Deserialize, Serialize};
+
+use crate::{auth, tenant::Tenant, AppError, AppState};
+
+pub const QUOTA_LIMIT: HeaderName = HeaderName::from_static("x-quota-limit");
+pub const QUOTA_REMAINING: HeaderName = HeaderName::from_static("x-quota-remaining");
+/// Seconds until the window resets.
+pub const QUOTA_RESET: HeaderName = HeaderName::from_static("x-quota-reset");
+
+const HOUR: Duration = Duration::from_secs(60 * 60);
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Debug, Deserialize)]
+pub struct KeyConfig {
+    name: String,
+    key: String,
+    tenant: Option<String>,
+    hourly: Option<u64>,
+    daily: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Period {
+    Hourly,
+    Daily,
+}
+
+impl Display for Period {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Period::Hourly => write!(f, "hourly"),
+            Period::Daily => write!(f, "daily"),
+        }
+    }
+}
+
+impl Period {
+    fn duration(self) -> Duration {
+        match self {
+            Period::Hourly => HOUR,
+            Period::Daily => DAY,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Quota {
+    period: Period,
+    limit: u64,
+    remaining: u64,
+    /// Index of the current window since the epoch.
+    window: u64,
+}
+
+/// State of a quota in its current window.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct QuotaStatus {
+    pub period: Period,
+    pub limit: u64,
+    pub remaining: u64,
+    /// Seconds until the window resets.
+    pub reset: u64,
+}
+
+impl Quota {
+    fn new(period: Period, limit: u64) -> Self {
+        Self {
+            period,
+            limit,
+            remaining: limit,
+            window: 0,
+        }
+    }
+
+    /// Starts a new window if the current one elapsed.
+    fn refresh(&mut self, now: Duration) {
+        let window = now.as_secs() / self.period.duration().as_secs();
+
+        if window != self.window {
+            self.window = window;
+            self.remaining = self.limit;
+        }
+    }
+
+    fn status(&self, now: Duration) -> QuotaStatus {
+        let period = self.period.duration().as_secs();
+
+        QuotaStatus {
+            period: self.period,
+            limit: self.limit,
+            remaining: self.remaining,
+            reset: (self.window + 1) * period - now.as_secs(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Key {
+    name: String,
+    token: String,
+    /// Tenant the key is bound to.
+    tenant: Tenant,
+    quotas: Vec<Quota>,
+}
+
+impl Key {
+    fn new(config: KeyConfig) -> Result<Self, AppError> {
+        let tenant = match &config.tenant {
+            Some(tenant) => Tenant::parse(tenant).ok_or_else(|| {
+                AppError::BadRequest(format!("invalid tenant of the API key {}", config.name))
+            })?,
+            None => Tenant::default(),
+        };
+
+        let quotas = [
+            config.hourly.map(|limit| Quota::new(Period::Hourly, limit)),
+            config.daily.map(|limit| Quota::new(Period::Daily, limit)),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+
+        Ok(Self {
+            name: config.name,
+            token: config.key,
+            tenant,
+            quotas,
+        })
+    }
+}
+
+/// The key used up one of its quotas.
+#[derive(Debug)]
+pub struct KeyQuotaExceeded {
+    pub name: String,
+    pub quota: QuotaStatus,
+}
+
+#[derive(Debug, Serialize)]
+pub struct KeyStatus {
+    name: String,
+    tenant: Tenant,
+    quotas: Vec<QuotaStatus>,
+}
+
+/// Remaining pings to set in the current windows.
+#[derive(Debug, Deserialize)]
+pub struct QuotaUpdate {
+    hourly: Option<u64>,
+    daily: Option<u64>,
+}
+
+#[derive(Debug)]
+pub struct ApiKeys {
+    /// Keys by name.
+    keys: Mutex<HashMap<String, Key>>,
+}
+
+fn now() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+impl ApiKeys {
+    pub fn load(path: &Path) -> eyre::Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("couldn't read {}", path.display()))?;
+        let configs: Vec<KeyConfig> = serde_json::from_str(&content)
+            .wrap_err_with(|| format!("invalid API keys in {}", path.display()))?;
+
+        let keys = Self {
+            keys: Mutex::default(),
+        };
+
+        for config in configs {
+            keys.add(config)
+                .map_err(|err| match err {
+                    AppError::BadRequest(msg) | AppError::Conflict(msg) => eyre::eyre!(msg),
+                    err => eyre::eyre!("{err:?}"),
+                })
+                .wrap_err_with(|| format!("invalid API keys in {}", path.display()))?;
+        }
+
+        Ok(keys)
+    }
+
+    fn keys(&self) -> MutexGuard<'_, HashMap<String, Key>> {
+        self.keys.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Name of the key with the token.
+    fn authenticate(&self, token: &str) -> Option<String> {
+        self.keys()
+            .values()
+            .find(|key| auth::token_matches(token, &key.token))
+            .map(|key| key.name.clone())
+    }
+
+    /// Tenant of the key with the token.
+    pub fn tenant(&self, token: &str) -> Option<Tenant> {
+        self.keys()
+            .values()
+            .find(|key| auth::token_matches(token, &key.token))
+            .map(|key| key.tenant.clone())
+    }
+
+    fn add(&self, config: KeyConfig) -> Result<KeyStatus, AppError> {
+        let mut keys = self.keys();
+
+        if keys.contains_key(&config.name) {
+            return Err(AppError::Conflict(format!(
+                "the API key name {} is already used",
+                config.name
+            )));
+        }
+
+        if keys.values().any(|key| key.token == config.key) {
+            return Err(AppError::Conflict(format!(
+                "the API key {} is the same as another one",
+                config.name
+            )));
+        }
+
+        let mut key = Key::new(config)?;
+        let status = Self::status(&mut key, now());
+        keys.insert(key.name.clone(), key);
+
+        Ok(status)
+    }
+
+    fn remove(&self, name: &str) -> bool {
+        self.keys().remove(name).is_some()
+    }
+
+    /// Takes a ping from the quotas of the key, returning the one with the fewest remaining.
+    pub fn consume(&self, name: &str) -> Result<Option<QuotaStatus>, KeyQuotaExceeded> {
+        let now = now();
+        let mut keys = self.keys();
+        let Some(key) = keys.get_mut(name) else {
+            return Ok(None);
+        };
+
+        for quota in &mut key.quotas {
+            quota.refresh(now);
+
+            if quota.remaining == 0 {
+                return Err(KeyQuotaExceeded {
+                    name: key.name.clone(),
+                    quota: quota.status(now),
+                });
+            }
+        }
+
+        let closest = key
+            .quotas
+            .iter_mut()
+            .map(|quota| {
+                quota.remaining -= 1;
+
+                quota.status(now)
+            })
+            .min_by_key(|status| status.remaining);
+
+        Ok(closest)
+    }
+
+    /// Gives back a ping taken from the quotas of the key, if it wasn't counted.
+    pub fn refund(&self, name: &str) {
+        let now = now();
+        let mut keys = self.keys();
+        let Some(key) = keys.get_mut(name) else {
+            return;
+        };
+
+        for quota in &mut key.quotas {
+            let window = quota.window;
+            quota.refresh(now);
+
+            // The new windows start with the whole quota
+            if quota.window == window {
+                quota.remaining = quota.remaining.saturating_add(1);
+            }
+        }
+    }
+
+    fn status(key: &mut Key, now: Duration) -> KeyStatus {
+        KeyStatus {
+            name: key.name.clone(),
+            tenant: key.tenant.clone(),
+            quotas: key
+                .quotas
+                .iter_mut()
+                .map(|quota| {
+                    quota.refresh(now);
+
+                    quota.status(now)
+                })
+                .collect(),
+        }
+    }
+
+    fn list(&self) -> Vec<KeyStatus> {
+        let now = now();
+        let mut keys = self.keys();
+
+        let mut list: Vec<KeyStatus> = keys
+            .values_mut()
+            .map(|key| Self::status(key, now))
+            .collect();
+        list.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+
+        list
+    }
+
+    fn update(&self, name: &str, update: &QuotaUpdate) -> Option<KeyStatus> {
+        let now = now();
+        let mut keys = self.keys();
+        let key = keys.get_mut(name)?;
+
+        for quota in &mut key.quotas {
+            quota.refresh(now);
+
+            let remaining = match quota.period {
+                Period::Hourly => update.hourly,
+                Period::Daily => update.daily,
+            };
+
+            if let Some(remaining) = remaining {
+                quota.remaining = remaining;
+            }
+        }
+
+        Some(Self::status(key, now))
+    }
+}
+
+/// Headers with the state of the quota of the key.
+#[derive(Debug, Clone, Copy)]
+pub struct QuotaHeaders(pub Option<QuotaStatus>);
+
+impl IntoResponseParts for QuotaHeaders {
+    type Error = std::convert::Infallible;
+
+    fn into_response_parts(self, mut res: ResponseParts) -> Result<ResponseParts, Self::Error> {
+        if let Some(status) = self.0 {
+            let headers = res.headers_mut();
+
+            headers.insert(QUOTA_LIMIT, HeaderValue::from(status.limit));
+            headers.insert(QUOTA_REMAINING, HeaderValue::from(status.remaining));
+            headers.insert(QUOTA_RESET, HeaderValue::from(status.reset));
+        }
+
+        Ok(res)
+    }
+}
+
+/// Name of the API key of the request, [`None`] if the keys aren't enabled.
+#[derive(Debug, Clone)]
+pub struct ApiKey(pub Option<String>);
+
+#[async_trait]
+impl FromRequestParts<AppState> for ApiKey {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let Some(keys) = &state.api_keys else {
+            return Ok(Self(None));
+        };
+
+        auth::api_key(parts)
+            .and_then(|token| keys.authenticate(token))
+            .map(|name| Self(Some(name)))
+            .ok_or_else(|| AppError::Unauthorized("invalid or missing API key".to_string()))
+    }
+}
+
+fn api_keys(state: &AppState) -> Result<&ApiKeys, AppError> {
+    state
+        .api_keys
+        .as_ref()
+        .ok_or_else(|| AppError::NotFound("the API keys aren't enabled".to_string()))
+}
+
+pub async fn list(State(state): State<AppState>) -> Result<Json<Vec<KeyStatus>>, AppError> {
+    Ok(Json(api_keys(&state)?.list()))
+}
+
+pub async fn add(
+    State(state): State<AppState>,
+    Json(config): Json<KeyConfig>,
+) -> Result<(StatusCode, Json<KeyStatus>), AppError> {
+    let status = api_keys(&state)?.add(config)?;
+
+    Ok((StatusCode::CREATED, Json(status)))
+}
+
+pub async fn remove(
+    State(state): State<AppState>,
+    UrlPath(name): UrlPath<String>,
+) -> Result<StatusCode, AppError> {
+    if !api_keys(&state)?.remove(&name) {
+        return Err(AppError::NotFound(format!("no API key named {name}")));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn update(
+    State(state): State<AppState>,
+    UrlPath(name): UrlPath<String>,
+    Json(update): Json<QuotaUpdate>,
+) -> Result<Json<KeyStatus>, AppError> {
+    api_keys(&state)?
+        .update(&name, &update)
+        .map(Json)
+        .ok_or_else(|| AppError::NotFound(format!("no API key named {name}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::response::IntoResponse;
+
+    use super::*;
+
+    fn keys(configs: serde_json::Value) -> ApiKeys {
+        let keys = ApiKeys {
+            keys: Mutex::default(),
+        };
+
+        for config in serde_json::from_value::<Vec<KeyConfig>>(configs).unwrap() {
+            keys.add(config).unwrap();
+        }
+
+        keys
+    }
+
+    #[test]
+    fn keys_bound_to_their_tenant() {
+        let keys = keys(serde_json::json!([
+            {"name": "a", "key": "k-a", "tenant": "acme"},
+            {"name": "b", "key": "k-b"},
+        ]));
+
+        assert_eq!(keys.tenant("k-a"), Tenant::parse("acme"));
+        assert_eq!(keys.tenant("k-b"), Some(Tenant::default()));
+        assert_eq!(keys.tenant("k-c"), None);
+    }
+
+    #[test]
+    fn invalid_tenants_refused() {
+        let keys = keys(serde_json::json!([]));
+        let config = serde_json::from_value(serde_json::json!(
+            {"name": "a", "key": "k-a", "tenant": "no spaces"}
+        ))
+        .unwrap();
+
+        assert!(matches!(keys.add(config), Err(AppError::BadRequest(_))));
+    }
+
+    #[test]
+    fn quotas_consumed_until_exhausted() {
+        let keys = keys(serde_json::json!([{"name": "a", "key": "k-a", "hourly": 2}]));
+
+        assert_eq!(keys.consume("a").unwrap().unwrap().remaining, 1);
+        assert_eq!(keys.consume("a").unwrap().unwrap().remaining, 0);
+
+        let err = keys.consume("a").unwrap_err();
+        assert_eq!(err.quota.period, Period::Hourly);
+    }
+
+    #[test]
+    fn quota_closest_to_exhaust_reported() {
+        let keys = keys(serde_json::json!([{"name": "a", "key": "k-a", "hourly": 5, "daily": 2}]));
+
+        let status = keys.consume("a").unwrap().unwrap();
+        assert_eq!(status.period, Period::Daily);
+        assert_eq!(status.limit, 2);
+        assert_eq!(status.remaining, 1);
+        assert!(status.reset > 0 && status.reset <= DAY.as_secs());
+    }
+
+    #[test]
+    fn nothing_reported_without_quotas() {
+        let keys = keys(serde_json::json!([{"name": "a", "key": "k-a"}]));
+
+        assert!(keys.consume("a").unwrap().is_none());
+        assert!(keys.consume("missing").unwrap().is_none());
+    }
+
+    #[test]
+    fn exhausted_quota_refused_without_consuming_the_others() {
+        let keys = keys(serde_json::json!([{"name": "a", "key": "k-a", "hourly": 5, "daily": 1}]));
+
+        keys.consume("a").unwrap();
+
+        let KeyQuotaExceeded { name, quota } = keys.consume("a").unwrap_err();
+        assert_eq!(name, "a");
+        assert_eq!(quota.period, Period::Daily);
+        assert_eq!(quota.limit, 1);
+        assert_eq!(quota.remaining, 0);
+
+        let status = &keys.list()[0];
+        assert_eq!(status.quotas[0].remaining, 4);
+    }
+
+    #[test]
+    fn quotas_reset_with_the_window() {
+        let keys = keys(serde_json::json!([{"name": "a", "key": "k-a", "hourly": 1}]));
+
+        keys.consume("a").unwrap();
+        assert!(keys.consume("a").is_err());
+
+        // As if consumed in the previous hour
+        keys.keys().get_mut("a").unwrap().quotas[0].window -= 1;
+
+        assert_eq!(keys.consume("a").unwrap().unwrap().remaining, 0);
+        assert!(keys.consume("a").is_err());
+    }
+
+    #[test]
+    fn quota_headers_of_the_status() {
+        let status = QuotaStatus {
+            period: Period::Hourly,
+            limit: 10,
+            remaining: 3,
+            reset: 42,
+        };
+
+        let res = (StatusCode::NO_CONTENT, QuotaHeaders(Some(status)), ()).into_response();
+        assert_eq!(res.headers()[QUOTA_LIMIT], "10");
+        assert_eq!(res.headers()[QUOTA_REMAINING], "3");
+        assert_eq!(res.headers()[QUOTA_RESET], "42");
+
+        let res = (StatusCode::NO_CONTENT, QuotaHeaders(None), ()).into_response();
+        assert!(!res.headers().contains_key(QUOTA_LIMIT));
+    }
+
+    #[test]
+    fn refunded_pings_given_back() {
+        let keys = keys(serde_json::json!([{"name": "a", "key": "k-a", "hourly": 1, "daily": 5}]));
+
+        keys.consume("a").unwrap();
+        keys.refund("a");
+
+        let status = keys.consume("a").unwrap().unwrap();
+        assert_eq!(status.period, Period::Hourly);
+        assert_eq!(status.remaining, 0);
+        assert!(keys.consume("a").is_err());
+        assert_eq!(keys.list()[0].quotas[1].remaining, 4);
+    }
+
+    #[test]
+    fn duplicated_keys_refused() {
+        let keys = keys(serde_json::json!([{"name": "a", "key": "k-a"}]));
+
+        for duplicate in [
+            serde_json::json!({"name": "a", "key": "k-b"}),
+            serde_json::json!({"name": "b", "key": "k-a"}),
+        ] {
+            let config = serde_json::from_value(duplicate).unwrap();
+
+            assert!(matches!(keys.add(config), Err(AppError::Conflict(_))));
+        }
+    }
+}
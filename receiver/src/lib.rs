@@ -0,0 +1,1472 @@
+use std::{
+    net::SocketAddr,
+    ops::Deref,
+    path::PathBuf,
+    pin::pin,
+    sync::{Arc, Weak},
+    time::{Duration, SystemTime},
+};
+
+use access::AccessLog;
+pub use admin::LogFilter;
+use audit::{AuditConfig, AuditLog, Fsync, Outcome, Source};
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{
+        header::{RETRY_AFTER, VARY, WWW_AUTHENTICATE},
+        StatusCode,
+    },
+    middleware,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use axum_extra::{
+    headers::{ETag, IfNoneMatch},
+    TypedHeader,
+};
+use callback::Callbacks;
+use capture::Capture;
+use cfg_if::cfg_if;
+use chaos::{Chaos, ChaosArgs};
+use clap::Args;
+use cluster::Cluster;
+use counter::{Expiry, ExpiryMode};
+use events::{Event, Events};
+use eyre::WrapErr;
+use freshness::Freshness;
+use geo::GeoIp;
+use history::{History, HistoryArgs, PingRecord};
+use idempotency::{Claim, Idempotency, IdempotencyKey};
+use ingest::Ingest;
+use ips::{IpStats, RateLimited};
+use jwt::{JwtArgs, Subject};
+use keys::{ApiKey, ApiKeys, KeyQuotaExceeded, QuotaHeaders, QuotaStatus};
+use maintenance::Maintenance;
+use metrics_exporter_prometheus::PrometheusHandle;
+use milestone::{MilestoneArgs, Milestones};
+use mirror::Mirror;
+use negotiate::{Accept, Negotiated};
+use notify::Notifier;
+use outbox::SlowConsumer;
+use protocol::{Count, Ping, Registration};
+use ratelimit::RateLimitHeaders;
+use recorder::Recorder;
+use sampling::{LogSampler, SamplingArgs};
+use schema::{PingSchema, ValidPing, Violation};
+use senders::{SenderInfo, Senders};
+use serde::Serialize;
+use server::{jwt::Jwt, oidc::Oidc, session::Sessions, telemetry, ServerArgs};
+use snapshot::{SnapshotArgs, Uploader};
+use tenant::{Counters, QuotaExceeded, Tenant};
+use tickets::Tickets;
+use tokio::{net::TcpListener, signal::unix::SignalKind};
+use tokio_metrics::TaskMonitor;
+use tokio_util::sync::CancellationToken;
+use tower_http::{catch_panic::CatchPanicLayer, trace::TraceLayer};
+use tracing::{debug, error, info, warn};
+use udp::UdpStats;
+use url::Url;
+use users::Users;
+use version::Endpoint;
+use wal::Wal;
+use watchdog::{Watchdog, WatchdogArgs};
+
+mod access;
+mod admin;
+mod audit;
+mod auth;
+mod callback;
+mod capture;
+mod chaos;
+pub mod client;
+mod cluster;
+mod counter;
+mod events;
+mod freshness;
+#[cfg(feature = "frontend")]
+mod frontend;
+mod geo;
+mod grafana;
+mod graphql;
+mod history;
+mod idempotency;
+mod ingest;
+mod ips;
+mod jwt;
+mod keys;
+mod maintenance;
+mod mdns;
+#[cfg(feature = "jemalloc")]
+mod memory;
+mod milestone;
+mod mirror;
+mod negotiate;
+mod notify;
+mod oidc;
+mod outbox;
+#[cfg(feature = "pprof")]
+mod profile;
+mod ratelimit;
+mod recorder;
+pub mod replay;
+mod runtime;
+mod sampling;
+mod schema;
+mod senders;
+mod snapshot;
+mod tenant;
+mod tickets;
+mod timing;
+mod tui;
+mod udp;
+mod users;
+mod version;
+mod wal;
+mod watchdog;
+
+/// Interval between the checks for the stale senders.
+const SENDERS_SWEEP: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+struct AppState {
+    shared: Arc<AppStateShared>,
+}
+
+impl Deref for AppState {
+    type Target = AppStateShared;
+
+    fn deref(&self) -> &Self::Target {
+        &self.shared
+    }
+}
+
+impl AppState {
+    /// State of the receiver, without starting the ingestion workers nor the background tasks.
+    async fn new(
+        args: &ReceiverArgs,
+        server: &ServerArgs,
+        local_addr: SocketAddr,
+        metrics: PrometheusHandle,
+        log_filter: Option<LogFilter>,
+        shutdown: CancellationToken,
+    ) -> eyre::Result<Self> {
+        let config = args.clone();
+
+        let advertise = match args.advertise.clone() {
+            Some(url) => url,
+            None => Url::parse(&format!("http://{local_addr}"))?,
+        };
+        let cluster = Cluster::new(
+            advertise,
+            args.peers.clone(),
+            args.peer_timeout,
+            args.cluster_secret.clone(),
+        );
+
+        info!(id = %cluster.id(), "cluster node started");
+
+        let ping_schema = args
+            .ping_schema
+            .as_deref()
+            .map(PingSchema::load)
+            .transpose()?;
+
+        let audit = args
+            .audit_log
+            .clone()
+            .map(|path| {
+                AuditLog::open(AuditConfig {
+                    path,
+                    fsync: args.audit_fsync,
+                    max_size: args.audit_max_size,
+                    keep: args.audit_keep,
+                })
+            })
+            .transpose()?;
+
+        let access_log = args
+            .access_log
+            .as_deref()
+            .map(AccessLog::open)
+            .transpose()?;
+
+        let recorder = args.record.as_deref().map(Recorder::create).transpose()?;
+        let mirror = args.mirror_to.as_ref().map(Mirror::new).transpose()?;
+
+        let expiry = args.counter_ttl.map(|ttl| Expiry {
+            ttl,
+            mode: args.counter_expiry,
+        });
+
+        let snapshots = Uploader::new(&args.snapshot)?;
+        let api_keys = args.api_keys.as_deref().map(ApiKeys::load).transpose()?;
+        let client = reqwest::Client::new();
+        let jwt = jwt::load(&args.jwt, &client).await?;
+        let oidc = Oidc::new(&server.oidc, &client).await?.map(Arc::new);
+
+        let geoip = GeoIp::open(args.geoip_country.as_deref(), args.geoip_asn.as_deref())?;
+
+        let counters = Counters::new(args.tenant_quota, args.max_tenants, expiry);
+        let wal = args
+            .wal
+            .clone()
+            .map(|path| Wal::open(path, &counters))
+            .transpose()?;
+        let notifier = args
+            .notify_config
+            .as_deref()
+            .map(|path| Notifier::load(path, counters.total()))
+            .transpose()?;
+
+        if args.read_only {
+            warn!("read-only, the pings are validated but not counted");
+        }
+
+        Ok(Self {
+            shared: Arc::new(AppStateShared {
+                #[cfg(feature = "frontend")]
+                started: std::time::Instant::now(),
+                #[cfg(feature = "frontend")]
+                dev: args.dev,
+                counters,
+                events: Events::new(),
+                fanout: TaskMonitor::new(),
+                history: History::new(&args.history),
+                udp: UdpStats::default(),
+                ips: IpStats::new(args.ip_rate_limit),
+                users: Users::default(),
+                metrics,
+                cluster,
+                senders: Senders::new(args.sender_timeout),
+                client,
+                callbacks: Callbacks::new(&args.callback_hosts)?,
+                shutdown,
+                idle_timeout: server.idle_timeout,
+                ws_buffer: args.ws_buffer,
+                slow_consumer: args.ws_slow_consumer,
+                ping_schema,
+                lax_content_type: args.lax_content_type,
+                audit,
+                access_log,
+                recorder,
+                mirror,
+                capture: args
+                    .capture_bodies
+                    .then(|| Capture::new(args.capture_limit)),
+                api_keys,
+                jwt,
+                oidc,
+                tickets: Tickets::new(args.ws_token_ttl),
+                idempotency: Idempotency::new(args.idempotency_window),
+                freshness: args.max_clock_skew.map(Freshness::new),
+                ingest: Ingest::new(
+                    args.ingest_queue,
+                    args.ingest_workers
+                        .or_else(|| std::thread::available_parallelism().ok().map(Into::into))
+                        .unwrap_or(1),
+                ),
+                ws_auth: args.ws_auth,
+                read_only: args.read_only,
+                metrics_tenants: args.metrics_tenants,
+                maintenance: Maintenance::default(),
+                snapshots,
+                geoip,
+                wal,
+                watchdog: Watchdog::new(&args.watchdog),
+                milestones: Milestones::new(&args.milestone),
+                notifier,
+                chaos: Chaos::new(&args.chaos),
+                log_sampler: LogSampler::new(&args.sampling),
+                request_sampler: LogSampler::new(&args.sampling),
+                log_filter,
+                config,
+            }),
+        })
+    }
+
+    /// State not kept alive by the holder, like the threads waiting on it.
+    fn downgrade(&self) -> WeakAppState {
+        WeakAppState {
+            shared: Arc::downgrade(&self.shared),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct WeakAppState {
+    shared: Weak<AppStateShared>,
+}
+
+impl WeakAppState {
+    fn upgrade(&self) -> Option<AppState> {
+        self.shared.upgrade().map(|shared| AppState { shared })
+    }
+}
+
+#[derive(Debug)]
+struct AppStateShared {
+    /// Reported in the summary on the index.
+    #[cfg(feature = "frontend")]
+    started: std::time::Instant,
+    /// Serves the template and the assets from the source tree.
+    #[cfg(feature = "frontend")]
+    dev: bool,
+    counters: Counters,
+    events: Events,
+    /// Monitor of the tasks streaming the events to the WebSocket clients.
+    fanout: TaskMonitor,
+    history: History,
+    udp: UdpStats,
+    ips: IpStats,
+    /// Totals of the pings by the user of the sender UI.
+    users: Users,
+    metrics: PrometheusHandle,
+    cluster: Cluster,
+    senders: Senders,
+    client: reqwest::Client,
+    /// Where the pongs are sent back to.
+    callbacks: Callbacks,
+    /// Closes the WebSockets on shutdown.
+    shutdown: CancellationToken,
+    /// Closes the WebSockets without activity from the client.
+    idle_timeout: Option<Duration>,
+    /// Events queued for each WebSocket client before it's considered slow.
+    ws_buffer: usize,
+    /// What happens to the events of the slow WebSocket clients.
+    slow_consumer: SlowConsumer,
+    /// Schema the pings are validated against, if supplied.
+    ping_schema: Option<PingSchema>,
+    /// Whether the pings without a supported `Content-Type` are decoded as JSON.
+    lax_content_type: bool,
+    /// Where the pings are traced, if enabled.
+    audit: Option<AuditLog>,
+    access_log: Option<AccessLog>,
+    /// Where the pings are recorded to be replayed, if enabled.
+    recorder: Option<Recorder>,
+    /// Shadow receiver the accepted pings are mirrored to, if any.
+    mirror: Option<Mirror>,
+    capture: Option<Capture>,
+    /// Keys the HTTP pings must carry, if enabled.
+    api_keys: Option<ApiKeys>,
+    /// Verifies the tokens the HTTP pings must carry, if enabled.
+    jwt: Option<Jwt>,
+    /// Logs the users in to the index page, if enabled.
+    oidc: Option<Arc<Oidc>>,
+    /// Tokens authenticating the WebSocket upgrades.
+    tickets: Tickets,
+    idempotency: Idempotency,
+    /// Refuses the pings with a stale timestamp or already received, if enabled.
+    freshness: Option<Freshness>,
+    ingest: Ingest,
+    /// Whether the WebSocket upgrades must carry a token.
+    ws_auth: bool,
+    /// Whether the pings are only validated and logged, without changing the counts.
+    read_only: bool,
+    /// Whether the count metric is labelled by tenant.
+    metrics_tenants: bool,
+    /// Refuses the pings when turned on from the admin routes.
+    maintenance: Maintenance,
+    /// Uploads the snapshots, if a bucket is configured.
+    snapshots: Option<Uploader>,
+    /// Databases the sources of the pings are located with, if supplied.
+    geoip: Option<GeoIp>,
+    /// Log the counts are recovered from after a crash, if enabled.
+    wal: Option<Wal>,
+    /// Fires when no ping is received for a while, if enabled.
+    watchdog: Option<Watchdog>,
+    /// Publishes the counts reaching the milestones, if enabled.
+    milestones: Option<Milestones>,
+    /// Sends the notifications to Slack or by email, if configured.
+    notifier: Option<Notifier>,
+    /// Faults injected into the requests, if enabled.
+    chaos: Option<Chaos>,
+    log_sampler: LogSampler,
+    request_sampler: LogSampler,
+    /// Changes the filter of the logs, if it's reloadable.
+    log_filter: Option<LogFilter>,
+    /// Shown on the admin routes.
+    config: ReceiverArgs,
+}
+
+#[derive(Debug)]
+pub enum AppError {
+    BadRequest(String),
+    UnsupportedMediaType(String),
+    NotAcceptable(String),
+    Unauthorized(String),
+    Forbidden(String),
+    Conflict(String),
+    NotFound(String),
+    SchemaViolation(Vec<Violation>),
+    QuotaExceeded {
+        tenant: Tenant,
+        quota: u64,
+    },
+    /// The ping would create a tenant past the maximum.
+    TooManyTenants(usize),
+    RateLimited(RateLimited),
+    KeyQuotaExceeded(KeyQuotaExceeded),
+    /// The pings are shed, to retry after the duration.
+    Overloaded(Duration),
+    /// The ping is valid but the receiver is read-only, it isn't counted.
+    ReadOnly,
+    /// The receiver is under maintenance, to retry after the duration.
+    Maintenance(Duration),
+    Internal(eyre::Report),
+}
+
+impl<E> From<E> for AppError
+where
+    E: std::error::Error + Send + Sync + 'static,
+{
+    fn from(value: E) -> Self {
+        AppError::Internal(eyre::Report::new(value))
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            AppError::BadRequest(msg) => (StatusCode::BAD_REQUEST, msg).into_response(),
+            AppError::UnsupportedMediaType(msg) => {
+                (StatusCode::UNSUPPORTED_MEDIA_TYPE, msg).into_response()
+            }
+            AppError::NotAcceptable(msg) => (StatusCode::NOT_ACCEPTABLE, msg).into_response(),
+            AppError::Unauthorized(msg) => (
+                StatusCode::UNAUTHORIZED,
+                [(WWW_AUTHENTICATE, "Bearer")],
+                msg,
+            )
+                .into_response(),
+            AppError::Forbidden(msg) => (StatusCode::FORBIDDEN, msg).into_response(),
+            AppError::Conflict(msg) => (StatusCode::CONFLICT, msg).into_response(),
+            AppError::NotFound(msg) => (StatusCode::NOT_FOUND, msg).into_response(),
+            AppError::SchemaViolation(violations) => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(serde_json::json!({
+                    "error": "the ping doesn't match the schema",
+                    "violations": violations,
+                })),
+            )
+                .into_response(),
+            AppError::QuotaExceeded { tenant, quota } => (
+                StatusCode::TOO_MANY_REQUESTS,
+                format!("tenant {tenant} reached its quota of {quota} pings"),
+            )
+                .into_response(),
+            AppError::TooManyTenants(max) => (
+                StatusCode::FORBIDDEN,
+                format!("the receiver doesn't count more than {max} tenants"),
+            )
+                .into_response(),
+            AppError::RateLimited(RateLimited { limit, retry_after }) => (
+                StatusCode::TOO_MANY_REQUESTS,
+                RateLimitHeaders {
+                    limit: limit.into(),
+                    remaining: 0,
+                    retry_after,
+                },
+                format!("the pings are limited to {limit} per second for each address"),
+            )
+                .into_response(),
+            AppError::KeyQuotaExceeded(KeyQuotaExceeded { name, quota }) => (
+                StatusCode::TOO_MANY_REQUESTS,
+                QuotaHeaders(Some(quota)),
+                RateLimitHeaders {
+                    limit: quota.limit,
+                    remaining: quota.remaining,
+                    retry_after: Duration::from_secs(quota.reset),
+                },
+                format!(
+                    "the API key {name} used its {} {} pings",
+                    quota.limit, quota.period
+                ),
+            )
+                .into_response(),
+            AppError::Overloaded(retry_after) => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                [(RETRY_AFTER, retry_after.as_secs().to_string())],
+                "the receiver is overloaded, try again later",
+            )
+                .into_response(),
+            AppError::Maintenance(retry_after) => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                [(RETRY_AFTER, retry_after.as_secs().to_string())],
+                "the receiver is under maintenance, try again later",
+            )
+                .into_response(),
+            AppError::ReadOnly => (
+                StatusCode::ACCEPTED,
+                "the receiver is read-only, the ping wasn't counted",
+            )
+                .into_response(),
+            AppError::Internal(err) => {
+                error!(error = %err, "insternal server error");
+
+                (StatusCode::INTERNAL_SERVER_ERROR, "something whent wrong").into_response()
+            }
+        }
+    }
+}
+
+/// Sets the count metric of the tenant, or the total without the labels by tenant, the public
+/// scrape mustn't list the tenants.
+fn count_gauge(state: &AppState, tenant: &Tenant, count: u64) {
+    if state.metrics_tenants {
+        metrics::gauge!("receiver_count", "tenant" => tenant.to_string()).set(count as f64);
+    } else {
+        metrics::gauge!("receiver_count").set(state.counters.total() as f64);
+    }
+}
+
+/// Counts the ping, sending the pong to the callback if requested.
+fn count_ping(
+    state: &AppState,
+    source: Source,
+    tenant: Tenant,
+    ping: Ping,
+) -> Result<u64, AppError> {
+    if let Err(err) = state.ips.hit(source.addr.ip()) {
+        if let Some(audit) = &state.audit {
+            audit.record(ping.id, source, &tenant, Outcome::RateLimited, None);
+        }
+
+        return Err(AppError::RateLimited(err));
+    }
+
+    let res = timing::store(|| match &state.wal {
+        Some(wal) => wal.increment(&state.counters, &tenant),
+        None => Ok(state.counters.increment(&tenant)),
+    })?;
+
+    if let Some(audit) = &state.audit {
+        match res {
+            Ok(count) => audit.record(ping.id, source, &tenant, Outcome::Counted, Some(count)),
+            Err(_) => audit.record(ping.id, source, &tenant, Outcome::QuotaExceeded, None),
+        }
+    }
+
+    let count = res.map_err(|err| match err {
+        QuotaExceeded::Pings(quota) => AppError::QuotaExceeded {
+            tenant: tenant.clone(),
+            quota,
+        },
+        QuotaExceeded::Tenants(max) => AppError::TooManyTenants(max),
+    })?;
+
+    let sampled = state.log_sampler.sample();
+    if let Some(skipped) = sampled {
+        info!(id = %ping.id, %tenant, count, skipped, "ping received");
+    }
+
+    metrics::counter!("receiver_pings_total", "transport" => source.transport.as_str())
+        .increment(1);
+    count_gauge(state, &tenant, count);
+
+    if let Some(sender) = ping.sender {
+        state.senders.pinged(sender, &tenant);
+    }
+
+    if let Some(user) = &ping.metadata.user {
+        state.users.pinged(&tenant, user);
+    }
+
+    watchdog::pinged(state);
+    notify::counted(state);
+    mirror::accepted(state, &tenant, &ping);
+
+    let location = match &state.geoip {
+        Some(geoip) => {
+            let location = geoip.locate(source.addr.ip());
+            geoip.record(&location);
+
+            location
+        }
+        None => Default::default(),
+    };
+
+    let record = PingRecord {
+        id: ping.id,
+        tenant: tenant.clone(),
+        count,
+        received_at: SystemTime::now(),
+        metadata: ping.metadata,
+        location,
+    };
+
+    timing::store(|| state.history.record(record.clone()));
+
+    state.events.publish(Event::Ping {
+        id: ping.id,
+        tenant: tenant.clone(),
+        count,
+    });
+    state.events.publish(Event::History(record.into()));
+    milestone::counted(state, &tenant, count);
+
+    if let Some(callback) = ping.callback {
+        state.callbacks.pong(callback, ping.id, sampled.is_some());
+    }
+
+    Ok(count)
+}
+
+/// Takes back a ping of the tenant for the pong.
+fn count_pong(
+    state: &AppState,
+    source: Source,
+    tenant: Tenant,
+    pong: Ping,
+) -> Result<u64, AppError> {
+    if let Err(err) = state.ips.hit(source.addr.ip()) {
+        if let Some(audit) = &state.audit {
+            audit.record(pong.id, source, &tenant, Outcome::RateLimited, None);
+        }
+
+        return Err(AppError::RateLimited(err));
+    }
+
+    let count = timing::store(|| match &state.wal {
+        Some(wal) => wal.decrement(&state.counters, &tenant),
+        None => Ok(state.counters.decrement(&tenant)),
+    })?;
+
+    if let Some(audit) = &state.audit {
+        match count {
+            Some(count) => audit.record(pong.id, source, &tenant, Outcome::TakenBack, Some(count)),
+            None => audit.record(pong.id, source, &tenant, Outcome::NothingToTakeBack, None),
+        }
+    }
+
+    let count = count.ok_or_else(|| AppError::NotFound(format!("tenant {tenant} has no pings")))?;
+
+    let sampled = state.log_sampler.sample();
+    if let Some(skipped) = sampled {
+        debug!(id = %pong.id, %tenant, count, skipped, "pong received");
+    }
+
+    count_gauge(state, &tenant, count);
+
+    state.events.publish(Event::Pong {
+        id: pong.id,
+        tenant,
+        count,
+    });
+
+    Ok(count)
+}
+
+async fn ping(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    ApiKey(key): ApiKey,
+    Subject(subject): Subject,
+    tenant: Tenant,
+    IdempotencyKey(idempotency_key): IdempotencyKey,
+    ValidPing(mut ping): ValidPing,
+) -> Result<Response, AppError> {
+    let source = Source {
+        transport: audit::Transport::Http,
+        addr,
+    };
+
+    if let Some(subject) = &subject {
+        debug!(id = %ping.id, subject, "token verified");
+    }
+
+    // Only the senders authenticated by a key or a token vouch for the users of their UI
+    if key.is_none() && state.jwt.is_none() {
+        ping.metadata.user = None;
+    }
+
+    // Before claiming the idempotency key and consuming the quota
+    ingest::writable(&state, source, &tenant, &ping)?;
+
+    // Spawned, so a request cancelled once the ping is queued still keeps the response for the
+    // retries with the same idempotency key
+    let counting = tokio::spawn(async move {
+        count_once(&state, source, key, tenant, idempotency_key, ping).await
+    });
+
+    counting
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?
+}
+
+/// Counts the ping unless already counted with the idempotency key, taking it from the quota of
+/// the API key.
+async fn count_once(
+    state: &AppState,
+    source: Source,
+    key: Option<String>,
+    tenant: Tenant,
+    idempotency_key: Option<String>,
+    ping: Ping,
+) -> Result<Response, AppError> {
+    let pending = match idempotency_key {
+        Some(idempotency_key) => {
+            match state
+                .idempotency
+                .claim(&tenant, key.as_deref(), idempotency_key, ping.id)?
+            {
+                Claim::New(pending) => Some(pending),
+                Claim::Replay(res) => return Ok(res),
+            }
+        }
+        None => None,
+    };
+
+    let quota = consume_quota(state, key.as_deref())?;
+
+    if let Err(err) = ingest::count(state, source, tenant, ping).await {
+        refund_quota(state, key.as_deref());
+
+        return Err(err);
+    }
+
+    let res = (StatusCode::NO_CONTENT, QuotaHeaders(quota), ()).into_response();
+
+    if let Some(pending) = pending {
+        pending.complete(&res);
+    }
+
+    Ok(res)
+}
+
+/// Takes the ping from the quota of the API key, if enabled.
+fn consume_quota(state: &AppState, key: Option<&str>) -> Result<Option<QuotaStatus>, AppError> {
+    match (&state.api_keys, key) {
+        (Some(keys), Some(name)) => keys.consume(name).map_err(AppError::KeyQuotaExceeded),
+        _ => Ok(None),
+    }
+}
+
+/// Gives the ping back to the quota of the API key, when it wasn't counted.
+fn refund_quota(state: &AppState, key: Option<&str>) {
+    if let (Some(keys), Some(name)) = (&state.api_keys, key) {
+        keys.refund(name);
+    }
+}
+
+/// Takes back a ping, authenticated, validated and limited like the pings.
+async fn pong(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    ApiKey(key): ApiKey,
+    Subject(subject): Subject,
+    tenant: Tenant,
+    ValidPing(pong): ValidPing,
+) -> Result<Response, AppError> {
+    let source = Source {
+        transport: audit::Transport::Http,
+        addr,
+    };
+
+    if let Some(subject) = &subject {
+        debug!(id = %pong.id, subject, "token verified");
+    }
+
+    // Before consuming the quota
+    ingest::writable(&state, source, &tenant, &pong)?;
+
+    let quota = consume_quota(&state, key.as_deref())?;
+
+    if let Err(err) = ingest::take_back(&state, source, tenant, pong).await {
+        refund_quota(&state, key.as_deref());
+
+        return Err(err);
+    }
+
+    Ok((StatusCode::NO_CONTENT, QuotaHeaders(quota), ()).into_response())
+}
+
+/// Returns the count, or only its ETag if it didn't change since the one in `If-None-Match`.
+async fn count(
+    State(state): State<AppState>,
+    Accept(format): Accept,
+    tenant: Tenant,
+    if_none_match: Option<TypedHeader<IfNoneMatch>>,
+) -> Result<Response, AppError> {
+    let count = timing::store(|| state.counters.get(&tenant));
+    // Weak, since the encodings of the count are equivalent
+    let etag: ETag = format!("W/\"{tenant}-{count}\"").parse()?;
+
+    if if_none_match.is_some_and(|TypedHeader(header)| !header.precondition_passes(&etag)) {
+        return Ok((
+            StatusCode::NOT_MODIFIED,
+            TypedHeader(etag),
+            [(VARY, "accept")],
+        )
+            .into_response());
+    }
+
+    let count = Negotiated(
+        format,
+        Count {
+            tenant: tenant.to_string(),
+            count,
+        },
+    );
+
+    Ok((TypedHeader(etag), count).into_response())
+}
+
+fn sender_event(sender: SenderInfo) -> Event {
+    Event::Sender {
+        id: sender.registration.id,
+        tenant: sender.tenant,
+        status: sender.status,
+        last_ping_at: sender.last_ping_at,
+    }
+}
+
+async fn register(
+    State(state): State<AppState>,
+    Json(registration): Json<Registration>,
+) -> StatusCode {
+    if let Some(sender) = state.senders.register(registration) {
+        state.events.publish(sender_event(sender));
+    }
+
+    StatusCode::NO_CONTENT
+}
+
+/// Senders that last pinged for the tenant.
+async fn senders(State(state): State<AppState>, tenant: Tenant) -> Json<Vec<SenderInfo>> {
+    Json(state.senders.list(Some(&tenant)))
+}
+
+async fn metrics(State(state): State<AppState>) -> String {
+    state.metrics.render()
+}
+
+fn app() -> Router<AppState> {
+    let router = version::routes([
+        Endpoint::new("/ping", "/ping", post(ping)),
+        Endpoint::new("/pong", "/pong", post(pong)),
+        Endpoint::new("/count", "/api/count", get(count)),
+        Endpoint::new("/udp", "/api/udp", get(udp::stats)),
+        Endpoint::new("/users/stats", "/api/users/stats", get(users::stats)),
+        Endpoint::new("/register", "/register", post(register)),
+        Endpoint::new("/senders", "/api/senders", get(senders)),
+        Endpoint::new("/events", "/events", get(events::events)),
+        Endpoint::new("/ws-token", "/api/ws-token", post(tickets::issue)),
+    ])
+    .route("/metrics", get(metrics))
+    .route("/debug/runtime", get(runtime::runtime));
+
+    #[cfg(feature = "jemalloc")]
+    let router = router.route("/debug/memory", get(memory::memory));
+
+    router
+}
+
+/// Publishes the senders becoming stale.
+async fn sweep_senders(state: AppState) {
+    let mut interval = tokio::time::interval(SENDERS_SWEEP);
+
+    loop {
+        interval.tick().await;
+
+        for sender in state.senders.sweep() {
+            state.events.publish(sender_event(sender));
+        }
+    }
+}
+
+async fn gossip(state: AppState, interval: Duration) {
+    let mut interval = tokio::time::interval(interval);
+
+    loop {
+        interval.tick().await;
+
+        state.cluster.gossip(state.counters.total()).await;
+    }
+}
+
+#[derive(Debug, Clone, Args, Serialize)]
+pub struct ReceiverArgs {
+    /// Url of another receiver to join the cluster through, can be repeated
+    #[arg(long = "peer", requires = "cluster_secret")]
+    peers: Vec<Url>,
+    /// Secret shared by the receivers of the cluster, the gossip is disabled without one
+    #[arg(long, env = "CLUSTER_SECRET", hide_env_values = true)]
+    #[serde(skip)]
+    cluster_secret: Option<String>,
+    /// Url the other peers use to reach this receiver, defaults to the listening address
+    #[arg(long)]
+    advertise: Option<Url>,
+    /// Interval between gossip rounds with the peers
+    #[arg(long, default_value = "1s", value_parser = server::parse_interval)]
+    #[serde(serialize_with = "admin::humantime")]
+    gossip_interval: Duration,
+    /// Time without updates after which a peer is considered dead
+    #[arg(long, default_value = "10s", value_parser = humantime::parse_duration)]
+    #[serde(serialize_with = "admin::humantime")]
+    peer_timeout: Duration,
+    /// Time without a registration after which a sender is considered stale
+    #[arg(long, default_value = "30s", value_parser = humantime::parse_duration)]
+    #[serde(serialize_with = "admin::humantime")]
+    sender_timeout: Duration,
+    /// Host the pongs can be sent back to, can be repeated, the callbacks to the other hosts
+    /// are ignored
+    #[arg(long = "callback-host", value_name = "HOST")]
+    callback_hosts: Vec<String>,
+    /// Maximum number of pings accepted for each tenant
+    #[arg(long)]
+    tenant_quota: Option<u64>,
+    /// Maximum number of tenants counted, the pings of the new ones are refused beyond it
+    #[arg(long, value_name = "TENANTS", default_value = "1000")]
+    max_tenants: usize,
+    /// Label the count metric by tenant, listing the tenants to whoever scrapes /metrics, instead
+    /// of reporting the total
+    #[arg(long)]
+    metrics_tenants: bool,
+    /// JSON file with the API keys the HTTP pings must carry and their quotas
+    #[arg(long, value_name = "FILE")]
+    api_keys: Option<PathBuf>,
+    /// Require a token from /v1/ws-token to open the event WebSockets
+    #[arg(long, requires = "api_keys")]
+    ws_auth: bool,
+    /// Time the tokens of the WebSockets are valid for
+    #[arg(long, default_value = "30s", value_parser = humantime::parse_duration)]
+    #[serde(serialize_with = "admin::humantime")]
+    ws_token_ttl: Duration,
+    /// Time the pings sent with an Idempotency-Key are answered again without counting them
+    #[arg(long, default_value = "24h", value_parser = humantime::parse_duration)]
+    #[serde(serialize_with = "admin::humantime")]
+    idempotency_window: Duration,
+    /// Refuse the pings sent further than this from the clock of the receiver, and the ones
+    /// received again while within it
+    #[arg(long, value_parser = humantime::parse_duration)]
+    #[serde(serialize_with = "admin::humantime_opt")]
+    max_clock_skew: Option<Duration>,
+    /// Number of pings accepted and waiting to be counted, the next ones are shed
+    #[arg(long, value_name = "PINGS", default_value = "1024")]
+    ingest_queue: usize,
+    /// Number of threads counting the pings, defaults to the available parallelism
+    #[arg(long, value_name = "THREADS")]
+    ingest_workers: Option<usize>,
+    /// Events queued for each WebSocket client before it's considered slow
+    #[arg(long, value_name = "EVENTS", default_value = "64")]
+    ws_buffer: usize,
+    /// What happens to the events of the WebSocket clients not keeping up
+    #[arg(long, value_enum, default_value_t = SlowConsumer::Coalesce)]
+    ws_slow_consumer: SlowConsumer,
+    /// Token required by the admin routes, they are disabled without one
+    #[arg(long, env = "ADMIN_TOKEN", hide_env_values = true)]
+    #[serde(skip)]
+    admin_token: Option<String>,
+    /// Serve the admin routes only on this port, on the same address, instead of the public one
+    #[arg(long, value_name = "PORT", requires = "admin_token")]
+    admin_port: Option<u16>,
+    /// Reset all the counts on SIGUSR2, it's ignored otherwise
+    #[arg(long)]
+    allow_signal_reset: bool,
+    /// Validate and log the pings without counting them, answering `202 Accepted`
+    #[arg(long)]
+    read_only: bool,
+    /// Maximum number of pings per second accepted from each address
+    #[arg(long, value_name = "PINGS")]
+    ip_rate_limit: Option<u32>,
+    /// Idle period after which the counters expire
+    #[arg(long, value_parser = humantime::parse_duration)]
+    #[serde(serialize_with = "admin::humantime_opt")]
+    counter_ttl: Option<Duration>,
+    /// How the counters expire once the TTL elapsed
+    #[arg(long, value_enum, default_value_t = ExpiryMode::Reset, requires = "counter_ttl")]
+    counter_expiry: ExpiryMode,
+    /// Add Server-Timing headers with the durations of the API requests, for debugging
+    #[arg(long)]
+    server_timing: bool,
+    /// Also receive the pings as UDP datagrams on the same address and port, not with the API
+    /// keys or the JWTs since the datagrams carry no credentials
+    #[arg(long, conflicts_with_all = ["api_keys", "jwt_secret", "jwt_jwks"])]
+    udp: bool,
+    /// Advertise the receiver on the local network over mDNS, for the senders to discover it
+    #[arg(long)]
+    mdns: bool,
+    /// JSON Schema file the bodies of the pings are validated against
+    #[arg(long, value_name = "FILE")]
+    ping_schema: Option<PathBuf>,
+    /// Decode the pings without a supported Content-Type as JSON, instead of refusing them with
+    /// `415 Unsupported Media Type`, for the legacy senders
+    #[arg(long)]
+    lax_content_type: bool,
+    /// MaxMind country or city database the sources of the pings are located with
+    #[arg(long, value_name = "FILE")]
+    geoip_country: Option<PathBuf>,
+    /// MaxMind ASN database the autonomous systems of the sources of the pings are found with
+    #[arg(long, value_name = "FILE")]
+    geoip_asn: Option<PathBuf>,
+    /// Write-ahead log the counts are recovered from, every ping is synced to it before the reply
+    #[arg(long, value_name = "FILE")]
+    wal: Option<PathBuf>,
+    /// File every counted or rejected ping is appended to, as JSON lines
+    #[arg(long, value_name = "FILE")]
+    audit_log: Option<PathBuf>,
+    /// When the audit log is synced to the disk
+    #[arg(long, value_enum, default_value_t = Fsync::Interval, requires = "audit_log")]
+    audit_fsync: Fsync,
+    /// Size in bytes after which the audit log is rotated
+    #[arg(long, default_value = "104857600", requires = "audit_log")]
+    audit_max_size: u64,
+    /// Number of rotated audit logs kept
+    #[arg(long, default_value = "5", requires = "audit_log")]
+    audit_keep: usize,
+    /// File each HTTP request is appended to as an ECS JSON line, `-` for the standard output
+    #[arg(long, value_name = "FILE")]
+    access_log: Option<PathBuf>,
+    /// File the pings received are recorded to with their timing, to replay them later
+    #[arg(long, value_name = "FILE")]
+    record: Option<PathBuf>,
+    /// Url of a shadow receiver the accepted pings are also sent to, in the background
+    #[arg(long, value_name = "URL")]
+    mirror_to: Option<Url>,
+    /// Log the bodies of the pings and of their responses at the debug level, redacted, to
+    /// debug the senders
+    #[arg(long)]
+    capture_bodies: bool,
+    /// Bytes of each captured body logged, the rest is truncated
+    #[arg(
+        long,
+        value_name = "BYTES",
+        default_value = "1024",
+        requires = "capture_bodies"
+    )]
+    capture_limit: usize,
+    /// TOML file with the Slack and email notifiers and the events triggering them
+    #[arg(long, value_name = "FILE")]
+    notify_config: Option<PathBuf>,
+    #[command(flatten)]
+    #[serde(flatten)]
+    snapshot: SnapshotArgs,
+    #[command(flatten)]
+    #[serde(flatten)]
+    history: HistoryArgs,
+    #[command(flatten)]
+    #[serde(flatten)]
+    watchdog: WatchdogArgs,
+    #[command(flatten)]
+    #[serde(flatten)]
+    milestone: MilestoneArgs,
+    #[command(flatten)]
+    #[serde(flatten)]
+    sampling: SamplingArgs,
+    #[command(flatten)]
+    #[serde(flatten)]
+    chaos: ChaosArgs,
+    #[command(flatten)]
+    #[serde(flatten)]
+    jwt: JwtArgs,
+    /// Serve only the ping API, without the index page and its assets
+    #[cfg(feature = "frontend")]
+    #[arg(long)]
+    no_frontend: bool,
+    /// Read the template and the assets from the source tree on each request, to edit them live
+    #[cfg(feature = "frontend")]
+    #[arg(long, conflicts_with = "no_frontend")]
+    dev: bool,
+    /// Open the index page in the default browser once listening
+    #[cfg(feature = "frontend")]
+    #[arg(long, conflicts_with = "no_frontend")]
+    open: bool,
+    /// Token required to take CPU profiles, they are disabled without one
+    #[cfg(feature = "pprof")]
+    #[arg(long, env = "PPROF_TOKEN", hide_env_values = true)]
+    #[serde(skip)]
+    pprof_token: Option<String>,
+}
+
+/// Serves the receiver until the shutdown is cancelled.
+///
+/// With `tui` the dashboard is shown in the terminal, quitting it cancels the shutdown. The
+/// `log_filter` is changed from the admin routes, if given.
+pub async fn run(
+    listener: TcpListener,
+    args: ReceiverArgs,
+    server: ServerArgs,
+    tui: bool,
+    metrics: PrometheusHandle,
+    log_filter: Option<LogFilter>,
+    shutdown: CancellationToken,
+) -> eyre::Result<()> {
+    let local_addr = listener.local_addr()?;
+
+    info!("listening on http://{}", local_addr);
+
+    let admin_listener = args
+        .admin_port
+        .map(|port| server::bind((local_addr.ip(), port).into(), &server))
+        .transpose()
+        .wrap_err("couldn't bind the admin port")?;
+
+    let state = AppState::new(
+        &args,
+        &server,
+        local_addr,
+        metrics,
+        log_filter,
+        shutdown.clone(),
+    )
+    .await?;
+    let geo_routes = state.geoip.is_some();
+    let sessions = Arc::new(Sessions::new(&server.session, "receiver_session").await?);
+
+    ingest::start(&state)?;
+
+    tokio::spawn(gossip(state.clone(), args.gossip_interval));
+    tokio::spawn(sweep_senders(state.clone()));
+    tokio::spawn(snapshot::run(state.clone(), shutdown.clone()));
+    tokio::spawn(watchdog::run(state.clone(), shutdown.clone()));
+    tokio::spawn(dump_stats(state.clone()));
+    tokio::spawn(reset_on_signal(state.clone(), args.allow_signal_reset));
+
+    if args.mdns {
+        mdns::advertise(state.cluster.id(), local_addr, shutdown.clone())?;
+    }
+
+    if args.udp {
+        let socket = server::bind_udp(local_addr, &server)?;
+
+        info!("receiving UDP pings on {local_addr}");
+
+        tokio::spawn(udp::serve(socket, state.clone(), shutdown.clone()));
+    }
+
+    let app = app()
+        .merge(graphql::routes(state.clone()))
+        .merge(grafana::routes());
+    let app = if geo_routes {
+        app.merge(geo::routes())
+    } else {
+        app
+    };
+    let app = if state.chaos.is_some() {
+        app.layer(middleware::from_fn_with_state(state.clone(), chaos::layer))
+    } else {
+        app
+    };
+    let app = if args.server_timing {
+        app.layer(middleware::from_fn(timing::layer))
+    } else {
+        app
+    };
+    let app = match &args.cluster_secret {
+        Some(secret) => app.merge(cluster::routes(secret)),
+        None => app,
+    };
+    let admin = args.admin_token.map(admin::routes);
+    let (app, admin) = match (admin, admin_listener) {
+        (Some(admin), Some(listener)) => (app, Some((admin, listener))),
+        (Some(admin), None) => (app.merge(admin), None),
+        (None, _) => (app, None),
+    };
+    #[cfg(feature = "pprof")]
+    let app = match args.pprof_token {
+        Some(token) => app.merge(profile::routes(token)),
+        None => app,
+    };
+    #[cfg(feature = "frontend")]
+    let app = match (args.no_frontend, &state.oidc) {
+        (true, _) => app,
+        (false, Some(oidc)) => app.merge(frontend::routes().route_layer(
+            middleware::from_fn_with_state(oidc.clone(), server::oidc::require),
+        )),
+        (false, None) => app.merge(frontend::routes()),
+    };
+    let app = match &state.oidc {
+        Some(oidc) => app.merge(server::oidc::routes(oidc.clone())),
+        None => app,
+    };
+    #[cfg(feature = "frontend")]
+    if args.open {
+        frontend::open_browser(local_addr);
+    }
+
+    let app = if state.capture.is_some() {
+        app.layer(middleware::from_fn_with_state(
+            state.clone(),
+            capture::layer,
+        ))
+    } else {
+        app
+    };
+    let app = app.layer(CatchPanicLayer::custom(telemetry::panic_response(
+        "receiver",
+    )));
+    let app = if state
+        .notifier
+        .as_ref()
+        .is_some_and(Notifier::watches_errors)
+    {
+        app.layer(middleware::from_fn_with_state(
+            state.clone(),
+            notify::errors,
+        ))
+    } else {
+        app
+    };
+    let app = if state.access_log.is_some() {
+        app.layer(middleware::from_fn_with_state(state.clone(), access::log))
+    } else {
+        app
+    };
+    let app = app
+        .route_layer(middleware::from_fn_with_state("receiver", telemetry::track))
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with({
+                    let state = state.clone();
+
+                    move |req: &Request| sampling::request_span(&state, req)
+                })
+                .on_request(sampling::on_request)
+                .on_response(sampling::on_response),
+        )
+        .with_state(state.clone());
+    let app = server::session::layer(app, sessions);
+    let app = server::request_id::layer(server::options::layer(app));
+
+    let admin = admin.map(|(admin, listener)| {
+        let admin = admin
+            .route_layer(middleware::from_fn_with_state("receiver", telemetry::track))
+            .layer(TraceLayer::new_for_http().make_span_with(server::request_id::span))
+            .with_state(state.clone());
+
+        (
+            server::request_id::layer(server::options::layer(admin)),
+            listener,
+        )
+    });
+    let admin = async {
+        let Some((admin, listener)) = admin else {
+            return Ok(());
+        };
+
+        info!(
+            "serving the admin routes on http://{}",
+            listener.local_addr()?
+        );
+
+        server::serve(listener, admin, server.clone(), shutdown.clone()).await
+    };
+    let server = async {
+        let (server, admin) = tokio::join!(
+            server::serve(listener, app, server.clone(), shutdown.clone()),
+            admin
+        );
+        server?;
+        admin
+    };
+
+    if !tui {
+        server.await?;
+
+        return Ok(());
+    }
+
+    let dashboard = async {
+        let res = tui::run(state, &shutdown).await;
+
+        // Quitting the dashboard stops the receiver
+        shutdown.cancel();
+
+        res
+    };
+
+    let (server, dashboard) = tokio::join!(server, dashboard);
+    server?;
+    dashboard?;
+
+    Ok(())
+}
+
+/// Logs the statistics on SIGUSR1, for when the HTTP API can't be reached.
+async fn dump_stats(state: AppState) {
+    let mut signal = match tokio::signal::unix::signal(SignalKind::user_defined1()) {
+        Ok(signal) => signal,
+        Err(err) => {
+            warn!(error = %eyre::Report::new(err), "couldn't wait for SIGUSR1");
+
+            return;
+        }
+    };
+
+    while signal.recv().await.is_some() {
+        info!("SIGUSR1 received");
+
+        admin::stats(&state).log();
+    }
+}
+
+/// Resets all the counts on SIGUSR2, if allowed, for when the admin routes can't be reached.
+///
+/// The signal is ignored otherwise, instead of terminating the receiver.
+async fn reset_on_signal(state: AppState, allowed: bool) {
+    let mut signal = match tokio::signal::unix::signal(SignalKind::user_defined2()) {
+        Ok(signal) => signal,
+        Err(err) => {
+            warn!(error = %eyre::Report::new(err), "couldn't wait for SIGUSR2");
+
+            return;
+        }
+    };
+
+    while signal.recv().await.is_some() {
+        if !allowed {
+            warn!("SIGUSR2 received, ignored without --allow-signal-reset");
+
+            continue;
+        }
+
+        info!("SIGUSR2 received, resetting the counts");
+
+        if let Err(err) = admin::reset_counts(&state, None) {
+            error!(error = %eyre::Report::new(err), "couldn't reset the counts");
+        }
+    }
+}
+
+/// Waits for SIGINT, or SIGTERM on unix.
+pub async fn shutdown_signal() {
+    async fn sigint() {
+        match tokio::signal::ctrl_c().await {
+            Ok(()) => {
+                info!("SIGINT received");
+            }
+            Err(err) => {
+                error!(error = %eyre::Report::new(err), "couldn't wait from signal");
+            }
+        }
+    }
+
+    cfg_if! {
+        if #[cfg(target_family = "unix")] {
+            let mut sigterm = match tokio::signal::unix::signal(SignalKind::terminate()) {
+                Ok(term) => term,
+                Err(err) => {
+                    error!(error = %eyre::Report::new(err), "couldn't wait from SIGTERM");
+
+                    // Wait only SIGINT
+                    sigint().await;
+
+                    return;
+                },
+            };
+
+            let sigterm = pin!(sigterm.recv());
+            let sigint = pin!(sigint());
+
+            if let futures::future::Either::Left(_) = futures::future::select(sigterm, sigint).await {
+                info!("SIGTERM receved");
+            }
+        } else {
+           sigint().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use metrics_exporter_prometheus::PrometheusBuilder;
+    use protocol::Metadata;
+    use uuid::Uuid;
+
+    use super::*;
+
+    #[derive(Debug, Parser)]
+    struct Cli {
+        #[command(flatten)]
+        args: ReceiverArgs,
+        #[command(flatten)]
+        server: ServerArgs,
+    }
+
+    async fn state() -> AppState {
+        let Cli { args, server } = Cli::try_parse_from(["receiver"]).unwrap();
+        let metrics = PrometheusBuilder::new().build_recorder().handle();
+
+        AppState::new(
+            &args,
+            &server,
+            SocketAddr::from(([127, 0, 0, 1], 0)),
+            metrics,
+            None,
+            CancellationToken::new(),
+        )
+        .await
+        .unwrap()
+    }
+
+    async fn send(state: &AppState, ping: &Ping) -> Result<Response, AppError> {
+        super::ping(
+            State(state.clone()),
+            ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4000))),
+            ApiKey(None),
+            Subject(None),
+            Tenant::default(),
+            IdempotencyKey(Some("key-1".to_string())),
+            ValidPing(ping.clone()),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn cancelled_pings_replayed_to_the_retries() {
+        let state = state().await;
+        let ping = Ping {
+            id: Uuid::new_v4(),
+            callback: None,
+            sender: None,
+            metadata: Metadata::default(),
+        };
+
+        // Cancelled while waiting for the workers, not started yet
+        {
+            let sent = pin!(send(&state, &ping));
+            assert!(futures::poll!(sent).is_pending());
+        }
+
+        ingest::start(&state).unwrap();
+
+        let mut res = send(&state, &ping).await;
+        for _ in 0..100 {
+            // Still being counted
+            if !matches!(res, Err(AppError::Conflict(_))) {
+                break;
+            }
+
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            res = send(&state, &ping).await;
+        }
+
+        let res = res.unwrap();
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        assert_eq!(res.headers()["idempotent-replayed"], "true");
+        assert_eq!(state.counters.get(&Tenant::default()), 1);
+    }
+
+    #[test]
+    fn udp_refused_with_the_credentials() {
+        assert!(Cli::try_parse_from(["receiver", "--udp"]).is_ok());
+
+        for credentials in [
+            ["--api-keys", "keys.json"],
+            ["--jwt-secret", "secret"],
+            ["--jwt-jwks", "https://example.com/jwks.json"],
+        ] {
+            let err = Cli::try_parse_from(["receiver", "--udp"].into_iter().chain(credentials))
+                .unwrap_err();
+
+            assert_eq!(
+                err.kind(),
+                clap::error::ErrorKind::ArgumentConflict,
+                "--udp accepted with {}",
+                credentials[0]
+            );
+        }
+    }
+}
@@ -1,72 +1,46 @@
-use std::{net::IpAddr, pin::pin, str::FromStr};
-
-use axum::{
-    http::StatusCode,
-    response::{Html, IntoResponse},
-    routing::get,
-    Router,
-};
-use axum_extra::{headers::ContentType, TypedHeader};
-use cfg_if::cfg_if;
-use clap::{builder::ValueParser, Parser};
-use tokio::{net::TcpListener, signal::unix::SignalKind};
-use tower_http::trace::TraceLayer;
-use tracing::{error, info};
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
-
-const LOG_LEVEL: &str = "receiver=info,tower_http=debug";
-
-#[derive(Debug)]
-enum AppError {
-    Internal(eyre::Report),
-}
-
-impl<E> From<E> for AppError
-where
-    E: std::error::Error + Send + Sync + 'static,
-{
-    fn from(value: E) -> Self {
-        AppError::Internal(eyre::Report::new(value))
-    }
-}
-
-impl IntoResponse for AppError {
-    fn into_response(self) -> axum::response::Response {
-        match self {
-            AppError::Internal(err) => {
-                error!(error = %err, "insternal server error");
-
-                (StatusCode::INTERNAL_SERVER_ERROR, "something whent wrong").into_response()
-            }
-        }
-    }
-}
-
-async fn index() -> Html<&'static str> {
-    Html(include_str!("../templates/index.html"))
-}
+use std::{net::IpAddr, str::FromStr};
 
-async fn favicon_ico() -> Result<(TypedHeader<ContentType>, &'static [u8]), AppError> {
-    let header = TypedHeader(ContentType::from_str("image/x-icon")?);
+use clap::{builder::ValueParser, Parser, Subcommand};
+use eyre::WrapErr;
+use receiver::{client::AdminArgs, replay::ReplayArgs, LogFilter, ReceiverArgs};
+use server::ServerArgs;
+use tokio_util::sync::CancellationToken;
+use tracing_subscriber::{layer::SubscriberExt, reload, util::SubscriberInitExt, EnvFilter};
 
-    Ok((header, include_bytes!("../../assets/favicon.ico")))
-}
-
-fn app() -> Router {
-    Router::new()
-        .route("/", get(index))
-        .route("/favicon.ico", get(favicon_ico))
-}
+const LOG_LEVEL: &str = "receiver=info,server=info,tower_http=debug";
 
 #[derive(Debug, Clone, Parser)]
-#[clap(name = env!("CARGO_PKG_NAME"), about, version)]
+#[clap(name = env!("CARGO_PKG_NAME"), about, version, args_conflicts_with_subcommands = true)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
     /// Address to listen on
     #[arg(default_value = "127.0.0.1", value_parser= ValueParser::new(IpAddr::from_str) )]
     address: IpAddr,
     /// Port to listen on
     #[arg(default_value = "9000")]
     port: u16,
+    #[command(flatten)]
+    receiver: ReceiverArgs,
+    #[command(flatten)]
+    server: ServerArgs,
+    /// Show a live dashboard in the terminal instead of the logs
+    #[arg(long)]
+    tui: bool,
+    /// StatsD or DogStatsD server the metrics are also sent to, over UDP
+    #[arg(long, value_name = "HOST:PORT")]
+    statsd_addr: Option<String>,
+    /// Base url of the OpenTelemetry collector the metrics are also exported to, over OTLP/HTTP
+    #[arg(long, env = "OTEL_EXPORTER_OTLP_ENDPOINT", value_name = "URL")]
+    otlp_endpoint: Option<String>,
+}
+
+#[derive(Debug, Clone, Subcommand)]
+enum Command {
+    /// Calls the admin API of a running receiver
+    Admin(AdminArgs),
+    /// Sends the pings of a recording to a running receiver, with their original pacing
+    Replay(ReplayArgs),
 }
 
 #[tokio::main]
@@ -75,58 +49,56 @@ async fn main() -> eyre::Result<()> {
 
     color_eyre::install()?;
 
+    // Reloadable from the admin routes
+    let (filter, log_filter) =
+        reload::Layer::new(EnvFilter::try_from_default_env().unwrap_or_else(|_| LOG_LEVEL.into()));
+
     tracing_subscriber::registry()
-        .with(tracing_subscriber::fmt::layer())
-        .with(EnvFilter::try_from_default_env().unwrap_or_else(|_| LOG_LEVEL.into()))
+        .with(filter)
+        // The logs would be drawn over the dashboard
+        .with((!cli.tui).then(tracing_subscriber::fmt::layer))
         .try_init()?;
 
-    let listener = TcpListener::bind((cli.address, cli.port)).await?;
-
-    info!("listening on http://{}", listener.local_addr()?);
-
-    let app = app().layer(TraceLayer::new_for_http());
-
-    axum::serve(listener, app)
-        .with_graceful_shutdown(shutdown_signal())
-        .await?;
-
-    Ok(())
-}
-
-async fn shutdown_signal() {
-    async fn sigint() {
-        match tokio::signal::ctrl_c().await {
-            Ok(()) => {
-                info!("SIGINT received");
-            }
-            Err(err) => {
-                error!(error = %eyre::Report::new(err), "couldn't wait from signal");
-            }
-        }
+    match cli.command {
+        Some(Command::Admin(args)) => receiver::client::run(args).await,
+        Some(Command::Replay(args)) => receiver::replay::run(args).await,
+        None => serve(cli, log_filter).await,
     }
+}
 
-    cfg_if! {
-        if #[cfg(target_family = "unix")] {
-            let mut sigterm = match tokio::signal::unix::signal(SignalKind::terminate()) {
-                Ok(term) => term,
-                Err(err) => {
-                    error!(error = %eyre::Report::new(err), "couldn't wait from SIGTERM");
-
-                    // Wait only SIGINT
-                    sigint().await;
+async fn serve(cli: Cli, log_filter: LogFilter) -> eyre::Result<()> {
+    let listener = server::bind((cli.address, cli.port).into(), &cli.server)
+        .wrap_err("couldn't bind the receiver port")?;
 
-                    return;
-                },
-            };
+    let shutdown = CancellationToken::new();
+    tokio::spawn({
+        let shutdown = shutdown.clone();
 
-            let sigterm = pin!(sigterm.recv());
-            let sigint = pin!(sigint());
+        async move {
+            receiver::shutdown_signal().await;
 
-            if let futures::future::Either::Left(_) = futures::future::select(sigterm, sigint).await {
-                info!("SIGTERM receved");
-            }
-        } else {
-           sigint().await;
+            shutdown.cancel();
         }
-    }
+    });
+
+    let metrics = server::telemetry::install_metrics(
+        env!("CARGO_PKG_NAME"),
+        cli.statsd_addr.as_deref(),
+        cli.otlp_endpoint.as_deref(),
+    )?;
+
+    let res = receiver::run(
+        listener,
+        cli.receiver,
+        cli.server,
+        cli.tui,
+        metrics,
+        Some(log_filter),
+        shutdown,
+    )
+    .await;
+
+    server::otlp::shutdown();
+
+    res
 }
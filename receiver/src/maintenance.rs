@@ -0,0 +1,89 @@
+//! Maintenance mode, toggled from the admin routes without restarting the receiver.
+//!
+//! While it's on the pings are refused with a `503 Service Unavailable` and a `Retry-After`, so
+//! the senders keep them for later, and the index page is replaced by a maintenance page with the
+//! message of the operators.
+
+use std::{
+    sync::RwLock,
+    time::{Duration, SystemTime},
+};
+
+use serde::Serialize;
+use tracing::info;
+
+/// Time the clients are asked to wait if the operators didn't say.
+pub const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone)]
+pub struct Window {
+    pub message: Option<String>,
+    pub retry_after: Duration,
+    since: SystemTime,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Status {
+    enabled: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    retry_after_secs: Option<u64>,
+    /// RFC 3339 timestamp of when it was turned on.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    since: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct Maintenance {
+    window: RwLock<Option<Window>>,
+}
+
+impl Maintenance {
+    pub fn enable(&self, message: Option<String>, retry_after: Duration) {
+        info!(?message, "maintenance mode on");
+
+        *self.window.write().unwrap_or_else(|err| err.into_inner()) = Some(Window {
+            message,
+            retry_after,
+            since: SystemTime::now(),
+        });
+
+        metrics::gauge!("receiver_maintenance").set(1.0);
+    }
+
+    pub fn disable(&self) {
+        info!("maintenance mode off");
+
+        *self.window.write().unwrap_or_else(|err| err.into_inner()) = None;
+
+        metrics::gauge!("receiver_maintenance").set(0.0);
+    }
+
+    /// The current maintenance, if any.
+    pub fn current(&self) -> Option<Window> {
+        self.window
+            .read()
+            .unwrap_or_else(|err| err.into_inner())
+            .clone()
+    }
+
+    pub fn status(&self) -> Status {
+        let window = self.window.read().unwrap_or_else(|err| err.into_inner());
+
+        match &*window {
+            Some(window) => Status {
+                enabled: true,
+                message: window.message.clone(),
+                retry_after_secs: Some(window.retry_after.as_secs()),
+                since: Some(humantime::format_rfc3339_millis(window.since).to_string()),
+            },
+            None => Status {
+                enabled: false,
+                message: None,
+                retry_after_secs: None,
+                since: None,
+            },
+        }
+    }
+}
@@ -0,0 +1,53 @@
+//! Advertisement of the receiver on the local network over mDNS.
+
+use std::net::SocketAddr;
+
+use mdns_sd::{ServiceDaemon, ServiceInfo};
+use protocol::MDNS_SERVICE;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// Advertises the receiver until the shutdown is cancelled.
+pub fn advertise(id: Uuid, addr: SocketAddr, shutdown: CancellationToken) -> eyre::Result<()> {
+    let daemon = ServiceDaemon::new()?;
+
+    let name = id.to_string();
+    let host = format!("{id}.local.");
+    let properties = [("version", env!("CARGO_PKG_VERSION"))];
+
+    let service = if addr.ip().is_unspecified() {
+        // Listening on all the interfaces, advertises their addresses as they change
+        ServiceInfo::new(MDNS_SERVICE, &name, &host, "", addr.port(), &properties[..])?
+            .enable_addr_auto()
+    } else {
+        ServiceInfo::new(
+            MDNS_SERVICE,
+            &name,
+            &host,
+            addr.ip(),
+            addr.port(),
+            &properties[..],
+        )?
+    };
+    let fullname = service.get_fullname().to_string();
+
+    daemon.register(service)?;
+
+    info!(name = fullname, "advertising over mDNS");
+
+    tokio::spawn(async move {
+        shutdown.cancelled().await;
+
+        // Announces the removal, the senders stop pinging the receiver right away
+        if let Err(err) = daemon.unregister(&fullname) {
+            warn!(error = %err, "couldn't stop the mDNS advertisement");
+        }
+
+        if let Err(err) = daemon.shutdown() {
+            warn!(error = %err, "couldn't shut down the mDNS daemon");
+        }
+    });
+
+    Ok(())
+}
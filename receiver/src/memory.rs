@@ -0,0 +1,47 @@
+//! Memory statistics of jemalloc, set as the global allocator with the `jemalloc` feature.
+
+use axum::{extract::State, Json};
+use serde::Serialize;
+use tikv_jemalloc_ctl::{epoch, stats};
+use tikv_jemallocator::Jemalloc;
+
+use crate::{AppError, AppState};
+
+#[global_allocator]
+static GLOBAL: Jemalloc = Jemalloc;
+
+/// Bytes tracked by the allocator, see the `stats.*` entries of the jemalloc manual.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct MemorySnapshot {
+    /// Allocated by the application.
+    allocated: usize,
+    /// In the active pages, including the fragmentation.
+    active: usize,
+    /// Used by the allocator for its own metadata.
+    metadata: usize,
+    /// In the pages mapped in physical memory.
+    resident: usize,
+    /// Mapped by the allocator.
+    mapped: usize,
+    /// Unmapped but kept to be reused.
+    retained: usize,
+    /// Pings kept in the history.
+    history_records: usize,
+    tenants: usize,
+}
+
+pub async fn memory(State(state): State<AppState>) -> Result<Json<MemorySnapshot>, AppError> {
+    // The statistics are cached until the epoch is advanced
+    epoch::advance()?;
+
+    Ok(Json(MemorySnapshot {
+        allocated: stats::allocated::read()?,
+        active: stats::active::read()?,
+        metadata: stats::metadata::read()?,
+        resident: stats::resident::read()?,
+        mapped: stats::mapped::read()?,
+        retained: stats::retained::read()?,
+        history_records: state.history.len(),
+        tenants: state.counters.tenants().len(),
+    }))
+}
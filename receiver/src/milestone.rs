@@ -0,0 +1,80 @@
+//! Events of the counts reaching round numbers.
+//!
+//! Each time the count of a tenant reaches a multiple of the configured number of pings, a
+//! `milestone` event is published to the WebSocket clients and posted to the webhook, if any.
+
+use std::num::NonZeroU64;
+
+use clap::Args;
+use serde::Serialize;
+use tracing::{info, warn};
+use url::Url;
+
+use crate::{events::Event, tenant::Tenant, AppState};
+
+#[derive(Debug, Clone, Args, Serialize)]
+pub struct MilestoneArgs {
+    /// Publish a milestone event each time the count of a tenant reaches a multiple of this
+    #[arg(long, value_name = "PINGS")]
+    milestone_every: Option<NonZeroU64>,
+    /// Url the milestone events are posted to as JSON
+    #[arg(long, value_name = "URL", requires = "milestone_every")]
+    milestone_webhook: Option<Url>,
+}
+
+#[derive(Debug)]
+pub struct Milestones {
+    every: u64,
+    webhook: Option<Url>,
+}
+
+impl Milestones {
+    pub fn new(args: &MilestoneArgs) -> Option<Self> {
+        let every = args.milestone_every?;
+
+        Some(Self {
+            every: every.get(),
+            webhook: args.milestone_webhook.clone(),
+        })
+    }
+}
+
+/// Publishes the milestone if the ping made the count of the tenant reach one.
+pub fn counted(state: &AppState, tenant: &Tenant, count: u64) {
+    let Some(milestones) = &state.milestones else {
+        return;
+    };
+
+    if count == 0 || !count.is_multiple_of(milestones.every) {
+        return;
+    }
+
+    info!(%tenant, count, "milestone reached");
+
+    metrics::counter!("receiver_milestones_total").increment(1);
+
+    let event = Event::Milestone {
+        tenant: tenant.clone(),
+        count,
+    };
+
+    if let Some(url) = milestones.webhook.clone() {
+        let client = state.client.clone();
+        let event = event.clone();
+
+        tokio::spawn(async move {
+            let res = client
+                .post(url.clone())
+                .json(&event)
+                .send()
+                .await
+                .and_then(|res| res.error_for_status());
+
+            if let Err(err) = res {
+                warn!(error = %err, %url, "couldn't post the milestone");
+            }
+        });
+    }
+
+    state.events.publish(event);
+}
@@ -0,0 +1,76 @@
+//! Mirroring of the accepted pings to a shadow receiver.
+//!
+//! Each counted ping is sent again, in the background, to the receiver at `--mirror-to`, so a new
+//! version can be tested against the live traffic. The mirrored pings don't carry the callbacks,
+//! the senders get a single pong, and the failures of the shadow are only logged and counted.
+//! Past a number of pings in flight they're dropped, a slow shadow can't pile them up.
+
+use std::sync::Arc;
+
+use protocol::Ping;
+use tokio::sync::Semaphore;
+use tracing::debug;
+use url::Url;
+
+use crate::{
+    tenant::{Tenant, TENANT_HEADER},
+    AppState,
+};
+
+/// Most pings being mirrored at the same time.
+const IN_FLIGHT: usize = 256;
+
+#[derive(Debug)]
+pub struct Mirror {
+    url: Url,
+    in_flight: Arc<Semaphore>,
+}
+
+impl Mirror {
+    pub fn new(url: &Url) -> eyre::Result<Self> {
+        Ok(Self {
+            url: url.join("/v1/ping")?,
+            in_flight: Arc::new(Semaphore::new(IN_FLIGHT)),
+        })
+    }
+}
+
+/// Mirrors the ping counted for the tenant, if enabled.
+pub fn accepted(state: &AppState, tenant: &Tenant, ping: &Ping) {
+    let Some(mirror) = &state.mirror else {
+        return;
+    };
+
+    let Ok(permit) = Arc::clone(&mirror.in_flight).try_acquire_owned() else {
+        metrics::counter!("receiver_mirrored_total", "outcome" => "dropped").increment(1);
+
+        return;
+    };
+
+    let ping = Ping {
+        callback: None,
+        ..ping.clone()
+    };
+    let req = state
+        .client
+        .post(mirror.url.clone())
+        .header(TENANT_HEADER, tenant.to_string())
+        .json(&ping);
+
+    tokio::spawn(async move {
+        let res = req.send().await.and_then(|res| res.error_for_status());
+
+        drop(permit);
+
+        match res {
+            Ok(_) => {
+                metrics::counter!("receiver_mirrored_total", "outcome" => "sent").increment(1);
+            }
+            Err(err) => {
+                debug!(id = %ping.id, error = %err, "couldn't mirror the ping");
+
+                metrics::counter!("receiver_mirrored_total", "outcome" => "failed").increment(1);
+            }
+        }
+    });
+}
@@ -0,0 +1,102 @@
+//! Content negotiation of the API bodies.
+//!
+//! The bodies can be encoded as JSON, MessagePack or CBOR. Requests are decoded by their
+//! `Content-Type` and responses encoded in the supported format with the highest weight of the
+//! `Accept` header, JSON by default.
+
+use axum::{
+    async_trait,
+    body::Bytes,
+    extract::{FromRequest, FromRequestParts, Request},
+    http::{
+        header::{ACCEPT, CONTENT_TYPE, VARY},
+        request::Parts,
+    },
+    response::{IntoResponse, Response},
+};
+use protocol::Format;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::AppError;
+
+const SUPPORTED: &str = "application/json, application/msgpack or application/cbor";
+
+/// Body decoded according to its `Content-Type`.
+#[derive(Debug)]
+pub struct Encoded<T>(pub T);
+
+#[async_trait]
+impl<S, T> FromRequest<S> for Encoded<T>
+where
+    S: Send + Sync,
+    T: DeserializeOwned,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let format = req
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(Format::from_media_type)
+            .ok_or_else(|| {
+                AppError::UnsupportedMediaType(format!("expected a body in {SUPPORTED}"))
+            })?;
+
+        let body = Bytes::from_request(req, state)
+            .await
+            .map_err(|err| AppError::BadRequest(err.body_text()))?;
+
+        format
+            .decode(&body)
+            .map(Self)
+            .map_err(|err| AppError::BadRequest(format!("invalid body: {err}")))
+    }
+}
+
+/// Format of the response requested by the `Accept` header.
+#[derive(Debug, Clone, Copy)]
+pub struct Accept(pub Format);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Accept
+where
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Some(accept) = parts.headers.get(ACCEPT) else {
+            return Ok(Self(Format::Json));
+        };
+
+        accept
+            .to_str()
+            .ok()
+            .and_then(Format::from_accept)
+            .map(Self)
+            .ok_or_else(|| AppError::NotAcceptable(format!("can only respond with {SUPPORTED}")))
+    }
+}
+
+/// Response encoded in the negotiated format.
+#[derive(Debug)]
+pub struct Negotiated<T>(pub Format, pub T);
+
+impl<T> IntoResponse for Negotiated<T>
+where
+    T: Serialize,
+{
+    fn into_response(self) -> Response {
+        let Self(format, value) = self;
+
+        match format.encode(&value) {
+            Ok(body) => (
+                [(CONTENT_TYPE, format.media_type()), (VARY, "accept")],
+                body,
+            )
+                .into_response(),
+            Err(err) => AppError::Internal(err).into_response(),
+        }
+    }
+}
@@ -0,0 +1,39 @@
+//! OpenID Connect login protecting the index page and the event WebSockets.
+//!
+//! The login itself is the one of [`server::oidc`], with `--oidc-issuer`. The machine API isn't
+//! protected, the senders keep their API keys.
+
+use axum::{async_trait, extract::FromRequestParts, http::request::Parts};
+use server::{oidc, session::Session};
+
+use crate::{AppError, AppState};
+
+/// Rejects the requests without a user if the login is enabled.
+#[derive(Debug)]
+pub struct LoggedIn;
+
+#[async_trait]
+impl FromRequestParts<AppState> for LoggedIn {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        if state.oidc.is_none() {
+            return Ok(Self);
+        }
+
+        let logged_in = parts
+            .extensions
+            .get::<Session>()
+            .and_then(oidc::user)
+            .is_some();
+
+        if !logged_in {
+            return Err(AppError::Unauthorized("not logged in".to_string()));
+        }
+
+        Ok(Self)
+    }
+}
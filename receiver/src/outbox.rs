@@ -0,0 +1,216 @@
+//! Messages waiting to be written to a WebSocket client.
+//!
+//! The events are queued up to a capacity, so a client that can't keep up doesn't hold back the
+//! others. Once full the [`SlowConsumer`] policy decides what happens to the next events, and to
+//! the replies to the pings of the client. The control messages, like the heartbeats, are always
+//! queued.
+
+use std::{
+    collections::VecDeque,
+    sync::{Mutex, MutexGuard},
+};
+
+use axum::extract::ws::{CloseFrame, Message, WebSocket};
+use clap::ValueEnum;
+use futures::{stream::SplitSink, SinkExt};
+use serde::Serialize;
+use tokio::sync::Notify;
+use tracing::debug;
+
+use crate::tenant::Tenant;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SlowConsumer {
+    /// Drops the new events.
+    Drop,
+    /// Replaces the queued count of the same tenant, or drops the oldest event.
+    Coalesce,
+    /// Closes the WebSocket.
+    Disconnect,
+}
+
+impl SlowConsumer {
+    fn as_str(self) -> &'static str {
+        match self {
+            SlowConsumer::Drop => "dropped",
+            SlowConsumer::Coalesce => "coalesced",
+            SlowConsumer::Disconnect => "disconnected",
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Kind {
+    Control,
+    Event,
+    /// Event with the count of the tenant, superseding the previous ones.
+    Count(Tenant),
+}
+
+#[derive(Debug)]
+struct Queued {
+    kind: Kind,
+    msg: Message,
+}
+
+#[derive(Debug)]
+pub struct Outbox {
+    capacity: usize,
+    policy: SlowConsumer,
+    queue: Mutex<VecDeque<Queued>>,
+    notify: Notify,
+}
+
+/// Counts the client lagging behind, by what happened to the events.
+pub fn lagged(action: &'static str) {
+    metrics::counter!("receiver_websocket_lagged_total", "action" => action).increment(1);
+}
+
+impl Outbox {
+    pub fn new(capacity: usize, policy: SlowConsumer) -> Self {
+        Self {
+            capacity,
+            policy,
+            queue: Mutex::new(VecDeque::with_capacity(capacity)),
+            notify: Notify::new(),
+        }
+    }
+
+    fn queue(&self) -> MutexGuard<'_, VecDeque<Queued>> {
+        self.queue.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn enqueue(&self, queue: &mut VecDeque<Queued>, queued: Queued) {
+        queue.push_back(queued);
+        metrics::gauge!("receiver_websocket_queued").increment(1.0);
+
+        self.notify.notify_one();
+    }
+
+    /// Queues a control message.
+    pub fn send(&self, msg: Message) {
+        let mut queue = self.queue();
+
+        self.enqueue(
+            &mut queue,
+            Queued {
+                kind: Kind::Control,
+                msg,
+            },
+        );
+    }
+
+    /// Queues an event or a reply, with the tenant if it's a count, returning `false` if the
+    /// client must be disconnected.
+    pub fn push(&self, msg: Message, tenant: Option<Tenant>) -> bool {
+        let mut queue = self.queue();
+        let kind = match tenant {
+            Some(tenant) => Kind::Count(tenant),
+            None => Kind::Event,
+        };
+
+        if queue.len() < self.capacity {
+            self.enqueue(&mut queue, Queued { kind, msg });
+
+            return true;
+        }
+
+        lagged(self.policy.as_str());
+
+        match self.policy {
+            SlowConsumer::Drop => true,
+            SlowConsumer::Disconnect => false,
+            SlowConsumer::Coalesce => {
+                let previous = queue.iter_mut().find(|queued| match (&queued.kind, &kind) {
+                    (Kind::Count(queued), Kind::Count(tenant)) => queued == tenant,
+                    _ => false,
+                });
+
+                if let Some(previous) = previous {
+                    previous.msg = msg;
+
+                    return true;
+                }
+
+                let oldest = queue
+                    .iter()
+                    .position(|queued| !matches!(queued.kind, Kind::Control));
+
+                // Only control messages queued, the new event is the oldest
+                let Some(oldest) = oldest else {
+                    return true;
+                };
+
+                queue.remove(oldest);
+                metrics::gauge!("receiver_websocket_queued").decrement(1.0);
+
+                self.enqueue(&mut queue, Queued { kind, msg });
+
+                true
+            }
+        }
+    }
+
+    /// Drops the queued messages.
+    fn clear(queue: &mut VecDeque<Queued>) {
+        metrics::gauge!("receiver_websocket_queued").decrement(queue.len() as f64);
+        queue.clear();
+    }
+
+    /// Drops the queued messages, once the client is gone.
+    pub fn discard(&self) {
+        Self::clear(&mut self.queue());
+    }
+
+    /// Drops the queued messages and queues the closing one, the last written.
+    pub fn close(&self, frame: Option<CloseFrame<'static>>) {
+        let mut queue = self.queue();
+
+        Self::clear(&mut queue);
+
+        self.enqueue(
+            &mut queue,
+            Queued {
+                kind: Kind::Control,
+                msg: Message::Close(frame),
+            },
+        );
+    }
+
+    async fn pop(&self) -> Message {
+        loop {
+            if let Some(queued) = self.queue().pop_front() {
+                metrics::gauge!("receiver_websocket_queued").decrement(1.0);
+
+                return queued.msg;
+            }
+
+            self.notify.notified().await;
+        }
+    }
+
+    /// Writes the queued messages until the closing one, or the client is gone.
+    pub async fn write(&self, mut sink: SplitSink<WebSocket, Message>) {
+        loop {
+            let msg = self.pop().await;
+            let close = matches!(msg, Message::Close(_));
+
+            if let Err(err) = sink.send(msg).await {
+                debug!(error = %err, "events client disconnected");
+
+                break;
+            }
+
+            if close {
+                break;
+            }
+        }
+    }
+}
+
+impl Drop for Outbox {
+    fn drop(&mut self) {
+        Self::clear(&mut self.queue());
+    }
+}
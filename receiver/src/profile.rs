@@ -0,0 +1,99 @@
+//! CPU profiles of the receiver taken on demand.
+//!
+//! The profiles are served only to the requests carrying the token given on the command line as
+//! a bearer in the `Authorization` header.
+
+use std::{sync::Arc, time::Duration};
+
+use axum::{
+    extract::Query,
+    http::header::CONTENT_TYPE,
+    middleware,
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use pprof::{protos::Message, ProfilerGuardBuilder};
+use serde::Deserialize;
+
+use crate::{auth::authorize, AppError, AppState};
+
+/// Samples taken each second.
+const FREQUENCY: i32 = 100;
+
+const MAX_SECONDS: u64 = 300;
+
+#[derive(Debug, Deserialize)]
+struct ProfileQuery {
+    /// How long to sample the CPU for.
+    #[serde(default = "default_seconds")]
+    seconds: u64,
+    #[serde(default)]
+    format: ProfileFormat,
+}
+
+fn default_seconds() -> u64 {
+    30
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ProfileFormat {
+    /// Protobuf profile read by `go tool pprof`.
+    #[default]
+    Proto,
+    /// SVG flamegraph, viewable in a browser.
+    Flamegraph,
+}
+
+/// Samples the CPU for the requested time, then returns the profile.
+async fn profile(Query(query): Query<ProfileQuery>) -> Result<Response, AppError> {
+    if query.seconds == 0 || query.seconds > MAX_SECONDS {
+        return Err(AppError::BadRequest(format!(
+            "the profile must last between 1 and {MAX_SECONDS} seconds"
+        )));
+    }
+
+    let guard = ProfilerGuardBuilder::default()
+        .frequency(FREQUENCY)
+        .blocklist(&["libc", "libgcc", "pthread", "vdso"])
+        .build()
+        .map_err(|err| match err {
+            pprof::Error::Running => {
+                AppError::Conflict("a profile is already being taken".to_string())
+            }
+            err => err.into(),
+        })?;
+
+    tokio::time::sleep(Duration::from_secs(query.seconds)).await;
+
+    // Resolving the symbols of the samples takes a while
+    let res = tokio::task::spawn_blocking(move || -> eyre::Result<Response> {
+        let report = guard.report().build()?;
+
+        let res = match query.format {
+            ProfileFormat::Proto => (
+                [(CONTENT_TYPE, "application/octet-stream")],
+                report.pprof()?.encode_to_vec(),
+            )
+                .into_response(),
+            ProfileFormat::Flamegraph => {
+                let mut svg = Vec::new();
+                report.flamegraph(&mut svg)?;
+
+                ([(CONTENT_TYPE, "image/svg+xml")], svg).into_response()
+            }
+        };
+
+        Ok(res)
+    })
+    .await?;
+
+    res.map_err(AppError::Internal)
+}
+
+pub fn routes(token: String) -> Router<AppState> {
+    Router::new()
+        .route("/debug/pprof/profile", get(profile))
+        .route_layer(middleware::from_fn_with_state(Arc::from(token), authorize))
+}
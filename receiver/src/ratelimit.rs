@@ -0,0 +1,39 @@
+//! Rate limit headers of the throttled responses.
+//!
+//! The `RateLimit-Limit` and `RateLimit-Remaining` headers follow the IETF draft on the rate
+//! limit fields, along with the standard `Retry-After` in seconds, so the senders can wait
+//! before sending again instead of retrying right away.
+
+use std::time::Duration;
+
+use axum::{
+    http::{header::RETRY_AFTER, HeaderName, HeaderValue},
+    response::{IntoResponseParts, ResponseParts},
+};
+
+pub const RATELIMIT_LIMIT: HeaderName = HeaderName::from_static("ratelimit-limit");
+pub const RATELIMIT_REMAINING: HeaderName = HeaderName::from_static("ratelimit-remaining");
+
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimitHeaders {
+    pub limit: u64,
+    pub remaining: u64,
+    pub retry_after: Duration,
+}
+
+impl IntoResponseParts for RateLimitHeaders {
+    type Error = std::convert::Infallible;
+
+    fn into_response_parts(self, mut res: ResponseParts) -> Result<ResponseParts, Self::Error> {
+        // Rounded up, so the sender doesn't come back too early
+        let retry_after =
+            self.retry_after.as_secs() + u64::from(self.retry_after.subsec_nanos() > 0);
+
+        let headers = res.headers_mut();
+        headers.insert(RATELIMIT_LIMIT, HeaderValue::from(self.limit));
+        headers.insert(RATELIMIT_REMAINING, HeaderValue::from(self.remaining));
+        headers.insert(RETRY_AFTER, HeaderValue::from(retry_after.max(1)));
+
+        Ok(res)
+    }
+}
@@ -0,0 +1,78 @@
+//! Recording of the pings received, to replay the traffic later.
+//!
+//! Each ping is written as a JSON line with its tenant and the time since the start of the
+//! recording, so `receiver replay` and `sender replay` can send them again with the same pacing.
+//! The lines are written by a dedicated thread, the file is replaced at every start.
+
+use std::{
+    fs::File,
+    io::{LineWriter, Write},
+    path::Path,
+    sync::mpsc,
+    time::Instant,
+};
+
+use eyre::WrapErr;
+use protocol::{Ping, Recorded};
+use tracing::error;
+
+use crate::tenant::Tenant;
+
+#[derive(Debug)]
+pub struct Recorder {
+    start: Instant,
+    lines: mpsc::Sender<Recorded>,
+}
+
+impl Recorder {
+    /// Creates the file and starts the thread writing to it.
+    pub fn create(path: &Path) -> eyre::Result<Self> {
+        let file =
+            File::create(path).wrap_err_with(|| format!("couldn't create {}", path.display()))?;
+        let (lines, rx) = mpsc::channel();
+
+        std::thread::Builder::new()
+            .name("recorder".to_string())
+            .spawn(move || write(LineWriter::new(file), rx))
+            .wrap_err("couldn't start the recorder")?;
+
+        Ok(Self {
+            start: Instant::now(),
+            lines,
+        })
+    }
+
+    pub fn record(&self, tenant: &Tenant, ping: &Ping) {
+        let recorded = Recorded {
+            offset_ms: self
+                .start
+                .elapsed()
+                .as_millis()
+                .try_into()
+                .unwrap_or(u64::MAX),
+            tenant: tenant.to_string(),
+            ping: ping.clone(),
+        };
+
+        if self.lines.send(recorded).is_err() {
+            error!(id = %ping.id, "recorder stopped, ping lost");
+        }
+    }
+}
+
+/// Writes the pings until the recorder is dropped.
+fn write(mut out: LineWriter<File>, lines: mpsc::Receiver<Recorded>) {
+    for recorded in lines {
+        let res = serde_json::to_vec(&recorded)
+            .map_err(Into::into)
+            .and_then(|mut line| {
+                line.push(b'\n');
+
+                out.write_all(&line)
+            });
+
+        if let Err(err) = res {
+            error!(id = %recorded.ping.id, error = %err, "couldn't record the ping");
+        }
+    }
+}
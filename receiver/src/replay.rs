@@ -0,0 +1,95 @@
+//! Replay of the pings recorded with `--record`, against a running receiver.
+//!
+//! The pings are sent over HTTP with the pacing of the recording and their tenants, as new pings
+//! without the callbacks, so the counts aren't deduplicated and the senders aren't called back.
+//! They are stamped with the time they are sent at, so the receivers checking `--max-clock-skew`
+//! don't refuse them as stale, and carry the `--api-key` as a bearer if given.
+
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+
+use clap::Args;
+use eyre::WrapErr;
+use protocol::{Ping, Recorded};
+use tokio::{task::JoinSet, time::Instant};
+use url::Url;
+use uuid::Uuid;
+
+use crate::tenant::TENANT_HEADER;
+
+#[derive(Debug, Clone, Args)]
+pub struct ReplayArgs {
+    /// Recording of the pings, written with `--record`
+    file: PathBuf,
+    /// Url of the receiver the pings are sent to
+    #[arg(long, env = "RECEIVER_URL", default_value = "http://127.0.0.1:9000")]
+    url: Url,
+    /// API key or token sent along the pings, for the receivers requiring one
+    #[arg(long, env = "PING_API_KEY", hide_env_values = true)]
+    api_key: Option<String>,
+}
+
+/// Sends the recorded pings, failing if any of them wasn't counted.
+pub async fn run(args: ReplayArgs) -> eyre::Result<()> {
+    let file = File::open(&args.file)
+        .wrap_err_with(|| format!("couldn't open {}", args.file.display()))?;
+    let url = args.url.join("/v1/ping")?;
+    let client = reqwest::Client::new();
+
+    let start = Instant::now();
+    let mut sent = JoinSet::new();
+
+    for line in BufReader::new(file).lines() {
+        let recorded: Recorded = serde_json::from_str(&line?).wrap_err("invalid recording")?;
+
+        tokio::time::sleep_until(start + Duration::from_millis(recorded.offset_ms)).await;
+
+        let mut ping = Ping {
+            id: Uuid::new_v4(),
+            callback: None,
+            ..recorded.ping
+        };
+        ping.metadata.sent_at =
+            Some(humantime::format_rfc3339_millis(SystemTime::now()).to_string());
+
+        let req = client
+            .post(url.clone())
+            .header(TENANT_HEADER, recorded.tenant)
+            .json(&ping);
+        let req = match &args.api_key {
+            Some(key) => req.bearer_auth(key),
+            None => req,
+        };
+
+        // Sent concurrently, the slow responses would delay the next pings
+        sent.spawn(async move {
+            req.send()
+                .await
+                .and_then(|res| res.error_for_status())
+                .is_ok()
+        });
+    }
+
+    let mut requested = 0;
+    let mut failed = 0;
+    while let Some(res) = sent.join_next().await {
+        requested += 1;
+
+        if !res.unwrap_or(false) {
+            failed += 1;
+        }
+    }
+
+    println!(
+        "replayed {requested} pings in {:.2}s",
+        start.elapsed().as_secs_f64()
+    );
+
+    eyre::ensure!(failed == 0, "{failed} of {requested} pings failed");
+
+    Ok(())
+}
@@ -0,0 +1,63 @@
+//! Metrics of the tokio runtime, to diagnose stalls of the WebSocket fan-out.
+//!
+//! Only the metrics available without the `tokio_unstable` cfg are reported, like the depth of
+//! the global queue but not the one of the blocking pool.
+
+use std::time::Duration;
+
+use axum::{extract::State, Json};
+use serde::Serialize;
+use tokio::runtime::Handle;
+
+use crate::AppState;
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct RuntimeSnapshot {
+    workers: usize,
+    alive_tasks: usize,
+    /// Tasks scheduled from outside the workers waiting to be picked up.
+    global_queue_depth: usize,
+    fanout: FanoutSnapshot,
+}
+
+/// Cumulative metrics of the tasks streaming the events to the WebSocket clients.
+#[derive(Debug, Clone, Copy, Serialize)]
+struct FanoutSnapshot {
+    /// Tasks currently connected.
+    tasks: u64,
+    polls: u64,
+    mean_poll_ms: f64,
+    /// Time spent waiting for a worker after being woken up.
+    mean_scheduled_ms: f64,
+    /// Polls longer than the slow threshold of the monitor.
+    slow_polls: u64,
+    /// Waits for a worker longer than the long delay threshold of the monitor.
+    long_delays: u64,
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+pub fn snapshot(state: &AppState) -> RuntimeSnapshot {
+    let metrics = Handle::current().metrics();
+    let fanout = state.fanout.cumulative();
+
+    RuntimeSnapshot {
+        workers: metrics.num_workers(),
+        alive_tasks: metrics.num_alive_tasks(),
+        global_queue_depth: metrics.global_queue_depth(),
+        fanout: FanoutSnapshot {
+            tasks: fanout.instrumented_count - fanout.dropped_count,
+            polls: fanout.total_poll_count,
+            mean_poll_ms: millis(fanout.mean_poll_duration()),
+            mean_scheduled_ms: millis(fanout.mean_scheduled_duration()),
+            slow_polls: fanout.total_slow_poll_count,
+            long_delays: fanout.total_long_delay_count,
+        },
+    }
+}
+
+pub async fn runtime(State(state): State<AppState>) -> Json<RuntimeSnapshot> {
+    Json(snapshot(&state))
+}
@@ -0,0 +1,123 @@
+//! Sampling of the logs written for each ping.
+//!
+//! Under load the per-ping logs would be written thousands of times a second, so only every
+//! n-th one is kept, up to a maximum per second. The kept logs have the number of the skipped
+//! ones since the previous. The traces of the HTTP requests of the pings are sampled the same.
+
+use std::{
+    num::NonZeroU64,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use axum::{extract::Request, http::Method, response::Response};
+use clap::Args;
+use serde::Serialize;
+use tower_http::trace::{DefaultOnRequest, DefaultOnResponse, OnRequest, OnResponse};
+use tracing::Span;
+
+use crate::{version, AppState};
+
+#[derive(Debug, Clone, Args, Serialize)]
+pub struct SamplingArgs {
+    /// Log only one ping out of this many
+    #[arg(long, value_name = "N", default_value = "1")]
+    log_sample_every: NonZeroU64,
+    /// Maximum number of pings logged each second
+    #[arg(long, value_name = "LOGS")]
+    log_sample_rate: Option<u32>,
+}
+
+#[derive(Debug)]
+struct Window {
+    start: Instant,
+    logged: u32,
+}
+
+#[derive(Debug)]
+pub struct LogSampler {
+    every: u64,
+    rate: Option<u32>,
+    seen: AtomicU64,
+    skipped: AtomicU64,
+    window: Mutex<Window>,
+}
+
+impl LogSampler {
+    pub fn new(args: &SamplingArgs) -> Self {
+        Self {
+            every: args.log_sample_every.get(),
+            rate: args.log_sample_rate,
+            seen: AtomicU64::new(0),
+            skipped: AtomicU64::new(0),
+            window: Mutex::new(Window {
+                start: Instant::now(),
+                logged: 0,
+            }),
+        }
+    }
+
+    fn within_rate(&self) -> bool {
+        let Some(rate) = self.rate else {
+            return true;
+        };
+
+        let mut window = self.window.lock().unwrap_or_else(|err| err.into_inner());
+
+        if window.start.elapsed() >= Duration::from_secs(1) {
+            *window = Window {
+                start: Instant::now(),
+                logged: 0,
+            };
+        }
+
+        if window.logged >= rate {
+            return false;
+        }
+
+        window.logged += 1;
+
+        true
+    }
+
+    /// Whether the log of the ping is kept, returning the logs skipped since the previous one.
+    pub fn sample(&self) -> Option<u64> {
+        let seen = self.seen.fetch_add(1, Ordering::Relaxed);
+
+        if !seen.is_multiple_of(self.every) || !self.within_rate() {
+            self.skipped.fetch_add(1, Ordering::Relaxed);
+
+            return None;
+        }
+
+        Some(self.skipped.swap(0, Ordering::Relaxed))
+    }
+}
+
+/// Span of the request, none if it's a ping not sampled.
+pub fn request_span(state: &AppState, req: &Request) -> Span {
+    let ping = req.method() == Method::POST && version::unversioned(req.uri().path()) == "/ping";
+
+    if ping && state.request_sampler.sample().is_none() {
+        return Span::none();
+    }
+
+    server::request_id::span(req)
+}
+
+/// Logs the start of the request if sampled.
+pub fn on_request(req: &Request, span: &Span) {
+    if !span.is_none() {
+        DefaultOnRequest::new().on_request(req, span);
+    }
+}
+
+/// Logs the end of the request if sampled.
+pub fn on_response(res: &Response, latency: Duration, span: &Span) {
+    if !span.is_none() {
+        DefaultOnResponse::new().on_response(res, latency, span);
+    }
+}
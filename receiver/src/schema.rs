@@ -0,0 +1,122 @@
+//! Validation of the incoming pings against a JSON Schema supplied by the operator.
+//!
+//! The pings encoded as MessagePack or CBOR are validated as their JSON equivalent. The pings
+//! without a supported `Content-Type` are refused, or decoded as JSON with `--lax-content-type`.
+
+use std::path::Path;
+
+use axum::{
+    async_trait,
+    extract::{FromRequest, Request},
+    http::{header::CONTENT_TYPE, HeaderValue},
+};
+use eyre::{eyre, WrapErr};
+use jsonschema::Validator;
+use protocol::{Format, Ping};
+use serde::Serialize;
+use serde_json::Value;
+use tracing::debug;
+
+use crate::{negotiate::Encoded, AppError, AppState};
+
+pub struct PingSchema {
+    validator: Validator,
+}
+
+impl std::fmt::Debug for PingSchema {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PingSchema").finish_non_exhaustive()
+    }
+}
+
+/// A value of the ping not matching the schema.
+#[derive(Debug, Clone, Serialize)]
+pub struct Violation {
+    /// JSON pointer to the value in the ping.
+    pub path: String,
+    pub message: String,
+}
+
+impl PingSchema {
+    pub fn load(path: &Path) -> eyre::Result<Self> {
+        let schema = std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("couldn't read the ping schema {}", path.display()))?;
+        let schema: Value = serde_json::from_str(&schema)
+            .wrap_err_with(|| format!("the ping schema {} isn't JSON", path.display()))?;
+
+        let validator = jsonschema::validator_for(&schema)
+            .map_err(|err| eyre!("invalid ping schema {}: {err}", path.display()))?;
+
+        Ok(Self { validator })
+    }
+
+    /// Decodes the ping, failing with all the violations of the schema.
+    pub fn decode(&self, value: Value) -> Result<Ping, AppError> {
+        let violations: Vec<Violation> = self
+            .validator
+            .iter_errors(&value)
+            .map(|err| Violation {
+                path: err.instance_path.to_string(),
+                message: err.to_string(),
+            })
+            .collect();
+
+        if !violations.is_empty() {
+            return Err(AppError::SchemaViolation(violations));
+        }
+
+        serde_json::from_value(value)
+            .map_err(|err| AppError::BadRequest(format!("invalid body: {err}")))
+    }
+}
+
+/// Marks the body as JSON if it isn't in a supported format, as sent by the legacy senders.
+fn assume_json(req: &mut Request) {
+    let supported = req
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(Format::from_media_type)
+        .is_some();
+
+    if supported {
+        return;
+    }
+
+    debug!(
+        content_type = ?req.headers().get(CONTENT_TYPE),
+        "decoding the ping as JSON"
+    );
+
+    metrics::counter!("receiver_pings_lax_content_type_total").increment(1);
+
+    req.headers_mut().insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static(Format::Json.media_type()),
+    );
+}
+
+/// Ping in the body, validated against the schema if there is one.
+#[derive(Debug)]
+pub struct ValidPing(pub Ping);
+
+#[async_trait]
+impl FromRequest<AppState> for ValidPing {
+    type Rejection = AppError;
+
+    async fn from_request(mut req: Request, state: &AppState) -> Result<Self, Self::Rejection> {
+        if state.lax_content_type {
+            assume_json(&mut req);
+        }
+
+        let Some(schema) = &state.ping_schema else {
+            let Encoded(ping) = Encoded::from_request(req, state).await?;
+
+            return Ok(Self(ping));
+        };
+
+        let Encoded(value) = Encoded::from_request(req, state).await?;
+
+        schema.decode(value).map(Self)
+    }
+}
@@ -0,0 +1,191 @@
+//! Senders registered with the receiver.
+//!
+//! The senders register again periodically, a sender not heard from within the timeout is
+//! reported as stale and forgotten after a while. The changes of their status are published as
+//! events.
+
+use std::{
+    collections::HashMap,
+    sync::RwLock,
+    time::{Duration, Instant, SystemTime},
+};
+
+use protocol::Registration;
+use serde::Serialize;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::tenant::Tenant;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SenderStatus {
+    Alive,
+    Stale,
+}
+
+/// A sender as reported by `/v1/senders`.
+#[derive(Debug, Clone, Serialize)]
+pub struct SenderInfo {
+    #[serde(flatten)]
+    pub registration: Registration,
+    pub status: SenderStatus,
+    /// RFC 3339 timestamp of the first registration.
+    pub registered_at: String,
+    /// Milliseconds since the last registration.
+    pub last_seen_ms: u128,
+    /// RFC 3339 timestamp of the last ping received from the sender.
+    pub last_ping_at: Option<String>,
+    /// Tenant of the last ping received from the sender.
+    pub tenant: Option<Tenant>,
+}
+
+#[derive(Debug)]
+struct Sender {
+    registration: Registration,
+    registered: SystemTime,
+    seen: Instant,
+    last_ping: Option<SystemTime>,
+    tenant: Option<Tenant>,
+    /// Cleared once the sender is found stale, to report the change only once.
+    online: bool,
+}
+
+impl Sender {
+    fn info(&self, timeout: Duration) -> SenderInfo {
+        let elapsed = self.seen.elapsed();
+        let status = if elapsed < timeout {
+            SenderStatus::Alive
+        } else {
+            SenderStatus::Stale
+        };
+
+        SenderInfo {
+            registration: self.registration.clone(),
+            status,
+            registered_at: rfc3339(self.registered),
+            last_seen_ms: elapsed.as_millis(),
+            last_ping_at: self.last_ping.map(rfc3339),
+            tenant: self.tenant.clone(),
+        }
+    }
+}
+
+fn rfc3339(time: SystemTime) -> String {
+    humantime::format_rfc3339_millis(time).to_string()
+}
+
+#[derive(Debug)]
+pub struct Senders {
+    timeout: Duration,
+    senders: RwLock<HashMap<Uuid, Sender>>,
+}
+
+impl Senders {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            senders: RwLock::default(),
+        }
+    }
+
+    /// Registers the sender, returning it if it just came online.
+    pub fn register(&self, registration: Registration) -> Option<SenderInfo> {
+        let mut senders = self.senders.write().unwrap_or_else(|err| err.into_inner());
+
+        let id = registration.id;
+        let online = match senders.get_mut(&id) {
+            Some(sender) => {
+                let online = !sender.online;
+                if online {
+                    info!(%id, "sender is alive again");
+                }
+
+                sender.registration = registration;
+                sender.seen = Instant::now();
+                sender.online = true;
+
+                online
+            }
+            None => {
+                info!(
+                    id = %registration.id,
+                    url = ?registration.url,
+                    version = registration.version,
+                    "sender registered"
+                );
+
+                senders.insert(
+                    registration.id,
+                    Sender {
+                        registration,
+                        registered: SystemTime::now(),
+                        seen: Instant::now(),
+                        last_ping: None,
+                        tenant: None,
+                        online: true,
+                    },
+                );
+
+                true
+            }
+        };
+
+        online.then(|| senders[&id].info(self.timeout))
+    }
+
+    /// Records a ping from the sender for the tenant, if it's registered.
+    pub fn pinged(&self, id: Uuid, tenant: &Tenant) {
+        let mut senders = self.senders.write().unwrap_or_else(|err| err.into_inner());
+
+        if let Some(sender) = senders.get_mut(&id) {
+            sender.last_ping = Some(SystemTime::now());
+
+            if sender.tenant.as_ref() != Some(tenant) {
+                sender.tenant = Some(tenant.clone());
+            }
+        }
+    }
+
+    /// Returns the senders that just became stale, forgetting the ones stale for a while.
+    pub fn sweep(&self) -> Vec<SenderInfo> {
+        let mut senders = self.senders.write().unwrap_or_else(|err| err.into_inner());
+
+        let forget = self.timeout * 3;
+        senders.retain(|id, sender| {
+            let keep = sender.seen.elapsed() < forget;
+
+            if !keep {
+                info!(%id, "removing stale sender");
+            }
+
+            keep
+        });
+
+        senders
+            .iter_mut()
+            .filter(|(_, sender)| sender.online && sender.seen.elapsed() >= self.timeout)
+            .map(|(id, sender)| {
+                info!(%id, "sender is stale");
+
+                sender.online = false;
+
+                sender.info(self.timeout)
+            })
+            .collect()
+    }
+
+    /// Lists the senders, only the ones that last pinged for the tenant if given.
+    pub fn list(&self, tenant: Option<&Tenant>) -> Vec<SenderInfo> {
+        let senders = self.senders.read().unwrap_or_else(|err| err.into_inner());
+
+        let mut list: Vec<SenderInfo> = senders
+            .values()
+            .filter(|sender| tenant.is_none_or(|tenant| sender.tenant.as_ref() == Some(tenant)))
+            .map(|sender| sender.info(self.timeout))
+            .collect();
+        list.sort_unstable_by(|a, b| a.registered_at.cmp(&b.registered_at));
+
+        list
+    }
+}
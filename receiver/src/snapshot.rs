@@ -0,0 +1,240 @@
+//! Periodic snapshots of the counts and the history uploaded to an S3 compatible bucket.
+//!
+//! Each snapshot is a JSON object stored as `PREFIX<NODE>/<TIMESTAMP>.json`, the timestamps sort
+//! in the order they were taken, so after every upload only the latest ones of the node are kept,
+//! leaving the ones of the other receivers sharing the bucket. The node is named after the host
+//! by default, so it's the same after a restart.
+//! The credentials are read from the usual AWS environment variables, profile or instance
+//! metadata.
+
+use std::{
+    num::NonZeroUsize,
+    time::{Duration, SystemTime},
+};
+
+use clap::Args;
+use protocol::Metadata;
+use s3::{creds::Credentials, Bucket, Region};
+use serde::Serialize;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use crate::{
+    admin,
+    geo::Location,
+    history::PingRecord,
+    tenant::{Tenant, TenantCount},
+    AppState,
+};
+
+#[derive(Debug, Clone, Args, Serialize)]
+pub struct SnapshotArgs {
+    /// Bucket the snapshots of the counts and the history are uploaded to
+    #[arg(long, value_name = "BUCKET")]
+    snapshot_bucket: Option<String>,
+    /// Endpoint of the S3 compatible storage, defaults to AWS
+    #[arg(long, value_name = "URL", requires = "snapshot_bucket")]
+    snapshot_endpoint: Option<String>,
+    /// Region of the bucket
+    #[arg(long, default_value = "us-east-1", requires = "snapshot_bucket")]
+    snapshot_region: String,
+    /// Prefix of the keys of the snapshots
+    #[arg(long, default_value = "ping-pong/", requires = "snapshot_bucket")]
+    snapshot_prefix: String,
+    /// Interval between the snapshots
+    #[arg(long, default_value = "5m", value_parser = server::parse_interval, requires = "snapshot_bucket")]
+    #[serde(serialize_with = "admin::humantime")]
+    snapshot_interval: Duration,
+    /// Name of the receiver in the keys of its snapshots, unique among the ones sharing the
+    /// bucket, defaults to the hostname
+    #[arg(long, value_name = "NAME", requires = "snapshot_bucket")]
+    snapshot_node: Option<String>,
+    /// Number of snapshots of the receiver kept in the bucket, the older ones are deleted
+    #[arg(long, default_value = "24", requires = "snapshot_bucket")]
+    snapshot_keep: NonZeroUsize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Snapshot {
+    node: Uuid,
+    taken_at: String,
+    tenants: Vec<TenantCount>,
+    /// The latest first.
+    history: Vec<Ping>,
+}
+
+/// Ping of the history.
+#[derive(Debug, Clone, Serialize)]
+pub struct Ping {
+    id: Uuid,
+    tenant: Tenant,
+    count: u64,
+    received_at: String,
+    #[serde(flatten)]
+    metadata: Metadata,
+    #[serde(flatten)]
+    location: Location,
+}
+
+impl Ping {
+    pub fn tenant(&self) -> &Tenant {
+        &self.tenant
+    }
+}
+
+impl From<PingRecord> for Ping {
+    fn from(record: PingRecord) -> Self {
+        Self {
+            id: record.id,
+            tenant: record.tenant,
+            count: record.count,
+            received_at: humantime::format_rfc3339_millis(record.received_at).to_string(),
+            metadata: record.metadata,
+            location: record.location,
+        }
+    }
+}
+
+/// Takes a snapshot of the current counts and history.
+pub fn take(state: &AppState) -> Snapshot {
+    Snapshot {
+        node: state.cluster.id(),
+        taken_at: humantime::format_rfc3339_millis(SystemTime::now()).to_string(),
+        tenants: state.counters.tenants(),
+        history: state
+            .history
+            .page(None, 0, usize::MAX)
+            .into_iter()
+            .map(Ping::from)
+            .collect(),
+    }
+}
+
+#[derive(Debug)]
+pub struct Uploader {
+    bucket: Box<Bucket>,
+    /// Prefix of the keys of the snapshots of the node.
+    prefix: String,
+    keep: NonZeroUsize,
+    interval: Duration,
+}
+
+impl Uploader {
+    /// Connects to the bucket, if one is configured.
+    pub fn new(args: &SnapshotArgs) -> eyre::Result<Option<Self>> {
+        let Some(name) = &args.snapshot_bucket else {
+            return Ok(None);
+        };
+
+        let region = match &args.snapshot_endpoint {
+            Some(endpoint) => Region::Custom {
+                region: args.snapshot_region.clone(),
+                endpoint: endpoint.clone(),
+            },
+            None => args.snapshot_region.parse()?,
+        };
+
+        let bucket = Bucket::new(name, region, Credentials::default()?)?;
+        // The compatible storages seldom support the virtual hosted buckets
+        let bucket = if args.snapshot_endpoint.is_some() {
+            bucket.with_path_style()
+        } else {
+            bucket
+        };
+
+        let node = match &args.snapshot_node {
+            Some(node) => node.clone(),
+            None => gethostname::gethostname()
+                .into_string()
+                .map_err(|_| eyre::eyre!("the hostname isn't valid UTF-8, set --snapshot-node"))?,
+        };
+        eyre::ensure!(
+            !node.is_empty() && !node.contains('/'),
+            "invalid snapshot node {node:?}"
+        );
+
+        Ok(Some(Self {
+            bucket,
+            prefix: format!("{}{node}/", args.snapshot_prefix),
+            keep: args.snapshot_keep,
+            interval: args.snapshot_interval,
+        }))
+    }
+
+    /// Uploads a snapshot and deletes the expired ones, returning the key of the new one.
+    pub async fn backup(&self, state: &AppState) -> eyre::Result<String> {
+        let snapshot = take(state);
+        let key = format!("{}{}.json", self.prefix, snapshot.taken_at);
+        let body = serde_json::to_vec(&snapshot)?;
+
+        self.bucket
+            .put_object_with_content_type(&key, &body, "application/json")
+            .await?;
+
+        info!(key, "snapshot uploaded");
+
+        if let Err(err) = self.prune().await {
+            warn!(error = %err, "couldn't delete the expired snapshots");
+        }
+
+        Ok(key)
+    }
+
+    /// Deletes the snapshots of the node older than the ones kept.
+    async fn prune(&self) -> eyre::Result<()> {
+        let mut keys: Vec<String> = self
+            .bucket
+            .list(self.prefix.clone(), None)
+            .await?
+            .into_iter()
+            .flat_map(|page| page.contents)
+            .map(|object| object.key)
+            // Not the ones of a prefix nested under the one of the node
+            .filter(|key| {
+                key.strip_prefix(&self.prefix)
+                    .is_some_and(|name| !name.contains('/') && name.ends_with(".json"))
+            })
+            .collect();
+
+        keys.sort_unstable();
+
+        let expired = keys.len().saturating_sub(self.keep.get());
+        for key in &keys[..expired] {
+            self.bucket.delete_object(key).await?;
+
+            debug!(key, "expired snapshot deleted");
+        }
+
+        Ok(())
+    }
+}
+
+/// Uploads the snapshots until the shutdown is cancelled, if a bucket is configured.
+pub async fn run(state: AppState, shutdown: CancellationToken) {
+    let Some(uploader) = &state.snapshots else {
+        return;
+    };
+
+    info!(
+        bucket = uploader.bucket.name(),
+        prefix = uploader.prefix,
+        "uploading snapshots every {:?}",
+        uploader.interval
+    );
+
+    let mut interval = tokio::time::interval(uploader.interval);
+    // The first tick completes immediately
+    interval.tick().await;
+
+    loop {
+        tokio::select! {
+            _ = shutdown.cancelled() => break,
+            _ = interval.tick() => {}
+        }
+
+        if let Err(err) = uploader.backup(&state).await {
+            warn!(error = %err, "couldn't upload the snapshot");
+        }
+    }
+}
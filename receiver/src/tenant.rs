@@ -0,0 +1,372 @@
+//! Tenant scoping of the counters.
+//!
+//! The tenant is read from the `X-Tenant-Id` header, requests without it belong to the
+//! [`DEFAULT_TENANT`]. With the API keys or the JWTs enabled the tenant is the one the credentials
+//! are bound to instead, the `tenant` of the key or the claim of the token, the default one
+//! without it, and the requests asking for another one are refused.
+//!
+//! The pings create the tenants up to `--max-tenants`, so the clients can't grow the memory
+//! without bounds, the ones of the other tenants are refused once reached.
+
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    sync::{Arc, Mutex, RwLock},
+};
+
+use axum::{async_trait, extract::FromRequestParts, http::request::Parts};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    auth,
+    counter::{Counter, Expiry},
+    jwt, AppError, AppState,
+};
+
+pub const TENANT_HEADER: &str = "x-tenant-id";
+pub const DEFAULT_TENANT: &str = "default";
+
+const MAX_TENANT_LEN: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Tenant(String);
+
+impl Tenant {
+    pub fn parse(value: &str) -> Option<Self> {
+        let valid = !value.is_empty()
+            && value.len() <= MAX_TENANT_LEN
+            && value
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+
+        valid.then(|| Self(value.to_string()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Default for Tenant {
+    fn default() -> Self {
+        Self(DEFAULT_TENANT.to_string())
+    }
+}
+
+impl Display for Tenant {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Tenant {
+    /// Tenant of the request asking for the `requested` one, with credentials `bound` to a tenant
+    /// if they are enabled.
+    pub fn bind(requested: Option<Tenant>, bound: Option<Tenant>) -> Result<Self, AppError> {
+        match (requested, bound) {
+            (Some(requested), Some(bound)) if requested != bound => Err(AppError::Forbidden(
+                format!("the credentials are bound to the tenant {bound}, not {requested}"),
+            )),
+            (_, Some(bound)) => Ok(bound),
+            (requested, None) => Ok(requested.unwrap_or_default()),
+        }
+    }
+}
+
+/// Tenant in the `X-Tenant-Id` header, trusted as is only by the admin routes.
+#[derive(Debug, Clone)]
+pub struct RequestedTenant(pub Option<Tenant>);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for RequestedTenant
+where
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Some(value) = parts.headers.get(TENANT_HEADER) else {
+            return Ok(Self(None));
+        };
+
+        value
+            .to_str()
+            .ok()
+            .and_then(Tenant::parse)
+            .map(|tenant| Self(Some(tenant)))
+            .ok_or_else(|| AppError::BadRequest("invalid tenant id".to_string()))
+    }
+}
+
+/// Tenant the API key or the JWT of the request is bound to, [`None`] if neither is enabled.
+///
+/// The requests without valid credentials are bound to the [`DEFAULT_TENANT`], the ones needing
+/// them are refused by their extractors.
+#[derive(Debug, Clone)]
+pub struct Bound(pub Option<Tenant>);
+
+#[async_trait]
+impl FromRequestParts<AppState> for Bound {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        if let Some(keys) = &state.api_keys {
+            let tenant = auth::api_key(parts)
+                .and_then(|token| keys.tenant(token))
+                .unwrap_or_default();
+
+            return Ok(Self(Some(tenant)));
+        }
+
+        if let Some(jwt) = &state.jwt {
+            let Ok(claims) = jwt::claims(parts, jwt).await else {
+                return Ok(Self(Some(Tenant::default())));
+            };
+
+            let tenant = match &claims.tenant {
+                Some(tenant) => Tenant::parse(tenant).ok_or_else(|| {
+                    AppError::Unauthorized("the token is bound to an invalid tenant".to_string())
+                })?,
+                None => Tenant::default(),
+            };
+
+            return Ok(Self(Some(tenant)));
+        }
+
+        Ok(Self(None))
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for Tenant {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let RequestedTenant(requested) = RequestedTenant::from_request_parts(parts, state).await?;
+        let Bound(bound) = Bound::from_request_parts(parts, state).await?;
+
+        Tenant::bind(requested, bound)
+    }
+}
+
+/// The ping isn't counted for the tenant.
+#[derive(Debug)]
+pub enum QuotaExceeded {
+    /// The tenant reached its quota of pings.
+    Pings(u64),
+    /// The tenant is new and there are already the maximum number of tenants.
+    Tenants(usize),
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TenantCount {
+    pub tenant: Tenant,
+    pub count: u64,
+    pub quota: Option<u64>,
+}
+
+/// Ping counters of every tenant.
+#[derive(Debug)]
+pub struct Counters {
+    quota: Option<u64>,
+    /// Number of tenants the pings can create.
+    max_tenants: usize,
+    expiry: Option<Expiry>,
+    tenants: RwLock<HashMap<Tenant, Arc<Mutex<Counter>>>>,
+}
+
+impl Counters {
+    pub fn new(quota: Option<u64>, max_tenants: usize, expiry: Option<Expiry>) -> Self {
+        Self {
+            quota,
+            max_tenants,
+            expiry,
+            tenants: RwLock::new(HashMap::new()),
+        }
+    }
+
+    fn existing(&self, tenant: &Tenant) -> Option<Arc<Mutex<Counter>>> {
+        let tenants = self.tenants.read().unwrap_or_else(|err| err.into_inner());
+
+        tenants.get(tenant).map(Arc::clone)
+    }
+
+    /// Counter of the tenant, created if there are less than `max` tenants.
+    fn counter_within(&self, tenant: &Tenant, max: usize) -> Option<Arc<Mutex<Counter>>> {
+        if let Some(counter) = self.existing(tenant) {
+            return Some(counter);
+        }
+
+        let mut tenants = self.tenants.write().unwrap_or_else(|err| err.into_inner());
+
+        if !tenants.contains_key(tenant) && tenants.len() >= max {
+            return None;
+        }
+
+        Some(Arc::clone(
+            tenants
+                .entry(tenant.clone())
+                .or_insert_with(|| Arc::new(Mutex::new(Counter::new()))),
+        ))
+    }
+
+    /// Counter of the tenant, created however many tenants there are, for the administrators.
+    fn counter(&self, tenant: &Tenant) -> Arc<Mutex<Counter>> {
+        self.counter_within(tenant, usize::MAX)
+            .unwrap_or_else(|| unreachable!("the tenants aren't more than usize::MAX"))
+    }
+
+    fn load(&self, counter: &Mutex<Counter>) -> u64 {
+        counter
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .get(self.expiry)
+    }
+
+    /// Increments the tenant counter, returning the new value.
+    pub fn increment(&self, tenant: &Tenant) -> Result<u64, QuotaExceeded> {
+        let quota = self.quota.unwrap_or(u64::MAX);
+
+        self.counter_within(tenant, self.max_tenants)
+            .ok_or(QuotaExceeded::Tenants(self.max_tenants))?
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .increment(self.expiry, quota)
+            .ok_or(QuotaExceeded::Pings(quota))
+    }
+
+    /// Decrements the tenant counter, returning the new value, or [`None`] if the tenant has no
+    /// counter to take a ping back from.
+    pub fn decrement(&self, tenant: &Tenant) -> Option<u64> {
+        let count = self
+            .existing(tenant)?
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .decrement(self.expiry);
+
+        Some(count)
+    }
+
+    /// Adds the delta to the tenant counter, ignoring the quota, returning the previous and the
+    /// new value.
+    pub fn add(&self, tenant: &Tenant, delta: i64) -> (u64, u64) {
+        let counter = self.counter(tenant);
+        let mut counter = counter.lock().unwrap_or_else(|err| err.into_inner());
+
+        let previous = counter.get(self.expiry);
+        let count = counter.add(self.expiry, delta);
+
+        (previous, count)
+    }
+
+    /// Sets the tenant counter, like when restoring the counts.
+    pub fn set(&self, tenant: &Tenant, count: u64) {
+        self.counter(tenant)
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .set(count);
+    }
+
+    /// Sets the tenant counter, returning the previous value.
+    pub fn replace(&self, tenant: &Tenant, count: u64) -> u64 {
+        self.counter(tenant)
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .replace(self.expiry, count)
+    }
+
+    pub fn get(&self, tenant: &Tenant) -> u64 {
+        let tenants = self.tenants.read().unwrap_or_else(|err| err.into_inner());
+
+        tenants.get(tenant).map_or(0, |counter| self.load(counter))
+    }
+
+    /// Sum of the counts of all the tenants.
+    pub fn total(&self) -> u64 {
+        let tenants = self.tenants.read().unwrap_or_else(|err| err.into_inner());
+
+        tenants.values().map(|counter| self.load(counter)).sum()
+    }
+
+    pub fn tenants(&self) -> Vec<TenantCount> {
+        let tenants = self.tenants.read().unwrap_or_else(|err| err.into_inner());
+
+        let mut list: Vec<TenantCount> = tenants
+            .iter()
+            .map(|(tenant, counter)| TenantCount {
+                tenant: tenant.clone(),
+                count: self.load(counter),
+                quota: self.quota,
+            })
+            .collect();
+
+        list.sort_by(|a, b| a.tenant.0.cmp(&b.tenant.0));
+
+        list
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tenant(name: &str) -> Tenant {
+        Tenant::parse(name).unwrap()
+    }
+
+    #[test]
+    fn requested_tenant_trusted_without_credentials() {
+        assert_eq!(Tenant::bind(Some(tenant("a")), None).unwrap(), tenant("a"));
+        assert_eq!(Tenant::bind(None, None).unwrap(), Tenant::default());
+    }
+
+    #[test]
+    fn tenant_of_the_credentials_by_default() {
+        assert_eq!(Tenant::bind(None, Some(tenant("a"))).unwrap(), tenant("a"));
+        assert_eq!(
+            Tenant::bind(Some(tenant("a")), Some(tenant("a"))).unwrap(),
+            tenant("a")
+        );
+    }
+
+    #[test]
+    fn other_tenants_refused_with_credentials() {
+        let err = Tenant::bind(Some(tenant("b")), Some(tenant("a"))).unwrap_err();
+
+        assert!(matches!(err, AppError::Forbidden(_)), "{err:?}");
+    }
+
+    #[test]
+    fn new_tenants_capped() {
+        let counters = Counters::new(None, 1, None);
+
+        assert_eq!(counters.increment(&tenant("a")).unwrap(), 1);
+        assert!(matches!(
+            counters.increment(&tenant("b")),
+            Err(QuotaExceeded::Tenants(1))
+        ));
+        assert_eq!(counters.increment(&tenant("a")).unwrap(), 2);
+        // Not created by the pongs either
+        assert_eq!(counters.decrement(&tenant("b")), None);
+    }
+
+    #[test]
+    fn pings_capped_by_the_quota() {
+        let counters = Counters::new(Some(1), 10, None);
+
+        counters.increment(&tenant("a")).unwrap();
+
+        assert!(matches!(
+            counters.increment(&tenant("a")),
+            Err(QuotaExceeded::Pings(1))
+        ));
+        assert_eq!(counters.increment(&tenant("b")).unwrap(), 1);
+    }
+}
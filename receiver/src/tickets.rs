@@ -0,0 +1,128 @@
+//! Short-lived tokens authenticating the upgrades of the WebSockets.
+//!
+//! The browsers can't set the headers of the upgrade requests, so the clients get a token from
+//! `/v1/ws-token`, with their API key or JWT if enabled, and pass it to `/v1/events` in the
+//! `token` query parameter or as a `Sec-WebSocket-Protocol` entry prefixed with
+//! [`TOKEN_PROTOCOL_PREFIX`]. The entry is echoed back if the client offers no other subprotocol
+//! of the server, as the browsers refuse the upgrades not selecting one of the offered ones. The
+//! WebSocket then pings with the API key the token was issued to, for its tenant. Each token is
+//! used once.
+
+use std::{
+    collections::HashMap,
+    sync::{Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::State,
+    http::{header::SEC_WEBSOCKET_PROTOCOL, HeaderMap},
+    Json,
+};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{jwt::Subject, keys::ApiKey, tenant::Tenant, AppState};
+
+/// Prefix of the entries of `Sec-WebSocket-Protocol` carrying a token.
+pub const TOKEN_PROTOCOL_PREFIX: &str = "pingpong.token.";
+
+#[derive(Debug)]
+pub struct Ticket {
+    /// Name of the API key the token was issued to.
+    pub key: Option<String>,
+    /// Tenant the token was issued for, the one of the WebSocket.
+    pub tenant: Tenant,
+    expires: Instant,
+}
+
+#[derive(Debug)]
+pub struct Tickets {
+    ttl: Duration,
+    tickets: Mutex<HashMap<String, Ticket>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Issued {
+    token: String,
+    /// Seconds the token is valid for.
+    expires_in: u64,
+}
+
+impl Tickets {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            tickets: Mutex::default(),
+        }
+    }
+
+    fn tickets(&self) -> MutexGuard<'_, HashMap<String, Ticket>> {
+        self.tickets.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn issue(&self, key: Option<String>, tenant: Tenant) -> Issued {
+        let token = Uuid::new_v4().simple().to_string();
+        let now = Instant::now();
+
+        let mut tickets = self.tickets();
+        tickets.retain(|_, ticket| ticket.expires > now);
+        tickets.insert(
+            token.clone(),
+            Ticket {
+                key,
+                tenant,
+                expires: now + self.ttl,
+            },
+        );
+
+        Issued {
+            token,
+            expires_in: self.ttl.as_secs(),
+        }
+    }
+
+    /// Uses the token, returning what it was issued to if still valid.
+    pub fn redeem(&self, token: &str) -> Option<Ticket> {
+        self.tickets()
+            .remove(token)
+            .filter(|ticket| ticket.expires > Instant::now())
+    }
+}
+
+/// Subprotocols offered by the client.
+fn offered(headers: &HeaderMap) -> impl Iterator<Item = &str> {
+    headers
+        .get_all(SEC_WEBSOCKET_PROTOCOL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+}
+
+/// Token of the upgrade request, from the query or the subprotocols.
+pub fn token<'a>(query: Option<&'a str>, headers: &'a HeaderMap) -> Option<&'a str> {
+    query.or_else(|| {
+        offered(headers).find_map(|protocol| protocol.strip_prefix(TOKEN_PROTOCOL_PREFIX))
+    })
+}
+
+/// Subprotocol carrying the token, to select if the client offers none of the `supported` ones.
+pub fn token_protocol(headers: &HeaderMap, supported: &[&str]) -> Option<String> {
+    if offered(headers).any(|protocol| supported.contains(&protocol)) {
+        return None;
+    }
+
+    offered(headers)
+        .find(|protocol| protocol.starts_with(TOKEN_PROTOCOL_PREFIX))
+        .map(str::to_string)
+}
+
+pub async fn issue(
+    State(state): State<AppState>,
+    ApiKey(key): ApiKey,
+    _: Subject,
+    tenant: Tenant,
+) -> Json<Issued> {
+    Json(state.tickets.issue(key, tenant))
+}
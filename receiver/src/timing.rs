@@ -0,0 +1,66 @@
+//! `Server-Timing` headers with the durations spent serving the API requests.
+
+use std::{
+    cell::Cell,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::Request,
+    http::{HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+
+static SERVER_TIMING: HeaderName = HeaderName::from_static("server-timing");
+
+tokio::task_local! {
+    /// Time spent in the store by the request served in the task.
+    static STORE: Cell<Duration>;
+}
+
+/// Runs an operation on the store, adding its duration to the timings of the request.
+///
+/// Outside of a timed request it only runs the operation.
+pub fn store<T>(f: impl FnOnce() -> T) -> T {
+    let start = Instant::now();
+    let res = f();
+
+    stored(start.elapsed());
+
+    res
+}
+
+/// Adds the time spent in the store to the timings of the request, like by the workers.
+pub fn stored(elapsed: Duration) {
+    let _ = STORE.try_with(|store| store.set(store.get() + elapsed));
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Middleware adding the handler and store durations to the response.
+pub async fn layer(req: Request, next: Next) -> Response {
+    let start = Instant::now();
+
+    let (mut res, store) = STORE
+        .scope(Cell::default(), async {
+            let res = next.run(req).await;
+
+            (res, STORE.with(Cell::get))
+        })
+        .await;
+
+    let timing = format!(
+        "store;dur={:.3}, handler;dur={:.3}",
+        millis(store),
+        millis(start.elapsed())
+    );
+
+    if let Ok(value) = HeaderValue::from_str(&timing) {
+        res.headers_mut().insert(SERVER_TIMING.clone(), value);
+    }
+
+    res
+}
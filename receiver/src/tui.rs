@@ -0,0 +1,195 @@
+//! Terminal dashboard with the live counters of the receiver.
+
+use std::{collections::VecDeque, time::SystemTime};
+
+use ratatui::{
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Style, Stylize},
+    text::Line,
+    widgets::{Block, List, Paragraph, Sparkline},
+    Frame,
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::{cluster::MemberStatus, events::Event, senders::SenderStatus, AppState};
+
+/// Number of buckets kept, more than the width of most terminals.
+const HISTORY: usize = 300;
+
+/// Number of pings shown in the recent list.
+const RECENT: usize = 100;
+
+/// Pings and pongs counted during a bucket.
+#[derive(Debug, Clone, Copy, Default)]
+struct Bucket {
+    pings: u64,
+    pongs: u64,
+}
+
+#[derive(Debug)]
+struct Dashboard {
+    state: AppState,
+    current: Bucket,
+    /// Completed buckets, the latest at the back.
+    history: VecDeque<Bucket>,
+    /// Events with the time they were received, the latest at the front.
+    recent: VecDeque<(SystemTime, Event)>,
+}
+
+impl Dashboard {
+    fn new(state: AppState) -> Self {
+        Self {
+            state,
+            current: Bucket::default(),
+            history: VecDeque::with_capacity(HISTORY),
+            recent: VecDeque::with_capacity(RECENT),
+        }
+    }
+
+    /// Last buckets fitting in the area, as the bars of the sparkline.
+    fn bars(&self, area: Rect) -> Vec<u64> {
+        let width = usize::from(area.width.saturating_sub(2));
+        let skip = self.history.len().saturating_sub(width);
+
+        self.history
+            .iter()
+            .skip(skip)
+            .map(|bucket| bucket.pings)
+            .collect()
+    }
+}
+
+impl server::tui::Dashboard for Dashboard {
+    type Event = Event;
+
+    fn record(&mut self, event: Event) {
+        match event {
+            Event::Ping { .. } => self.current.pings += 1,
+            Event::Pong { .. } => self.current.pongs += 1,
+            Event::Sender { .. } | Event::Adjusted { .. } | Event::Milestone { .. } => {}
+            // Already shown by the ping events
+            Event::Rate { .. } | Event::History(_) => return,
+        }
+
+        if self.recent.len() == RECENT {
+            self.recent.pop_back();
+        }
+
+        self.recent.push_front((SystemTime::now(), event));
+    }
+
+    fn rotate(&mut self) {
+        if self.history.len() == HISTORY {
+            self.history.pop_front();
+        }
+
+        self.history.push_back(std::mem::take(&mut self.current));
+    }
+
+    fn render(&self, frame: &mut Frame) {
+        let state = &self.state;
+        let [summary, rate, recent, help] = Layout::vertical([
+            Constraint::Length(7),
+            Constraint::Length(8),
+            Constraint::Fill(1),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        let last = self.history.back().copied().unwrap_or_default();
+        let total = state.counters.total();
+        let membership = state.cluster.membership(total);
+        let alive = membership
+            .members
+            .iter()
+            .filter(|member| member.status == MemberStatus::Alive)
+            .count();
+        let senders = state.senders.list(None);
+        let alive_senders = senders
+            .iter()
+            .filter(|sender| sender.status == SenderStatus::Alive)
+            .count();
+
+        let lines = vec![
+            Line::from(format!(
+                "count:    {total} in {} tenants",
+                state.counters.tenants().len()
+            )),
+            Line::from(format!(
+                "rate:     {} pings/s, {} pongs/s",
+                last.pings, last.pongs
+            )),
+            Line::from(format!(
+                "cluster:  {alive} of {} members alive, {} counted",
+                membership.members.len(),
+                membership.count
+            )),
+            Line::from(format!(
+                "senders:  {alive_senders} of {} registered alive",
+                senders.len()
+            )),
+            Line::from(format!(
+                "events:   {} WebSocket clients",
+                state.events.clients()
+            )),
+        ];
+
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title(format!(" {} ", membership.id))),
+            summary,
+        );
+
+        frame.render_widget(
+            Sparkline::default()
+                .block(Block::bordered().title(" pings/s "))
+                .style(Style::default().fg(Color::Green))
+                .data(self.bars(rate)),
+            rate,
+        );
+
+        let items = self.recent.iter().map(|(at, event)| {
+            let at = humantime::format_rfc3339_seconds(*at);
+
+            match event {
+                Event::Ping { id, tenant, count } => {
+                    Line::from(format!("{at}  ping  {id}  {tenant}  {count}"))
+                }
+                Event::Pong { id, tenant, count } => {
+                    Line::from(format!("{at}  pong  {id}  {tenant}  {count}")).dim()
+                }
+                Event::Sender { id, status, .. } => {
+                    let status = match status {
+                        SenderStatus::Alive => "online",
+                        SenderStatus::Stale => "offline",
+                    };
+
+                    Line::from(format!("{at}  sender  {id}  {status}")).italic()
+                }
+                Event::Adjusted {
+                    tenant,
+                    count,
+                    delta,
+                    ..
+                } => Line::from(format!("{at}  adjusted  {tenant}  {delta:+}  {count}")).italic(),
+                Event::Milestone { tenant, count } => {
+                    Line::from(format!("{at}  milestone  {tenant}  {count}")).bold()
+                }
+                Event::Rate { .. } | Event::History(_) => Line::default(),
+            }
+        });
+
+        frame.render_widget(
+            List::new(items).block(Block::bordered().title(" recent ")),
+            recent,
+        );
+
+        frame.render_widget(Line::from(" q: quit").dim(), help);
+    }
+}
+
+/// Shows the dashboard until the user quits or the receiver shuts down.
+pub async fn run(state: AppState, shutdown: &CancellationToken) -> eyre::Result<()> {
+    let events = state.events.subscribe();
+
+    server::tui::run(Dashboard::new(state), events, shutdown).await
+}
@@ -0,0 +1,139 @@
+//! Pings received as UDP datagrams.
+//!
+//! Each datagram carries the same JSON body as the HTTP ping and is counted for the default
+//! tenant. Once counted, the receiver replies with the pong to the source of the datagram, so the
+//! sender can measure the round trip and detect the lost datagrams.
+//!
+//! The datagrams carry no API key or JWT, so the transport can't be enabled with them, it would
+//! count the pings those are required for.
+
+use std::{
+    net::SocketAddr,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use axum::{extract::State, Json};
+use serde::Serialize;
+use tokio::net::UdpSocket;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, warn};
+
+use protocol::{Ping, Pong};
+
+use crate::{
+    audit::{Source, Transport},
+    ingest,
+    tenant::Tenant,
+    AppError, AppState,
+};
+
+/// Largest datagram accepted, bigger ones are truncated and fail to parse.
+const MAX_DATAGRAM: usize = 2048;
+
+#[derive(Debug, Default)]
+pub struct UdpStats {
+    received: AtomicU64,
+    invalid: AtomicU64,
+    rejected: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct UdpStatsSnapshot {
+    /// Datagrams received.
+    received: u64,
+    /// Datagrams that weren't a valid ping.
+    invalid: u64,
+    /// Pings that weren't counted, like when the tenant reached its quota.
+    rejected: u64,
+}
+
+impl UdpStats {
+    pub fn snapshot(&self) -> UdpStatsSnapshot {
+        UdpStatsSnapshot {
+            received: self.received.load(Ordering::Relaxed),
+            invalid: self.invalid.load(Ordering::Relaxed),
+            rejected: self.rejected.load(Ordering::Relaxed),
+        }
+    }
+}
+
+pub async fn stats(State(state): State<AppState>) -> Json<UdpStatsSnapshot> {
+    Json(state.udp.snapshot())
+}
+
+/// Receives the pings until the shutdown is cancelled.
+pub async fn serve(socket: UdpSocket, state: AppState, shutdown: CancellationToken) {
+    let mut buf = [0; MAX_DATAGRAM];
+
+    loop {
+        let (len, source) = tokio::select! {
+            _ = shutdown.cancelled() => break,
+            res = socket.recv_from(&mut buf) => match res {
+                Ok(received) => received,
+                Err(err) => {
+                    warn!(error = %err, "couldn't receive datagram");
+
+                    continue;
+                }
+            },
+        };
+
+        state.udp.received.fetch_add(1, Ordering::Relaxed);
+
+        handle(&socket, &state, &buf[..len], source).await;
+    }
+}
+
+async fn handle(socket: &UdpSocket, state: &AppState, datagram: &[u8], source: SocketAddr) {
+    let ping = match &state.ping_schema {
+        Some(schema) => serde_json::from_slice(datagram)
+            .map_err(AppError::from)
+            .and_then(|value| schema.decode(value)),
+        None => serde_json::from_slice::<Ping>(datagram).map_err(AppError::from),
+    };
+
+    let mut ping = match ping {
+        Ok(ping) => ping,
+        Err(err) => {
+            state.udp.invalid.fetch_add(1, Ordering::Relaxed);
+
+            if let Some(skipped) = state.log_sampler.sample() {
+                debug!(%source, error = ?err, skipped, "invalid ping datagram");
+            }
+
+            return;
+        }
+    };
+
+    let id = ping.id;
+    // The datagrams aren't authenticated, the users can't be vouched for
+    ping.metadata.user = None;
+
+    let from = Source {
+        transport: Transport::Udp,
+        addr: source,
+    };
+
+    if let Err(err) = ingest::count(state, from, Tenant::default(), ping).await {
+        state.udp.rejected.fetch_add(1, Ordering::Relaxed);
+
+        if let Some(skipped) = state.log_sampler.sample() {
+            debug!(%id, %source, error = ?err, skipped, "ping datagram rejected");
+        }
+
+        return;
+    }
+
+    let pong = match serde_json::to_vec(&Pong { id }) {
+        Ok(pong) => pong,
+        Err(err) => {
+            warn!(error = %err, "couldn't serialize pong");
+
+            return;
+        }
+    };
+
+    if let Err(err) = socket.send_to(&pong, source).await {
+        warn!(%id, %source, error = %err, "couldn't send pong datagram");
+    }
+}
@@ -0,0 +1,135 @@
+//! Totals of the pings by the user of the sender UI they were sent from.
+//!
+//! The senders with a login attribute the pings sent from their UI to the logged-in user, in the
+//! metadata, trusted only from the senders authenticated by an API key or a token. Only the
+//! most recently seen users are tracked, so the names can't grow the memory without bounds. The
+//! users are tracked by tenant and listed only for the tenant of the request, to the users logged
+//! in to the receiver if the login is enabled.
+
+use std::{
+    num::NonZeroUsize,
+    sync::{Mutex, MutexGuard},
+    time::SystemTime,
+};
+
+use axum::{extract::State, Json};
+use lru::LruCache;
+use serde::Serialize;
+
+use crate::{oidc::LoggedIn, tenant::Tenant, AppState};
+
+/// Number of users tracked, the least recently seen are dropped first.
+const TRACKED: NonZeroUsize = NonZeroUsize::new(1_000).unwrap();
+
+#[derive(Debug)]
+struct Entry {
+    pings: u64,
+    first_seen: SystemTime,
+    last_seen: SystemTime,
+}
+
+#[derive(Debug)]
+pub struct Users {
+    /// Entries by tenant and user.
+    entries: Mutex<LruCache<(Tenant, String), Entry>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UserSnapshot {
+    user: String,
+    pings: u64,
+    first_seen: String,
+    last_seen: String,
+}
+
+impl Default for Users {
+    fn default() -> Self {
+        Self {
+            entries: Mutex::new(LruCache::new(TRACKED)),
+        }
+    }
+}
+
+impl Users {
+    fn entries(&self) -> MutexGuard<'_, LruCache<(Tenant, String), Entry>> {
+        self.entries.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Counts a ping sent by the user for the tenant.
+    pub fn pinged(&self, tenant: &Tenant, user: &str) {
+        let now = SystemTime::now();
+        let mut entries = self.entries();
+        let key = (tenant.clone(), user.to_string());
+
+        match entries.get_mut(&key) {
+            Some(entry) => {
+                entry.pings += 1;
+                entry.last_seen = now;
+            }
+            None => {
+                entries.put(
+                    key,
+                    Entry {
+                        pings: 1,
+                        first_seen: now,
+                        last_seen: now,
+                    },
+                );
+            }
+        }
+    }
+
+    /// The users of the tenant with the most pings first.
+    fn snapshot(&self, tenant: &Tenant) -> Vec<UserSnapshot> {
+        let mut list: Vec<UserSnapshot> = self
+            .entries()
+            .iter()
+            .filter(|((of, _), _)| of == tenant)
+            .map(|((_, user), entry)| UserSnapshot {
+                user: user.clone(),
+                pings: entry.pings,
+                first_seen: humantime::format_rfc3339_seconds(entry.first_seen).to_string(),
+                last_seen: humantime::format_rfc3339_seconds(entry.last_seen).to_string(),
+            })
+            .collect();
+
+        list.sort_unstable_by(|a, b| b.pings.cmp(&a.pings).then_with(|| a.user.cmp(&b.user)));
+
+        list
+    }
+}
+
+pub async fn stats(
+    _: LoggedIn,
+    State(state): State<AppState>,
+    tenant: Tenant,
+) -> Json<Vec<UserSnapshot>> {
+    Json(state.users.snapshot(&tenant))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn users_listed_only_for_their_tenant() {
+        let users = Users::default();
+        let (a, b) = (Tenant::parse("a").unwrap(), Tenant::parse("b").unwrap());
+
+        users.pinged(&a, "alice");
+        users.pinged(&a, "alice");
+        users.pinged(&b, "alice");
+        users.pinged(&b, "bob");
+
+        let list = users.snapshot(&a);
+        assert_eq!(list.len(), 1);
+        assert_eq!((list[0].user.as_str(), list[0].pings), ("alice", 2));
+
+        let list: Vec<(String, u64)> = users
+            .snapshot(&b)
+            .into_iter()
+            .map(|user| (user.user, user.pings))
+            .collect();
+        assert_eq!(list, [("alice".to_string(), 1), ("bob".to_string(), 1)]);
+    }
+}
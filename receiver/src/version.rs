@@ -0,0 +1,79 @@
+//! Versions of the machine API.
+//!
+//! The endpoints are served under `/v1`, and on their paths before the versioning as deprecated
+//! aliases, whose responses carry the `Deprecation` header and a `Link` to the versioned path.
+//! The protocol can then change under `/v2` without breaking the senders not yet upgraded.
+
+use axum::{
+    extract::Request,
+    http::{header::LINK, HeaderName, HeaderValue},
+    middleware::{self, Next},
+    response::Response,
+    routing::MethodRouter,
+    Router,
+};
+
+use crate::AppState;
+
+/// Prefix of the current version of the API.
+pub const V1: &str = "/v1";
+
+static DEPRECATION: HeaderName = HeaderName::from_static("deprecation");
+
+/// Endpoint served under the version and on its legacy path.
+pub struct Endpoint {
+    path: &'static str,
+    legacy: &'static str,
+    route: MethodRouter<AppState>,
+}
+
+impl Endpoint {
+    pub fn new(path: &'static str, legacy: &'static str, route: MethodRouter<AppState>) -> Self {
+        Self {
+            path,
+            legacy,
+            route,
+        }
+    }
+}
+
+/// Routes of the endpoints, under `/v1` and deprecated on the legacy paths.
+pub fn routes(endpoints: impl IntoIterator<Item = Endpoint>) -> Router<AppState> {
+    let mut versioned = Router::new();
+    let mut legacy = Router::new();
+
+    for endpoint in endpoints {
+        let successor = format!("<{V1}{}>; rel=\"successor-version\"", endpoint.path);
+        let successor =
+            HeaderValue::from_str(&successor).expect("the paths are valid header values");
+
+        versioned = versioned.route(endpoint.path, endpoint.route.clone());
+        legacy = legacy.route(
+            endpoint.legacy,
+            endpoint
+                .route
+                .layer(middleware::from_fn(move |req: Request, next: Next| {
+                    deprecated(successor.clone(), req, next)
+                })),
+        );
+    }
+
+    legacy.nest(V1, versioned)
+}
+
+async fn deprecated(successor: HeaderValue, req: Request, next: Next) -> Response {
+    let mut res = next.run(req).await;
+
+    res.headers_mut()
+        .insert(DEPRECATION.clone(), HeaderValue::from_static("true"));
+    res.headers_mut().insert(LINK, successor);
+
+    res
+}
+
+/// Path of the request without the version, like the legacy paths that didn't change.
+pub fn unversioned(path: &str) -> &str {
+    path.strip_prefix(V1)
+        .filter(|path| path.starts_with('/'))
+        .unwrap_or(path)
+}
@@ -0,0 +1,457 @@
+//! Write-ahead log of the counters.
+//!
+//! Every change of a counter is appended with the new count and synced to the disk before the
+//! ping is acknowledged, so the counts survive a crash. At startup the last count of each tenant
+//! is restored and the log is compacted into a checkpoint with only those, which is also
+//! rewritten once enough records piled up.
+//!
+//! The idle time of the counters isn't logged, the restored counts expire counting from the
+//! startup.
+
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard},
+};
+
+use eyre::WrapErr;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::tenant::{Counters, QuotaExceeded, Tenant, TenantCount};
+
+/// Records appended after which the log is compacted.
+const COMPACT_AFTER: u64 = 100_000;
+
+/// Count of the tenant after a change.
+#[derive(Debug, Serialize, Deserialize)]
+struct Record {
+    tenant: Tenant,
+    count: u64,
+}
+
+#[derive(Debug)]
+pub struct Wal {
+    log: Mutex<Log>,
+}
+
+#[derive(Debug)]
+struct Log {
+    path: PathBuf,
+    file: File,
+    /// Records appended since the last checkpoint.
+    records: u64,
+    /// Appends that failed since the startup.
+    failures: u64,
+}
+
+/// State of the log, for the statistics.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct WalHealth {
+    /// Records appended since the last checkpoint.
+    records: u64,
+    /// Appends that failed since the startup.
+    failures: u64,
+}
+
+impl Log {
+    fn append(&mut self, record: &Record) -> io::Result<()> {
+        let res = self.write(record);
+        if res.is_err() {
+            self.failures += 1;
+        }
+
+        res
+    }
+
+    fn write(&mut self, record: &Record) -> io::Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+
+        self.commit(|file| {
+            file.write_all(&line)?;
+            file.sync_data()
+        })?;
+        self.records += 1;
+
+        Ok(())
+    }
+
+    /// Runs the write, truncating the log back to its length before it if it fails.
+    ///
+    /// A failed write can leave a partial record, the next one would be appended to it and the
+    /// replay would refuse the log.
+    fn commit(&mut self, write: impl FnOnce(&mut File) -> io::Result<()>) -> io::Result<()> {
+        let len = self.file.metadata()?.len();
+
+        let res = write(&mut self.file);
+        if res.is_err() {
+            if let Err(err) = self.file.set_len(len) {
+                warn!(error = %err, "couldn't truncate the partial record");
+            }
+        }
+
+        res
+    }
+
+    /// Replaces the log with the current counts, written aside and then renamed over it.
+    fn checkpoint(&mut self, counters: &Counters) -> io::Result<()> {
+        let mut tmp = OsString::from(&self.path);
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+
+        let mut writer = BufWriter::new(File::create(&tmp)?);
+        for TenantCount { tenant, count, .. } in counters.tenants() {
+            if count == 0 {
+                continue;
+            }
+
+            serde_json::to_writer(&mut writer, &Record { tenant, count })?;
+            writer.write_all(b"\n")?;
+        }
+        writer.into_inner()?.sync_all()?;
+
+        std::fs::rename(&tmp, &self.path)?;
+        // Persist the rename
+        if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            File::open(dir)?.sync_all()?;
+        }
+
+        self.file = append(&self.path)?;
+        self.records = 0;
+
+        Ok(())
+    }
+
+    fn compact_if_due(&mut self, counters: &Counters) {
+        if self.records < COMPACT_AFTER {
+            return;
+        }
+
+        if let Err(err) = self.checkpoint(counters) {
+            warn!(error = %err, "couldn't compact the write-ahead log");
+        }
+    }
+}
+
+fn append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// Reads the last count of each tenant, ignoring a truncated last record left by a crash.
+fn replay(path: &Path) -> eyre::Result<HashMap<Tenant, u64>> {
+    let mut counts = HashMap::new();
+
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(counts),
+        Err(err) => return Err(err.into()),
+    };
+
+    let mut lines = BufReader::new(file).lines().enumerate().peekable();
+    while let Some((n, line)) = lines.next() {
+        let record = match serde_json::from_str(&line?) {
+            Ok(record) => record,
+            Err(err) if lines.peek().is_none() => {
+                warn!(line = n + 1, error = %err, "ignoring the truncated last record");
+
+                break;
+            }
+            Err(err) => {
+                return Err(err).wrap_err_with(|| format!("invalid record on line {}", n + 1))
+            }
+        };
+
+        let Record { tenant, count } = record;
+        counts.insert(tenant, count);
+    }
+
+    Ok(counts)
+}
+
+impl Wal {
+    /// Restores the counts in the log and compacts it.
+    pub fn open(path: PathBuf, counters: &Counters) -> eyre::Result<Self> {
+        let counts =
+            replay(&path).wrap_err_with(|| format!("couldn't replay {}", path.display()))?;
+
+        info!(path = %path.display(), tenants = counts.len(), "write-ahead log replayed");
+
+        for (tenant, count) in counts {
+            counters.set(&tenant, count);
+        }
+
+        let mut log = Log {
+            file: append(&path)?,
+            path,
+            records: 0,
+            failures: 0,
+        };
+        log.checkpoint(counters)
+            .wrap_err("couldn't compact the write-ahead log")?;
+
+        Ok(Self {
+            log: Mutex::new(log),
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Log> {
+        self.log.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    pub fn health(&self) -> WalHealth {
+        let log = self.lock();
+
+        WalHealth {
+            records: log.records,
+            failures: log.failures,
+        }
+    }
+
+    /// Increments the tenant counter once the increment is logged.
+    ///
+    /// The counter and the log are updated under the same lock, so the order of the records
+    /// matches the one of the counts.
+    pub fn increment(
+        &self,
+        counters: &Counters,
+        tenant: &Tenant,
+    ) -> io::Result<Result<u64, QuotaExceeded>> {
+        let mut log = self.lock();
+
+        let count = match counters.increment(tenant) {
+            Ok(count) => count,
+            Err(err) => return Ok(Err(err)),
+        };
+
+        if let Err(err) = log.append(&Record {
+            tenant: tenant.clone(),
+            count,
+        }) {
+            // The ping isn't acknowledged, so it mustn't be counted either
+            counters.decrement(tenant);
+
+            return Err(err);
+        }
+
+        log.compact_if_due(counters);
+
+        Ok(Ok(count))
+    }
+
+    pub fn decrement(&self, counters: &Counters, tenant: &Tenant) -> io::Result<Option<u64>> {
+        let mut log = self.lock();
+
+        let previous = counters.get(tenant);
+        let Some(count) = counters.decrement(tenant) else {
+            return Ok(None);
+        };
+
+        if let Err(err) = log.append(&Record {
+            tenant: tenant.clone(),
+            count,
+        }) {
+            // Not logged, so not kept after a restart either
+            counters.set(tenant, previous);
+
+            return Err(err);
+        }
+        log.compact_if_due(counters);
+
+        Ok(Some(count))
+    }
+
+    /// Adds the delta to the counter, returning the previous and the new value.
+    pub fn add(&self, counters: &Counters, tenant: &Tenant, delta: i64) -> io::Result<(u64, u64)> {
+        let mut log = self.lock();
+
+        let (previous, count) = counters.add(tenant, delta);
+
+        if let Err(err) = log.append(&Record {
+            tenant: tenant.clone(),
+            count,
+        }) {
+            // Not logged, so not kept after a restart either
+            counters.set(tenant, previous);
+
+            return Err(err);
+        }
+        log.compact_if_due(counters);
+
+        Ok((previous, count))
+    }
+
+    pub fn set(&self, counters: &Counters, tenant: &Tenant, count: u64) -> io::Result<()> {
+        let mut log = self.lock();
+
+        log.append(&Record {
+            tenant: tenant.clone(),
+            count,
+        })?;
+        counters.set(tenant, count);
+        log.compact_if_due(counters);
+
+        Ok(())
+    }
+
+    /// Sets the count, returning the previous one.
+    pub fn replace(&self, counters: &Counters, tenant: &Tenant, count: u64) -> io::Result<u64> {
+        let mut log = self.lock();
+
+        log.append(&Record {
+            tenant: tenant.clone(),
+            count,
+        })?;
+        let previous = counters.replace(tenant, count);
+        log.compact_if_due(counters);
+
+        Ok(previous)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+
+    /// Path of a log in the temporary directory, removed once dropped.
+    struct TempLog(PathBuf);
+
+    impl TempLog {
+        fn new(content: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("wal-{}.jsonl", Uuid::new_v4()));
+            std::fs::write(&path, content).unwrap();
+
+            Self(path)
+        }
+    }
+
+    impl Drop for TempLog {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn tenant(name: &str) -> Tenant {
+        Tenant::parse(name).unwrap()
+    }
+
+    fn counters() -> Counters {
+        Counters::new(None, 100, None)
+    }
+
+    #[test]
+    fn replay_keeps_the_last_count_of_each_tenant() {
+        let log = TempLog::new(concat!(
+            "{\"tenant\":\"a\",\"count\":1}\n",
+            "{\"tenant\":\"b\",\"count\":1}\n",
+            "{\"tenant\":\"a\",\"count\":2}\n",
+        ));
+
+        let counts = replay(&log.0).unwrap();
+
+        assert_eq!(counts, HashMap::from([(tenant("a"), 2), (tenant("b"), 1)]));
+    }
+
+    #[test]
+    fn replay_ignores_a_truncated_last_record() {
+        let log = TempLog::new(concat!(
+            "{\"tenant\":\"a\",\"count\":1}\n",
+            "{\"tenant\":\"a\",\"count\":2}\n",
+            "{\"tenant\":\"a\",\"co",
+        ));
+
+        let counts = replay(&log.0).unwrap();
+
+        assert_eq!(counts, HashMap::from([(tenant("a"), 2)]));
+    }
+
+    #[test]
+    fn replay_refuses_an_invalid_record_before_the_last() {
+        let log = TempLog::new(concat!(
+            "{\"tenant\":\"a\",\"count\":1}\n",
+            "{\"tenant\":\"a\",\"co\n",
+            "{\"tenant\":\"a\",\"count\":3}\n",
+        ));
+
+        let err = replay(&log.0).unwrap_err();
+
+        assert_eq!(err.to_string(), "invalid record on line 2");
+    }
+
+    #[test]
+    fn replay_of_a_missing_log_is_empty() {
+        let path = std::env::temp_dir().join(format!("wal-{}.jsonl", Uuid::new_v4()));
+
+        assert!(replay(&path).unwrap().is_empty());
+    }
+
+    #[test]
+    fn open_restores_and_compacts_the_log() {
+        let log = TempLog::new(concat!(
+            "{\"tenant\":\"a\",\"count\":1}\n",
+            "{\"tenant\":\"a\",\"count\":2}\n",
+            "{\"tenant\":\"b\",\"count\":1}\n",
+            "{\"tenant\":\"b\",\"count\":0}\n",
+            "{\"tenant\":\"a\",\"co",
+        ));
+        let counters = counters();
+
+        Wal::open(log.0.clone(), &counters).unwrap();
+
+        assert_eq!(counters.get(&tenant("a")), 2);
+        assert_eq!(counters.get(&tenant("b")), 0);
+        assert_eq!(
+            std::fs::read_to_string(&log.0).unwrap(),
+            "{\"tenant\":\"a\",\"count\":2}\n"
+        );
+    }
+
+    #[test]
+    fn changes_survive_a_restart() {
+        let log = TempLog::new("");
+        let counters = counters();
+        let wal = Wal::open(log.0.clone(), &counters).unwrap();
+
+        wal.increment(&counters, &tenant("a")).unwrap().unwrap();
+        wal.increment(&counters, &tenant("a")).unwrap().unwrap();
+        wal.add(&counters, &tenant("b"), 5).unwrap();
+        wal.decrement(&counters, &tenant("b")).unwrap();
+        drop(wal);
+
+        let restored = self::counters();
+        Wal::open(log.0.clone(), &restored).unwrap();
+
+        assert_eq!(restored.get(&tenant("a")), 2);
+        assert_eq!(restored.get(&tenant("b")), 4);
+    }
+
+    #[test]
+    fn failed_append_leaves_no_partial_record() {
+        let log = TempLog::new("");
+        let counters = counters();
+        let wal = Wal::open(log.0.clone(), &counters).unwrap();
+
+        wal.increment(&counters, &tenant("a")).unwrap().unwrap();
+        let err = wal
+            .lock()
+            .commit(|file| {
+                file.write_all(b"{\"tenant\":\"a\",\"co")?;
+
+                Err(io::Error::other("disk full"))
+            })
+            .unwrap_err();
+        assert_eq!(err.to_string(), "disk full");
+        wal.increment(&counters, &tenant("a")).unwrap().unwrap();
+        drop(wal);
+
+        let restored = self::counters();
+        Wal::open(log.0.clone(), &restored).unwrap();
+
+        assert_eq!(restored.get(&tenant("a")), 2);
+    }
+}
@@ -0,0 +1,222 @@
+//! Dead man's switch firing when no ping is received for a while.
+//!
+//! The pings are considered stale once none is received for the configured time, counting from
+//! the startup, and recovered on the next one. Each change fires the configured actions once.
+
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime},
+};
+
+use clap::{Args, ValueEnum};
+use serde::Serialize;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+use url::Url;
+
+use crate::AppState;
+
+/// Shortest and longest times between the checks.
+const MIN_CHECK_INTERVAL: Duration = Duration::from_millis(10);
+const MAX_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Args, Serialize)]
+pub struct WatchdogArgs {
+    /// Time without pings after which they are considered stale
+    #[arg(long, value_parser = humantime::parse_duration)]
+    #[serde(serialize_with = "crate::admin::humantime_opt")]
+    stale_after: Option<Duration>,
+    /// What happens when the pings become stale or recover, can be repeated
+    #[arg(
+        long = "stale-action",
+        value_enum,
+        default_values_t = [StaleAction::Log],
+        requires = "stale_after"
+    )]
+    stale_actions: Vec<StaleAction>,
+    /// Url the changes are posted to with the webhook action
+    #[arg(long, value_name = "URL", required_if_eq("stale_actions", "webhook"))]
+    stale_webhook: Option<Url>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StaleAction {
+    /// Logs a warning.
+    Log,
+    /// Posts the change as JSON to the webhook url.
+    Webhook,
+    /// Sets the `receiver_stale` gauge to one while stale.
+    Metric,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Stale,
+    Recovered,
+}
+
+/// Body posted to the webhook.
+#[derive(Debug, Serialize)]
+struct Alert {
+    status: Status,
+    /// RFC 3339 timestamp of the last ping, missing if none was received since the startup.
+    last_ping_at: Option<String>,
+    stale_after: String,
+}
+
+#[derive(Debug)]
+struct Pings {
+    /// Last ping, or the startup.
+    last: Instant,
+    last_at: Option<SystemTime>,
+    stale: bool,
+}
+
+#[derive(Debug)]
+pub struct Watchdog {
+    stale_after: Duration,
+    actions: Vec<StaleAction>,
+    webhook: Option<Url>,
+    pings: Mutex<Pings>,
+}
+
+impl Watchdog {
+    pub fn new(args: &WatchdogArgs) -> Option<Self> {
+        let stale_after = args.stale_after?;
+
+        Some(Self {
+            stale_after,
+            actions: args.stale_actions.clone(),
+            webhook: args.stale_webhook.clone(),
+            pings: Mutex::new(Pings {
+                last: Instant::now(),
+                last_at: None,
+                stale: false,
+            }),
+        })
+    }
+
+    fn pings(&self) -> std::sync::MutexGuard<'_, Pings> {
+        self.pings.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Records a ping, returning whether the pings recovered.
+    fn pinged(&self) -> bool {
+        let mut pings = self.pings();
+
+        pings.last = Instant::now();
+        pings.last_at = Some(SystemTime::now());
+
+        std::mem::replace(&mut pings.stale, false)
+    }
+
+    /// Marks the pings as stale if none was received in time, returning whether they became so.
+    fn check(&self) -> bool {
+        let mut pings = self.pings();
+
+        if pings.stale || pings.last.elapsed() < self.stale_after {
+            return false;
+        }
+
+        pings.stale = true;
+
+        true
+    }
+
+    async fn fire(&self, state: &AppState, status: Status) {
+        let last_ping_at = self
+            .pings()
+            .last_at
+            .map(|at| humantime::format_rfc3339_millis(at).to_string());
+
+        for action in &self.actions {
+            match action {
+                StaleAction::Log => match status {
+                    Status::Stale => warn!(
+                        last_ping_at,
+                        "no ping received for {:?}, the pings are stale", self.stale_after
+                    ),
+                    Status::Recovered => info!("ping received, the pings recovered"),
+                },
+                StaleAction::Webhook => {
+                    let Some(url) = &self.webhook else {
+                        continue;
+                    };
+
+                    let alert = Alert {
+                        status,
+                        last_ping_at: last_ping_at.clone(),
+                        stale_after: humantime::format_duration(self.stale_after).to_string(),
+                    };
+
+                    let res = state
+                        .client
+                        .post(url.clone())
+                        .json(&alert)
+                        .send()
+                        .await
+                        .and_then(|res| res.error_for_status());
+
+                    if let Err(err) = res {
+                        warn!(error = %err, %url, "couldn't post the stale alert");
+                    }
+                }
+                StaleAction::Metric => {
+                    let stale = match status {
+                        Status::Stale => 1.0,
+                        Status::Recovered => 0.0,
+                    };
+
+                    metrics::gauge!("receiver_stale").set(stale);
+                }
+            }
+        }
+
+        crate::notify::stale(state, status, self.stale_after);
+    }
+}
+
+/// Records a ping, firing the actions if the pings were stale.
+pub fn pinged(state: &AppState) {
+    let Some(watchdog) = &state.watchdog else {
+        return;
+    };
+
+    if watchdog.pinged() {
+        let state = state.clone();
+
+        tokio::spawn(async move {
+            if let Some(watchdog) = &state.watchdog {
+                watchdog.fire(&state, Status::Recovered).await;
+            }
+        });
+    }
+}
+
+/// Checks the pings until the shutdown is cancelled, if enabled.
+pub async fn run(state: AppState, shutdown: CancellationToken) {
+    let Some(watchdog) = &state.watchdog else {
+        return;
+    };
+
+    if watchdog.actions.contains(&StaleAction::Metric) {
+        metrics::gauge!("receiver_stale").set(0.0);
+    }
+
+    let mut interval = tokio::time::interval(
+        (watchdog.stale_after / 4).clamp(MIN_CHECK_INTERVAL, MAX_CHECK_INTERVAL),
+    );
+
+    loop {
+        tokio::select! {
+            _ = shutdown.cancelled() => break,
+            _ = interval.tick() => {}
+        }
+
+        if watchdog.check() {
+            watchdog.fire(&state, Status::Stale).await;
+        }
+    }
+}
@@ -0,0 +1,36 @@
+//! Bursts of pings sent concurrently.
+
+use std::future::Future;
+
+use futures::StreamExt;
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct BurstReport {
+    pub requested: u32,
+    pub succeeded: u32,
+    pub failed: u32,
+}
+
+/// Runs `count` pings with at most `concurrency` of them in flight.
+///
+/// Each ping returns whether it was delivered.
+pub async fn run<F, Fut>(count: u32, concurrency: usize, ping: F) -> BurstReport
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = bool>,
+{
+    let succeeded = futures::stream::iter(0..count)
+        .map(|_| ping())
+        .buffer_unordered(concurrency.max(1))
+        .fold(0, |succeeded, delivered| async move {
+            succeeded + u32::from(delivered)
+        })
+        .await;
+
+    BurstReport {
+        requested: count,
+        succeeded,
+        failed: count - succeeded,
+    }
+}
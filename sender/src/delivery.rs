@@ -0,0 +1,505 @@
+//! Delivery of the pings to the receiver.
+
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, SystemTime},
+};
+
+use clap::{Args, ValueEnum};
+use eyre::{eyre, OptionExt};
+use protocol::{
+    Metadata, Ping, Registration, IDEMPOTENCY_KEY_HEADER, MDNS_SERVICE, REQUEST_ID_HEADER,
+};
+use reqwest::{
+    header::{HeaderValue, RETRY_AFTER},
+    StatusCode, Url,
+};
+use tracing::{debug, warn};
+use url::Host;
+use uuid::Uuid;
+
+#[cfg(feature = "kubernetes")]
+use crate::kubernetes::Kubernetes;
+use crate::{dns::Dns, icmp::Icmp, mdns::Mdns, srv::Srv, stats::Stats, udp};
+
+/// Delay before the first retry, doubled at each attempt.
+const RETRY_BACKOFF: Duration = Duration::from_millis(100);
+/// Longest delay between two retries, however many were attempted.
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(10);
+
+/// Longest `Retry-After` waited for before retrying a throttled ping, it fails if longer.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Args)]
+pub struct DeliveryArgs {
+    /// Url of the receiver internal port
+    #[arg(default_value = "http://receiver:9000")]
+    pub receiver: Url,
+    /// DNS SRV record listing the receivers, used instead of the url
+    #[arg(long, value_name = "NAME", conflicts_with = "receiver")]
+    pub receiver_srv: Option<String>,
+    /// Discover the receivers advertised over mDNS on the local network, instead of the url
+    #[arg(long, conflicts_with_all = ["receiver", "receiver_srv"])]
+    pub discover: bool,
+    /// Kubernetes service, as `[NAMESPACE/]NAME`, whose endpoints the pings are spread over
+    #[cfg(feature = "kubernetes")]
+    #[arg(long, value_name = "SERVICE", conflicts_with_all = ["receiver", "receiver_srv", "discover"])]
+    pub kubernetes_service: Option<String>,
+    /// API key sent along the pings, for the receivers requiring one
+    #[arg(long, env = "PING_API_KEY", hide_env_values = true)]
+    pub api_key: Option<String>,
+    /// Number of times a failed ping is sent again
+    #[arg(long, default_value = "0")]
+    pub retries: u32,
+    /// How the pings are sent to the receiver
+    #[arg(long, value_enum, default_value_t = Transport::Http)]
+    pub transport: Transport,
+    /// Time the resolved addresses and SRV records are used before resolving them again
+    #[arg(long, default_value = "30s", value_parser = humantime::parse_duration)]
+    pub dns_ttl: Duration,
+}
+
+impl DeliveryArgs {
+    /// Sends the pings over HTTP to the receiver at the url, with the defaults of the flags.
+    pub fn new(receiver: Url) -> Self {
+        Self {
+            receiver,
+            receiver_srv: None,
+            discover: false,
+            #[cfg(feature = "kubernetes")]
+            kubernetes_service: None,
+            api_key: None,
+            retries: 0,
+            transport: Transport::Http,
+            dns_ttl: Duration::from_secs(30),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Transport {
+    /// Posts the ping to the receiver API
+    #[default]
+    Http,
+    /// Posts the ping to the receiver API, also sending an ICMP echo request to its host to
+    /// compare the latency of the API with the one of the network
+    Icmp,
+    /// Sends the ping as a UDP datagram to the receiver port
+    Udp,
+}
+
+/// A ping acknowledged by the receiver.
+#[derive(Debug, Clone, Copy)]
+pub struct Delivered {
+    /// Status of the response, [`None`] for the transports not using HTTP.
+    pub status: Option<StatusCode>,
+    /// Round-trip time, [`None`] when the round trip is completed by the pong.
+    pub latency: Option<Duration>,
+}
+
+/// Where the pings are sent to.
+#[derive(Debug)]
+enum Receivers {
+    Url(Url),
+    Srv(Box<Srv>),
+    Mdns(Mdns),
+    #[cfg(feature = "kubernetes")]
+    Kubernetes(Kubernetes),
+}
+
+#[derive(Debug)]
+pub struct Delivery {
+    /// Replaced when the addresses of the receiver change, to not reuse the old connections.
+    client: Mutex<reqwest::Client>,
+    dns: Dns,
+    receivers: Receivers,
+    callback: Option<Url>,
+    api_key: Option<String>,
+    retries: u32,
+    transport: Transport,
+    /// Set when the ICMP echoes are sent along the pings.
+    icmp: Option<Icmp>,
+    stats: Stats,
+    /// Id the sender registers with, sent along the pings.
+    id: Uuid,
+    hostname: Option<String>,
+    /// Sequence number of the next ping.
+    sequence: AtomicU64,
+}
+
+/// The receiver throttled the ping, asking to wait before sending it again.
+#[derive(Debug, Clone, Copy)]
+pub struct Throttled {
+    pub retry_after: Duration,
+}
+
+impl std::fmt::Display for Throttled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "throttled by the receiver for {:?}", self.retry_after)
+    }
+}
+
+/// Status of the response that caused the error, if it was received.
+pub fn error_status(err: &eyre::Report) -> Option<StatusCode> {
+    err.downcast_ref::<reqwest::Error>()
+        .and_then(reqwest::Error::status)
+}
+
+/// Resolves the address of the receiver, using the default port of the scheme if missing.
+pub(crate) async fn resolve(url: &Url) -> eyre::Result<SocketAddr> {
+    let port = url.port_or_known_default().unwrap_or_default();
+
+    match url.host().ok_or_eyre("the receiver url has no host")? {
+        Host::Ipv4(ip) => Ok((ip, port).into()),
+        Host::Ipv6(ip) => Ok((ip, port).into()),
+        Host::Domain(domain) => tokio::net::lookup_host((domain, port))
+            .await?
+            .next()
+            .ok_or_else(|| eyre!("couldn't resolve {domain}")),
+    }
+}
+
+fn http_client(dns: &Dns) -> reqwest::Result<reqwest::Client> {
+    reqwest::Client::builder()
+        .dns_resolver(Arc::new(dns.clone()))
+        .build()
+}
+
+/// Delay before sending the message again after the error, if it can be retried.
+fn retry_delay(err: &eyre::Report, attempt: u32) -> Option<Duration> {
+    if let Some(throttled) = err.downcast_ref::<Throttled>() {
+        return (throttled.retry_after <= MAX_RETRY_AFTER).then_some(throttled.retry_after);
+    }
+
+    let retryable = err
+        .downcast_ref::<reqwest::Error>()
+        .is_some_and(|err| err.status().is_none_or(|status| status.is_server_error()));
+
+    retryable.then(|| {
+        2u32.checked_pow(attempt)
+            .and_then(|factor| RETRY_BACKOFF.checked_mul(factor))
+            .map_or(MAX_RETRY_BACKOFF, |delay| delay.min(MAX_RETRY_BACKOFF))
+    })
+}
+
+impl Delivery {
+    pub fn new(args: DeliveryArgs, callback: Option<Url>) -> eyre::Result<Self> {
+        let icmp = match args.transport {
+            Transport::Icmp => Some(Icmp::new()?),
+            Transport::Http | Transport::Udp => None,
+        };
+
+        let dns = Dns::new(args.dns_ttl);
+        let receivers = match args.receiver_srv {
+            Some(name) => Receivers::Srv(Box::new(Srv::new(name, args.dns_ttl)?)),
+            None if args.discover => Receivers::Mdns(Mdns::new()?),
+            None => Receivers::Url(args.receiver),
+        };
+        #[cfg(feature = "kubernetes")]
+        let receivers = match args.kubernetes_service {
+            Some(service) => Receivers::Kubernetes(Kubernetes::new(service)),
+            None => receivers,
+        };
+
+        Ok(Self {
+            client: Mutex::new(http_client(&dns)?),
+            dns,
+            receivers,
+            callback,
+            api_key: args.api_key,
+            retries: args.retries,
+            transport: args.transport,
+            icmp,
+            stats: Stats::default(),
+            id: Uuid::new_v4(),
+            hostname: gethostname::gethostname().into_string().ok(),
+            sequence: AtomicU64::new(0),
+        })
+    }
+
+    /// The receiver url, or where the receivers are discovered from.
+    pub fn receiver(&self) -> &str {
+        match &self.receivers {
+            Receivers::Url(url) => url.as_str(),
+            Receivers::Srv(srv) => srv.name(),
+            Receivers::Mdns(_) => MDNS_SERVICE,
+            #[cfg(feature = "kubernetes")]
+            Receivers::Kubernetes(kubernetes) => kubernetes.service(),
+        }
+    }
+
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    /// Metadata of the next ping.
+    fn metadata(&self, user: Option<&str>) -> Metadata {
+        Metadata {
+            hostname: self.hostname.clone(),
+            version: Some(env!("CARGO_PKG_VERSION").to_string()),
+            sequence: Some(self.sequence.fetch_add(1, Ordering::Relaxed)),
+            sent_at: Some(humantime::format_rfc3339_millis(SystemTime::now()).to_string()),
+            user: user.map(str::to_string),
+        }
+    }
+
+    /// Receiver to send the next ping to.
+    async fn target(&self) -> eyre::Result<Url> {
+        match &self.receivers {
+            Receivers::Url(url) => Ok(url.clone()),
+            Receivers::Srv(srv) => srv.pick().await,
+            Receivers::Mdns(mdns) => mdns.pick().await,
+            #[cfg(feature = "kubernetes")]
+            Receivers::Kubernetes(kubernetes) => kubernetes.pick().await,
+        }
+    }
+
+    /// Client for the current addresses of the receiver.
+    async fn client(&self, receiver: &Url) -> reqwest::Client {
+        if let Some(Host::Domain(host)) = receiver.host() {
+            // A failed resolution is reported by the request
+            if let Ok(true) = self.dns.refresh(host).await {
+                match http_client(&self.dns) {
+                    Ok(client) => {
+                        *self.client.lock().unwrap_or_else(|err| err.into_inner()) = client;
+                    }
+                    Err(err) => warn!(error = %err, "couldn't replace the client"),
+                }
+            }
+        }
+
+        self.client
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .clone()
+    }
+
+    async fn send(
+        &self,
+        receiver: &Url,
+        path: &str,
+        body: &Ping,
+        request_id: Option<&HeaderValue>,
+    ) -> eyre::Result<StatusCode> {
+        let req = self
+            .client(receiver)
+            .await
+            .post(receiver.join(path)?)
+            .header(IDEMPOTENCY_KEY_HEADER, body.id.to_string())
+            .json(body);
+        let req = match &self.api_key {
+            Some(key) => req.bearer_auth(key),
+            None => req,
+        };
+        let req = match request_id {
+            Some(id) => req.header(REQUEST_ID_HEADER, id),
+            None => req,
+        };
+
+        let res = req.send().await?;
+
+        let retry_after = (res.status() == StatusCode::TOO_MANY_REQUESTS)
+            .then(|| res.headers().get(RETRY_AFTER))
+            .flatten()
+            .and_then(|value| value.to_str().ok()?.parse().ok())
+            .map(Duration::from_secs);
+
+        match (res.error_for_status_ref(), retry_after) {
+            (Ok(_), _) => Ok(res.status()),
+            (Err(err), Some(retry_after)) => {
+                Err(eyre::Report::new(err).wrap_err(Throttled { retry_after }))
+            }
+            (Err(err), None) => Err(err.into()),
+        }
+    }
+
+    /// Sends the message, retrying on connection and server errors.
+    async fn deliver(
+        &self,
+        receiver: &Url,
+        path: &str,
+        body: &Ping,
+        request_id: Option<&HeaderValue>,
+    ) -> eyre::Result<StatusCode> {
+        let mut attempt = 0;
+
+        loop {
+            let err = match self.send(receiver, path, body, request_id).await {
+                Ok(status) => return Ok(status),
+                Err(err) => err,
+            };
+
+            let delay = match retry_delay(&err, attempt) {
+                Some(delay) if attempt < self.retries => delay,
+                _ => return Err(err),
+            };
+            attempt += 1;
+
+            warn!(id = %body.id, attempt, error = %err, "retrying in {delay:?}");
+
+            self.stats.retried(receiver.as_str());
+
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    /// Sends a ping, returning the response status and the latency.
+    pub async fn ping(&self, id: Uuid) -> eyre::Result<Delivered> {
+        self.ping_traced(id, None, None).await
+    }
+
+    /// Sends a ping on behalf of a request, passing its id along over HTTP, and of the user
+    /// logged in to the UI if any.
+    pub async fn ping_traced(
+        &self,
+        id: Uuid,
+        request_id: Option<&HeaderValue>,
+        user: Option<&str>,
+    ) -> eyre::Result<Delivered> {
+        let receiver = self.target().await?;
+
+        let Some(icmp) = &self.icmp else {
+            return self.transmit(&receiver, id, request_id, user).await;
+        };
+
+        let (delivered, ()) = tokio::join!(
+            self.transmit(&receiver, id, request_id, user),
+            self.echo(icmp, &receiver, id)
+        );
+
+        delivered
+    }
+
+    /// Sends the ping over the transport.
+    async fn transmit(
+        &self,
+        receiver: &Url,
+        id: Uuid,
+        request_id: Option<&HeaderValue>,
+        user: Option<&str>,
+    ) -> eyre::Result<Delivered> {
+        if self.transport == Transport::Udp {
+            return self.datagram(receiver, id, user).await;
+        }
+
+        let ping = Ping {
+            id,
+            callback: self.callback.clone(),
+            sender: Some(self.id),
+            metadata: self.metadata(user),
+        };
+
+        self.stats.sent(ping.id, receiver.as_str());
+
+        let status = match self.deliver(receiver, "v1/ping", &ping, request_id).await {
+            Ok(status) => status,
+            Err(err) => {
+                self.stats
+                    .failed(ping.id, receiver.as_str(), &err.to_string());
+
+                return Err(err);
+            }
+        };
+
+        let latency = if ping.callback.is_some() {
+            None
+        } else {
+            self.stats.completed(ping.id)
+        };
+
+        Ok(Delivered {
+            status: Some(status),
+            latency,
+        })
+    }
+
+    /// Sends an echo request carrying the id of the ping to the receiver host.
+    async fn echo(&self, icmp: &Icmp, receiver: &Url, id: Uuid) {
+        match icmp.echo(receiver, id.as_bytes()).await {
+            Ok(rtt) => self.stats.echoed(rtt),
+            Err(err) => debug!(error = format!("{err:#}"), %id, "no echo reply"),
+        }
+    }
+
+    /// Sends the ping as a datagram, waiting for the pong from the receiver.
+    async fn datagram(
+        &self,
+        receiver: &Url,
+        id: Uuid,
+        user: Option<&str>,
+    ) -> eyre::Result<Delivered> {
+        let ping = serde_json::to_vec(&Ping {
+            id,
+            callback: None,
+            sender: Some(self.id),
+            metadata: self.metadata(user),
+        })?;
+
+        self.stats.sent(id, receiver.as_str());
+
+        let res = match resolve(receiver).await {
+            Ok(target) => udp::exchange(target, &ping, id).await,
+            Err(err) => Err(err),
+        };
+
+        match res {
+            Ok(Some(rtt)) => {
+                self.stats.completed_in(id, rtt);
+
+                Ok(Delivered {
+                    status: None,
+                    latency: Some(rtt),
+                })
+            }
+            Ok(None) => {
+                let err = eyre!("no pong received within {:?}", udp::PONG_TIMEOUT);
+
+                self.stats.lost(id, receiver.as_str(), &err.to_string());
+
+                Err(err)
+            }
+            Err(err) => {
+                self.stats.failed(id, receiver.as_str(), &err.to_string());
+
+                Err(err)
+            }
+        }
+    }
+
+    /// Registers the sender with the receiver.
+    pub async fn register(&self, registration: &Registration) -> eyre::Result<()> {
+        let receiver = self.target().await?;
+
+        self.client(&receiver)
+            .await
+            .post(receiver.join("v1/register")?)
+            .json(registration)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+
+    pub async fn pong(&self) -> eyre::Result<()> {
+        let pong = Ping {
+            id: Uuid::new_v4(),
+            callback: None,
+            sender: Some(self.id),
+            metadata: Metadata::default(),
+        };
+
+        self.deliver(&self.target().await?, "v1/pong", &pong, None)
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,84 @@
+//! Receivers discovered at runtime, updated in the background while the pings are sent.
+
+use std::{collections::BTreeMap, sync::Mutex, time::Duration};
+
+use eyre::eyre;
+use rand::seq::IteratorRandom;
+use tokio::sync::Notify;
+use tracing::info;
+use url::Url;
+
+/// Time a ping waits for the first receiver to be discovered.
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Default)]
+pub(crate) struct Discovered {
+    /// Receivers by the name they were discovered with.
+    receivers: Mutex<BTreeMap<String, Url>>,
+    changed: Notify,
+}
+
+impl Discovered {
+    fn receivers(&self) -> std::sync::MutexGuard<'_, BTreeMap<String, Url>> {
+        self.receivers.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    pub(crate) fn insert(&self, name: String, url: Url) {
+        let mut receivers = self.receivers();
+
+        if receivers.get(&name) != Some(&url) {
+            info!(name, %url, "discovered receiver");
+        }
+
+        receivers.insert(name, url);
+
+        self.changed.notify_waiters();
+    }
+
+    pub(crate) fn remove(&self, name: &str) {
+        if let Some(url) = self.receivers().remove(name) {
+            info!(name, %url, "receiver removed");
+        }
+    }
+
+    /// Replaces all the receivers, like when the full list is discovered at once.
+    #[cfg(feature = "kubernetes")]
+    pub(crate) fn replace(&self, new: BTreeMap<String, Url>) {
+        let mut receivers = self.receivers();
+
+        if *receivers != new {
+            info!(receivers = ?new.keys().collect::<Vec<_>>(), "the receivers changed");
+        }
+
+        *receivers = new;
+
+        self.changed.notify_waiters();
+    }
+
+    fn choose(&self) -> Option<Url> {
+        self.receivers()
+            .values()
+            .choose(&mut rand::thread_rng())
+            .cloned()
+    }
+
+    /// Picks a receiver at random for a ping, waiting for one to be discovered.
+    pub(crate) async fn pick(&self) -> eyre::Result<Url> {
+        let discovery = async {
+            loop {
+                // Created before the check to not miss the receivers discovered in between
+                let changed = self.changed.notified();
+
+                if let Some(url) = self.choose() {
+                    return url;
+                }
+
+                changed.await;
+            }
+        };
+
+        tokio::time::timeout(DISCOVERY_TIMEOUT, discovery)
+            .await
+            .map_err(|_| eyre!("no receivers discovered in {DISCOVERY_TIMEOUT:?}"))
+    }
+}
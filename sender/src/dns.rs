@@ -0,0 +1,117 @@
+//! Resolution of the receiver host, cached for a limited time.
+//!
+//! The addresses are resolved again once expired, so a change of the DNS records, like on a
+//! failover, is picked up without restarting the sender.
+
+use std::{
+    collections::HashMap,
+    io,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use tracing::{info, warn};
+
+#[derive(Debug)]
+struct Entry {
+    /// Sorted to compare them between the resolutions.
+    addrs: Vec<SocketAddr>,
+    resolved: Instant,
+}
+
+/// Addresses resolved for the hosts.
+#[derive(Debug, Clone)]
+pub(crate) struct Dns {
+    ttl: Duration,
+    cache: Arc<Mutex<HashMap<String, Entry>>>,
+}
+
+impl Dns {
+    pub(crate) fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            cache: Arc::default(),
+        }
+    }
+
+    fn cached(&self, host: &str) -> Option<Vec<SocketAddr>> {
+        let cache = self.cache.lock().unwrap_or_else(|err| err.into_inner());
+
+        cache
+            .get(host)
+            .filter(|entry| entry.resolved.elapsed() < self.ttl)
+            .map(|entry| entry.addrs.clone())
+    }
+
+    /// Resolves the host if expired, returning whether the addresses changed.
+    ///
+    /// If the resolution fails the previous addresses are kept, until it succeeds again.
+    pub(crate) async fn refresh(&self, host: &str) -> io::Result<bool> {
+        if self.cached(host).is_some() {
+            return Ok(false);
+        }
+
+        let res = tokio::net::lookup_host((host, 0)).await;
+
+        let mut cache = self.cache.lock().unwrap_or_else(|err| err.into_inner());
+
+        let mut addrs: Vec<SocketAddr> = match res {
+            Ok(addrs) => addrs.collect(),
+            Err(err) if cache.contains_key(host) => {
+                warn!(host, error = %err, "couldn't resolve again, keeping the previous addresses");
+
+                return Ok(false);
+            }
+            Err(err) => return Err(err),
+        };
+        addrs.sort_unstable();
+        addrs.dedup();
+
+        let entry = Entry {
+            addrs,
+            resolved: Instant::now(),
+        };
+
+        let changed = match cache.insert(host.to_string(), entry) {
+            Some(previous) if previous.addrs != cache[host].addrs => {
+                info!(
+                    host,
+                    previous = ?previous.addrs,
+                    current = ?cache[host].addrs,
+                    "the addresses of the host changed"
+                );
+
+                true
+            }
+            Some(_) | None => false,
+        };
+
+        Ok(changed)
+    }
+
+    async fn resolve(&self, host: &str) -> io::Result<Vec<SocketAddr>> {
+        self.refresh(host).await?;
+
+        let cache = self.cache.lock().unwrap_or_else(|err| err.into_inner());
+
+        Ok(cache
+            .get(host)
+            .map(|entry| entry.addrs.clone())
+            .unwrap_or_default())
+    }
+}
+
+impl Resolve for Dns {
+    fn resolve(&self, name: Name) -> Resolving {
+        let dns = self.clone();
+
+        Box::pin(async move {
+            let addrs = dns.resolve(name.as_str()).await?;
+            let addrs: Addrs = Box::new(addrs.into_iter());
+
+            Ok(addrs)
+        })
+    }
+}
@@ -0,0 +1,147 @@
+//! Live delivery events streamed to the sender page.
+
+use axum::{
+    extract::{
+        ws::{close_code, CloseFrame, Message, WebSocket},
+        State, WebSocketUpgrade,
+    },
+    response::Response,
+};
+use serde::Serialize;
+use server::{Beat, Heartbeat};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use crate::{stats::StatsSnapshot, AppState};
+
+/// Capacity of the channel, slower clients skip the older events.
+const CAPACITY: usize = 128;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    /// The receiver acknowledged the ping.
+    Delivered { id: Uuid, latency_ms: Option<f64> },
+    /// The ping couldn't be delivered.
+    Failed { id: Uuid, error: String },
+    /// The receiver called back with the pong.
+    Pong { id: Uuid, latency_ms: f64 },
+}
+
+/// Event sent on the WebSocket, with the statistics after it happened.
+#[derive(Debug, Clone, Serialize)]
+pub struct EventMessage {
+    #[serde(flatten)]
+    pub event: Event,
+    pub stats: StatsSnapshot,
+}
+
+#[derive(Debug)]
+pub struct Events {
+    tx: broadcast::Sender<EventMessage>,
+}
+
+impl Events {
+    pub fn new() -> Self {
+        let (tx, _rx) = broadcast::channel(CAPACITY);
+
+        Self { tx }
+    }
+
+    pub fn publish(&self, event: Event, stats: StatsSnapshot) {
+        // Fails only when no page is connected
+        let _ = self.tx.send(EventMessage { event, stats });
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<EventMessage> {
+        self.tx.subscribe()
+    }
+}
+
+pub async fn events(State(state): State<AppState>, ws: WebSocketUpgrade) -> Response {
+    let rx = state.events.subscribe();
+    let heartbeat = Heartbeat::new(state.idle_timeout);
+    let shutdown = state.shutdown.clone();
+
+    ws.on_upgrade(|socket| stream(socket, rx, heartbeat, shutdown))
+}
+
+/// Closes the WebSocket when the server is going away from the client.
+fn going_away(reason: &'static str) -> Message {
+    Message::Close(Some(CloseFrame {
+        code: close_code::AWAY,
+        reason: reason.into(),
+    }))
+}
+
+async fn stream(
+    mut socket: WebSocket,
+    mut rx: broadcast::Receiver<EventMessage>,
+    mut heartbeat: Heartbeat,
+    shutdown: CancellationToken,
+) {
+    loop {
+        let res = tokio::select! {
+            _ = shutdown.cancelled() => {
+                let _ = socket.send(going_away("shutting down")).await;
+
+                break;
+            }
+            msg = socket.recv() => match msg {
+                Some(Ok(Message::Close(_)) | Err(_)) | None => break,
+                // Anything from the client, like the pongs, shows it's alive
+                Some(Ok(_)) => {
+                    heartbeat.seen();
+
+                    continue;
+                }
+            },
+            beat = heartbeat.tick() => {
+                let msg = match beat {
+                    Beat::Ping => Message::Ping(Vec::new()),
+                    Beat::Idle => {
+                        debug!("closing idle events client");
+
+                        let _ = socket.send(going_away("idle")).await;
+
+                        break;
+                    }
+                };
+
+                if socket.send(msg).await.is_err() {
+                    break;
+                }
+
+                continue;
+            }
+            res = rx.recv() => res,
+        };
+
+        let msg = match res {
+            Ok(msg) => msg,
+            Err(RecvError::Lagged(skipped)) => {
+                debug!(skipped, "events client lagging behind");
+
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+
+        let text = match serde_json::to_string(&msg) {
+            Ok(text) => text,
+            Err(err) => {
+                warn!(error = %err, "couldn't serialize event");
+
+                continue;
+            }
+        };
+
+        if socket.send(Message::Text(text)).await.is_err() {
+            debug!("events client disconnected");
+
+            break;
+        }
+    }
+}
@@ -0,0 +1,64 @@
+//! Pings sent as ICMP echo requests to the host of the receiver.
+
+use std::{
+    net::IpAddr,
+    sync::atomic::{AtomicU16, Ordering},
+    time::Duration,
+};
+
+use eyre::OptionExt;
+use surge_ping::{Client, Config, PingIdentifier, PingSequence, ICMP};
+use url::Url;
+
+use crate::delivery;
+
+/// Time to wait for the echo reply.
+const ECHO_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct Icmp {
+    v4: Client,
+    /// Missing if the host doesn't support IPv6.
+    v6: Option<Client>,
+    sequence: AtomicU16,
+}
+
+impl std::fmt::Debug for Icmp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Icmp")
+            .field("v6", &self.v6.is_some())
+            .field("sequence", &self.sequence)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Icmp {
+    /// Opens the ICMP sockets, unprivileged if the system allows it or raw otherwise.
+    pub fn new() -> eyre::Result<Self> {
+        let v4 = Client::new(&Config::default())?;
+        let v6 = Client::new(&Config::builder().kind(ICMP::V6).build()).ok();
+
+        Ok(Self {
+            v4,
+            v6,
+            sequence: AtomicU16::new(0),
+        })
+    }
+
+    /// Sends an echo request to the host of the url, returning the round-trip time.
+    pub async fn echo(&self, url: &Url, payload: &[u8]) -> eyre::Result<Duration> {
+        let ip = delivery::resolve(url).await?.ip();
+
+        let client = match ip {
+            IpAddr::V4(_) => &self.v4,
+            IpAddr::V6(_) => self.v6.as_ref().ok_or_eyre("IPv6 ICMP isn't available")?,
+        };
+
+        let mut pinger = client.pinger(ip, PingIdentifier(rand::random())).await;
+        pinger.timeout(ECHO_TIMEOUT);
+
+        let sequence = self.sequence.fetch_add(1, Ordering::Relaxed);
+        let (_reply, rtt) = pinger.ping(PingSequence(sequence), payload).await?;
+
+        Ok(rtt)
+    }
+}
@@ -0,0 +1,208 @@
+//! Discovery of the receivers from the endpoints of a Kubernetes service.
+//!
+//! The endpoints are watched, so the pings are spread over the ready pods as soon as the service
+//! is scaled, without going through its virtual IP.
+
+use std::{
+    collections::BTreeMap,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+
+use futures::StreamExt;
+use k8s_openapi::api::core::v1::Endpoints;
+use kube::{
+    runtime::{watcher, WatchStreamExt},
+    Api, Client,
+};
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info, warn};
+use url::Url;
+
+use crate::discovery::Discovered;
+
+/// Name of the service port the pings are sent to, the first port is used if missing.
+const PORT_NAME: &str = "http";
+
+#[derive(Debug)]
+pub(crate) struct Kubernetes {
+    service: String,
+    /// Receivers by their url.
+    discovered: Arc<Discovered>,
+    watch: JoinHandle<()>,
+}
+
+impl Kubernetes {
+    /// Watches the endpoints of the service, written as `[NAMESPACE/]NAME`, in the background.
+    pub(crate) fn new(service: String) -> Self {
+        let discovered = Arc::new(Discovered::default());
+
+        let watch = tokio::spawn({
+            let service = service.clone();
+            let discovered = Arc::clone(&discovered);
+
+            async move {
+                if let Err(err) = watch(&service, &discovered).await {
+                    error!(service, error = %err, "couldn't watch the endpoints of the service");
+                }
+            }
+        });
+
+        Self {
+            service,
+            discovered,
+            watch,
+        }
+    }
+
+    pub(crate) fn service(&self) -> &str {
+        &self.service
+    }
+
+    pub(crate) async fn pick(&self) -> eyre::Result<Url> {
+        self.discovered.pick().await
+    }
+}
+
+impl Drop for Kubernetes {
+    fn drop(&mut self) {
+        self.watch.abort();
+    }
+}
+
+async fn watch(service: &str, discovered: &Discovered) -> eyre::Result<()> {
+    let client = Client::try_default().await?;
+
+    let (api, name) = match service.split_once('/') {
+        Some((namespace, name)) => (Api::<Endpoints>::namespaced(client, namespace), name),
+        None => (Api::<Endpoints>::default_namespaced(client), service),
+    };
+
+    info!(service, "watching the endpoints of the service");
+
+    let config = watcher::Config::default().fields(&format!("metadata.name={name}"));
+    // The errors are returned before retrying, spaced by the backoff
+    let mut events = watcher(api, config).default_backoff().boxed();
+
+    while let Some(event) = events.next().await {
+        let event = match event {
+            Ok(event) => event,
+            Err(err) => {
+                warn!(service, error = %err, "couldn't watch the endpoints, retrying");
+
+                continue;
+            }
+        };
+
+        match event {
+            watcher::Event::Apply(endpoints) | watcher::Event::InitApply(endpoints) => {
+                discovered.replace(receivers(&endpoints));
+            }
+            watcher::Event::Delete(_) => discovered.replace(BTreeMap::new()),
+            watcher::Event::Init | watcher::Event::InitDone => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Urls of the ready addresses of the endpoints.
+fn receivers(endpoints: &Endpoints) -> BTreeMap<String, Url> {
+    let mut receivers = BTreeMap::new();
+
+    for subset in endpoints.subsets.iter().flatten() {
+        let ports = subset.ports.as_deref().unwrap_or_default();
+        let Some(port) = ports
+            .iter()
+            .find(|port| port.name.as_deref() == Some(PORT_NAME))
+            .or_else(|| ports.first())
+            .and_then(|port| u16::try_from(port.port).ok())
+        else {
+            continue;
+        };
+
+        for address in subset.addresses.iter().flatten() {
+            let Ok(ip) = address.ip.parse::<IpAddr>() else {
+                debug!(ip = address.ip, "invalid endpoint address");
+
+                continue;
+            };
+
+            if let Ok(url) = Url::parse(&format!("http://{}", SocketAddr::from((ip, port)))) {
+                receivers.insert(url.to_string(), url);
+            }
+        }
+    }
+
+    receivers
+}
+
+#[cfg(test)]
+mod tests {
+    use k8s_openapi::api::core::v1::{EndpointAddress, EndpointPort, EndpointSubset};
+
+    use super::*;
+
+    fn port(name: Option<&str>, port: i32) -> EndpointPort {
+        EndpointPort {
+            name: name.map(str::to_string),
+            port,
+            ..Default::default()
+        }
+    }
+
+    fn subset(ips: &[&str], ports: Vec<EndpointPort>) -> EndpointSubset {
+        EndpointSubset {
+            addresses: Some(
+                ips.iter()
+                    .map(|ip| EndpointAddress {
+                        ip: ip.to_string(),
+                        ..Default::default()
+                    })
+                    .collect(),
+            ),
+            ports: Some(ports),
+            ..Default::default()
+        }
+    }
+
+    fn urls(subsets: Vec<EndpointSubset>) -> Vec<String> {
+        let endpoints = Endpoints {
+            subsets: Some(subsets),
+            ..Default::default()
+        };
+
+        receivers(&endpoints).into_keys().collect()
+    }
+
+    #[test]
+    fn named_port_preferred() {
+        let ports = vec![port(Some("metrics"), 9000), port(Some(PORT_NAME), 3000)];
+
+        assert_eq!(
+            urls(vec![subset(&["10.0.0.1"], ports)]),
+            ["http://10.0.0.1:3000/"]
+        );
+    }
+
+    #[test]
+    fn first_port_without_the_named_one() {
+        let ports = vec![port(Some("metrics"), 9000), port(None, 3000)];
+
+        assert_eq!(
+            urls(vec![subset(&["10.0.0.1", "fd00::1"], ports)]),
+            ["http://10.0.0.1:9000/", "http://[fd00::1]:9000/"]
+        );
+    }
+
+    #[test]
+    fn invalid_addresses_and_subsets_without_ports_skipped() {
+        assert_eq!(
+            urls(vec![
+                subset(&["10.0.0.1", "not-an-ip"], vec![port(None, 3000)]),
+                subset(&["10.0.0.2"], Vec::new()),
+            ]),
+            ["http://10.0.0.1:3000/"]
+        );
+    }
+}
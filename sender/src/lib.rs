@@ -0,0 +1,423 @@
+use std::{ops::Deref, pin::pin, str::FromStr, sync::Arc, time::Duration};
+
+use axum::{
+    extract::{Query, State},
+    http::{HeaderMap, HeaderValue, StatusCode},
+    middleware,
+    response::{Html, IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use axum_extra::{headers::ContentType, TypedHeader};
+use cfg_if::cfg_if;
+use clap::Args;
+use delivery::{Delivery, DeliveryArgs};
+use events::{Event, Events};
+use metrics_exporter_prometheus::PrometheusHandle;
+use protocol::{Pong, Registration, REQUEST_ID_HEADER};
+use reqwest::Url;
+use schedule::{Schedule, ScheduleStatus};
+use serde::{Deserialize, Serialize};
+use server::{
+    oidc::Oidc,
+    session::{Session, Sessions},
+    telemetry, ServerArgs,
+};
+use stats::StatsSnapshot;
+use tokio::{net::TcpListener, signal::unix::SignalKind};
+use tokio_util::sync::CancellationToken;
+use tower_http::{catch_panic::CatchPanicLayer, trace::TraceLayer};
+use tracing::{debug, error, info};
+use uuid::Uuid;
+
+mod burst;
+pub mod delivery;
+mod discovery;
+mod dns;
+mod events;
+mod icmp;
+#[cfg(feature = "kubernetes")]
+mod kubernetes;
+pub mod load;
+mod mdns;
+pub mod ping;
+mod register;
+pub mod replay;
+mod schedule;
+mod srv;
+mod stats;
+mod tui;
+mod udp;
+
+/// Maximum number of pings that can be requested in a single burst.
+const MAX_BURST: u32 = 10_000;
+
+#[derive(Debug, Clone)]
+struct AppState {
+    shared: Arc<AppStateShared>,
+}
+
+impl Deref for AppState {
+    type Target = AppStateShared;
+
+    fn deref(&self) -> &Self::Target {
+        &self.shared
+    }
+}
+
+#[derive(Debug)]
+struct AppStateShared {
+    delivery: Delivery,
+    events: Events,
+    schedule: Option<Schedule>,
+    burst_concurrency: usize,
+    metrics: PrometheusHandle,
+    /// Closes the WebSockets on shutdown.
+    shutdown: CancellationToken,
+    /// Closes the WebSockets without activity from the client.
+    idle_timeout: Option<Duration>,
+    /// Logs the users in to the UI, if enabled.
+    oidc: Option<Arc<Oidc>>,
+}
+
+#[derive(Debug)]
+enum AppError {
+    BadRequest(String),
+    NotFound(String),
+    Internal(eyre::Report),
+}
+
+impl<E> From<E> for AppError
+where
+    E: std::error::Error + Send + Sync + 'static,
+{
+    fn from(value: E) -> Self {
+        AppError::Internal(eyre::Report::new(value))
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            AppError::BadRequest(msg) => (StatusCode::BAD_REQUEST, msg).into_response(),
+            AppError::NotFound(msg) => (StatusCode::NOT_FOUND, msg).into_response(),
+            AppError::Internal(err) => {
+                error!(error = %err, "insternal server error");
+
+                (StatusCode::INTERNAL_SERVER_ERROR, "something whent wrong").into_response()
+            }
+        }
+    }
+}
+
+async fn index() -> Html<&'static str> {
+    Html(include_str!("../templates/index.html"))
+}
+
+/// Sends a ping, on behalf of the request with the id and of the user if any, publishing the
+/// outcome to the events.
+async fn ping(
+    state: &AppState,
+    request_id: Option<&HeaderValue>,
+    user: Option<&str>,
+) -> eyre::Result<Option<Duration>> {
+    let id = Uuid::new_v4();
+
+    let latency = match state.delivery.ping_traced(id, request_id, user).await {
+        Ok(delivered) => delivered.latency,
+        Err(err) => {
+            state.events.publish(
+                Event::Failed {
+                    id,
+                    error: err.to_string(),
+                },
+                state.delivery.stats().snapshot(),
+            );
+
+            return Err(err);
+        }
+    };
+
+    debug!(%id, ?latency, "ping delivered");
+
+    state.events.publish(
+        Event::Delivered {
+            id,
+            latency_ms: latency.map(|latency| latency.as_secs_f64() * 1000.0),
+        },
+        state.delivery.stats().snapshot(),
+    );
+
+    Ok(latency)
+}
+
+#[derive(Debug, Deserialize)]
+struct SendPingQuery {
+    /// Number of pings to send in a burst
+    count: Option<u32>,
+}
+
+async fn send_ping(
+    State(state): State<AppState>,
+    Query(query): Query<SendPingQuery>,
+    session: Session,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let request_id = headers.get(REQUEST_ID_HEADER);
+    let user = server::oidc::user(&session).map(|user| user.name.unwrap_or(user.subject));
+    let user = user.as_deref();
+
+    let Some(count) = query.count else {
+        ping(&state, request_id, user)
+            .await
+            .map_err(AppError::Internal)?;
+
+        return Ok(StatusCode::NO_CONTENT.into_response());
+    };
+
+    if count > MAX_BURST {
+        return Err(AppError::BadRequest(format!(
+            "at most {MAX_BURST} pings can be sent in a burst"
+        )));
+    }
+
+    let report = burst::run(count, state.burst_concurrency, || async {
+        ping(&state, request_id, user).await.is_ok()
+    })
+    .await;
+
+    info!(
+        requested = report.requested,
+        succeeded = report.succeeded,
+        "burst completed"
+    );
+
+    Ok(Json(report).into_response())
+}
+
+async fn pong(
+    State(state): State<AppState>,
+    Json(pong): Json<Pong>,
+) -> Result<StatusCode, AppError> {
+    let latency =
+        state.delivery.stats().pong(pong.id).ok_or_else(|| {
+            AppError::NotFound(format!("no outstanding ping with id {}", pong.id))
+        })?;
+
+    debug!(id = %pong.id, ?latency, "pong received");
+
+    state.events.publish(
+        Event::Pong {
+            id: pong.id,
+            latency_ms: latency.as_secs_f64() * 1000.0,
+        },
+        state.delivery.stats().snapshot(),
+    );
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn send_pong(State(state): State<AppState>) -> Result<StatusCode, AppError> {
+    state.delivery.pong().await.map_err(AppError::Internal)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Serialize)]
+struct StatsResponse {
+    #[serde(flatten)]
+    stats: StatsSnapshot,
+    schedule: Option<ScheduleStatus>,
+}
+
+async fn stats(State(state): State<AppState>) -> Json<StatsResponse> {
+    Json(StatsResponse {
+        stats: state.delivery.stats().snapshot(),
+        schedule: state.schedule.as_ref().map(Schedule::status),
+    })
+}
+
+async fn metrics(State(state): State<AppState>) -> String {
+    state.metrics.render()
+}
+
+async fn favicon_ico() -> Result<(TypedHeader<ContentType>, &'static [u8]), AppError> {
+    let header = TypedHeader(ContentType::from_str("image/x-icon")?);
+
+    Ok((header, include_bytes!("../../assets/favicon.ico")))
+}
+
+/// Routes of the UI, behind the login if enabled.
+fn ui() -> Router<AppState> {
+    Router::new()
+        .route("/", get(index))
+        .route("/send-ping", post(send_ping))
+        .route("/send-pong", post(send_pong))
+        .route("/api/stats", get(stats))
+        .route("/events", get(events::events))
+}
+
+fn app() -> Router<AppState> {
+    Router::new()
+        .route("/favicon.ico", get(favicon_ico))
+        .route("/pong", post(pong))
+        .route("/metrics", get(metrics))
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct SenderArgs {
+    /// Url the receiver sends a pong to after each ping, if its host is allowed by the receiver
+    #[arg(long)]
+    callback: Option<Url>,
+    /// Interval between pings sent automatically
+    #[arg(long, value_parser = server::parse_interval)]
+    interval: Option<Duration>,
+    /// Maximum number of pings in flight when sending a burst
+    #[arg(long, default_value = "16")]
+    burst_concurrency: usize,
+    /// Interval between the registrations with the receiver, that keep the sender alive on it
+    #[arg(long, default_value = "10s", value_parser = server::parse_interval)]
+    heartbeat_interval: Duration,
+}
+
+/// Serves the sender until the shutdown is cancelled.
+///
+/// Without a listener the sender is headless and only sends the scheduled pings. With `tui` the
+/// dashboard is shown in the terminal, quitting it cancels the shutdown.
+pub async fn run(
+    listener: Option<TcpListener>,
+    delivery: DeliveryArgs,
+    args: SenderArgs,
+    server: ServerArgs,
+    tui: bool,
+    metrics: PrometheusHandle,
+    shutdown: CancellationToken,
+) -> eyre::Result<()> {
+    let oidc = Oidc::new(&server.oidc, &reqwest::Client::new())
+        .await?
+        .map(Arc::new);
+
+    let state = AppState {
+        shared: Arc::new(AppStateShared {
+            delivery: Delivery::new(delivery, args.callback)?,
+            events: Events::new(),
+            schedule: args.interval.map(Schedule::new),
+            burst_concurrency: args.burst_concurrency,
+            metrics,
+            shutdown: shutdown.clone(),
+            idle_timeout: server.idle_timeout,
+            oidc,
+        }),
+    };
+
+    tokio::spawn(schedule::run(state.clone()));
+
+    let registration = Registration {
+        id: state.delivery.id(),
+        url: listener
+            .as_ref()
+            .map(|listener| listener.local_addr())
+            .transpose()?
+            .map(|addr| Url::parse(&format!("http://{addr}")))
+            .transpose()?,
+        version: env!("CARGO_PKG_VERSION").to_string(),
+    };
+    tokio::spawn(register::run(
+        state.clone(),
+        registration,
+        args.heartbeat_interval,
+    ));
+
+    let sessions = Arc::new(Sessions::new(&server.session, "sender_session").await?);
+
+    let app = match &state.oidc {
+        Some(oidc) => app()
+            .merge(ui().route_layer(middleware::from_fn_with_state(
+                oidc.clone(),
+                server::oidc::require,
+            )))
+            .merge(server::oidc::routes(oidc.clone())),
+        None => app().merge(ui()),
+    };
+    let app = app
+        .layer(CatchPanicLayer::custom(telemetry::panic_response("sender")))
+        .route_layer(middleware::from_fn_with_state("sender", telemetry::track))
+        .layer(TraceLayer::new_for_http().make_span_with(server::request_id::span))
+        .with_state(state.clone());
+    let app = server::session::layer(app, sessions);
+    let app = server::request_id::layer(server::options::layer(app));
+
+    let server = async {
+        let Some(listener) = listener else {
+            info!("running headless");
+
+            shutdown.cancelled().await;
+
+            return Ok(());
+        };
+
+        info!("listening on http://{}", listener.local_addr()?);
+
+        server::serve(listener, app, server, shutdown.clone()).await
+    };
+
+    if !tui {
+        server.await?;
+
+        return Ok(());
+    }
+
+    let dashboard = async {
+        let res = tui::run(state, &shutdown).await;
+
+        // Quitting the dashboard stops the sender
+        shutdown.cancel();
+
+        res
+    };
+
+    let (server, dashboard) = tokio::join!(server, dashboard);
+    server?;
+    dashboard?;
+
+    Ok(())
+}
+
+/// Waits for SIGINT, or SIGTERM on unix.
+pub async fn shutdown_signal() {
+    async fn sigint() {
+        match tokio::signal::ctrl_c().await {
+            Ok(()) => {
+                info!("SIGINT received");
+            }
+            Err(err) => {
+                error!(error = %eyre::Report::new(err), "couldn't wait from signal");
+            }
+        }
+    }
+
+    cfg_if! {
+        if #[cfg(target_family = "unix")] {
+            let mut sigterm = match tokio::signal::unix::signal(SignalKind::terminate()) {
+                Ok(term) => term,
+                Err(err) => {
+                    error!(error = %eyre::Report::new(err), "couldn't wait from SIGTERM");
+
+                    // Wait only SIGINT
+                    sigint().await;
+
+                    return;
+                },
+            };
+
+            let sigterm = pin!(sigterm.recv());
+            let sigint = pin!(sigint());
+
+            if let futures::future::Either::Left(_) = futures::future::select(sigterm, sigint).await {
+                info!("SIGTERM receved");
+            }
+        } else {
+           sigint().await;
+        }
+    }
+}
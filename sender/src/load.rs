@@ -0,0 +1,249 @@
+//! Load test driving pings at a target rate.
+
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use clap::{Args, ValueEnum};
+use serde::Serialize;
+use tokio::{sync::Semaphore, task::JoinSet, time::MissedTickBehavior};
+use tracing::{debug, info};
+use uuid::Uuid;
+
+use crate::delivery::{self, Delivery};
+
+/// Highest rate, for the interval between the pings not to be zero.
+const MAX_RATE: i64 = 1_000_000_000;
+
+#[derive(Debug, Clone, Args)]
+pub struct LoadArgs {
+    /// Target number of pings per second, up to one per nanosecond
+    #[arg(long, default_value = "100", value_parser = clap::value_parser!(u32).range(1..=MAX_RATE))]
+    rate: u32,
+    /// How long to send pings for
+    #[arg(long, default_value = "10s", value_parser = humantime::parse_duration)]
+    duration: Duration,
+    /// Maximum number of pings in flight
+    #[arg(long, default_value = "16")]
+    concurrency: usize,
+    /// File to write the result of every ping to
+    #[arg(long, short)]
+    output: Option<PathBuf>,
+    /// Format of the output file, guessed from its extension by default
+    #[arg(long, value_enum, requires = "output")]
+    format: Option<ExportFormat>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+    Json,
+    Csv,
+}
+
+impl ExportFormat {
+    fn from_path(path: &Path) -> Self {
+        match path.extension() {
+            Some(ext) if ext.eq_ignore_ascii_case("csv") => ExportFormat::Csv,
+            _ => ExportFormat::Json,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct Sample {
+    /// Time the ping was sent, since the start of the test.
+    #[serde(rename = "offset_ms", serialize_with = "serialize_millis")]
+    offset: Duration,
+    #[serde(rename = "latency_ms", serialize_with = "serialize_millis")]
+    latency: Duration,
+    success: bool,
+    /// Status of the response, if one was received.
+    status: Option<u16>,
+    error: Option<String>,
+}
+
+/// Results of the load test as written to a JSON file.
+#[derive(Debug, Serialize)]
+struct Results<'a> {
+    rate: u32,
+    concurrency: usize,
+    #[serde(rename = "elapsed_ms", serialize_with = "serialize_millis")]
+    elapsed: Duration,
+    samples: &'a [Sample],
+}
+
+fn serialize_millis<S>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    serializer.serialize_f64(millis(*duration))
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+pub async fn run(delivery: Arc<Delivery>, args: LoadArgs) -> eyre::Result<()> {
+    eyre::ensure!(
+        args.concurrency > 0,
+        "the concurrency must be greater than zero"
+    );
+
+    info!(
+        rate = args.rate,
+        duration = ?args.duration,
+        concurrency = args.concurrency,
+        "starting load test"
+    );
+
+    let semaphore = Arc::new(Semaphore::new(args.concurrency));
+    let mut interval = tokio::time::interval(Duration::from_secs(1) / args.rate);
+    // Don't burst to catch up when the concurrency limit is reached
+    interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+    let mut tasks = JoinSet::new();
+    let start = Instant::now();
+
+    while start.elapsed() < args.duration {
+        interval.tick().await;
+
+        let permit = Arc::clone(&semaphore).acquire_owned().await?;
+        let delivery = Arc::clone(&delivery);
+
+        tasks.spawn(async move {
+            let sent = Instant::now();
+            let res = delivery.ping(Uuid::new_v4()).await;
+            let latency = sent.elapsed();
+
+            drop(permit);
+
+            let (status, error) = match res {
+                Ok(delivered) => (delivered.status, None),
+                Err(err) => {
+                    debug!(error = %err, "ping failed");
+
+                    (delivery::error_status(&err), Some(err.to_string()))
+                }
+            };
+
+            Sample {
+                offset: sent - start,
+                latency,
+                success: error.is_none(),
+                status: status.map(|status| status.as_u16()),
+                error,
+            }
+        });
+    }
+
+    let mut samples = Vec::with_capacity(tasks.len());
+    while let Some(sample) = tasks.join_next().await {
+        samples.push(sample?);
+    }
+    let elapsed = start.elapsed();
+
+    samples.sort_unstable_by_key(|sample| sample.offset);
+
+    report(&args, &samples, elapsed);
+
+    if let Some(path) = &args.output {
+        let format = args.format.unwrap_or_else(|| ExportFormat::from_path(path));
+
+        export(path, format, &args, &samples, elapsed)?;
+
+        println!("results:    written to {}", path.display());
+    }
+
+    Ok(())
+}
+
+fn export(
+    path: &Path,
+    format: ExportFormat,
+    args: &LoadArgs,
+    samples: &[Sample],
+    elapsed: Duration,
+) -> eyre::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+
+    match format {
+        ExportFormat::Json => {
+            let results = Results {
+                rate: args.rate,
+                concurrency: args.concurrency,
+                elapsed,
+                samples,
+            };
+
+            serde_json::to_writer_pretty(&mut file, &results)?;
+        }
+        ExportFormat::Csv => {
+            writeln!(file, "offset_ms,latency_ms,success,status,error")?;
+
+            for sample in samples {
+                let status = sample
+                    .status
+                    .map(|status| status.to_string())
+                    .unwrap_or_default();
+                // Quote the error, escaping the quotes in the message
+                let error = sample
+                    .error
+                    .as_deref()
+                    .map(|error| format!("\"{}\"", error.replace('"', "\"\"")))
+                    .unwrap_or_default();
+
+                writeln!(
+                    file,
+                    "{:.3},{:.3},{},{status},{error}",
+                    millis(sample.offset),
+                    millis(sample.latency),
+                    sample.success,
+                )?;
+            }
+        }
+    }
+
+    file.flush()?;
+
+    Ok(())
+}
+
+fn report(args: &LoadArgs, samples: &[Sample], elapsed: Duration) {
+    let succeeded = samples.iter().filter(|sample| sample.success).count();
+    let failed = samples.len() - succeeded;
+
+    let mut latencies: Vec<Duration> = samples.iter().map(|sample| sample.latency).collect();
+    latencies.sort_unstable();
+
+    println!(
+        "pings:      {} sent, {succeeded} succeeded, {failed} failed",
+        samples.len()
+    );
+    println!("elapsed:    {:.2}s", elapsed.as_secs_f64());
+    println!(
+        "throughput: {:.2} pings/s (target {})",
+        samples.len() as f64 / elapsed.as_secs_f64(),
+        args.rate
+    );
+
+    let Some(max) = latencies.last() else {
+        return;
+    };
+
+    let total: Duration = latencies.iter().sum();
+    let percentile = |p: usize| millis(latencies[(latencies.len() * p).div_ceil(100) - 1]);
+
+    println!(
+        "latency:    min {:.2}ms, avg {:.2}ms, p50 {:.2}ms, p90 {:.2}ms, p99 {:.2}ms, max {:.2}ms",
+        millis(latencies[0]),
+        millis(total) / latencies.len() as f64,
+        percentile(50),
+        percentile(90),
+        percentile(99),
+        millis(*max),
+    );
+}
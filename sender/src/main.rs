@@ -1,113 +1,75 @@
-use std::{collections::HashMap, net::IpAddr, ops::Deref, pin::pin, str::FromStr, sync::Arc};
-
-use axum::{
-    extract::State,
-    http::StatusCode,
-    response::{Html, IntoResponse},
-    routing::{get, post},
-    Router,
+use std::{net::IpAddr, str::FromStr, sync::Arc};
+
+use clap::{builder::ValueParser, Parser, Subcommand};
+use eyre::WrapErr;
+use sender::{
+    delivery::{Delivery, DeliveryArgs},
+    load::{self, LoadArgs},
+    ping::{self, PingArgs},
+    replay::{self, ReplayArgs},
+    SenderArgs,
 };
-use axum_extra::{headers::ContentType, TypedHeader};
-use cfg_if::cfg_if;
-use clap::{builder::ValueParser, Parser};
-use reqwest::Url;
-use tokio::{net::TcpListener, signal::unix::SignalKind};
-use tower_http::trace::TraceLayer;
-use tracing::{error, info};
+use server::ServerArgs;
+use tokio_util::sync::CancellationToken;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
-use uuid::Uuid;
 
-const LOG_LEVEL: &str = "sender=info,tower_http=debug";
-
-#[derive(Debug, Clone)]
-struct AppState {
-    shared: Arc<AppStateShared>,
-}
-
-impl Deref for AppState {
-    type Target = AppStateShared;
-
-    fn deref(&self) -> &Self::Target {
-        &self.shared
-    }
-}
-
-#[derive(Debug)]
-struct AppStateShared {
-    receiver: Url,
-}
-
-#[derive(Debug)]
-enum AppError {
-    Internal(eyre::Report),
-}
-
-impl<E> From<E> for AppError
-where
-    E: std::error::Error + Send + Sync + 'static,
-{
-    fn from(value: E) -> Self {
-        AppError::Internal(eyre::Report::new(value))
-    }
-}
-
-impl IntoResponse for AppError {
-    fn into_response(self) -> axum::response::Response {
-        match self {
-            AppError::Internal(err) => {
-                error!(error = %err, "insternal server error");
-
-                (StatusCode::INTERNAL_SERVER_ERROR, "something whent wrong").into_response()
-            }
-        }
-    }
-}
-
-async fn index() -> Html<&'static str> {
-    Html(include_str!("../templates/index.html"))
-}
-
-async fn send_ping(State(state): State<AppState>) -> Result<StatusCode, AppError> {
-    let client = reqwest::Client::new();
-
-    let mut body = HashMap::with_capacity(1);
-    body.insert("id", Uuid::new_v4());
-
-    client
-        .post(state.receiver.clone())
-        .json(&body)
-        .send()
-        .await?
-        .error_for_status()?;
-
-    Ok(StatusCode::NO_CONTENT)
-}
-
-async fn favicon_ico() -> Result<(TypedHeader<ContentType>, &'static [u8]), AppError> {
-    let header = TypedHeader(ContentType::from_str("image/x-icon")?);
-
-    Ok((header, include_bytes!("../../assets/favicon.ico")))
-}
-
-fn app() -> Router<AppState> {
-    Router::new()
-        .route("/", get(index))
-        .route("/favicon.ico", get(favicon_ico))
-        .route("/send-ping", post(send_ping))
-}
+const LOG_LEVEL: &str = "sender=info,server=info,tower_http=debug";
 
 #[derive(Debug, Clone, Parser)]
-#[clap(name = env!("CARGO_PKG_NAME"), about, version)]
+#[clap(name = env!("CARGO_PKG_NAME"), about, version, args_conflicts_with_subcommands = true)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
     /// Address to listen on
     #[arg(default_value = "127.0.0.1", value_parser= ValueParser::new(IpAddr::from_str) )]
     address: IpAddr,
     /// Port to listen on
     #[arg(default_value = "9000")]
     port: u16,
-    /// Url of the receiver internal port
-    #[arg(default_value = "http://receiver:9000")]
-    receiver: Url,
+    #[command(flatten)]
+    delivery: DeliveryArgs,
+    #[command(flatten)]
+    sender: SenderArgs,
+    #[command(flatten)]
+    server: ServerArgs,
+    /// Show a live dashboard in the terminal instead of the logs
+    #[arg(long)]
+    tui: bool,
+    /// Only send the scheduled pings, without serving the UI and the API
+    #[arg(long, requires = "interval")]
+    headless: bool,
+    /// StatsD or DogStatsD server the metrics are also sent to, over UDP
+    #[arg(long, value_name = "HOST:PORT")]
+    statsd_addr: Option<String>,
+    /// Base url of the OpenTelemetry collector the metrics are also exported to, over OTLP/HTTP
+    #[arg(long, env = "OTEL_EXPORTER_OTLP_ENDPOINT", value_name = "URL")]
+    otlp_endpoint: Option<String>,
+}
+
+#[derive(Debug, Clone, Subcommand)]
+enum Command {
+    /// Sends pings at a target rate and reports the latency and throughput
+    Load {
+        #[command(flatten)]
+        delivery: DeliveryArgs,
+        #[command(flatten)]
+        load: LoadArgs,
+    },
+    /// Sends pings and exits, with a non-zero code if any of them failed
+    Ping {
+        #[command(flatten)]
+        delivery: DeliveryArgs,
+        #[command(flatten)]
+        ping: PingArgs,
+    },
+    /// Sends the pings of a recording of a receiver, with their original pacing
+    Replay {
+        // First, the url of the receiver after it is optional
+        #[command(flatten)]
+        replay: ReplayArgs,
+        #[command(flatten)]
+        delivery: DeliveryArgs,
+    },
 }
 
 #[tokio::main]
@@ -117,63 +79,64 @@ async fn main() -> eyre::Result<()> {
     color_eyre::install()?;
 
     tracing_subscriber::registry()
-        .with(tracing_subscriber::fmt::layer())
+        // The logs would be drawn over the dashboard
+        .with((!cli.tui).then(tracing_subscriber::fmt::layer))
         .with(EnvFilter::try_from_default_env().unwrap_or_else(|_| LOG_LEVEL.into()))
         .try_init()?;
 
-    let listener = TcpListener::bind((cli.address, cli.port)).await?;
-
-    info!("listening on http://{}", listener.local_addr()?);
-
-    let app = app()
-        .layer(TraceLayer::new_for_http())
-        .with_state(AppState {
-            shared: Arc::new(AppStateShared {
-                receiver: cli.receiver,
-            }),
-        });
-
-    axum::serve(listener, app)
-        .with_graceful_shutdown(shutdown_signal())
-        .await?;
-
-    Ok(())
-}
-
-async fn shutdown_signal() {
-    async fn sigint() {
-        match tokio::signal::ctrl_c().await {
-            Ok(()) => {
-                info!("SIGINT received");
-            }
-            Err(err) => {
-                error!(error = %eyre::Report::new(err), "couldn't wait from signal");
-            }
+    match cli.command {
+        Some(Command::Load { delivery, load }) => {
+            load::run(Arc::new(Delivery::new(delivery, None)?), load).await
+        }
+        Some(Command::Ping { delivery, ping }) => {
+            ping::run(&Delivery::new(delivery, None)?, ping).await
+        }
+        Some(Command::Replay { delivery, replay }) => {
+            replay::run(Arc::new(Delivery::new(delivery, None)?), replay).await
         }
+        None => serve(cli).await,
     }
+}
 
-    cfg_if! {
-        if #[cfg(target_family = "unix")] {
-            let mut sigterm = match tokio::signal::unix::signal(SignalKind::terminate()) {
-                Ok(term) => term,
-                Err(err) => {
-                    error!(error = %eyre::Report::new(err), "couldn't wait from SIGTERM");
+async fn serve(cli: Cli) -> eyre::Result<()> {
+    let listener = if cli.headless {
+        None
+    } else {
+        let listener = server::bind((cli.address, cli.port).into(), &cli.server)
+            .wrap_err("couldn't bind the sender port")?;
 
-                    // Wait only SIGINT
-                    sigint().await;
+        Some(listener)
+    };
 
-                    return;
-                },
-            };
+    let shutdown = CancellationToken::new();
+    tokio::spawn({
+        let shutdown = shutdown.clone();
 
-            let sigterm = pin!(sigterm.recv());
-            let sigint = pin!(sigint());
+        async move {
+            sender::shutdown_signal().await;
 
-            if let futures::future::Either::Left(_) = futures::future::select(sigterm, sigint).await {
-                info!("SIGTERM receved");
-            }
-        } else {
-           sigint().await;
+            shutdown.cancel();
         }
-    }
+    });
+
+    let metrics = server::telemetry::install_metrics(
+        env!("CARGO_PKG_NAME"),
+        cli.statsd_addr.as_deref(),
+        cli.otlp_endpoint.as_deref(),
+    )?;
+
+    let res = sender::run(
+        listener,
+        cli.delivery,
+        cli.sender,
+        cli.server,
+        cli.tui,
+        metrics,
+        shutdown,
+    )
+    .await;
+
+    server::otlp::shutdown();
+
+    res
 }
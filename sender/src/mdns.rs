@@ -0,0 +1,95 @@
+//! Discovery of the receivers advertised on the local network over mDNS.
+
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+
+use mdns_sd::{ServiceDaemon, ServiceEvent};
+use protocol::MDNS_SERVICE;
+use tracing::{debug, info};
+use url::Url;
+
+use crate::discovery::Discovered;
+
+pub(crate) struct Mdns {
+    daemon: ServiceDaemon,
+    /// Receivers by their full service name.
+    discovered: Arc<Discovered>,
+}
+
+impl std::fmt::Debug for Mdns {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Mdns")
+            .field("discovered", &self.discovered)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Mdns {
+    /// Browses the local network for the receivers in the background.
+    pub(crate) fn new() -> eyre::Result<Self> {
+        let daemon = ServiceDaemon::new()?;
+        let events = daemon.browse(MDNS_SERVICE)?;
+        let discovered = Arc::new(Discovered::default());
+
+        info!("discovering the receivers over mDNS");
+
+        // The events are received until the daemon is shut down
+        std::thread::spawn({
+            let discovered = Arc::clone(&discovered);
+
+            move || {
+                while let Ok(event) = events.recv() {
+                    update(&discovered, event);
+                }
+            }
+        });
+
+        Ok(Self { daemon, discovered })
+    }
+
+    pub(crate) async fn pick(&self) -> eyre::Result<Url> {
+        self.discovered.pick().await
+    }
+}
+
+fn update(discovered: &Discovered, event: ServiceEvent) {
+    match event {
+        ServiceEvent::ServiceResolved(service) => {
+            // Prefers the IPv4 addresses, the IPv6 link-local ones need the interface
+            let Some(ip) = service
+                .get_addresses()
+                .iter()
+                .map(|ip| ip.to_ip_addr())
+                .min_by_key(IpAddr::is_ipv6)
+            else {
+                debug!(
+                    name = service.fullname,
+                    "receiver resolved without addresses"
+                );
+
+                return;
+            };
+
+            match Url::parse(&format!(
+                "http://{}",
+                SocketAddr::from((ip, service.get_port()))
+            )) {
+                Ok(url) => discovered.insert(service.fullname.clone(), url),
+                Err(err) => {
+                    debug!(name = service.fullname, error = %err, "invalid receiver address");
+                }
+            }
+        }
+        ServiceEvent::ServiceRemoved(_, fullname) => discovered.remove(&fullname),
+        _ => {}
+    }
+}
+
+impl Drop for Mdns {
+    fn drop(&mut self) {
+        // Also stops the thread receiving the events
+        let _ = self.daemon.shutdown();
+    }
+}
@@ -0,0 +1,53 @@
+//! One-shot pings from the command line.
+
+use clap::Args;
+use uuid::Uuid;
+
+use crate::{
+    burst,
+    delivery::{Delivered, Delivery},
+};
+
+#[derive(Debug, Clone, Args)]
+pub struct PingArgs {
+    /// Number of pings to send
+    #[arg(long, short, default_value = "1")]
+    count: u32,
+    /// Maximum number of pings in flight
+    #[arg(long, default_value = "1")]
+    concurrency: usize,
+}
+
+/// Sends the pings, failing if any of them couldn't be delivered.
+pub async fn run(delivery: &Delivery, args: PingArgs) -> eyre::Result<()> {
+    let report = burst::run(args.count, args.concurrency, || async {
+        let id = Uuid::new_v4();
+
+        match delivery.ping(id).await {
+            Ok(Delivered {
+                latency: Some(latency),
+                ..
+            }) => {
+                println!("{id}: delivered in {:.2}ms", latency.as_secs_f64() * 1000.0);
+            }
+            Ok(Delivered { latency: None, .. }) => println!("{id}: delivered"),
+            Err(err) => {
+                println!("{id}: failed: {err}");
+
+                return false;
+            }
+        }
+
+        true
+    })
+    .await;
+
+    eyre::ensure!(
+        report.failed == 0,
+        "{} of {} pings failed",
+        report.failed,
+        report.requested
+    );
+
+    Ok(())
+}
@@ -0,0 +1,43 @@
+//! Registration of the sender with the receiver, sent again every interval as a heartbeat.
+
+use std::time::Duration;
+
+use protocol::Registration;
+use tokio::time::MissedTickBehavior;
+use tracing::{debug, info, warn};
+
+use crate::AppState;
+
+/// Registers the sender every interval, for as long as it runs.
+pub async fn run(state: AppState, registration: Registration, interval: Duration) {
+    info!(id = %registration.id, ?interval, "registering with the receiver");
+
+    let mut interval = tokio::time::interval(interval);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    // Warns only once while the receiver is unreachable
+    let mut registered = true;
+
+    loop {
+        tokio::select! {
+            _ = state.shutdown.cancelled() => return,
+            _ = interval.tick() => {}
+        }
+
+        match state.delivery.register(&registration).await {
+            Ok(()) => {
+                if !registered {
+                    info!("registered with the receiver again");
+                }
+
+                registered = true;
+            }
+            Err(err) if registered => {
+                warn!(error = %err, "couldn't register with the receiver");
+
+                registered = false;
+            }
+            Err(err) => debug!(error = %err, "couldn't register with the receiver"),
+        }
+    }
+}
@@ -0,0 +1,66 @@
+//! Replay of the pings recorded by a receiver with `--record`.
+//!
+//! The pings are delivered as new ones with the pacing of the recording, through the transport
+//! and to the receivers the sender is configured with, to reproduce the load of production. The
+//! tenants of the recording aren't kept, the sender has none.
+
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
+
+use clap::Args;
+use eyre::WrapErr;
+use protocol::Recorded;
+use tokio::{task::JoinSet, time::Instant};
+use uuid::Uuid;
+
+use crate::delivery::Delivery;
+
+#[derive(Debug, Clone, Args)]
+pub struct ReplayArgs {
+    /// Recording of the pings, written by the receiver with `--record`
+    file: PathBuf,
+}
+
+/// Sends the recorded pings, failing if any of them couldn't be delivered.
+pub async fn run(delivery: Arc<Delivery>, args: ReplayArgs) -> eyre::Result<()> {
+    let file = File::open(&args.file)
+        .wrap_err_with(|| format!("couldn't open {}", args.file.display()))?;
+
+    let start = Instant::now();
+    let mut sent = JoinSet::new();
+
+    for line in BufReader::new(file).lines() {
+        let recorded: Recorded = serde_json::from_str(&line?).wrap_err("invalid recording")?;
+
+        tokio::time::sleep_until(start + Duration::from_millis(recorded.offset_ms)).await;
+
+        let delivery = Arc::clone(&delivery);
+
+        // Sent concurrently, the slow deliveries would delay the next pings
+        sent.spawn(async move { delivery.ping(Uuid::new_v4()).await.is_ok() });
+    }
+
+    let mut requested = 0;
+    let mut failed = 0;
+    while let Some(res) = sent.join_next().await {
+        requested += 1;
+
+        if !res.unwrap_or(false) {
+            failed += 1;
+        }
+    }
+
+    println!(
+        "replayed {requested} pings in {:.2}s",
+        start.elapsed().as_secs_f64()
+    );
+
+    eyre::ensure!(failed == 0, "{failed} of {requested} pings failed");
+
+    Ok(())
+}
@@ -0,0 +1,88 @@
+//! Periodic pings sent by the sender on its own.
+
+use std::{
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+use serde::Serialize;
+use tokio::time::MissedTickBehavior;
+use tracing::{info, warn};
+
+use crate::AppState;
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Fires {
+    last: Option<SystemTime>,
+    next: Option<SystemTime>,
+}
+
+#[derive(Debug)]
+pub struct Schedule {
+    interval: Duration,
+    fires: Mutex<Fires>,
+}
+
+/// Status of the schedule as reported by the stats API.
+#[derive(Debug, Clone, Serialize)]
+pub struct ScheduleStatus {
+    pub interval_ms: u128,
+    /// RFC 3339 timestamp of the last scheduled ping.
+    pub last_fire: Option<String>,
+    /// RFC 3339 timestamp of the next scheduled ping.
+    pub next_fire: Option<String>,
+}
+
+fn rfc3339(time: SystemTime) -> String {
+    humantime::format_rfc3339_millis(time).to_string()
+}
+
+impl Schedule {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            fires: Mutex::new(Fires::default()),
+        }
+    }
+
+    fn fires(&self) -> std::sync::MutexGuard<'_, Fires> {
+        self.fires.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    pub fn status(&self) -> ScheduleStatus {
+        let fires = *self.fires();
+
+        ScheduleStatus {
+            interval_ms: self.interval.as_millis(),
+            last_fire: fires.last.map(rfc3339),
+            next_fire: fires.next.map(rfc3339),
+        }
+    }
+}
+
+/// Sends a ping every interval, for as long as the sender runs.
+pub async fn run(state: AppState) {
+    let Some(schedule) = &state.schedule else {
+        return;
+    };
+
+    info!(interval = ?schedule.interval, "scheduling pings");
+
+    let mut interval = tokio::time::interval(schedule.interval);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        interval.tick().await;
+
+        let now = SystemTime::now();
+        {
+            let mut fires = schedule.fires();
+            fires.last = Some(now);
+            fires.next = Some(now + schedule.interval);
+        }
+
+        if let Err(err) = crate::ping(&state, None, None).await {
+            warn!(error = %err, "scheduled ping failed");
+        }
+    }
+}
@@ -0,0 +1,187 @@
+//! Discovery of the receivers from the DNS SRV records.
+//!
+//! The records are looked up again once the TTL expires, a receiver is picked for each ping
+//! between the ones with the lowest priority, proportionally to their weight.
+
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use eyre::{eyre, Context};
+use hickory_resolver::TokioAsyncResolver;
+use rand::Rng;
+use tracing::{info, warn};
+use url::Url;
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct Target {
+    priority: u16,
+    weight: u16,
+    url: Url,
+}
+
+#[derive(Debug, Default)]
+struct Records {
+    /// Sorted to compare them between the lookups.
+    targets: Vec<Target>,
+    resolved: Option<Instant>,
+}
+
+impl Records {
+    fn is_expired(&self, ttl: Duration) -> bool {
+        self.resolved
+            .is_none_or(|resolved| resolved.elapsed() >= ttl)
+    }
+}
+
+pub(crate) struct Srv {
+    name: String,
+    ttl: Duration,
+    resolver: TokioAsyncResolver,
+    records: Mutex<Records>,
+}
+
+impl std::fmt::Debug for Srv {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Srv")
+            .field("name", &self.name)
+            .field("ttl", &self.ttl)
+            .field("records", &self.records)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Srv {
+    pub(crate) fn new(name: String, ttl: Duration) -> eyre::Result<Self> {
+        let resolver = TokioAsyncResolver::tokio_from_system_conf()
+            .wrap_err("couldn't read the system DNS configuration")?;
+
+        Ok(Self {
+            name,
+            ttl,
+            resolver,
+            records: Mutex::default(),
+        })
+    }
+
+    pub(crate) fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn lookup(&self) -> eyre::Result<Vec<Target>> {
+        let lookup = self.resolver.srv_lookup(self.name.as_str()).await?;
+
+        let mut targets = lookup
+            .iter()
+            .map(|srv| {
+                let host = srv.target().to_utf8();
+                let url = Url::parse(&format!(
+                    "http://{}:{}",
+                    host.trim_end_matches('.'),
+                    srv.port()
+                ))?;
+
+                Ok(Target {
+                    priority: srv.priority(),
+                    weight: srv.weight(),
+                    url,
+                })
+            })
+            .collect::<eyre::Result<Vec<_>>>()?;
+        targets.sort_unstable();
+
+        Ok(targets)
+    }
+
+    /// Looks up the records if expired.
+    ///
+    /// If the lookup fails the previous receivers are kept, until it succeeds again.
+    async fn refresh(&self) -> eyre::Result<()> {
+        if !self
+            .records
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .is_expired(self.ttl)
+        {
+            return Ok(());
+        }
+
+        let res = self.lookup().await;
+
+        let mut records = self.records.lock().unwrap_or_else(|err| err.into_inner());
+
+        let targets = match res {
+            Ok(targets) => targets,
+            Err(err) if !records.targets.is_empty() => {
+                warn!(
+                    name = self.name,
+                    error = %err,
+                    "couldn't look up the receivers again, keeping the previous ones"
+                );
+
+                return Ok(());
+            }
+            Err(err) => {
+                return Err(err.wrap_err(format!("couldn't look up the receivers of {}", self.name)))
+            }
+        };
+
+        if targets != records.targets {
+            let receivers: Vec<String> = targets
+                .iter()
+                .map(|target| {
+                    format!(
+                        "{} (priority {}, weight {})",
+                        target.url, target.priority, target.weight
+                    )
+                })
+                .collect();
+
+            info!(name = self.name, ?receivers, "discovered the receivers");
+        }
+
+        records.targets = targets;
+        records.resolved = Some(Instant::now());
+
+        Ok(())
+    }
+
+    /// Picks the receiver for a ping.
+    pub(crate) async fn pick(&self) -> eyre::Result<Url> {
+        self.refresh().await?;
+
+        let records = self.records.lock().unwrap_or_else(|err| err.into_inner());
+
+        choose(&records.targets, &mut rand::thread_rng())
+            .map(|target| target.url.clone())
+            .ok_or_else(|| eyre!("no receivers in {}", self.name))
+    }
+}
+
+/// Weighted choice between the targets with the lowest priority, as described in RFC 2782.
+fn choose<'a>(targets: &'a [Target], rng: &mut impl Rng) -> Option<&'a Target> {
+    let priority = targets.iter().map(|target| target.priority).min()?;
+    let group: Vec<&Target> = targets
+        .iter()
+        .filter(|target| target.priority == priority)
+        .collect();
+
+    let total: u32 = group.iter().map(|target| u32::from(target.weight)).sum();
+    // Without weights all the targets are equally likely
+    if total == 0 {
+        return Some(group[rng.gen_range(0..group.len())]);
+    }
+
+    let mut pick = rng.gen_range(0..total);
+    group.into_iter().find(|target| {
+        let weight = u32::from(target.weight);
+        if pick < weight {
+            return true;
+        }
+
+        pick -= weight;
+
+        false
+    })
+}
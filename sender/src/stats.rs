@@ -0,0 +1,374 @@
+//! Delivery statistics of the sender.
+//!
+//! The round-trip latency of the pings is correlated by their id. When a callback is configured
+//! the round trip ends with the pong sent back by the receiver, otherwise with the response to
+//! the ping. The ICMP echoes sent along the pings are measured apart, and the latencies are
+//! labeled by their transport in the metrics.
+
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+
+use hdrhistogram::Histogram;
+use serde::Serialize;
+use uuid::Uuid;
+
+/// Highest latency tracked by the histogram, in microseconds.
+const MAX_LATENCY_MICROS: u64 = 60_000_000;
+/// Significant figures kept by the latency histogram.
+const SIGNIFICANT_FIGURES: u8 = 3;
+/// Time after which a ping still waiting for its pong is considered lost.
+const PONG_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Default)]
+pub struct Stats {
+    outstanding: Mutex<Outstanding>,
+    latencies: Mutex<Latencies>,
+    /// Round-trip times of the ICMP echoes.
+    echoes: Mutex<Latencies>,
+    pongs: AtomicU64,
+    unknown_pongs: AtomicU64,
+    lost_pongs: AtomicU64,
+    lost_datagrams: AtomicU64,
+    deliveries: Mutex<Deliveries>,
+}
+
+/// Pings waiting for their pong.
+#[derive(Debug, Default)]
+struct Outstanding {
+    /// When the pings were sent, by id.
+    starts: HashMap<Uuid, Instant>,
+    /// Ids in the order they were sent, the completed ones are dropped once at the front.
+    sent: VecDeque<(Instant, Uuid)>,
+}
+
+impl Outstanding {
+    fn insert(&mut self, id: Uuid, start: Instant) {
+        self.starts.insert(id, start);
+        self.sent.push_back((start, id));
+    }
+
+    fn remove(&mut self, id: &Uuid) -> Option<Instant> {
+        self.starts.remove(id)
+    }
+
+    fn len(&self) -> usize {
+        self.starts.len()
+    }
+
+    /// Forgets the pings waiting for longer than the timeout, returning how many.
+    fn expire(&mut self) -> usize {
+        let mut lost = 0;
+
+        while let Some(&(start, id)) = self.sent.front() {
+            let waiting = self.starts.get(&id) == Some(&start);
+
+            if waiting && start.elapsed() < PONG_TIMEOUT {
+                break;
+            }
+
+            self.sent.pop_front();
+
+            if waiting {
+                self.starts.remove(&id);
+                lost += 1;
+            }
+        }
+
+        lost
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LastError {
+    pub message: String,
+    /// RFC 3339 timestamp of the failure.
+    pub at: String,
+}
+
+/// Delivery counters, for all the pings or a single receiver.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DeliveryStats {
+    /// Pings sent, not counting the retries.
+    pub sent: u64,
+    /// Pings that couldn't be delivered even after retrying.
+    pub failures: u64,
+    pub retries: u64,
+    pub last_error: Option<LastError>,
+}
+
+#[derive(Debug, Default)]
+struct Deliveries {
+    total: DeliveryStats,
+    receivers: BTreeMap<String, DeliveryStats>,
+}
+
+impl Deliveries {
+    fn update(&mut self, receiver: &str, f: impl Fn(&mut DeliveryStats)) {
+        f(&mut self.total);
+
+        if let Some(stats) = self.receivers.get_mut(receiver) {
+            f(stats);
+        } else {
+            let mut stats = DeliveryStats::default();
+            f(&mut stats);
+            self.receivers.insert(receiver.to_string(), stats);
+        }
+    }
+}
+
+/// Histogram of the latencies in microseconds.
+#[derive(Debug)]
+struct Latencies(Histogram<u64>);
+
+impl Default for Latencies {
+    fn default() -> Self {
+        Self(
+            Histogram::new_with_bounds(1, MAX_LATENCY_MICROS, SIGNIFICANT_FIGURES)
+                .expect("the histogram bounds should be valid"),
+        )
+    }
+}
+
+/// Latency statistics in milliseconds, since the sender started.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct LatencyStats {
+    pub samples: u64,
+    pub min_ms: Option<f64>,
+    pub avg_ms: Option<f64>,
+    pub p50_ms: Option<f64>,
+    pub p90_ms: Option<f64>,
+    pub p95_ms: Option<f64>,
+    pub p99_ms: Option<f64>,
+    pub max_ms: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StatsSnapshot {
+    #[serde(flatten)]
+    pub deliveries: DeliveryStats,
+    pub receivers: BTreeMap<String, DeliveryStats>,
+    pub outstanding: usize,
+    pub pongs: u64,
+    pub unknown_pongs: u64,
+    pub lost_pongs: u64,
+    /// Pings sent over UDP without a pong from the receiver.
+    pub lost_datagrams: u64,
+    pub latency: LatencyStats,
+    /// Round-trip times of the ICMP echoes sent along the pings, with `--transport icmp`.
+    pub echo_latency: LatencyStats,
+}
+
+fn millis(micros: u64) -> f64 {
+    micros as f64 / 1000.0
+}
+
+impl Latencies {
+    fn record(&mut self, latency: Duration) {
+        let micros = u64::try_from(latency.as_micros()).unwrap_or(u64::MAX);
+
+        self.0.saturating_record(micros);
+    }
+
+    fn stats(&self) -> LatencyStats {
+        let histogram = &self.0;
+
+        if histogram.is_empty() {
+            return LatencyStats::default();
+        }
+
+        let percentile = |p: f64| Some(millis(histogram.value_at_percentile(p)));
+
+        LatencyStats {
+            samples: histogram.len(),
+            min_ms: Some(millis(histogram.min())),
+            avg_ms: Some(histogram.mean() / 1000.0),
+            p50_ms: percentile(50.0),
+            p90_ms: percentile(90.0),
+            p95_ms: percentile(95.0),
+            p99_ms: percentile(99.0),
+            max_ms: Some(millis(histogram.max())),
+        }
+    }
+}
+
+impl Stats {
+    fn deliveries(&self) -> std::sync::MutexGuard<'_, Deliveries> {
+        self.deliveries
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Starts tracking a ping that is about to be sent.
+    pub fn sent(&self, id: Uuid, receiver: &str) {
+        self.deliveries().update(receiver, |stats| stats.sent += 1);
+
+        let mut outstanding = self
+            .outstanding
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+
+        let lost = outstanding.expire();
+        if lost > 0 {
+            self.lost_pongs.fetch_add(lost as u64, Ordering::Relaxed);
+        }
+
+        outstanding.insert(id, Instant::now());
+    }
+
+    /// Completes a ping, returning the measured round-trip time.
+    pub fn completed(&self, id: Uuid) -> Option<Duration> {
+        let start = self
+            .outstanding
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .remove(&id)?;
+
+        let elapsed = start.elapsed();
+
+        self.record("http", elapsed);
+
+        Some(elapsed)
+    }
+
+    /// Completes a ping sent as a datagram with the round-trip time of its pong.
+    pub fn completed_in(&self, id: Uuid, latency: Duration) {
+        self.outstanding
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .remove(&id);
+
+        self.record("udp", latency);
+    }
+
+    fn record(&self, transport: &'static str, latency: Duration) {
+        self.latencies
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .record(latency);
+
+        metrics::histogram!("sender_ping_duration_seconds", "transport" => transport)
+            .record(latency);
+    }
+
+    /// Records the round-trip time of the ICMP echo sent along a ping.
+    pub fn echoed(&self, latency: Duration) {
+        self.echoes
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .record(latency);
+
+        metrics::histogram!("sender_ping_duration_seconds", "transport" => "icmp").record(latency);
+    }
+
+    /// Completes a ping with the pong called back by the receiver.
+    ///
+    /// Returns [`None`] if the id doesn't match any outstanding ping.
+    pub fn pong(&self, id: Uuid) -> Option<Duration> {
+        let Some(latency) = self.completed(id) else {
+            self.unknown_pongs.fetch_add(1, Ordering::Relaxed);
+
+            return None;
+        };
+
+        self.pongs.fetch_add(1, Ordering::Relaxed);
+
+        Some(latency)
+    }
+
+    /// A ping to the receiver is being sent again.
+    pub fn retried(&self, receiver: &str) {
+        self.deliveries()
+            .update(receiver, |stats| stats.retries += 1);
+    }
+
+    /// Stops tracking a ping that couldn't be delivered.
+    pub fn failed(&self, id: Uuid, receiver: &str, error: &str) {
+        let last_error = LastError {
+            message: error.to_string(),
+            at: humantime::format_rfc3339_millis(SystemTime::now()).to_string(),
+        };
+
+        self.deliveries().update(receiver, |stats| {
+            stats.failures += 1;
+            stats.last_error = Some(last_error.clone());
+        });
+
+        let mut outstanding = self
+            .outstanding
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+
+        outstanding.remove(&id);
+    }
+
+    /// Stops tracking a ping sent as a datagram that got no pong.
+    pub fn lost(&self, id: Uuid, receiver: &str, error: &str) {
+        self.lost_datagrams.fetch_add(1, Ordering::Relaxed);
+
+        self.failed(id, receiver, error);
+    }
+
+    pub fn snapshot(&self) -> StatsSnapshot {
+        let outstanding = self
+            .outstanding
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .len();
+
+        let latency = self
+            .latencies
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .stats();
+        let echo_latency = self
+            .echoes
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .stats();
+
+        let deliveries = self.deliveries();
+
+        StatsSnapshot {
+            deliveries: deliveries.total.clone(),
+            receivers: deliveries.receivers.clone(),
+            outstanding,
+            pongs: self.pongs.load(Ordering::Relaxed),
+            unknown_pongs: self.unknown_pongs.load(Ordering::Relaxed),
+            lost_pongs: self.lost_pongs.load(Ordering::Relaxed),
+            lost_datagrams: self.lost_datagrams.load(Ordering::Relaxed),
+            latency,
+            echo_latency,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pings_waiting_past_the_timeout_lost() {
+        let mut outstanding = Outstanding::default();
+        let (lost, completed, waiting) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let expired = Instant::now() - PONG_TIMEOUT;
+
+        outstanding.insert(completed, expired);
+        outstanding.insert(lost, expired);
+        outstanding.insert(waiting, Instant::now());
+        outstanding.remove(&completed);
+
+        assert_eq!(outstanding.expire(), 1);
+        assert_eq!(outstanding.len(), 1);
+        assert!(outstanding.remove(&waiting).is_some());
+
+        // The completed pings at the front are dropped too
+        assert_eq!(outstanding.expire(), 0);
+        assert!(outstanding.sent.is_empty());
+    }
+}
@@ -0,0 +1,177 @@
+//! Terminal dashboard with the live statistics of the sender.
+
+use std::collections::VecDeque;
+
+use ratatui::{
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Style, Stylize},
+    text::Line,
+    widgets::{Block, Paragraph, Sparkline},
+    Frame,
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    events::{Event, EventMessage},
+    stats::StatsSnapshot,
+    AppState,
+};
+
+/// Number of buckets kept, more than the width of most terminals.
+const HISTORY: usize = 300;
+
+/// Pings sent during a bucket.
+#[derive(Debug, Clone, Copy, Default)]
+struct Bucket {
+    sent: u64,
+    failed: u64,
+    latency_ms: f64,
+    latencies: u64,
+}
+
+impl Bucket {
+    fn avg_latency_ms(&self) -> Option<f64> {
+        (self.latencies > 0).then(|| self.latency_ms / self.latencies as f64)
+    }
+}
+
+#[derive(Debug)]
+struct Dashboard {
+    receiver: String,
+    stats: StatsSnapshot,
+    current: Bucket,
+    /// Completed buckets, the latest at the back.
+    history: VecDeque<Bucket>,
+}
+
+impl Dashboard {
+    fn new(state: &AppState) -> Self {
+        Self {
+            receiver: state.delivery.receiver().to_string(),
+            stats: state.delivery.stats().snapshot(),
+            current: Bucket::default(),
+            history: VecDeque::with_capacity(HISTORY),
+        }
+    }
+
+    /// Last buckets fitting in the area, mapped to the bars of a sparkline.
+    fn bars(&self, area: Rect, f: impl Fn(&Bucket) -> u64) -> Vec<u64> {
+        let width = usize::from(area.width.saturating_sub(2));
+        let skip = self.history.len().saturating_sub(width);
+
+        self.history.iter().skip(skip).map(f).collect()
+    }
+}
+
+impl server::tui::Dashboard for Dashboard {
+    type Event = EventMessage;
+
+    fn record(&mut self, msg: EventMessage) {
+        let latency = match msg.event {
+            Event::Delivered { latency_ms, .. } => {
+                self.current.sent += 1;
+
+                latency_ms
+            }
+            Event::Failed { .. } => {
+                self.current.sent += 1;
+                self.current.failed += 1;
+
+                None
+            }
+            Event::Pong { latency_ms, .. } => Some(latency_ms),
+        };
+
+        if let Some(latency) = latency {
+            self.current.latency_ms += latency;
+            self.current.latencies += 1;
+        }
+
+        self.stats = msg.stats;
+    }
+
+    fn rotate(&mut self) {
+        if self.history.len() == HISTORY {
+            self.history.pop_front();
+        }
+
+        self.history.push_back(std::mem::take(&mut self.current));
+    }
+
+    fn render(&self, frame: &mut Frame) {
+        let [summary, rate, latency, help] = Layout::vertical([
+            Constraint::Length(6),
+            Constraint::Fill(1),
+            Constraint::Fill(1),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        let last = self.history.back().copied().unwrap_or_default();
+        let stats = &self.stats;
+        let ms = |value: Option<f64>| {
+            value.map_or_else(|| "-".to_string(), |value| format!("{value:.2}ms"))
+        };
+
+        let lines = vec![
+            Line::from(format!(
+                "rate:     {} pings/s, {} failed/s",
+                last.sent, last.failed
+            )),
+            Line::from(format!(
+                "pings:    {} sent, {} failed, {} retries",
+                stats.deliveries.sent, stats.deliveries.failures, stats.deliveries.retries
+            )),
+            Line::from(format!(
+                "pongs:    {} received, {} outstanding, {} lost",
+                stats.pongs, stats.outstanding, stats.lost_pongs
+            )),
+            Line::from(format!(
+                "latency:  avg {}, p50 {}, p90 {}, p99 {}, max {}",
+                ms(stats.latency.avg_ms),
+                ms(stats.latency.p50_ms),
+                ms(stats.latency.p90_ms),
+                ms(stats.latency.p99_ms),
+                ms(stats.latency.max_ms),
+            )),
+        ];
+
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title(format!(" {} ", self.receiver))),
+            summary,
+        );
+
+        frame.render_widget(
+            Sparkline::default()
+                .block(Block::bordered().title(" pings/s "))
+                .style(Style::default().fg(Color::Green))
+                .data(self.bars(rate, |bucket| bucket.sent)),
+            rate,
+        );
+
+        frame.render_widget(
+            Sparkline::default()
+                .block(
+                    Block::bordered()
+                        .title(format!(" latency, last {} ", ms(last.avg_latency_ms()))),
+                )
+                .style(Style::default().fg(Color::Yellow))
+                // Bars in microseconds, to not round the faster pings to zero
+                .data(self.bars(latency, |bucket| {
+                    bucket
+                        .avg_latency_ms()
+                        .map_or(0, |latency| (latency * 1000.0) as u64)
+                })),
+            latency,
+        );
+
+        frame.render_widget(Line::from(" q: quit").dim(), help);
+    }
+}
+
+/// Shows the dashboard until the user quits or the sender shuts down.
+pub async fn run(state: AppState, shutdown: &CancellationToken) -> eyre::Result<()> {
+    let events = state.events.subscribe();
+
+    server::tui::run(Dashboard::new(&state), events, shutdown).await
+}
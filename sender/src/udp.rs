@@ -0,0 +1,51 @@
+//! Pings sent as UDP datagrams to the receiver.
+
+use std::{
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::{Duration, Instant},
+};
+
+use protocol::Pong;
+use tokio::net::UdpSocket;
+use uuid::Uuid;
+
+/// Time to wait for the pong before considering the datagram lost.
+pub const PONG_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Largest pong accepted from the receiver.
+const MAX_DATAGRAM: usize = 2048;
+
+/// Sends the ping and waits for its pong, returning the round-trip time.
+///
+/// Returns [`None`] if the pong didn't arrive within the [`PONG_TIMEOUT`].
+pub async fn exchange(target: SocketAddr, ping: &[u8], id: Uuid) -> eyre::Result<Option<Duration>> {
+    let local: SocketAddr = match target {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+
+    // A socket for each ping, connected to receive only from the receiver
+    let socket = UdpSocket::bind(local).await?;
+    socket.connect(target).await?;
+
+    let start = Instant::now();
+    socket.send(ping).await?;
+
+    let pong = async {
+        let mut buf = [0; MAX_DATAGRAM];
+
+        loop {
+            let len = socket.recv(&mut buf).await?;
+
+            // Skip anything that isn't the pong of this ping
+            if serde_json::from_slice::<Pong>(&buf[..len]).is_ok_and(|pong| pong.id == id) {
+                return eyre::Ok(start.elapsed());
+            }
+        }
+    };
+
+    match tokio::time::timeout(PONG_TIMEOUT, pong).await {
+        Ok(rtt) => rtt.map(Some),
+        Err(_elapsed) => Ok(None),
+    }
+}
@@ -0,0 +1,62 @@
+//! Detection of the idle clients of the long lived connections, like the WebSockets.
+
+use std::{future, time::Duration};
+
+use tokio::time::{Instant, Interval, MissedTickBehavior};
+
+/// What to do with the connection at a tick of the [`Heartbeat`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Beat {
+    /// Send a ping, to which a live client responds.
+    Ping,
+    /// Nothing was received from the client within the timeout, close the connection.
+    Idle,
+}
+
+#[derive(Debug)]
+pub struct Heartbeat {
+    timeout: Duration,
+    /// Missing without a timeout, then the connection is never idle.
+    interval: Option<Interval>,
+    last_seen: Instant,
+}
+
+impl Heartbeat {
+    pub fn new(timeout: Option<Duration>) -> Self {
+        // Pings twice per timeout, so a live client has time to respond
+        let interval = timeout.map(|timeout| {
+            // Not zero for the timeouts of a nanosecond
+            let period = (timeout / 2).max(Duration::from_nanos(1));
+            let mut interval = tokio::time::interval_at(Instant::now() + period, period);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+            interval
+        });
+
+        Self {
+            timeout: timeout.unwrap_or_default(),
+            interval,
+            last_seen: Instant::now(),
+        }
+    }
+
+    /// Something was received from the client.
+    pub fn seen(&mut self) {
+        self.last_seen = Instant::now();
+    }
+
+    /// Waits for the next tick, never completing without a timeout.
+    pub async fn tick(&mut self) -> Beat {
+        let Some(interval) = &mut self.interval else {
+            return future::pending().await;
+        };
+
+        interval.tick().await;
+
+        if self.last_seen.elapsed() >= self.timeout {
+            Beat::Idle
+        } else {
+            Beat::Ping
+        }
+    }
+}
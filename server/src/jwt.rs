@@ -0,0 +1,556 @@
+//! Verification of the JSON Web Tokens issued by an identity provider.
+//!
+//! The HS256 tokens are verified with a shared secret, the RS256 ones with the public keys of the
+//! provider, fetched at the start and again when a token is signed by an unknown key, at most
+//! once a minute. The tokens must not be expired, and must carry the audience and the issuer if
+//! configured.
+
+use std::{
+    collections::HashMap,
+    fmt::{self, Display},
+    sync::{RwLock, RwLockReadGuard},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use eyre::WrapErr;
+use ring::{
+    hmac,
+    signature::{RsaPublicKeyComponents, RSA_PKCS1_2048_8192_SHA256},
+};
+use serde::{de::DeserializeOwned, Deserialize};
+use tokio::{sync::Mutex, time::Instant};
+use tracing::{debug, info};
+use url::Url;
+
+/// Shortest time between two fetches of the keys.
+const REFETCH: Duration = Duration::from_secs(60);
+
+/// Reason a token is refused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Invalid(pub String);
+
+impl Display for Invalid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+fn invalid(msg: impl Into<String>) -> Invalid {
+    Invalid(msg.into())
+}
+
+#[derive(Debug, Deserialize)]
+struct Header {
+    alg: String,
+    kid: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Audience {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Audience {
+    fn contains(&self, audience: &str) -> bool {
+        match self {
+            Audience::One(aud) => aud == audience,
+            Audience::Many(auds) => auds.iter().any(|aud| aud == audience),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Claims {
+    pub sub: Option<String>,
+    iss: Option<String>,
+    aud: Option<Audience>,
+    exp: u64,
+    nbf: Option<u64>,
+    /// Value of the login the ID tokens were issued for.
+    pub nonce: Option<String>,
+    pub email: Option<String>,
+    pub preferred_username: Option<String>,
+    /// Tenant of the receiver the token is bound to.
+    pub tenant: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct JwkSet {
+    keys: Vec<JwkKey>,
+}
+
+#[derive(Debug, Deserialize)]
+struct JwkKey {
+    kty: String,
+    kid: Option<String>,
+    n: Option<String>,
+    e: Option<String>,
+}
+
+type RsaKey = RsaPublicKeyComponents<Vec<u8>>;
+
+#[derive(Debug)]
+pub struct Jwks {
+    url: Url,
+    client: reqwest::Client,
+    /// Keys by id, the ones without an id under the empty one.
+    keys: RwLock<HashMap<String, RsaKey>>,
+    /// When the keys were last fetched, serializing the fetches.
+    fetched: Mutex<Instant>,
+}
+
+impl Jwks {
+    async fn fetch(url: &Url, client: &reqwest::Client) -> eyre::Result<HashMap<String, RsaKey>> {
+        let set: JwkSet = client
+            .get(url.clone())
+            .send()
+            .await
+            .and_then(|res| res.error_for_status())
+            .wrap_err_with(|| format!("couldn't fetch the JWKS from {url}"))?
+            .json()
+            .await
+            .wrap_err("invalid JWKS")?;
+
+        let keys = set
+            .keys
+            .into_iter()
+            .filter(|key| key.kty == "RSA")
+            .filter_map(|key| {
+                let n = URL_SAFE_NO_PAD.decode(key.n?).ok()?;
+                let e = URL_SAFE_NO_PAD.decode(key.e?).ok()?;
+
+                Some((key.kid.unwrap_or_default(), RsaKey { n, e }))
+            })
+            .collect();
+
+        Ok(keys)
+    }
+
+    pub async fn load(url: &Url, client: &reqwest::Client) -> eyre::Result<Self> {
+        let keys = Self::fetch(url, client).await?;
+
+        info!(keys = keys.len(), %url, "JWKS fetched");
+
+        Ok(Self {
+            url: url.clone(),
+            client: client.clone(),
+            keys: RwLock::new(keys),
+            fetched: Mutex::new(Instant::now()),
+        })
+    }
+
+    fn keys(&self) -> RwLockReadGuard<'_, HashMap<String, RsaKey>> {
+        self.keys.read().unwrap_or_else(|err| err.into_inner())
+    }
+
+    async fn verify(&self, kid: &str, message: &[u8], signature: &[u8]) -> Result<(), Invalid> {
+        if !self.keys().contains_key(kid) {
+            self.refetch().await;
+        }
+
+        let keys = self.keys();
+        let key = keys
+            .get(kid)
+            .ok_or_else(|| invalid(format!("unknown signing key {kid:?}")))?;
+
+        key.verify(&RSA_PKCS1_2048_8192_SHA256, message, signature)
+            .map_err(|_| invalid("invalid token signature"))
+    }
+
+    /// Fetches the keys again, if they weren't fetched recently, for the rotated keys.
+    async fn refetch(&self) {
+        let mut fetched = self.fetched.lock().await;
+
+        if fetched.elapsed() < REFETCH {
+            return;
+        }
+
+        *fetched = Instant::now();
+
+        match Self::fetch(&self.url, &self.client).await {
+            Ok(keys) => {
+                info!(keys = keys.len(), "JWKS fetched again");
+
+                *self.keys.write().unwrap_or_else(|err| err.into_inner()) = keys;
+            }
+            Err(err) => {
+                debug!(error = format!("{err:#}"), "couldn't fetch the JWKS again");
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Jwt {
+    secret: Option<hmac::Key>,
+    jwks: Option<Jwks>,
+    audience: Option<String>,
+    issuer: Option<String>,
+    leeway: Duration,
+}
+
+fn decode<T: DeserializeOwned>(part: &str) -> Result<T, Invalid> {
+    URL_SAFE_NO_PAD
+        .decode(part)
+        .ok()
+        .and_then(|json| serde_json::from_slice(&json).ok())
+        .ok_or_else(|| invalid("malformed token"))
+}
+
+impl Jwt {
+    /// Verifies the HS256 tokens with the secret and the RS256 ones with the keys, each refused
+    /// without them.
+    pub fn new(
+        secret: Option<&str>,
+        jwks: Option<Jwks>,
+        audience: Option<String>,
+        issuer: Option<String>,
+        leeway: Duration,
+    ) -> Self {
+        Self {
+            secret: secret.map(|secret| hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes())),
+            jwks,
+            audience,
+            issuer,
+            leeway,
+        }
+    }
+
+    /// Verifies the signature and the claims of the token.
+    pub async fn verify(&self, token: &str) -> Result<Claims, Invalid> {
+        let mut parts = token.split('.');
+        let (Some(header), Some(payload), Some(signature), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(invalid("malformed token"));
+        };
+
+        let message = &token[..header.len() + 1 + payload.len()];
+        let signature = URL_SAFE_NO_PAD
+            .decode(signature)
+            .map_err(|_| invalid("malformed token"))?;
+        let header: Header = decode(header)?;
+
+        match (header.alg.as_str(), &self.secret, &self.jwks) {
+            ("HS256", Some(secret), _) => {
+                hmac::verify(secret, message.as_bytes(), &signature)
+                    .map_err(|_| invalid("invalid token signature"))?;
+            }
+            ("RS256", _, Some(jwks)) => {
+                let kid = header.kid.unwrap_or_default();
+
+                jwks.verify(&kid, message.as_bytes(), &signature).await?;
+            }
+            (alg, _, _) => {
+                return Err(invalid(format!("unsupported token algorithm {alg}")));
+            }
+        }
+
+        let claims: Claims = decode(payload)?;
+        self.validate(&claims)?;
+
+        Ok(claims)
+    }
+
+    fn validate(&self, claims: &Claims) -> Result<(), Invalid> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let leeway = self.leeway.as_secs();
+
+        if claims.exp.saturating_add(leeway) <= now {
+            return Err(invalid("the token expired"));
+        }
+
+        if claims
+            .nbf
+            .is_some_and(|nbf| nbf > now.saturating_add(leeway))
+        {
+            return Err(invalid("the token isn't valid yet"));
+        }
+
+        if let Some(issuer) = &self.issuer {
+            if claims.iss.as_ref() != Some(issuer) {
+                return Err(invalid(format!("the token wasn't issued by {issuer}")));
+            }
+        }
+
+        if let Some(audience) = &self.audience {
+            if !claims
+                .aud
+                .as_ref()
+                .is_some_and(|aud| aud.contains(audience))
+            {
+                return Err(invalid(format!("the token wasn't issued for {audience}")));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ring::{
+        rand::SystemRandom,
+        signature::{RsaKeyPair, RSA_PKCS1_SHA256},
+    };
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    const SECRET: &str = "s3cret";
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    fn encode(value: &Value) -> String {
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(value).unwrap())
+    }
+
+    fn hs256(secret: &str, claims: Value) -> String {
+        let message = format!(
+            "{}.{}",
+            encode(&json!({"alg": "HS256", "typ": "JWT"})),
+            encode(&claims)
+        );
+        let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+        let signature = hmac::sign(&key, message.as_bytes());
+
+        format!("{message}.{}", URL_SAFE_NO_PAD.encode(signature))
+    }
+
+    fn key_pair() -> RsaKeyPair {
+        let pem = include_str!("../testdata/rs256.pem");
+        let der: String = pem
+            .lines()
+            .filter(|line| !line.starts_with("-----"))
+            .collect();
+        let der = base64::engine::general_purpose::STANDARD
+            .decode(der)
+            .unwrap();
+
+        RsaKeyPair::from_pkcs8(&der).unwrap()
+    }
+
+    fn rs256(key: &RsaKeyPair, kid: &str, claims: Value) -> String {
+        let message = format!(
+            "{}.{}",
+            encode(&json!({"alg": "RS256", "typ": "JWT", "kid": kid})),
+            encode(&claims)
+        );
+        let mut signature = vec![0; key.public().modulus_len()];
+        key.sign(
+            &RSA_PKCS1_SHA256,
+            &SystemRandom::new(),
+            message.as_bytes(),
+            &mut signature,
+        )
+        .unwrap();
+
+        format!("{message}.{}", URL_SAFE_NO_PAD.encode(signature))
+    }
+
+    /// Keys of the provider, fetched just now so the unknown ones aren't fetched again.
+    fn jwks(key: &RsaKeyPair, kid: &str) -> Jwks {
+        let key = RsaKey::from(key.public());
+
+        Jwks {
+            url: Url::parse("http://localhost/jwks.json").unwrap(),
+            client: reqwest::Client::new(),
+            keys: RwLock::new(HashMap::from([(kid.to_string(), key)])),
+            fetched: Mutex::new(Instant::now()),
+        }
+    }
+
+    fn hs256_jwt() -> Jwt {
+        Jwt::new(Some(SECRET), None, None, None, Duration::ZERO)
+    }
+
+    #[tokio::test]
+    async fn hs256_verified_with_the_secret() {
+        let token = hs256(SECRET, json!({"sub": "alice", "exp": now() + 60}));
+
+        let claims = hs256_jwt().verify(&token).await.unwrap();
+
+        assert_eq!(claims.sub.as_deref(), Some("alice"));
+    }
+
+    #[tokio::test]
+    async fn hs256_refused_with_another_secret() {
+        let token = hs256("other", json!({"exp": now() + 60}));
+
+        let err = hs256_jwt().verify(&token).await.unwrap_err();
+
+        assert_eq!(err, invalid("invalid token signature"));
+    }
+
+    #[tokio::test]
+    async fn rs256_verified_with_the_keys() {
+        let key = key_pair();
+        let jwt = Jwt::new(None, Some(jwks(&key, "k1")), None, None, Duration::ZERO);
+        let token = rs256(&key, "k1", json!({"sub": "bob", "exp": now() + 60}));
+
+        let claims = jwt.verify(&token).await.unwrap();
+
+        assert_eq!(claims.sub.as_deref(), Some("bob"));
+    }
+
+    #[tokio::test]
+    async fn rs256_refused_with_an_unknown_key() {
+        let key = key_pair();
+        let jwt = Jwt::new(None, Some(jwks(&key, "k1")), None, None, Duration::ZERO);
+        let token = rs256(&key, "k2", json!({"exp": now() + 60}));
+
+        let err = jwt.verify(&token).await.unwrap_err();
+
+        assert_eq!(err, invalid("unknown signing key \"k2\""));
+    }
+
+    #[tokio::test]
+    async fn rs256_refused_when_tampered() {
+        let key = key_pair();
+        let jwt = Jwt::new(None, Some(jwks(&key, "k1")), None, None, Duration::ZERO);
+        let token = rs256(&key, "k1", json!({"sub": "bob", "exp": now() + 60}));
+        let (message, signature) = token.rsplit_once('.').unwrap();
+        let (header, _) = message.split_once('.').unwrap();
+        let forged = encode(&json!({"sub": "mallory", "exp": now() + 60}));
+
+        let err = jwt
+            .verify(&format!("{header}.{forged}.{signature}"))
+            .await
+            .unwrap_err();
+
+        assert_eq!(err, invalid("invalid token signature"));
+    }
+
+    #[tokio::test]
+    async fn unsigned_tokens_refused() {
+        let token = format!(
+            "{}.{}.",
+            encode(&json!({"alg": "none"})),
+            encode(&json!({"exp": now() + 60}))
+        );
+
+        let err = hs256_jwt().verify(&token).await.unwrap_err();
+
+        assert_eq!(err, invalid("unsupported token algorithm none"));
+    }
+
+    #[tokio::test]
+    async fn hs256_refused_without_a_secret() {
+        // The public key of the provider can't be used as the secret of an HS256 token
+        let key = key_pair();
+        let jwt = Jwt::new(None, Some(jwks(&key, "k1")), None, None, Duration::ZERO);
+        let token = hs256(SECRET, json!({"exp": now() + 60}));
+
+        let err = jwt.verify(&token).await.unwrap_err();
+
+        assert_eq!(err, invalid("unsupported token algorithm HS256"));
+    }
+
+    #[tokio::test]
+    async fn rs256_refused_without_the_keys() {
+        let token = rs256(&key_pair(), "k1", json!({"exp": now() + 60}));
+
+        let err = hs256_jwt().verify(&token).await.unwrap_err();
+
+        assert_eq!(err, invalid("unsupported token algorithm RS256"));
+    }
+
+    #[tokio::test]
+    async fn malformed_tokens_refused() {
+        let jwt = hs256_jwt();
+
+        for token in ["", "a.b", "a.b.c.d", "!.!.!"] {
+            assert_eq!(
+                jwt.verify(token).await.unwrap_err(),
+                invalid("malformed token")
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn expired_tokens_refused_past_the_leeway() {
+        let token = hs256(SECRET, json!({"exp": now() - 30}));
+
+        let err = hs256_jwt().verify(&token).await.unwrap_err();
+        assert_eq!(err, invalid("the token expired"));
+
+        let jwt = Jwt::new(Some(SECRET), None, None, None, Duration::from_secs(60));
+        assert!(jwt.verify(&token).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn tokens_refused_before_not_before() {
+        let token = hs256(SECRET, json!({"exp": now() + 600, "nbf": now() + 300}));
+
+        let err = hs256_jwt().verify(&token).await.unwrap_err();
+        assert_eq!(err, invalid("the token isn't valid yet"));
+
+        let jwt = Jwt::new(Some(SECRET), None, None, None, Duration::from_secs(600));
+        assert!(jwt.verify(&token).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn audience_required_if_configured() {
+        let jwt = Jwt::new(
+            Some(SECRET),
+            None,
+            Some("pingpong".to_string()),
+            None,
+            Duration::ZERO,
+        );
+        let exp = now() + 60;
+
+        for aud in [json!("pingpong"), json!(["other", "pingpong"])] {
+            let token = hs256(SECRET, json!({"aud": aud, "exp": exp}));
+
+            assert!(jwt.verify(&token).await.is_ok(), "{aud} refused");
+        }
+
+        for claims in [json!({"aud": "other", "exp": exp}), json!({"exp": exp})] {
+            let token = hs256(SECRET, claims);
+
+            assert_eq!(
+                jwt.verify(&token).await.unwrap_err(),
+                invalid("the token wasn't issued for pingpong")
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn issuer_required_if_configured() {
+        let jwt = Jwt::new(
+            Some(SECRET),
+            None,
+            None,
+            Some("https://idp.example".to_string()),
+            Duration::ZERO,
+        );
+        let exp = now() + 60;
+
+        let token = hs256(SECRET, json!({"iss": "https://idp.example", "exp": exp}));
+        assert!(jwt.verify(&token).await.is_ok());
+
+        for claims in [
+            json!({"iss": "https://other.example", "exp": exp}),
+            json!({"exp": exp}),
+        ] {
+            let token = hs256(SECRET, claims);
+
+            assert_eq!(
+                jwt.verify(&token).await.unwrap_err(),
+                invalid("the token wasn't issued by https://idp.example")
+            );
+        }
+    }
+}
@@ -0,0 +1,401 @@
+//! HTTP server shared by the receiver and the sender.
+//!
+//! Like [`axum::serve`] with a graceful shutdown, but the open connections are given a limited
+//! time to complete once the shutdown is cancelled, after which they are closed. The handlers
+//! can extract the address of the client as a [`ConnectInfo<SocketAddr>`](ConnectInfo).
+
+use std::{
+    future, io,
+    net::SocketAddr,
+    pin::pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use axum::{extract::ConnectInfo, Extension, Router};
+use clap::{ArgAction, Args};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::conn::auto::Builder,
+    service::TowerToHyperService,
+};
+use socket2::{Domain, Protocol, SockRef, Socket, TcpKeepalive, Type};
+use tokio::{
+    net::{TcpListener, TcpStream, UdpSocket},
+    task::JoinSet,
+};
+use tokio_util::sync::CancellationToken;
+use tower::Layer;
+use tracing::{debug, error, info, trace, warn};
+
+mod heartbeat;
+mod idle;
+pub mod jwt;
+pub mod oidc;
+pub mod options;
+pub mod otlp;
+mod redis;
+pub mod request_id;
+pub mod session;
+pub mod telemetry;
+pub mod tui;
+
+pub use self::heartbeat::{Beat, Heartbeat};
+
+use self::{
+    idle::{Activity, Tracked},
+    oidc::OidcArgs,
+    session::SessionArgs,
+};
+
+/// Connections being served by all the servers of the process.
+static OPEN_CONNECTIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// Number of connections open on all the servers of the process.
+pub fn open_connections() -> usize {
+    OPEN_CONNECTIONS.load(Ordering::Relaxed)
+}
+
+/// Counts the connection as open on its server and the process until dropped.
+#[derive(Debug)]
+struct Open(Arc<AtomicUsize>);
+
+impl Open {
+    fn new(server: &Arc<AtomicUsize>) -> Self {
+        OPEN_CONNECTIONS.fetch_add(1, Ordering::Relaxed);
+        server.fetch_add(1, Ordering::Relaxed);
+
+        Self(Arc::clone(server))
+    }
+}
+
+impl Drop for Open {
+    fn drop(&mut self) {
+        OPEN_CONNECTIONS.fetch_sub(1, Ordering::Relaxed);
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct ServerArgs {
+    /// Time the open connections have to complete after the shutdown, before being closed
+    #[arg(long, default_value = "30s", value_parser = humantime::parse_duration)]
+    pub shutdown_timeout: Duration,
+    /// Let other processes bind the same port, like a new instance during a restart
+    #[arg(long)]
+    pub reuse_port: bool,
+    /// Number of following ports tried when the requested one is in use
+    #[arg(long, default_value = "0")]
+    pub port_fallback: u16,
+    /// Maximum number of connections queued by the kernel before being accepted
+    #[arg(long, default_value = "128")]
+    pub backlog: i32,
+    /// Send the small responses right away instead of batching them
+    #[arg(long, default_value_t = true, action = ArgAction::Set)]
+    pub tcp_nodelay: bool,
+    /// Idle time after which the connections are probed, and interval between the probes
+    #[arg(long, value_parser = humantime::parse_duration)]
+    pub tcp_keepalive: Option<Duration>,
+    /// Size in bytes of the receive buffer of the sockets
+    #[arg(long)]
+    pub recv_buffer: Option<usize>,
+    /// Maximum number of open connections, the new ones are reset beyond it
+    #[arg(long)]
+    pub max_connections: Option<usize>,
+    /// Time after which the connections and WebSockets without activity from the client are closed
+    #[arg(long, value_parser = parse_interval)]
+    pub idle_timeout: Option<Duration>,
+    #[command(flatten)]
+    pub session: SessionArgs,
+    #[command(flatten)]
+    pub oidc: OidcArgs,
+}
+
+/// Parses a duration like [`humantime::parse_duration`], refusing zero for the intervals and
+/// the timeouts they are derived from.
+pub fn parse_interval(s: &str) -> Result<Duration, String> {
+    let interval =
+        humantime::parse_duration(s).map_err(|err| format!("invalid duration {s}: {err}"))?;
+
+    if interval.is_zero() {
+        return Err("the duration can't be zero".to_string());
+    }
+
+    Ok(interval)
+}
+
+/// Binds the listener of the server, falling back to the following ports if it's in use.
+pub fn bind(addr: SocketAddr, args: &ServerArgs) -> io::Result<TcpListener> {
+    // The ephemeral port is never in use
+    let last = if addr.port() == 0 {
+        0
+    } else {
+        addr.port().saturating_add(args.port_fallback)
+    };
+
+    for port in addr.port()..=last {
+        let candidate = SocketAddr::new(addr.ip(), port);
+
+        match listen(candidate, args) {
+            Ok(listener) => {
+                if port != addr.port() {
+                    warn!("port {} in use, listening on {candidate}", addr.port());
+                }
+
+                return Ok(listener);
+            }
+            Err(err) if err.kind() == io::ErrorKind::AddrInUse && port < last => {
+                debug!(%candidate, "port in use, trying the next one");
+            }
+            Err(err) if err.kind() == io::ErrorKind::AddrInUse => {
+                let ports = if last == addr.port() {
+                    format!("port {last}")
+                } else {
+                    format!("ports {} to {last}", addr.port())
+                };
+
+                return Err(io::Error::new(
+                    err.kind(),
+                    format!(
+                        "{ports} on {} already in use, stop the process using it or pass \
+                         --port-fallback or --reuse-port",
+                        addr.ip()
+                    ),
+                ));
+            }
+            Err(err) => {
+                return Err(io::Error::new(
+                    err.kind(),
+                    format!("couldn't listen on {candidate}: {err}"),
+                ))
+            }
+        }
+    }
+
+    unreachable!("the range of ports is never empty")
+}
+
+fn listen(addr: SocketAddr, args: &ServerArgs) -> io::Result<TcpListener> {
+    let socket = socket(addr, Type::STREAM, Protocol::TCP, args)?;
+    socket.listen(args.backlog)?;
+
+    TcpListener::from_std(socket.into())
+}
+
+/// Binds a UDP socket with the same options of the listener.
+pub fn bind_udp(addr: SocketAddr, args: &ServerArgs) -> io::Result<UdpSocket> {
+    let socket = socket(addr, Type::DGRAM, Protocol::UDP, args)?;
+
+    UdpSocket::from_std(socket.into())
+}
+
+fn socket(addr: SocketAddr, ty: Type, protocol: Protocol, args: &ServerArgs) -> io::Result<Socket> {
+    let socket = Socket::new(Domain::for_address(addr), ty, Some(protocol))?;
+
+    // Set by the standard library on unix, to rebind the ports in TIME_WAIT
+    if ty == Type::STREAM {
+        socket.set_reuse_address(true)?;
+    }
+    // The kernel balances the new connections between the processes bound to the port, so the
+    // old instance can drain its connections while the new one accepts
+    if args.reuse_port {
+        socket.set_reuse_port(true)?;
+    }
+
+    // Set before listening, the accepted connections inherit it
+    if let Some(size) = args.recv_buffer {
+        socket.set_recv_buffer_size(size)?;
+    }
+
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+
+    Ok(socket)
+}
+
+fn is_connection_error(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::ConnectionReset
+    )
+}
+
+async fn accept(listener: &TcpListener) -> Option<TcpStream> {
+    match listener.accept().await {
+        Ok((stream, _)) => Some(stream),
+        Err(err) if is_connection_error(&err) => None,
+        Err(err) => {
+            // Likely out of file descriptors, wait for some connections to close
+            error!(error = %err, "couldn't accept connection");
+
+            tokio::time::sleep(Duration::from_secs(1)).await;
+
+            None
+        }
+    }
+}
+
+/// Sets the options of an accepted connection.
+fn configure(stream: &TcpStream, args: &ServerArgs) -> io::Result<()> {
+    stream.set_nodelay(args.tcp_nodelay)?;
+
+    if let Some(keepalive) = args.tcp_keepalive {
+        let keepalive = TcpKeepalive::new()
+            .with_time(keepalive)
+            .with_interval(keepalive);
+
+        SockRef::from(stream).set_tcp_keepalive(&keepalive)?;
+    }
+
+    Ok(())
+}
+
+/// Closes the connection with a reset, without waiting for the client.
+fn reset(stream: TcpStream) {
+    if let Err(err) = SockRef::from(&stream).set_linger(Some(Duration::ZERO)) {
+        trace!(error = %err, "couldn't set the linger of the connection");
+    }
+}
+
+/// Serves the connection until it's closed, or completes the pending requests on shutdown.
+async fn connection(
+    stream: TcpStream,
+    open: Open,
+    app: Router,
+    idle_timeout: Option<Duration>,
+    shutdown: CancellationToken,
+) {
+    let remote = match stream.peer_addr() {
+        Ok(remote) => remote,
+        Err(err) => {
+            trace!(error = %err, "couldn't get the address of the connection");
+
+            return;
+        }
+    };
+    // Like axum::serve with the connect info, for the handlers extracting the client address
+    let app = Extension(ConnectInfo(remote)).layer(app);
+
+    let activity = Arc::new(Activity::new());
+    let io = TokioIo::new(Tracked::new(stream, Arc::clone(&activity), open));
+
+    let builder = Builder::new(TokioExecutor::new());
+    let mut conn = pin!(builder.serve_connection_with_upgrades(io, TowerToHyperService::new(app)));
+
+    // Hyper doesn't time out the keep-alive connections waiting for the next request
+    let idle = async {
+        let Some(timeout) = idle_timeout else {
+            return future::pending().await;
+        };
+
+        loop {
+            let idle = activity.idle();
+            if idle >= timeout {
+                break;
+            }
+
+            tokio::time::sleep(timeout - idle).await;
+        }
+
+        debug!("closing idle connection");
+    };
+
+    // Once upgraded the connection completes, leaving the WebSockets to their heartbeat
+    let res = tokio::select! {
+        res = conn.as_mut() => res,
+        _ = shutdown.cancelled() => {
+            conn.as_mut().graceful_shutdown();
+
+            conn.await
+        }
+        _ = idle => {
+            // Waits for the request in flight, if any
+            conn.as_mut().graceful_shutdown();
+
+            conn.await
+        }
+    };
+
+    if let Err(err) = res {
+        trace!(error = %err, "couldn't serve connection");
+    }
+}
+
+/// Serves the app until the shutdown is cancelled, then drains the connections.
+pub async fn serve(
+    listener: TcpListener,
+    app: Router,
+    args: ServerArgs,
+    shutdown: CancellationToken,
+) -> io::Result<()> {
+    let mut connections = JoinSet::new();
+    // Open connections, counting the WebSockets no longer served by the tasks once upgraded
+    let open = Arc::new(AtomicUsize::new(0));
+
+    loop {
+        let stream = tokio::select! {
+            _ = shutdown.cancelled() => break,
+            stream = accept(&listener) => stream,
+        };
+
+        // Reap the closed connections
+        while connections.try_join_next().is_some() {}
+
+        let Some(stream) = stream else {
+            continue;
+        };
+
+        if args
+            .max_connections
+            .is_some_and(|max| open.load(Ordering::Relaxed) >= max)
+        {
+            debug!("too many connections, resetting the new one");
+
+            reset(stream);
+
+            continue;
+        }
+
+        if let Err(err) = configure(&stream, &args) {
+            warn!(error = %err, "couldn't set the options of the connection");
+        }
+
+        connections.spawn(connection(
+            stream,
+            Open::new(&open),
+            app.clone(),
+            args.idle_timeout,
+            shutdown.clone(),
+        ));
+    }
+
+    drop(listener);
+
+    let open = connections.len();
+    if open > 0 {
+        info!(open, "draining connections for {:?}", args.shutdown_timeout);
+    }
+
+    let drain = async { while connections.join_next().await.is_some() {} };
+
+    if tokio::time::timeout(args.shutdown_timeout, drain)
+        .await
+        .is_ok()
+    {
+        debug!("connections drained");
+    } else {
+        warn!(
+            aborted = connections.len(),
+            "closing the connections still open"
+        );
+
+        connections.shutdown().await;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,541 @@
+//! OpenID Connect login of the users of the UI.
+//!
+//! With `--oidc-issuer` the browsers without a user in their [`Session`] are redirected to the
+//! provider, found through its discovery document, to log in with the authorization code flow.
+//! The ID token returned for the code is verified with the keys of the provider and the nonce of
+//! the login, and the user is kept in the session until it expires or the user logs out by posting
+//! to `/auth/logout`.
+
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use axum::{
+    extract::{Query, Request, State},
+    http::{header::LOCATION, HeaderValue, Method, StatusCode, Uri},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Router,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use clap::Args;
+use eyre::WrapErr;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info};
+use url::{form_urlencoded, Url};
+
+use crate::{
+    jwt::{Claims, Jwks, Jwt},
+    session::Session,
+};
+
+/// Key of the login in progress in the session.
+const LOGIN: &str = "oidc.login";
+/// Key of the user logged in in the session.
+const USER: &str = "oidc.user";
+/// Time the users have to log in on the provider.
+const LOGIN_TTL: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Debug, Clone, Default, Args)]
+pub struct OidcArgs {
+    /// Url of the OpenID Connect provider the users log in to the UI with
+    #[arg(
+        long,
+        value_name = "URL",
+        requires_all = ["oidc_client_id", "oidc_client_secret", "oidc_redirect_url"]
+    )]
+    pub oidc_issuer: Option<Url>,
+    /// Id of the server as a client of the provider
+    #[arg(long, requires = "oidc_issuer")]
+    pub oidc_client_id: Option<String>,
+    /// Secret of the server as a client of the provider
+    #[arg(long, env = "OIDC_CLIENT_SECRET", hide_env_values = true)]
+    pub oidc_client_secret: Option<String>,
+    /// Url of `/auth/callback` on the server, as registered on the provider
+    #[arg(long, value_name = "URL", requires = "oidc_issuer")]
+    pub oidc_redirect_url: Option<Url>,
+}
+
+/// Endpoints of the provider, from its discovery document.
+#[derive(Debug, Deserialize)]
+struct Provider {
+    issuer: String,
+    authorization_endpoint: Url,
+    token_endpoint: Url,
+    jwks_uri: Url,
+    end_session_endpoint: Option<Url>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Login {
+    state: String,
+    nonce: String,
+    /// Path the user is sent back to once logged in.
+    redirect: String,
+    started: SystemTime,
+}
+
+/// User logged in with the provider.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct User {
+    pub subject: String,
+    /// Email or username of the user, for display.
+    pub name: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct Oidc {
+    provider: Provider,
+    id_tokens: Jwt,
+    client: reqwest::Client,
+    client_id: String,
+    client_secret: String,
+    redirect_url: Url,
+}
+
+type Rejection = (StatusCode, String);
+
+fn unauthorized(msg: impl Into<String>) -> Rejection {
+    (StatusCode::UNAUTHORIZED, msg.into())
+}
+
+/// Random value unguessable by the other clients.
+fn random() -> String {
+    URL_SAFE_NO_PAD.encode(rand::thread_rng().gen::<[u8; 32]>())
+}
+
+/// Path to send the user back to, only on the server.
+fn local_path(redirect: Option<String>) -> String {
+    redirect
+        .filter(|path| is_local(path))
+        .unwrap_or_else(|| "/".to_string())
+}
+
+/// Whether the redirect is a path without an authority, the browsers reading the backslashes as
+/// slashes and dropping the control characters, like in `/\evil.example`.
+fn is_local(path: &str) -> bool {
+    let plain = path.starts_with('/')
+        && !path.starts_with("//")
+        && !path.contains('\\')
+        && !path.chars().any(char::is_control);
+
+    plain
+        && path
+            .parse::<Uri>()
+            .is_ok_and(|uri| uri.scheme().is_none() && uri.authority().is_none())
+}
+
+fn see_other(location: &str) -> Result<Response, Rejection> {
+    let location = HeaderValue::from_str(location)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "invalid redirect".to_string()))?;
+
+    Ok((StatusCode::SEE_OTHER, [(LOCATION, location)]).into_response())
+}
+
+impl Oidc {
+    pub async fn new(args: &OidcArgs, client: &reqwest::Client) -> eyre::Result<Option<Self>> {
+        let (Some(issuer), Some(client_id), Some(client_secret), Some(redirect_url)) = (
+            &args.oidc_issuer,
+            &args.oidc_client_id,
+            &args.oidc_client_secret,
+            &args.oidc_redirect_url,
+        ) else {
+            return Ok(None);
+        };
+
+        let discovery = Url::parse(&format!(
+            "{}/.well-known/openid-configuration",
+            issuer.as_str().trim_end_matches('/')
+        ))?;
+        let provider: Provider = client
+            .get(discovery.clone())
+            .send()
+            .await
+            .and_then(|res| res.error_for_status())
+            .wrap_err_with(|| format!("couldn't fetch the discovery document from {discovery}"))?
+            .json()
+            .await
+            .wrap_err("invalid discovery document")?;
+
+        eyre::ensure!(
+            provider.issuer.trim_end_matches('/') == issuer.as_str().trim_end_matches('/'),
+            "the provider at {issuer} reports the issuer {}",
+            provider.issuer
+        );
+
+        let jwks = Jwks::load(&provider.jwks_uri, client).await?;
+        // Signed with the keys of the provider or the secret of the client
+        let id_tokens = Jwt::new(
+            Some(client_secret),
+            Some(jwks),
+            Some(client_id.clone()),
+            Some(provider.issuer.clone()),
+            Duration::from_secs(60),
+        );
+
+        info!(issuer = provider.issuer, "OpenID Connect login enabled");
+
+        Ok(Some(Self {
+            provider,
+            id_tokens,
+            client: client.clone(),
+            client_id: client_id.clone(),
+            client_secret: client_secret.clone(),
+            redirect_url: redirect_url.clone(),
+        }))
+    }
+
+    /// Exchanges the code for an ID token, verifying it for the nonce of the login.
+    async fn exchange(&self, code: &str, nonce: &str) -> Result<User, Rejection> {
+        #[derive(Deserialize)]
+        struct Tokens {
+            id_token: String,
+        }
+
+        let tokens: Tokens = self
+            .client
+            .post(self.provider.token_endpoint.clone())
+            .basic_auth(&self.client_id, Some(&self.client_secret))
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", self.redirect_url.as_str()),
+            ])
+            .send()
+            .await
+            .and_then(|res| res.error_for_status())
+            .map_err(|err| {
+                debug!(error = %err, "couldn't exchange the code");
+
+                unauthorized("the provider refused the login")
+            })?
+            .json()
+            .await
+            .map_err(|err| {
+                debug!(error = %err, "invalid tokens");
+
+                (
+                    StatusCode::BAD_GATEWAY,
+                    "invalid response of the provider".to_string(),
+                )
+            })?;
+
+        let claims = self
+            .id_tokens
+            .verify(&tokens.id_token)
+            .await
+            .map_err(|err| unauthorized(err.0))?;
+
+        logged_in(claims, nonce)
+    }
+}
+
+/// User of the verified ID token, if issued for the nonce of the login.
+fn logged_in(claims: Claims, nonce: &str) -> Result<User, Rejection> {
+    if claims.nonce.as_deref() != Some(nonce) {
+        return Err(unauthorized("the ID token wasn't issued for this login"));
+    }
+
+    let subject = claims
+        .sub
+        .ok_or_else(|| unauthorized("the ID token has no subject"))?;
+
+    Ok(User {
+        subject,
+        name: claims.email.or(claims.preferred_username),
+    })
+}
+
+/// User of the session, if logged in.
+pub fn user(session: &Session) -> Option<User> {
+    session.get(USER)
+}
+
+/// Redirects the requests without a user to the login, to use with
+/// [`axum::middleware::from_fn_with_state`].
+pub async fn require(
+    State(_): State<Arc<Oidc>>,
+    session: Session,
+    mut req: Request,
+    next: Next,
+) -> Result<Response, Rejection> {
+    if let Some(user) = user(&session) {
+        req.extensions_mut().insert(user);
+
+        return Ok(next.run(req).await);
+    }
+
+    // The WebSockets and the scripts can't follow the login
+    if req.method() != Method::GET || req.headers().contains_key("upgrade") {
+        return Err(unauthorized("not logged in"));
+    }
+
+    let path = req.uri().path_and_query().map_or("/", |path| path.as_str());
+    let redirect: String = form_urlencoded::byte_serialize(path.as_bytes()).collect();
+
+    see_other(&format!("/auth/login?redirect={redirect}"))
+}
+
+#[derive(Debug, Deserialize)]
+struct LoginQuery {
+    redirect: Option<String>,
+}
+
+async fn login(
+    State(oidc): State<Arc<Oidc>>,
+    session: Session,
+    Query(query): Query<LoginQuery>,
+) -> Result<Response, Rejection> {
+    let login = Login {
+        state: random(),
+        nonce: random(),
+        redirect: local_path(query.redirect),
+        started: SystemTime::now(),
+    };
+
+    let mut url = oidc.provider.authorization_endpoint.clone();
+    url.query_pairs_mut()
+        .append_pair("response_type", "code")
+        .append_pair("client_id", &oidc.client_id)
+        .append_pair("redirect_uri", oidc.redirect_url.as_str())
+        .append_pair("scope", "openid email profile")
+        .append_pair("state", &login.state)
+        .append_pair("nonce", &login.nonce);
+
+    // Bound to the browser starting it, not kept past the login for the anonymous ones
+    session.insert(LOGIN, login);
+    if user(&session).is_none() {
+        session.expire_in(LOGIN_TTL);
+    }
+
+    see_other(url.as_str())
+}
+
+#[derive(Debug, Deserialize)]
+struct CallbackQuery {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+}
+
+/// Login of the session the provider sent the user back with, if started by it and not expired.
+fn pending(session: &Session, state: &str) -> Result<Login, Rejection> {
+    let login = session
+        .remove::<Login>(LOGIN)
+        .filter(|login| login.state == state)
+        .ok_or_else(|| unauthorized("the login wasn't started by this browser"))?;
+
+    let expired = login
+        .started
+        .elapsed()
+        .map_or(true, |elapsed| elapsed > LOGIN_TTL);
+    if expired {
+        return Err(unauthorized("the login expired"));
+    }
+
+    Ok(login)
+}
+
+async fn callback(
+    State(oidc): State<Arc<Oidc>>,
+    session: Session,
+    Query(query): Query<CallbackQuery>,
+) -> Result<Response, Rejection> {
+    if let Some(error) = query.error {
+        return Err(unauthorized(format!("the login failed: {error}")));
+    }
+
+    let (Some(code), Some(state)) = (query.code, query.state) else {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "missing code or state of the login".to_string(),
+        ));
+    };
+
+    let login = pending(&session, &state)?;
+    let user = oidc.exchange(&code, &login.nonce).await?;
+
+    info!(subject = user.subject, name = user.name, "user logged in");
+
+    // A session known before the login can't be used to impersonate the user
+    session.renew();
+    session.insert(USER, user);
+
+    see_other(&login.redirect)
+}
+
+async fn logout(State(oidc): State<Arc<Oidc>>, session: Session) -> Result<Response, Rejection> {
+    if let Some(user) = user(&session) {
+        info!(subject = user.subject, "user logged out");
+    }
+
+    session.destroy();
+
+    // Logged out of the provider too, if it supports it
+    let location = match &oidc.provider.end_session_endpoint {
+        Some(endpoint) => {
+            let mut url = endpoint.clone();
+            url.query_pairs_mut()
+                .append_pair("client_id", &oidc.client_id);
+
+            url.to_string()
+        }
+        None => "/".to_string(),
+    };
+
+    see_other(&location)
+}
+
+pub fn routes<S>(oidc: Arc<Oidc>) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    Router::new()
+        .route("/auth/login", get(login))
+        .route("/auth/callback", get(callback))
+        // Not a GET, the other sites could log the users out with a link or an image
+        .route("/auth/logout", post(logout))
+        .with_state(oidc)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn start(session: &Session, started: SystemTime) {
+        session.insert(
+            LOGIN,
+            Login {
+                state: "state".to_string(),
+                nonce: "nonce".to_string(),
+                redirect: "/pings".to_string(),
+                started,
+            },
+        );
+    }
+
+    fn claims(claims: serde_json::Value) -> Claims {
+        serde_json::from_value(claims).unwrap()
+    }
+
+    #[test]
+    fn redirects_kept_on_the_server() {
+        for path in ["/", "/pings?tenant=a", "/a//b"] {
+            assert_eq!(local_path(Some(path.to_string())), path);
+        }
+    }
+
+    #[test]
+    fn redirects_to_other_sites_refused() {
+        for path in [
+            "https://evil.example",
+            "//evil.example",
+            "/\\evil.example",
+            "/\t/evil.example",
+            "/\n/evil.example",
+            "evil.example",
+            "",
+        ] {
+            assert_eq!(local_path(Some(path.to_string())), "/", "{path:?} accepted");
+        }
+
+        assert_eq!(local_path(None), "/");
+    }
+
+    #[test]
+    fn pending_login_of_the_state() {
+        let session = Session::default();
+        start(&session, SystemTime::now());
+
+        let login = pending(&session, "state").unwrap();
+
+        assert_eq!(login.nonce, "nonce");
+        assert_eq!(login.redirect, "/pings");
+    }
+
+    #[test]
+    fn pending_login_used_once() {
+        let session = Session::default();
+        start(&session, SystemTime::now());
+
+        pending(&session, "state").unwrap();
+        let (status, _) = pending(&session, "state").unwrap_err();
+
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[test]
+    fn pending_login_refused_for_another_state() {
+        let session = Session::default();
+        start(&session, SystemTime::now());
+
+        let (_, msg) = pending(&session, "other").unwrap_err();
+        assert_eq!(msg, "the login wasn't started by this browser");
+
+        // Forgotten, so the state can't be guessed with retries
+        assert!(pending(&session, "state").is_err());
+    }
+
+    #[test]
+    fn pending_login_refused_without_one() {
+        let (_, msg) = pending(&Session::default(), "state").unwrap_err();
+
+        assert_eq!(msg, "the login wasn't started by this browser");
+    }
+
+    #[test]
+    fn pending_login_refused_once_expired() {
+        let session = Session::default();
+        start(
+            &session,
+            SystemTime::now() - LOGIN_TTL - Duration::from_secs(1),
+        );
+
+        let (_, msg) = pending(&session, "state").unwrap_err();
+
+        assert_eq!(msg, "the login expired");
+    }
+
+    #[test]
+    fn logged_in_with_the_nonce_of_the_login() {
+        let user = logged_in(
+            claims(json!({
+                "sub": "alice",
+                "exp": 0,
+                "nonce": "nonce",
+                "preferred_username": "alice",
+            })),
+            "nonce",
+        )
+        .unwrap();
+
+        assert_eq!(user.subject, "alice");
+        assert_eq!(user.name.as_deref(), Some("alice"));
+    }
+
+    #[test]
+    fn logged_in_refused_for_another_nonce() {
+        for nonce in [json!("other"), json!(null)] {
+            let claims = claims(json!({"sub": "alice", "exp": 0, "nonce": nonce}));
+
+            let (status, msg) = logged_in(claims, "nonce").unwrap_err();
+
+            assert_eq!(status, StatusCode::UNAUTHORIZED);
+            assert_eq!(msg, "the ID token wasn't issued for this login");
+        }
+    }
+
+    #[test]
+    fn logged_in_refused_without_a_subject() {
+        let claims = claims(json!({"exp": 0, "nonce": "nonce"}));
+
+        let (_, msg) = logged_in(claims, "nonce").unwrap_err();
+
+        assert_eq!(msg, "the ID token has no subject");
+    }
+}
@@ -0,0 +1,54 @@
+//! Answers to the `OPTIONS` requests, with the methods allowed on the path.
+//!
+//! The router refuses the methods without a handler with a `405 Method Not Allowed` listing the
+//! allowed ones, `OPTIONS` included. Those are answered with a `204 No Content` and the same
+//! `Allow` header instead, so the clients probing the routes get a meaningful response. The
+//! `HEAD` requests are answered by the `GET` handlers already, without the bodies.
+
+use axum::{
+    body::Body,
+    extract::Request,
+    http::{
+        header::{ALLOW, CONTENT_LENGTH, CONTENT_TYPE},
+        HeaderValue, Method, StatusCode,
+    },
+    middleware::{self, Next},
+    response::Response,
+    Router,
+};
+use tower::Layer;
+
+/// Answers the `OPTIONS` requests to the routes not handling them.
+pub fn layer(router: Router) -> Router {
+    // Around the whole router, the `Allow` header is set after the layers of the routes
+    Router::new().fallback_service(middleware::from_fn(answer).layer(router))
+}
+
+async fn answer(req: Request, next: Next) -> Response {
+    if req.method() != Method::OPTIONS {
+        return next.run(req).await;
+    }
+
+    let res = next.run(req).await;
+
+    if res.status() != StatusCode::METHOD_NOT_ALLOWED {
+        return res;
+    }
+
+    let (mut parts, _) = res.into_parts();
+
+    let allow = parts
+        .headers
+        .get(ALLOW)
+        .and_then(|value| value.to_str().ok())
+        .map(|allow| format!("{allow},OPTIONS"))
+        .and_then(|allow| HeaderValue::from_str(&allow).ok())
+        .unwrap_or_else(|| HeaderValue::from_static("OPTIONS"));
+
+    parts.status = StatusCode::NO_CONTENT;
+    parts.headers.insert(ALLOW, allow);
+    parts.headers.remove(CONTENT_TYPE);
+    parts.headers.remove(CONTENT_LENGTH);
+
+    Response::from_parts(parts, Body::empty())
+}
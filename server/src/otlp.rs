@@ -0,0 +1,223 @@
+//! Metrics exported to an OpenTelemetry collector over OTLP/HTTP.
+//!
+//! The [`OtlpRecorder`] forwards the metrics recorded with the [`metrics`] macros to the
+//! OpenTelemetry instruments of the same name, with the labels as attributes, so the same metrics
+//! served to Prometheus are exported. The export is tuned with the usual `OTEL_*` environment
+//! variables, like `OTEL_METRIC_EXPORT_INTERVAL`, and the attributes of the [`resource`] with
+//! `OTEL_SERVICE_NAME` and `OTEL_RESOURCE_ATTRIBUTES`.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+};
+
+use metrics::{
+    Counter, CounterFn, Gauge, GaugeFn, Histogram, HistogramFn, Key, KeyName, Metadata, Recorder,
+    SharedString, Unit,
+};
+use opentelemetry::{metrics::Meter, KeyValue};
+use opentelemetry_otlp::{ExporterBuildError, MetricExporter, WithExportConfig};
+use opentelemetry_sdk::{metrics::SdkMeterProvider, Resource};
+use tracing::warn;
+
+/// Boundaries of the histograms of durations, in seconds.
+const SECONDS_BOUNDARIES: [f64; 14] = [
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Provider of the installed recorder, flushed on [`shutdown`].
+static PROVIDER: OnceLock<SdkMeterProvider> = OnceLock::new();
+
+/// Attributes of the service, shared by all the OpenTelemetry signals.
+pub fn resource(service: &'static str) -> Resource {
+    let resource = Resource::builder()
+        .with_attribute(KeyValue::new("service.version", env!("CARGO_PKG_VERSION")));
+
+    // The name from the environment has the precedence
+    let resource = if std::env::var_os("OTEL_SERVICE_NAME").is_some() {
+        resource
+    } else {
+        resource.with_service_name(service)
+    };
+
+    resource.build()
+}
+
+#[derive(Debug)]
+pub struct OtlpRecorder {
+    meter: Meter,
+    counters: Mutex<HashMap<Key, Counter>>,
+    gauges: Mutex<HashMap<Key, Gauge>>,
+    histograms: Mutex<HashMap<Key, Histogram>>,
+}
+
+impl OtlpRecorder {
+    /// Exports the metrics to the collector at the base url, like `http://localhost:4318`.
+    pub fn new(endpoint: &str, service: &'static str) -> Result<Self, ExporterBuildError> {
+        let exporter = MetricExporter::builder()
+            .with_http()
+            .with_endpoint(format!("{}/v1/metrics", endpoint.trim_end_matches('/')))
+            .build()?;
+
+        let provider = SdkMeterProvider::builder()
+            .with_periodic_exporter(exporter)
+            .with_resource(resource(service))
+            .build();
+        let meter = opentelemetry::metrics::MeterProvider::meter(&provider, service);
+
+        if PROVIDER.set(provider).is_err() {
+            warn!("OTLP recorder already installed, it won't be flushed on shutdown");
+        }
+
+        Ok(Self {
+            meter,
+            counters: Mutex::default(),
+            gauges: Mutex::default(),
+            histograms: Mutex::default(),
+        })
+    }
+}
+
+/// Exports the metrics not yet sent, if the recorder was installed.
+pub fn shutdown() {
+    let Some(provider) = PROVIDER.get() else {
+        return;
+    };
+
+    if let Err(err) = provider.shutdown() {
+        warn!(error = %err, "couldn't flush the OTLP metrics");
+    }
+}
+
+fn attributes(key: &Key) -> Vec<KeyValue> {
+    key.labels()
+        .map(|label| KeyValue::new(label.key().to_string(), label.value().to_string()))
+        .collect()
+}
+
+/// Returns the metric of the key, registering it the first time.
+fn cached<T: Clone>(cache: &Mutex<HashMap<Key, T>>, key: &Key, register: impl FnOnce() -> T) -> T {
+    cache
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .entry(key.clone())
+        .or_insert_with(register)
+        .clone()
+}
+
+struct OtlpCounter {
+    counter: opentelemetry::metrics::Counter<u64>,
+    attributes: Vec<KeyValue>,
+    /// Total, for the absolute values.
+    total: AtomicU64,
+}
+
+impl CounterFn for OtlpCounter {
+    fn increment(&self, value: u64) {
+        self.total.fetch_add(value, Ordering::Relaxed);
+        self.counter.add(value, &self.attributes);
+    }
+
+    fn absolute(&self, value: u64) {
+        let previous = self.total.fetch_max(value, Ordering::Relaxed);
+
+        self.counter
+            .add(value.saturating_sub(previous), &self.attributes);
+    }
+}
+
+struct OtlpGauge {
+    gauge: opentelemetry::metrics::Gauge<f64>,
+    attributes: Vec<KeyValue>,
+    /// Bits of the current value, for the increments.
+    value: AtomicU64,
+}
+
+impl OtlpGauge {
+    fn update(&self, f: impl Fn(f64) -> f64) {
+        let mut value = 0.0;
+        let _ = self
+            .value
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+                value = f(f64::from_bits(bits));
+
+                Some(value.to_bits())
+            });
+
+        self.gauge.record(value, &self.attributes);
+    }
+}
+
+impl GaugeFn for OtlpGauge {
+    fn increment(&self, value: f64) {
+        self.update(|current| current + value);
+    }
+
+    fn decrement(&self, value: f64) {
+        self.update(|current| current - value);
+    }
+
+    fn set(&self, value: f64) {
+        self.update(|_| value);
+    }
+}
+
+struct OtlpHistogram {
+    histogram: opentelemetry::metrics::Histogram<f64>,
+    attributes: Vec<KeyValue>,
+}
+
+impl HistogramFn for OtlpHistogram {
+    fn record(&self, value: f64) {
+        self.histogram.record(value, &self.attributes);
+    }
+}
+
+impl Recorder for OtlpRecorder {
+    fn describe_counter(&self, _key: KeyName, _unit: Option<Unit>, _description: SharedString) {}
+
+    fn describe_gauge(&self, _key: KeyName, _unit: Option<Unit>, _description: SharedString) {}
+
+    fn describe_histogram(&self, _key: KeyName, _unit: Option<Unit>, _description: SharedString) {}
+
+    fn register_counter(&self, key: &Key, _metadata: &Metadata<'_>) -> Counter {
+        cached(&self.counters, key, || {
+            Counter::from_arc(Arc::new(OtlpCounter {
+                counter: self.meter.u64_counter(key.name().to_string()).build(),
+                attributes: attributes(key),
+                total: AtomicU64::new(0),
+            }))
+        })
+    }
+
+    fn register_gauge(&self, key: &Key, _metadata: &Metadata<'_>) -> Gauge {
+        cached(&self.gauges, key, || {
+            Gauge::from_arc(Arc::new(OtlpGauge {
+                gauge: self.meter.f64_gauge(key.name().to_string()).build(),
+                attributes: attributes(key),
+                value: AtomicU64::new(0.0f64.to_bits()),
+            }))
+        })
+    }
+
+    fn register_histogram(&self, key: &Key, _metadata: &Metadata<'_>) -> Histogram {
+        cached(&self.histograms, key, || {
+            let histogram = self.meter.f64_histogram(key.name().to_string());
+            let histogram = if key.name().ends_with("_seconds") {
+                histogram
+                    .with_unit("s")
+                    .with_boundaries(SECONDS_BOUNDARIES.to_vec())
+            } else {
+                histogram
+            };
+
+            Histogram::from_arc(Arc::new(OtlpHistogram {
+                histogram: histogram.build(),
+                attributes: attributes(key),
+            }))
+        })
+    }
+}
@@ -0,0 +1,133 @@
+//! Minimal Redis client, for the few commands the sessions are stored with.
+//!
+//! A single connection is kept, opened again after an error, with the password and the database
+//! of the url, as in `redis://:password@host:6379/1`. The connection is only kept once the reply
+//! of a command is read, so a command cancelled or timed out can't leave its reply to the next.
+
+use std::{io, time::Duration};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufStream},
+    net::TcpStream,
+    sync::Mutex,
+};
+use url::Url;
+
+/// Time a command, with the connection if needed, can take.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug)]
+pub struct Redis {
+    addr: String,
+    password: Option<String>,
+    db: Option<u32>,
+    conn: Mutex<Option<BufStream<TcpStream>>>,
+}
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+impl Redis {
+    pub fn new(url: &Url) -> io::Result<Self> {
+        if url.scheme() != "redis" {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unsupported scheme {}, only redis is", url.scheme()),
+            ));
+        }
+
+        let host = url.host_str().unwrap_or("127.0.0.1");
+        let port = url.port().unwrap_or(6379);
+        let db = url.path().trim_start_matches('/').parse().ok();
+
+        Ok(Self {
+            addr: format!("{host}:{port}"),
+            password: url.password().map(str::to_string),
+            db,
+            conn: Mutex::new(None),
+        })
+    }
+
+    async fn connect(&self) -> io::Result<BufStream<TcpStream>> {
+        let mut conn = BufStream::new(TcpStream::connect(&self.addr).await?);
+
+        if let Some(password) = &self.password {
+            Self::send(&mut conn, &[b"AUTH", password.as_bytes()]).await?;
+        }
+
+        if let Some(db) = self.db {
+            Self::send(&mut conn, &[b"SELECT", db.to_string().as_bytes()]).await?;
+        }
+
+        Ok(conn)
+    }
+
+    /// Sends the command, connecting first if needed, returning the value of a bulk reply.
+    pub async fn command(&self, args: &[&[u8]]) -> io::Result<Option<Vec<u8>>> {
+        let mut conn = self.conn.lock().await;
+
+        // Taken while in use, dropped with the future if cancelled
+        let taken = conn.take();
+
+        let reply = tokio::time::timeout(TIMEOUT, async {
+            let mut stream = match taken {
+                Some(stream) => stream,
+                None => self.connect().await?,
+            };
+
+            Self::send(&mut stream, args)
+                .await
+                .map(|reply| (stream, reply))
+        })
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "the command timed out"))?;
+
+        // The replies could be out of sync after an error
+        let (stream, reply) = reply?;
+        *conn = Some(stream);
+
+        Ok(reply)
+    }
+
+    async fn send(conn: &mut BufStream<TcpStream>, args: &[&[u8]]) -> io::Result<Option<Vec<u8>>> {
+        let mut cmd = format!("*{}\r\n", args.len()).into_bytes();
+
+        for arg in args {
+            cmd.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+            cmd.extend_from_slice(arg);
+            cmd.extend_from_slice(b"\r\n");
+        }
+
+        conn.write_all(&cmd).await?;
+        conn.flush().await?;
+
+        let mut line = String::new();
+        conn.read_line(&mut line).await?;
+        let line = line.trim_end();
+
+        let (kind, rest) = line
+            .split_at_checked(1)
+            .ok_or_else(|| invalid("connection closed"))?;
+
+        match kind {
+            "+" | ":" => Ok(None),
+            "-" => Err(io::Error::other(rest.to_string())),
+            "$" => {
+                let Ok(len) = usize::try_from(
+                    rest.parse::<i64>()
+                        .map_err(|_| invalid(format!("invalid length {rest}")))?,
+                ) else {
+                    return Ok(None);
+                };
+
+                let mut value = vec![0; len + 2];
+                conn.read_exact(&mut value).await?;
+                value.truncate(len);
+
+                Ok(Some(value))
+            }
+            kind => Err(invalid(format!("unsupported reply {kind}"))),
+        }
+    }
+}
@@ -0,0 +1,45 @@
+//! Ids of the requests, reused from the `X-Request-Id` header or generated.
+//!
+//! The id is returned in the response and recorded in the span of the request, so a request can
+//! be traced across the sender and the receiver.
+
+use axum::{extract::Request, http::HeaderName, Router};
+use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
+use tracing::Span;
+
+static X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Sets the ids of the requests missing one, returning them in the responses.
+///
+/// Must be the outermost layer, for the others to see the id.
+pub fn layer<S>(router: Router<S>) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    router
+        .layer(PropagateRequestIdLayer::new(X_REQUEST_ID.clone()))
+        .layer(SetRequestIdLayer::new(
+            X_REQUEST_ID.clone(),
+            MakeRequestUuid,
+        ))
+}
+
+/// Id of the request, once set by the [`layer`].
+pub fn get(req: &Request) -> Option<&str> {
+    req.headers()
+        .get(&X_REQUEST_ID)
+        .and_then(|id| id.to_str().ok())
+}
+
+/// Span of the request with its id, otherwise as the default one of the trace layer.
+pub fn span(req: &Request) -> Span {
+    // Same target, so it's enabled by the same filters
+    tracing::debug_span!(
+        target: "tower_http::trace::make_span",
+        "request",
+        method = %req.method(),
+        uri = %req.uri(),
+        version = ?req.version(),
+        request_id = get(req),
+    )
+}
@@ -0,0 +1,448 @@
+//! Server-side sessions of the browsers, kept by a cookie.
+//!
+//! The data of the sessions is stored in memory, or in Redis with `--session-redis` so it's
+//! shared between the instances and survives the restarts. The handlers extract the [`Session`]
+//! of the request and read and change its values, the session is saved once the response is
+//! ready and only if changed, so the clients without one aren't given a cookie. The sessions
+//! expire after `--session-ttl` without changes, or sooner if the handler asks, and only the most
+//! recently used are kept in memory. The sessions expiring sooner, like the logins in progress of
+//! the anonymous clients, are kept apart in memory, so they can't evict the users logged in.
+
+use std::{
+    collections::HashMap,
+    io,
+    num::NonZeroUsize,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Request, State},
+    http::{
+        header::{COOKIE, SET_COOKIE},
+        request::Parts,
+        HeaderMap, HeaderValue, StatusCode,
+    },
+    middleware::{self, Next},
+    response::Response,
+    Router,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use clap::Args;
+use lru::LruCache;
+use rand::Rng;
+use serde::{de::DeserializeOwned, Serialize};
+use tracing::{debug, warn};
+use url::Url;
+
+use crate::redis::Redis;
+
+type Data = HashMap<String, serde_json::Value>;
+
+/// Number of sessions kept in memory, the least recently used are dropped first.
+const MEMORY_SESSIONS: NonZeroUsize = NonZeroUsize::new(10_000).unwrap();
+/// Number of sessions expiring sooner kept in memory, apart from the others.
+const MEMORY_SHORT_SESSIONS: NonZeroUsize = NonZeroUsize::new(1_000).unwrap();
+
+#[derive(Debug, Clone, Args)]
+pub struct SessionArgs {
+    /// Url of the Redis the sessions are stored in, instead of the memory
+    #[arg(long, value_name = "URL")]
+    pub session_redis: Option<Url>,
+    /// Time the sessions are kept without changes
+    #[arg(long, default_value = "12h", value_parser = humantime::parse_duration)]
+    pub session_ttl: Duration,
+    /// Send the session cookies only over HTTPS
+    #[arg(long)]
+    pub session_secure: bool,
+}
+
+#[derive(Debug)]
+struct Stored {
+    data: Data,
+    expires: Instant,
+}
+
+#[derive(Debug)]
+struct Memory {
+    sessions: LruCache<String, Stored>,
+    /// Sessions expiring sooner than the ttl.
+    short: LruCache<String, Stored>,
+}
+
+#[derive(Debug)]
+enum Store {
+    Memory(Mutex<Memory>),
+    Redis(Redis),
+}
+
+/// Store of the sessions and attributes of their cookie.
+#[derive(Debug)]
+pub struct Sessions {
+    store: Store,
+    /// Name of the cookie, and prefix of the keys in Redis.
+    cookie: &'static str,
+    ttl: Duration,
+    secure: bool,
+}
+
+/// Random value unguessable by the other clients.
+fn random_id() -> String {
+    URL_SAFE_NO_PAD.encode(rand::thread_rng().gen::<[u8; 32]>())
+}
+
+/// Value of the cookie sent with the request.
+fn cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .find_map(|pair| {
+            let (key, value) = pair.trim().split_once('=')?;
+
+            (key == name).then_some(value)
+        })
+}
+
+impl Sessions {
+    /// Opens the store, checking the connection to Redis if used.
+    pub async fn new(args: &SessionArgs, cookie: &'static str) -> io::Result<Self> {
+        let store = match &args.session_redis {
+            Some(url) => {
+                let redis = Redis::new(url)?;
+                redis.command(&[b"PING"]).await?;
+
+                Store::Redis(redis)
+            }
+            None => Store::Memory(Mutex::new(Memory {
+                sessions: LruCache::new(MEMORY_SESSIONS),
+                short: LruCache::new(MEMORY_SHORT_SESSIONS),
+            })),
+        };
+
+        Ok(Self {
+            store,
+            cookie,
+            ttl: args.session_ttl,
+            secure: args.session_secure,
+        })
+    }
+
+    fn memory(store: &Mutex<Memory>) -> MutexGuard<'_, Memory> {
+        store.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn key(&self, id: &str) -> String {
+        format!("{}:{id}", self.cookie)
+    }
+
+    async fn load(&self, id: &str) -> io::Result<Option<Data>> {
+        match &self.store {
+            Store::Memory(store) => {
+                let mut memory = Self::memory(store);
+
+                let stored = match memory.sessions.get(id) {
+                    Some(stored) => Some(stored),
+                    None => memory.short.get(id),
+                };
+
+                Ok(stored
+                    .filter(|stored| stored.expires > Instant::now())
+                    .map(|stored| stored.data.clone()))
+            }
+            Store::Redis(redis) => match redis.command(&[b"GET", self.key(id).as_bytes()]).await? {
+                Some(json) => serde_json::from_slice(&json)
+                    .map(Some)
+                    .map_err(io::Error::other),
+                None => Ok(None),
+            },
+        }
+    }
+
+    async fn save(&self, id: &str, data: Data, ttl: Duration) -> io::Result<()> {
+        match &self.store {
+            Store::Memory(store) => {
+                let mut memory = Self::memory(store);
+                let stored = Stored {
+                    data,
+                    expires: Instant::now() + ttl,
+                };
+
+                if ttl < self.ttl {
+                    memory.sessions.pop(id);
+                    memory.short.put(id.to_string(), stored);
+                } else {
+                    memory.short.pop(id);
+                    memory.sessions.put(id.to_string(), stored);
+                }
+            }
+            Store::Redis(redis) => {
+                let json = serde_json::to_vec(&data).map_err(io::Error::other)?;
+                let ttl = ttl.as_secs().max(1).to_string();
+
+                redis
+                    .command(&[
+                        b"SET",
+                        self.key(id).as_bytes(),
+                        &json,
+                        b"EX",
+                        ttl.as_bytes(),
+                    ])
+                    .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn delete(&self, id: &str) -> io::Result<()> {
+        match &self.store {
+            Store::Memory(store) => {
+                let mut memory = Self::memory(store);
+                memory.sessions.pop(id);
+                memory.short.pop(id);
+            }
+            Store::Redis(redis) => {
+                redis.command(&[b"DEL", self.key(id).as_bytes()]).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Cookie setting the id kept for the ttl, or removing it without one.
+    fn set_cookie(&self, id: Option<(&str, Duration)>) -> Option<HeaderValue> {
+        let (id, max_age) = id.map_or(("", 0), |(id, ttl)| (id, ttl.as_secs()));
+        let secure = if self.secure { "; Secure" } else { "" };
+
+        let cookie = format!(
+            "{}={id}; Path=/; Max-Age={max_age}; HttpOnly; SameSite=Lax{secure}",
+            self.cookie,
+        );
+
+        HeaderValue::from_str(&cookie).ok()
+    }
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    /// Id sent by the client, if the session exists.
+    id: Option<String>,
+    data: Data,
+    changed: bool,
+    /// Whether the id is changed when saved.
+    renewed: bool,
+    destroyed: bool,
+    /// Time the session is kept for instead of the configured one.
+    ttl: Option<Duration>,
+}
+
+/// Session of the request, empty until a value is inserted.
+#[derive(Debug, Clone)]
+#[cfg_attr(test, derive(Default))]
+pub struct Session {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl Session {
+    fn inner(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    pub fn get<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        self.inner()
+            .data
+            .get(key)
+            .and_then(|value| serde_json::from_value(value.clone()).ok())
+    }
+
+    pub fn insert<T: Serialize>(&self, key: &str, value: T) {
+        let Ok(value) = serde_json::to_value(value) else {
+            return;
+        };
+
+        let mut inner = self.inner();
+        inner.data.insert(key.to_string(), value);
+        inner.changed = true;
+    }
+
+    pub fn remove<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        let mut inner = self.inner();
+        let value = inner.data.remove(key)?;
+        inner.changed = true;
+
+        serde_json::from_value(value).ok()
+    }
+
+    /// Gives the session a new id, like when logging in, so an id known before can't be used.
+    pub fn renew(&self) {
+        let mut inner = self.inner();
+        inner.renewed = true;
+        inner.changed = true;
+    }
+
+    /// Keeps the session, when saved by this request, at most for the ttl, like for the data only
+    /// needed shortly by the anonymous clients.
+    pub fn expire_in(&self, ttl: Duration) {
+        let mut inner = self.inner();
+        inner.ttl = Some(ttl);
+        inner.changed = true;
+    }
+
+    /// Removes the session and its cookie.
+    pub fn destroy(&self) {
+        let mut inner = self.inner();
+        inner.data.clear();
+        inner.destroyed = true;
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Session
+where
+    S: Send + Sync,
+{
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts.extensions.get::<Session>().cloned().ok_or((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "the sessions aren't enabled",
+        ))
+    }
+}
+
+/// Loads the sessions of the requests, saving them with the responses.
+pub fn layer<S>(router: Router<S>, sessions: Arc<Sessions>) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    router.layer(middleware::from_fn_with_state(sessions, load))
+}
+
+async fn load(State(sessions): State<Arc<Sessions>>, mut req: Request, next: Next) -> Response {
+    let sent = cookie(req.headers(), sessions.cookie).map(str::to_string);
+
+    let mut inner = Inner::default();
+    if let Some(id) = sent.as_deref() {
+        match sessions.load(id).await {
+            Ok(Some(data)) => {
+                inner.id = Some(id.to_string());
+                inner.data = data;
+            }
+            Ok(None) => {}
+            Err(err) => warn!(error = %err, "couldn't load the session"),
+        }
+    }
+
+    let session = Session {
+        inner: Arc::new(Mutex::new(inner)),
+    };
+    req.extensions_mut().insert(session.clone());
+
+    let mut res = next.run(req).await;
+
+    let inner = std::mem::take(&mut *session.inner());
+    if let Err(err) = save(&sessions, inner, sent.is_some(), &mut res).await {
+        warn!(error = %err, "couldn't save the session");
+    }
+
+    res
+}
+
+async fn save(sessions: &Sessions, inner: Inner, sent: bool, res: &mut Response) -> io::Result<()> {
+    if !inner.destroyed && !inner.changed {
+        return Ok(());
+    }
+
+    if inner.destroyed || inner.data.is_empty() {
+        if let Some(id) = &inner.id {
+            sessions.delete(id).await?;
+        }
+
+        if sent {
+            if let Some(cookie) = sessions.set_cookie(None) {
+                res.headers_mut().append(SET_COOKIE, cookie);
+            }
+        }
+
+        return Ok(());
+    }
+
+    let id = match inner.id {
+        Some(id) if inner.renewed => {
+            sessions.delete(&id).await?;
+
+            random_id()
+        }
+        Some(id) => id,
+        None => random_id(),
+    };
+
+    let ttl = inner.ttl.map_or(sessions.ttl, |ttl| ttl.min(sessions.ttl));
+    sessions.save(&id, inner.data, ttl).await?;
+
+    debug!("session saved");
+
+    if let Some(cookie) = sessions.set_cookie(Some((&id, ttl))) {
+        res.headers_mut().append(SET_COOKIE, cookie);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn sessions() -> Sessions {
+        let args = SessionArgs {
+            session_redis: None,
+            session_ttl: Duration::from_secs(60 * 60),
+            session_secure: false,
+        };
+
+        Sessions::new(&args, "session").await.unwrap()
+    }
+
+    fn data() -> Data {
+        Data::from([("user".to_string(), serde_json::json!("alice"))])
+    }
+
+    #[tokio::test]
+    async fn short_sessions_dont_evict_the_others() {
+        let sessions = sessions().await;
+
+        sessions.save("user", data(), sessions.ttl).await.unwrap();
+
+        for n in 0..MEMORY_SESSIONS.get() + 1 {
+            sessions
+                .save(&format!("login-{n}"), data(), Duration::from_secs(60))
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(sessions.load("user").await.unwrap(), Some(data()));
+        assert!(sessions.load("login-0").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn sessions_moved_when_kept_longer() {
+        let sessions = sessions().await;
+
+        sessions
+            .save("id", data(), Duration::from_secs(60))
+            .await
+            .unwrap();
+        sessions.save("id", data(), sessions.ttl).await.unwrap();
+
+        let Store::Memory(store) = &sessions.store else {
+            unreachable!("the sessions are in memory");
+        };
+        let memory = Sessions::memory(store);
+        assert!(memory.sessions.contains("id"));
+        assert!(!memory.short.contains("id"));
+    }
+}
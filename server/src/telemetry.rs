@@ -0,0 +1,158 @@
+//! Prometheus metrics of the servers and of their HTTP requests.
+//!
+//! The metrics can also be sent to a StatsD server, with the labels as DogStatsD tags and the
+//! durations as timers in milliseconds, and exported to an OpenTelemetry collector over OTLP.
+//! The metrics of the requests are named after the prefix of the server, like
+//! `receiver_http_requests_total`.
+
+use std::{
+    any::Any,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::{HeaderName, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
+use metrics_exporter_statsd::StatsdBuilder;
+use metrics_util::layers::FanoutBuilder;
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::otlp::OtlpRecorder;
+
+static REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+const UPKEEP: Duration = Duration::from_secs(5);
+
+/// Installs the global Prometheus recorder, rendered by the `/metrics` route, along with the
+/// StatsD one if a `HOST:PORT` address is given and the OTLP one, exporting as the `service`, if
+/// a collector url is given.
+///
+/// Must be called once per process, from within the runtime.
+pub fn install_metrics(
+    service: &'static str,
+    statsd: Option<&str>,
+    otlp: Option<&str>,
+) -> eyre::Result<PrometheusHandle> {
+    let prometheus = PrometheusBuilder::new()
+        .set_quantiles(&[0.5, 0.9, 0.99, 1.0])?
+        .build_recorder();
+    let handle = prometheus.handle();
+
+    if statsd.is_none() && otlp.is_none() {
+        metrics::set_global_recorder(prometheus)?;
+
+        tokio::spawn(upkeep(handle.clone()));
+
+        return Ok(handle);
+    }
+
+    let mut fanout = FanoutBuilder::default().add_recorder(prometheus);
+
+    if let Some(addr) = statsd {
+        let (host, port) = addr
+            .rsplit_once(':')
+            .and_then(|(host, port)| Some((host, port.parse().ok()?)))
+            .ok_or_else(|| eyre::eyre!("invalid StatsD address {addr}, expected HOST:PORT"))?;
+
+        let statsd = StatsdBuilder::from(host, port)
+            .histogram_is_timer()
+            .build(None)?;
+
+        info!("sending the metrics to StatsD at {addr}");
+
+        fanout = fanout.add_recorder(statsd);
+    }
+
+    if let Some(endpoint) = otlp {
+        let otlp = OtlpRecorder::new(endpoint, service)?;
+
+        info!("exporting the metrics over OTLP to {endpoint}");
+
+        fanout = fanout.add_recorder(otlp);
+    }
+
+    // The error holds the recorder, which isn't Send
+    metrics::set_global_recorder(fanout.build()).map_err(|err| eyre::eyre!("{err}"))?;
+
+    tokio::spawn(upkeep(handle.clone()));
+
+    Ok(handle)
+}
+
+/// Drains the histograms of the recorder, otherwise it's done only when rendering.
+async fn upkeep(handle: PrometheusHandle) {
+    let mut interval = tokio::time::interval(UPKEEP);
+
+    loop {
+        interval.tick().await;
+
+        handle.run_upkeep();
+    }
+}
+
+/// Middleware recording the requests and their latency by route, method and status class, to
+/// use with [`axum::middleware::from_fn_with_state`] and the prefix of the metrics.
+pub async fn track(
+    State(prefix): State<&'static str>,
+    route: MatchedPath,
+    req: Request,
+    next: Next,
+) -> Response {
+    let method = req.method().to_string();
+    let route = route.as_str().to_string();
+
+    let start = Instant::now();
+    let res = next.run(req).await;
+    let latency = start.elapsed();
+
+    let status = format!("{}xx", res.status().as_u16() / 100);
+
+    metrics::counter!(
+        format!("{prefix}_http_requests_total"),
+        "method" => method.clone(),
+        "route" => route.clone(),
+        "status" => status
+    )
+    .increment(1);
+    metrics::histogram!(
+        format!("{prefix}_http_request_duration_seconds"),
+        "method" => method,
+        "route" => route
+    )
+    .record(latency);
+
+    res
+}
+
+/// Responds to a panicking handler, logging the panic with an id returned to the client, to use
+/// with `CatchPanicLayer::custom` and the prefix of the metrics.
+pub fn panic_response(
+    prefix: &'static str,
+) -> impl Fn(Box<dyn Any + Send + 'static>) -> Response + Clone {
+    move |panic| respond(prefix, panic)
+}
+
+fn respond(prefix: &str, panic: Box<dyn Any + Send + 'static>) -> Response {
+    let id = Uuid::new_v4();
+    let msg = panic
+        .downcast_ref::<String>()
+        .map(String::as_str)
+        .or_else(|| panic.downcast_ref::<&str>().copied())
+        .unwrap_or("unknown panic");
+
+    error!(request_id = %id, panic = msg, "handler panicked");
+
+    metrics::counter!(format!("{prefix}_http_panics_total")).increment(1);
+
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        [(REQUEST_ID.clone(), id.to_string())],
+        format!("something went wrong, request id {id}"),
+    )
+        .into_response()
+}
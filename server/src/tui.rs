@@ -0,0 +1,97 @@
+//! Terminal dashboards of the receiver and the sender.
+//!
+//! The terminal is set up and restored here, also when the dashboard is dropped on shutdown,
+//! and the dashboard is redrawn periodically with the events it's updated from. The widgets are
+//! the ones of each binary, implementing [`Dashboard`].
+
+use std::time::Duration;
+
+use crossterm::event::{Event as TermEvent, EventStream, KeyCode, KeyEventKind, KeyModifiers};
+use futures::StreamExt;
+use ratatui::{DefaultTerminal, Frame};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio_util::sync::CancellationToken;
+
+/// Width of a bar of the sparklines.
+pub const BUCKET: Duration = Duration::from_secs(1);
+
+const REDRAW: Duration = Duration::from_millis(250);
+
+/// Widgets of a terminal dashboard.
+pub trait Dashboard {
+    /// Events the dashboard is updated from.
+    type Event: Clone;
+
+    fn record(&mut self, event: Self::Event);
+
+    /// Completes the current bucket of the sparklines, once every [`BUCKET`].
+    fn rotate(&mut self);
+
+    fn render(&self, frame: &mut Frame);
+}
+
+fn is_quit(event: &TermEvent) -> bool {
+    let TermEvent::Key(key) = event else {
+        return false;
+    };
+
+    key.kind == KeyEventKind::Press
+        && (matches!(key.code, KeyCode::Char('q') | KeyCode::Esc)
+            || (key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL)))
+}
+
+/// Restores the terminal also when the dashboard is dropped on shutdown.
+struct Restore;
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        ratatui::restore();
+    }
+}
+
+/// Shows the dashboard until the user quits or the server shuts down.
+pub async fn run<D: Dashboard>(
+    dashboard: D,
+    events: broadcast::Receiver<D::Event>,
+    shutdown: &CancellationToken,
+) -> eyre::Result<()> {
+    let mut terminal = ratatui::init();
+    let _restore = Restore;
+
+    draw(&mut terminal, dashboard, events, shutdown).await
+}
+
+async fn draw<D: Dashboard>(
+    terminal: &mut DefaultTerminal,
+    mut dashboard: D,
+    mut events: broadcast::Receiver<D::Event>,
+    shutdown: &CancellationToken,
+) -> eyre::Result<()> {
+    let mut input = EventStream::new();
+
+    let mut bucket = tokio::time::interval(BUCKET);
+    // The first tick completes immediately
+    bucket.tick().await;
+    let mut redraw = tokio::time::interval(REDRAW);
+
+    loop {
+        tokio::select! {
+            _ = shutdown.cancelled() => return Ok(()),
+            _ = redraw.tick() => {
+                terminal.draw(|frame| dashboard.render(frame))?;
+            }
+            _ = bucket.tick() => dashboard.rotate(),
+            event = events.recv() => match event {
+                Ok(event) => dashboard.record(event),
+                Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => return Ok(()),
+            },
+            event = input.next() => match event {
+                Some(Ok(event)) if is_quit(&event) => return Ok(()),
+                Some(Ok(_)) => {}
+                Some(Err(err)) => return Err(err.into()),
+                None => return Ok(()),
+            },
+        }
+    }
+}